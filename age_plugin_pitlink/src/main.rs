@@ -0,0 +1,207 @@
+//! `age-plugin-pitlink`: use pitlink ML-KEM/Kyber keys with the age encryption tool
+//!
+//! - Recipients are `age1pitlink1...`: Bech32 of `kem_id u8 | public key`.
+//! - Identities are `AGE-PLUGIN-PITLINK-1...`: Bech32 of the name of an identity in
+//!   the pitlink keyring (`~/.pitlink/keys`). The private key never leaves the keyring.
+//! - Each file key is wrapped in a `-> pitlink <kem_id>` stanza whose body is
+//!   `kem_ciphertext | wrapped_file_key`. The file key is sealed with XChaCha20-Poly1305
+//!   under the KEK from the encapsulation, which is fresh per stanza, so the nonce is zero.
+//!
+//! Stanzas carry no recipient hint, so an identity tries every pitlink stanza; a
+//! stanza for another key fails its tag and is skipped.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use age_core::format::{FileKey, Stanza, FILE_KEY_BYTES};
+use age_core::secrecy::ExposeSecret as _;
+use age_plugin::identity::{self, IdentityPluginV1};
+use age_plugin::recipient::{self, RecipientPluginV1};
+use age_plugin::{print_new_identity, run_state_machine, Callbacks};
+use anyhow::{bail, Result};
+use clap::Parser;
+use zeroize::Zeroizing;
+
+use pitlink_pqc::cipher::Cipher;
+use pitlink_pqc::keyfile::{KeyFile, KeyKind};
+use pitlink_pqc::keyring::Keyring;
+use pitlink_pqc::{kem, perms, AeadId, ExposeSecret, KemId};
+
+const PLUGIN_NAME: &str = "pitlink";
+const STANZA_TAG: &str = "pitlink";
+const WRAP_NONCE: [u8; 24] = [0; 24];
+/// XChaCha20-Poly1305 tag appended to the wrapped file key
+const WRAP_TAG_LEN: usize = 16;
+
+#[derive(Parser)]
+#[command(name = "age-plugin-pitlink", version, about = "age plugin for pitlink ML-KEM/Kyber keys")]
+struct Args {
+    /// Run the age plugin protocol (set by age)
+    #[arg(long, hide = true)]
+    age_plugin: Option<String>,
+    /// Print the age identity and recipient of this keyring identity
+    #[arg(long, value_name = "NAME")]
+    identity: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if let Some(state_machine) = args.age_plugin {
+        run_state_machine(&state_machine, Some(RecipientPlugin::default), Some(IdentityPlugin::default))?;
+        return Ok(());
+    }
+    let Some(name) = args.identity else { bail!("pass --identity NAME to print the age identity of a keyring key") };
+    let keyring = Keyring::open_default()?;
+    let public = KeyFile::read(keyring.resolve(&name, KeyKind::Public)?, KeyKind::Public)?;
+    // Only identities with a private key can decrypt
+    keyring.resolve(&name, KeyKind::Private)?;
+    print_new_identity(PLUGIN_NAME, name.as_bytes(), &recipient_bytes(public.kem, public.key.expose_secret()));
+    Ok(())
+}
+
+fn recipient_bytes(kem: KemId, public: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![kem.as_u8()];
+    bytes.extend_from_slice(public);
+    bytes
+}
+
+fn parse_recipient(bytes: &[u8]) -> Result<(KemId, Vec<u8>)> {
+    let Some((&id, public)) = bytes.split_first() else { bail!("empty recipient") };
+    let kem = KemId::from_u8(id)?;
+    if public.len() != kem::public_key_len(kem) {
+        bail!("{:?} public key has the wrong length ({} bytes)", kem, public.len());
+    }
+    Ok((kem, public.to_vec()))
+}
+
+/// The private key of the keyring identity named by `bytes`
+fn load_identity(bytes: &[u8]) -> Result<KeyFile> {
+    let name = std::str::from_utf8(bytes)?;
+    let path = Keyring::open_default()?.resolve(name, KeyKind::Private)?;
+    perms::check_private(&path, false)?;
+    KeyFile::read(path, KeyKind::Private)
+}
+
+/// Encapsulate to the recipient and seal `file_key` under the resulting KEK
+fn wrap_file_key(kem: KemId, public: &[u8], file_key: &FileKey) -> Result<Stanza> {
+    let (mut body, kek) = kem::encapsulate(kem, public)?;
+    let wrapped = Cipher::new(AeadId::XChaCha20Poly1305, kek.expose_secret())?.encrypt(&WRAP_NONCE, file_key.expose_secret(), &[kem.as_u8()])?;
+    body.extend_from_slice(&wrapped);
+    Ok(Stanza { tag: STANZA_TAG.into(), args: vec![kem.as_u8().to_string()], body })
+}
+
+/// The file key in `stanza`, if one of `identities` can open it
+fn unwrap_stanza(identities: &[KeyFile], stanza: &Stanza) -> Result<Option<FileKey>> {
+    let [id] = stanza.args.as_slice() else { bail!("pitlink stanza needs exactly one argument") };
+    let kem = KemId::from_u8(id.parse()?)?;
+    let Some(ct_len) = stanza.body.len().checked_sub(FILE_KEY_BYTES + WRAP_TAG_LEN).filter(|&n| n > 0) else { bail!("pitlink stanza body is too short") };
+    let (ct, wrapped) = stanza.body.split_at(ct_len);
+    for identity in identities.iter().filter(|i| kem::key_compatible(i.kem, kem)) {
+        // A stanza for another key decapsulates to an unrelated KEK and fails the tag
+        let Ok(kek) = kem::decapsulate(kem, identity.key.expose_secret(), ct) else { continue };
+        let Ok(key) = Cipher::new(AeadId::XChaCha20Poly1305, kek.expose_secret())?.decrypt(&WRAP_NONCE, wrapped, &[kem.as_u8()]) else { continue };
+        let key = Zeroizing::new(key);
+        let mut file_key = Box::new([0u8; FILE_KEY_BYTES]);
+        file_key.copy_from_slice(&key);
+        return Ok(Some(FileKey::new(file_key)));
+    }
+    Ok(None)
+}
+
+#[derive(Default)]
+struct RecipientPlugin {
+    recipients: Vec<(KemId, Vec<u8>)>,
+}
+
+impl RecipientPluginV1 for RecipientPlugin {
+    fn add_recipient(&mut self, index: usize, _plugin_name: &str, bytes: &[u8]) -> Result<(), recipient::Error> {
+        let recipient = parse_recipient(bytes).map_err(|e| recipient::Error::Recipient { index, message: e.to_string() })?;
+        self.recipients.push(recipient);
+        Ok(())
+    }
+
+    /// Encrypting to an identity encrypts to its public key
+    fn add_identity(&mut self, index: usize, _plugin_name: &str, bytes: &[u8]) -> Result<(), recipient::Error> {
+        let identity = load_identity(bytes).map_err(|e| recipient::Error::Identity { index, message: e.to_string() })?;
+        let public = kem::public_from_secret(identity.kem, identity.key.expose_secret()).map_err(|e| recipient::Error::Identity { index, message: e.to_string() })?;
+        self.recipients.push((identity.kem, public));
+        Ok(())
+    }
+
+    /// Keeps age from mixing these recipients with ones that are not post-quantum
+    fn labels(&mut self) -> HashSet<String> {
+        HashSet::from(["postquantum".to_string()])
+    }
+
+    fn wrap_file_keys(&mut self, file_keys: Vec<FileKey>, _callbacks: impl Callbacks<recipient::Error>) -> io::Result<Result<Vec<Vec<Stanza>>, Vec<recipient::Error>>> {
+        let mut errors = Vec::new();
+        let stanzas = file_keys
+            .iter()
+            .map(|file_key| {
+                self.recipients
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, (kem, public))| {
+                        wrap_file_key(*kem, public, file_key).map_err(|e| errors.push(recipient::Error::Recipient { index, message: e.to_string() })).ok()
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(if errors.is_empty() { Ok(stanzas) } else { Err(errors) })
+    }
+}
+
+#[derive(Default)]
+struct IdentityPlugin {
+    identities: Vec<KeyFile>,
+}
+
+impl IdentityPluginV1 for IdentityPlugin {
+    fn add_identity(&mut self, index: usize, _plugin_name: &str, bytes: &[u8]) -> Result<(), identity::Error> {
+        let identity = load_identity(bytes).map_err(|e| identity::Error::Identity { index, message: e.to_string() })?;
+        self.identities.push(identity);
+        Ok(())
+    }
+
+    fn unwrap_file_keys(&mut self, files: Vec<Vec<Stanza>>, _callbacks: impl Callbacks<identity::Error>) -> io::Result<HashMap<usize, Result<FileKey, Vec<identity::Error>>>> {
+        let mut file_keys = HashMap::new();
+        for (file_index, stanzas) in files.iter().enumerate() {
+            let mut errors = Vec::new();
+            for (stanza_index, stanza) in stanzas.iter().enumerate().filter(|(_, s)| s.tag == STANZA_TAG) {
+                match unwrap_stanza(&self.identities, stanza) {
+                    Ok(Some(file_key)) => {
+                        file_keys.insert(file_index, Ok(file_key));
+                        break;
+                    }
+                    Ok(None) => {}
+                    Err(e) => errors.push(identity::Error::Stanza { file_index, stanza_index, message: e.to_string() }),
+                }
+            }
+            if !errors.is_empty() {
+                file_keys.entry(file_index).or_insert(Err(errors));
+            }
+        }
+        Ok(file_keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitlink_pqc::generate_keypair;
+
+    #[test]
+    fn stanza_roundtrip() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let (_, other) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let recipient = parse_recipient(&recipient_bytes(public.kem, public.key.expose_secret())).unwrap();
+        let file_key = FileKey::new(Box::new([7u8; FILE_KEY_BYTES]));
+
+        let stanza = wrap_file_key(recipient.0, &recipient.1, &file_key).unwrap();
+        assert_eq!((stanza.tag.as_str(), stanza.args.len()), (STANZA_TAG, 1));
+        let unwrapped = unwrap_stanza(&[other.clone(), private], &stanza).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+        assert!(unwrap_stanza(&[other], &stanza).unwrap().is_none());
+        assert!(parse_recipient(&[KemId::MlKem768.as_u8(), 1, 2, 3]).is_err());
+    }
+}
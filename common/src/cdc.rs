@@ -0,0 +1,164 @@
+//! Content-defined chunking (FastCDC)
+//!
+//! Cut points are chosen by a rolling gear hash over the content rather than at
+//! fixed offsets, so an insertion or deletion only moves the boundaries near it
+//! and the chunks around it keep their bytes and hashes. That is what lets a
+//! later run deduplicate chunks, or a transfer send only the ones that changed.
+//!
+//! This is FastCDC with normalized chunking (Xia et al., 2016): no cut before
+//! `min`, a stricter mask up to `avg` and a looser one after it, and a forced cut
+//! at `max`. The gear table is fixed, so boundaries are stable across runs and
+//! builds.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Smallest accepted `min`
+pub const MIN_SIZE_FLOOR: usize = 64;
+/// Largest accepted `max`
+pub const MAX_SIZE_CEILING: usize = 1 << 30;
+
+/// Chunk size bounds, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cdc {
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
+
+impl Default for Cdc {
+    /// 256 KiB / 1 MiB / 4 MiB
+    fn default() -> Self {
+        Self { min: 256 * 1024, avg: 1024 * 1024, max: 4 * 1024 * 1024 }
+    }
+}
+
+impl Cdc {
+    /// Check `min <= avg <= max` within the supported range; `avg` is rounded to a
+    /// power of two for the masks
+    pub fn new(min: usize, avg: usize, max: usize) -> Result<Self> {
+        if min < MIN_SIZE_FLOOR || max > MAX_SIZE_CEILING {
+            bail!("content-defined chunk sizes must be between {} bytes and {} MiB", MIN_SIZE_FLOOR, MAX_SIZE_CEILING >> 20);
+        }
+        if !(min <= avg && avg <= max) {
+            bail!("content-defined chunk sizes need min <= avg <= max (got {} / {} / {})", min, avg, max);
+        }
+        Ok(Self { min, avg: avg.next_power_of_two().min(max), max })
+    }
+
+    /// Length of the first chunk of `data`. `data` should hold at least `max`
+    /// bytes unless it is the end of the input; a shorter tail is one chunk.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let len = data.len().min(self.max);
+        if len <= self.min {
+            return len;
+        }
+        let bits = self.avg.trailing_zeros();
+        // More bits before the average make early cuts rarer, fewer after it make late ones likelier
+        let (mask_small, mask_large) = (mask(bits + 1), mask(bits.saturating_sub(1)));
+        let normal = self.avg.min(len);
+        let mut hash = 0u64;
+        for (i, &byte) in data.iter().enumerate().take(len).skip(self.min) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < normal { mask_small } else { mask_large };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        len
+    }
+
+    /// Chunk lengths of `data`, in order
+    pub fn split(&self, data: &[u8]) -> alloc::vec::Vec<usize> {
+        let mut lens = alloc::vec::Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let n = self.cut(rest);
+            lens.push(n);
+            rest = &rest[n..];
+        }
+        lens
+    }
+
+    /// Chunk lengths and BLAKE3 hashes of everything `reader` yields, holding at
+    /// most `max` bytes plus one read buffer in memory
+    #[cfg(feature = "std")]
+    pub fn scan<R: std::io::Read>(&self, mut reader: R) -> Result<alloc::vec::Vec<(u64, [u8; 32])>> {
+        let mut chunks = alloc::vec::Vec::new();
+        let mut buf = alloc::vec::Vec::with_capacity(self.max + READ_SIZE);
+        let mut block = alloc::vec![0u8; READ_SIZE];
+        let mut eof = false;
+        loop {
+            while !eof && buf.len() < self.max {
+                let n = reader.read(&mut block)?;
+                eof = n == 0;
+                buf.extend_from_slice(&block[..n]);
+            }
+            if buf.is_empty() {
+                return Ok(chunks);
+            }
+            let n = self.cut(&buf);
+            chunks.push((n as u64, blake3::hash(&buf[..n]).into()));
+            buf.drain(..n);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+const READ_SIZE: usize = 64 * 1024;
+
+/// The top `bits` bits of the hash, which depend on the last 64 bytes seen
+const fn mask(bits: u32) -> u64 {
+    if bits == 0 { 0 } else { u64::MAX << (64 - bits) }
+}
+
+/// Gear table: 256 fixed pseudo-random words from SplitMix64
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x5049_544c_494e_4b00u64; // "PITLINK\0"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| { state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407); (state >> 56) as u8 }).collect()
+    }
+
+    #[test]
+    fn cuts_within_bounds_and_resync_after_an_edit() {
+        let cdc = Cdc::new(4 * 1024, 16 * 1024, 64 * 1024).unwrap();
+        let data = pseudo_random(1 << 20, 7);
+        let lens = cdc.split(&data);
+        assert_eq!(lens.iter().sum::<usize>(), data.len());
+        assert!(lens[..lens.len() - 1].iter().all(|&n| (cdc.min..=cdc.max).contains(&n)));
+        let avg = data.len() / lens.len();
+        assert!((cdc.avg / 2..cdc.avg * 2).contains(&avg), "average chunk {} bytes", avg);
+
+        // Inserting bytes near the start only changes the chunks around the edit
+        let mut edited = data[..1000].to_vec();
+        edited.extend_from_slice(b"inserted");
+        edited.extend_from_slice(&data[1000..]);
+        let hashes = |d: &[u8]| cdc.scan(d).unwrap().into_iter().map(|(_, h)| h).collect::<Vec<_>>();
+        let (before, after) = (hashes(&data), hashes(&edited));
+        let shared = after.iter().filter(|h| before.contains(h)).count();
+        assert!(shared + 2 >= before.len(), "{} of {} chunks kept", shared, before.len());
+
+        let scanned = cdc.scan(&data[..]).unwrap();
+        assert_eq!(scanned.iter().map(|&(n, _)| n as usize).collect::<Vec<_>>(), lens);
+        assert!(Cdc::new(1024, 512, 4096).is_err());
+        assert_eq!(Cdc::new(1000, 3000, 100_000).unwrap().avg, 4096);
+    }
+}
@@ -0,0 +1,80 @@
+//! Per-chunk compression applied before encryption
+//!
+//! When the header carries the compression extension every chunk frame holds
+//! `raw_len u32 LE | LZ4 block` (the `lz4_flex` size-prepended block layout)
+//! instead of the raw plaintext. Merkle leaves, the plaintext length and the
+//! trailer totals still describe the uncompressed plaintext.
+
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+
+/// Compression algorithm identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Lz4,
+}
+
+impl Compression {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Compression::Lz4 => 1,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            1 => Ok(Compression::Lz4),
+            other => bail!("unsupported compression id {}", other),
+        }
+    }
+
+    /// Parse a command-line name such as `lz4`
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "lz4" => Ok(Compression::Lz4),
+            other => bail!("unsupported compression '{}' (expected lz4)", other),
+        }
+    }
+
+    /// Largest compressed form of a `chunk_size` byte chunk, for bounding frame reads
+    pub fn max_compressed_len(self, chunk_size: usize) -> usize {
+        4 + lz4_flex::block::get_maximum_output_size(chunk_size)
+    }
+
+    /// Compress one plaintext chunk
+    pub fn compress(self, chunk: &[u8]) -> Vec<u8> {
+        lz4_flex::block::compress_prepend_size(chunk)
+    }
+
+    /// Decompress one chunk into `out`, refusing more than `max_len` bytes of output
+    pub fn decompress_into(self, data: &[u8], max_len: usize, out: &mut Vec<u8>) -> Result<()> {
+        if data.len() < 4 { bail!("compressed chunk is truncated"); }
+        let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        if len > max_len { bail!("compressed chunk expands to {} bytes, more than the {} byte chunk size", len, max_len); }
+        out.clear();
+        out.resize(len, 0);
+        let n = lz4_flex::block::decompress_into(&data[4..], out).map_err(|e| anyhow::anyhow!("LZ4 decompression failed: {}", e))?;
+        if n != len { bail!("compressed chunk holds {} bytes but records {}", n, len); }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lz4_roundtrip_and_bounds() {
+        let chunk: Vec<u8> = b"speed=212,rpm=11800,gear=7\n".iter().copied().cycle().take(64 * 1024).collect();
+        let packed = Compression::Lz4.compress(&chunk);
+        assert!(packed.len() < chunk.len() / 5);
+        assert!(packed.len() <= Compression::Lz4.max_compressed_len(chunk.len()));
+
+        let mut out = Vec::new();
+        Compression::Lz4.decompress_into(&packed, chunk.len(), &mut out).unwrap();
+        assert_eq!(out, chunk);
+        assert!(Compression::Lz4.decompress_into(&packed, chunk.len() - 1, &mut out).is_err());
+        assert!(Compression::Lz4.decompress_into(&packed[..packed.len() / 2], chunk.len(), &mut out).is_err());
+        assert_eq!(Compression::from_u8(Compression::Lz4.as_u8()).unwrap(), Compression::Lz4);
+    }
+}
@@ -0,0 +1,1176 @@
+//! RKPQ container header encoding and compatibility reader
+//!
+//! Two header layouts exist on disk:
+//!
+//! - **v1** (original): `MAGIC | kem_ct_len u16 | kem_ct | wrap_nonce[24] | wrap_len u16 | wrap_ct`,
+//!   followed by unframed `nonce[24] | len u32 | ct` chunks with no associated data.
+//! - **v2**: `MAGIC | version u8 | kem_id u8 | aead_id u8 | ext_len u16 | extensions |
+//!   kem_ct_len u16 | kem_ct | nonce_len u8 | wrap_nonce | wrap_len u16 | wrap_ct`,
+//!   followed by `nonce | flags u8 | len u32 | ct` chunks. When the header carries a
+//!   nonce prefix extension the per-chunk nonce is omitted and derived as
+//!   `prefix || chunk_counter (u64 BE)`.
+//!
+//! When the header carries a Merkle root extension, the header is followed by the
+//! tree's leaf hashes (`leaf_count * 32` bytes) before the first chunk. When it
+//! carries the trailer extension, the package ends with
+//! `TRAILER_MAGIC | plaintext_len u64 | chunk_count u64 | mac[32]`, a keyed BLAKE3
+//! MAC over every byte before the MAC. A seekable package (index extension) puts an
+//! encrypted `ChunkIndex` frame just before the trailer, and a package with the
+//! metadata extension has an encrypted `FileMetadata` frame before the first chunk.
+//! An appendable package is a run of complete packages (members) carrying the member
+//! extension, each linked to the trailer of the member before it. With the chunk KDF
+//! extension every frame is encrypted under its own subkey (`ChunkKdf`) instead of
+//! the file key.
+//!
+//! v1 has no version byte. The byte following MAGIC in a v1 file is the high byte of
+//! the Kyber-768 ciphertext length (0x04), which never collides with a version number,
+//! so readers can tell the layouts apart without ambiguity.
+//!
+//! Encoding (`Header::to_bytes`, `ChunkFrame::encode_into`, `Trailer::to_bytes`)
+//! only needs `alloc`; the `read_from`/`write_to` methods need the `std` feature.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+#[cfg(feature = "std")]
+use crate::error::PitlinkError;
+use crate::compress::Compression;
+use crate::{CHUNK_SIZE, MAGIC};
+
+/// Current container version written by new encryptors
+pub const CURRENT_VERSION: u8 = 2;
+
+/// Chunk flag marking the last chunk of a package
+pub const CHUNK_FLAG_FINAL: u8 = 0x01;
+
+/// Flag of the encrypted sender signature frame that follows the final chunk
+pub const CHUNK_FLAG_SIGNATURE: u8 = 0x02;
+
+/// Flag of the encrypted chunk index frame that precedes the trailer
+pub const CHUNK_FLAG_INDEX: u8 = 0x04;
+
+/// Flag of the encrypted file metadata frame that precedes the first chunk
+pub const CHUNK_FLAG_METADATA: u8 = 0x08;
+
+/// Nonce position of the metadata frame, which no chunk reaches
+pub const METADATA_SEQ: u64 = u64::MAX;
+
+/// High byte of the Kyber-768 ciphertext length (1088), the first byte after MAGIC in v1 files
+#[cfg(feature = "std")]
+const V1_CT_LEN_HI: u8 = 0x04;
+
+/// Header extension: per-file random nonce prefix for counter-based chunk nonces
+pub const EXT_NONCE_PREFIX: u8 = 0x01;
+
+/// Header extension: the package carries a sender signature frame; value is the `SigId`
+pub const EXT_SIGNATURE: u8 = 0x02;
+
+/// Header extension: BLAKE3 Merkle root over the chunks; value is `root[32] | leaf_count u64`
+pub const EXT_MERKLE_ROOT: u8 = 0x03;
+
+/// Header extension: salted hint of the recipient public key; value is `salt[16] | hint[8]`
+pub const EXT_RECIPIENT_HINT: u8 = 0x04;
+
+/// Length of the recipient hint salt
+pub const RECIPIENT_HINT_SALT_LEN: usize = 16;
+
+/// Header extension: the package ends with an authenticated trailer (empty value)
+pub const EXT_TRAILER: u8 = 0x05;
+
+/// Magic prefix of the package trailer
+pub const TRAILER_MAGIC: &[u8] = b"RKPQT";
+
+/// Size of the package trailer on disk
+pub const TRAILER_LEN: usize = 5 + 8 + 8 + 32;
+
+/// Upper bound on the number of Merkle leaves a reader will allocate
+pub const MAX_MERKLE_LEAVES: u64 = 1 << 24;
+
+/// Upper bound on an encrypted signature frame (SPHINCS+-256s signature plus overhead)
+pub const MAX_SIGNATURE_FRAME: usize = 64 * 1024;
+
+/// Header extension: the plaintext is a directory archive (empty value)
+pub const EXT_ARCHIVE: u8 = 0x06;
+
+/// Header extension: plaintext bytes per chunk; value is `chunk_size u32`. Packages
+/// without it use `CHUNK_SIZE`.
+pub const EXT_CHUNK_SIZE: u8 = 0x07;
+
+/// Header extension: total plaintext length when known before encrypting; value
+/// is `plaintext_len u64`
+pub const EXT_PLAINTEXT_LEN: u8 = 0x08;
+
+/// Header extension: chunks are compressed before encryption. The value is the
+/// one-byte `Compression` id.
+pub const EXT_COMPRESSION: u8 = 0x09;
+
+/// Header extension: the package ends with an encrypted `ChunkIndex` frame, after
+/// the signature frame and before the trailer, so readers can seek (empty value)
+pub const EXT_INDEX: u8 = 0x0a;
+
+/// Header extension: an encrypted `FileMetadata` frame follows the Merkle leaves,
+/// ahead of the first chunk (empty value)
+pub const EXT_METADATA: u8 = 0x0b;
+
+/// Header extension: the package is one member of an appendable package. The value
+/// is the `member_link` of the member before it, all zeros for the first member.
+pub const EXT_MEMBER: u8 = 0x0c;
+
+/// Header extension: every frame is encrypted under a subkey derived from the file
+/// key and the frame's sequence number. The value is the one-byte `ChunkKdf` id;
+/// packages without it encrypt every frame under the file key.
+pub const EXT_CHUNK_KDF: u8 = 0x0d;
+
+/// Smallest chunk size a writer may choose
+pub const MIN_CHUNK_SIZE: usize = 4 * 1024;
+
+/// Largest chunk size a reader will allocate for
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Bytes of the chunk nonce taken by the chunk counter
+pub const NONCE_COUNTER_LEN: usize = 8;
+
+/// Maximum size of the header extension area
+pub const MAX_EXTENSIONS_LEN: usize = 16 * 1024;
+
+/// Container format version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatVersion {
+    /// Original layout without version byte or authenticated header
+    V1,
+    /// Versioned layout with algorithm identifiers and header extensions
+    V2,
+}
+
+/// Key encapsulation mechanism identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KemId {
+    Kyber512,
+    Kyber768,
+    Kyber1024,
+    /// FIPS 203 ML-KEM parameter sets
+    MlKem512,
+    MlKem768,
+    MlKem1024,
+    /// X25519 + Kyber-768 hybrid; KEK derived from both shared secrets
+    X25519Kyber768,
+}
+
+impl KemId {
+    /// Every supported KEM
+    pub const ALL: [KemId; 7] = [
+        KemId::Kyber512, KemId::Kyber768, KemId::Kyber1024,
+        KemId::MlKem512, KemId::MlKem768, KemId::MlKem1024,
+        KemId::X25519Kyber768,
+    ];
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            KemId::Kyber768 => 0x01,
+            KemId::X25519Kyber768 => 0x02,
+            KemId::Kyber512 => 0x03,
+            KemId::Kyber1024 => 0x04,
+            KemId::MlKem512 => 0x05,
+            KemId::MlKem768 => 0x06,
+            KemId::MlKem1024 => 0x07,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            0x01 => Ok(KemId::Kyber768),
+            0x02 => Ok(KemId::X25519Kyber768),
+            0x03 => Ok(KemId::Kyber512),
+            0x04 => Ok(KemId::Kyber1024),
+            0x05 => Ok(KemId::MlKem512),
+            0x06 => Ok(KemId::MlKem768),
+            0x07 => Ok(KemId::MlKem1024),
+            other => bail!("unsupported KEM id 0x{:02x}", other),
+        }
+    }
+
+    /// HKDF info label under which the KEM shared secret becomes the key-encryption key
+    pub fn kek_label(self) -> &'static [u8] {
+        match self {
+            KemId::MlKem512 | KemId::MlKem768 | KemId::MlKem1024 => b"mlkem-kek-v1",
+            KemId::Kyber512 | KemId::Kyber768 | KemId::Kyber1024 => b"kyber-kek-v1",
+            KemId::X25519Kyber768 => b"x25519-kyber768-kek-v1",
+        }
+    }
+}
+
+/// Signature algorithm identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigId {
+    /// FIPS 204 ML-DSA (Dilithium) parameter sets
+    MlDsa44,
+    MlDsa65,
+    MlDsa87,
+    /// SPHINCS+-SHA2 "small" simple parameter sets (hash-based, conservative)
+    SphincsSha2128s,
+    SphincsSha2192s,
+    SphincsSha2256s,
+    /// Falcon-512: compact signatures for constrained links
+    Falcon512,
+}
+
+impl SigId {
+    /// Every supported signature algorithm
+    pub const ALL: [SigId; 7] = [
+        SigId::MlDsa44, SigId::MlDsa65, SigId::MlDsa87,
+        SigId::SphincsSha2128s, SigId::SphincsSha2192s, SigId::SphincsSha2256s,
+        SigId::Falcon512,
+    ];
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            SigId::MlDsa44 => 0x01,
+            SigId::MlDsa65 => 0x02,
+            SigId::MlDsa87 => 0x03,
+            SigId::SphincsSha2128s => 0x04,
+            SigId::SphincsSha2192s => 0x05,
+            SigId::SphincsSha2256s => 0x06,
+            SigId::Falcon512 => 0x07,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            0x01 => Ok(SigId::MlDsa44),
+            0x02 => Ok(SigId::MlDsa65),
+            0x03 => Ok(SigId::MlDsa87),
+            0x04 => Ok(SigId::SphincsSha2128s),
+            0x05 => Ok(SigId::SphincsSha2192s),
+            0x06 => Ok(SigId::SphincsSha2256s),
+            0x07 => Ok(SigId::Falcon512),
+            other => bail!("unsupported signature algorithm id 0x{:02x}", other),
+        }
+    }
+}
+
+/// AEAD cipher identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadId {
+    XChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl AeadId {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            AeadId::XChaCha20Poly1305 => 0x01,
+            AeadId::Aes256Gcm => 0x02,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            0x01 => Ok(AeadId::XChaCha20Poly1305),
+            0x02 => Ok(AeadId::Aes256Gcm),
+            other => bail!("unsupported AEAD id 0x{:02x}", other),
+        }
+    }
+
+    /// Nonce length in bytes
+    pub fn nonce_len(self) -> usize {
+        match self {
+            AeadId::XChaCha20Poly1305 => 24,
+            AeadId::Aes256Gcm => 12,
+        }
+    }
+
+    /// Authentication tag length in bytes
+    pub fn tag_len(self) -> usize {
+        16
+    }
+}
+
+/// A single tag-length-value header extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub tag: u8,
+    pub value: Vec<u8>,
+}
+
+/// Parsed container header
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: FormatVersion,
+    pub kem: KemId,
+    pub aead: AeadId,
+    pub extensions: Vec<Extension>,
+    pub kem_ct: Vec<u8>,
+    pub wrap_nonce: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+}
+
+impl Header {
+    /// Create a v2 header with no wrapped key yet
+    pub fn new(kem: KemId, aead: AeadId, kem_ct: Vec<u8>, wrap_nonce: Vec<u8>) -> Self {
+        Self {
+            version: FormatVersion::V2,
+            kem,
+            aead,
+            extensions: Vec::new(),
+            kem_ct,
+            wrap_nonce,
+            wrapped_key: Vec::new(),
+        }
+    }
+
+    /// Look up an extension value by tag
+    pub fn extension(&self, tag: u8) -> Option<&[u8]> {
+        self.extensions.iter().find(|e| e.tag == tag).map(|e| e.value.as_slice())
+    }
+
+    /// Add or replace an extension
+    pub fn set_extension(&mut self, tag: u8, value: Vec<u8>) {
+        match self.extensions.iter_mut().find(|e| e.tag == tag) {
+            Some(e) => e.value = value,
+            None => self.extensions.push(Extension { tag, value }),
+        }
+    }
+
+    /// Per-file nonce prefix, if chunk nonces are derived from a counter
+    pub fn nonce_prefix(&self) -> Option<&[u8]> {
+        self.extension(EXT_NONCE_PREFIX)
+    }
+
+    /// Nonce for chunk `seq` when the header carries a nonce prefix
+    pub fn chunk_nonce(&self, seq: u64) -> Option<Vec<u8>> {
+        self.nonce_prefix().map(|prefix| {
+            let mut nonce = Vec::with_capacity(prefix.len() + NONCE_COUNTER_LEN);
+            nonce.extend_from_slice(prefix);
+            nonce.extend_from_slice(&seq.to_be_bytes());
+            nonce
+        })
+    }
+
+    /// Merkle root and leaf count, if the package carries a chunk tree
+    pub fn merkle_root(&self) -> Result<Option<([u8; 32], u64)>> {
+        let Some(value) = self.extension(EXT_MERKLE_ROOT) else { return Ok(None) };
+        if value.len() != 40 { bail!("malformed Merkle root extension"); }
+        let mut root = [0u8; 32];
+        root.copy_from_slice(&value[..32]);
+        let count = u64::from_be_bytes(value[32..].try_into().expect("8 bytes"));
+        if count == 0 || count > MAX_MERKLE_LEAVES { bail!("invalid Merkle leaf count {}", count); }
+        Ok(Some((root, count)))
+    }
+
+    /// Record the Merkle root extension
+    pub fn set_merkle_root(&mut self, root: &[u8; 32], leaf_count: u64) {
+        let mut value = root.to_vec();
+        value.extend_from_slice(&leaf_count.to_be_bytes());
+        self.set_extension(EXT_MERKLE_ROOT, value);
+    }
+
+    /// Plaintext bytes per chunk
+    pub fn chunk_size(&self) -> Result<usize> {
+        let Some(value) = self.extension(EXT_CHUNK_SIZE) else { return Ok(CHUNK_SIZE) };
+        let Ok(bytes) = <[u8; 4]>::try_from(value) else { bail!("malformed chunk size extension") };
+        let size = u32::from_be_bytes(bytes) as usize;
+        if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&size) { bail!("invalid chunk size {}", size); }
+        Ok(size)
+    }
+
+    /// Record the chunk size extension
+    pub fn set_chunk_size(&mut self, size: usize) {
+        self.set_extension(EXT_CHUNK_SIZE, (size as u32).to_be_bytes().to_vec());
+    }
+
+    /// Total plaintext length, if the writer knew it up front
+    pub fn plaintext_len(&self) -> Result<Option<u64>> {
+        let Some(value) = self.extension(EXT_PLAINTEXT_LEN) else { return Ok(None) };
+        let Ok(bytes) = <[u8; 8]>::try_from(value) else { bail!("malformed plaintext length extension") };
+        Ok(Some(u64::from_be_bytes(bytes)))
+    }
+
+    /// Record the plaintext length extension
+    pub fn set_plaintext_len(&mut self, len: u64) {
+        self.set_extension(EXT_PLAINTEXT_LEN, len.to_be_bytes().to_vec());
+    }
+
+    /// Compression applied to every chunk before encryption, if any
+    pub fn compression(&self) -> Result<Option<Compression>> {
+        let Some(value) = self.extension(EXT_COMPRESSION) else { return Ok(None) };
+        let [id] = value else { bail!("malformed compression extension") };
+        Compression::from_u8(*id).map(Some)
+    }
+
+    /// Record the compression extension
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.set_extension(EXT_COMPRESSION, vec![compression.as_u8()]);
+    }
+
+    /// How frame subkeys are derived from the file key, if frames have their own
+    pub fn chunk_kdf(&self) -> Result<Option<ChunkKdf>> {
+        let Some(value) = self.extension(EXT_CHUNK_KDF) else { return Ok(None) };
+        let [id] = value else { bail!("malformed chunk KDF extension") };
+        ChunkKdf::from_u8(*id).map(Some)
+    }
+
+    /// Record the chunk KDF extension
+    pub fn set_chunk_kdf(&mut self, kdf: ChunkKdf) {
+        self.set_extension(EXT_CHUNK_KDF, vec![kdf.as_u8()]);
+    }
+
+    /// Largest file metadata frame payload a reader must accept
+    pub fn max_metadata_frame_len(&self) -> usize {
+        FileMetadata::MAX_ENCODED_LEN + self.aead.tag_len()
+    }
+
+    /// Largest chunk index frame payload a reader must accept after `chunk_count` chunks
+    pub fn max_index_frame_len(&self, chunk_count: u64) -> usize {
+        (ChunkIndex::encoded_len(chunk_count) as usize).saturating_add(self.aead.tag_len())
+    }
+
+    /// Largest frame payload a reader must accept: a chunk plus AEAD tag, or its
+    /// worst-case compressed form plus tag
+    pub fn max_frame_len(&self) -> Result<usize> {
+        let chunk_size = self.chunk_size()?;
+        let payload = match self.compression()? {
+            Some(compression) => compression.max_compressed_len(chunk_size),
+            None => chunk_size,
+        };
+        Ok(payload + self.aead.tag_len())
+    }
+
+    /// Whether the package ends with a chunk index frame
+    pub fn is_seekable(&self) -> bool {
+        self.extension(EXT_INDEX).is_some()
+    }
+
+    /// Sequence number of the index frame in a package of `chunk_count` chunks:
+    /// the next one after the final chunk and the signature frame, if any
+    pub fn index_seq(&self, chunk_count: u64) -> u64 {
+        chunk_count + u64::from(self.extension(EXT_SIGNATURE).is_some())
+    }
+
+    /// The link to the previous member when the package is a member of an
+    /// appendable package
+    pub fn previous_member(&self) -> Result<Option<[u8; 32]>> {
+        let Some(value) = self.extension(EXT_MEMBER) else { return Ok(None) };
+        let link: [u8; 32] = value.try_into().map_err(|_| anyhow::anyhow!("malformed member extension"))?;
+        Ok(Some(link))
+    }
+
+    pub fn set_previous_member(&mut self, link: &[u8; 32]) {
+        self.set_extension(EXT_MEMBER, link.to_vec());
+    }
+
+    /// Whether the recipient hint, if present, matches `public_key`. Packages
+    /// without a hint match every key.
+    pub fn matches_recipient(&self, public_key: &[u8]) -> Result<bool> {
+        let Some(value) = self.extension(EXT_RECIPIENT_HINT) else { return Ok(true) };
+        if value.len() != RECIPIENT_HINT_SALT_LEN + 8 { bail!("malformed recipient hint extension"); }
+        let (salt, hint) = value.split_at(RECIPIENT_HINT_SALT_LEN);
+        Ok(recipient_hint(salt, public_key) == hint)
+    }
+
+    /// Record a recipient hint for `public_key` under a fresh `salt`
+    pub fn set_recipient_hint(&mut self, salt: &[u8], public_key: &[u8]) {
+        let mut value = salt.to_vec();
+        value.extend_from_slice(&recipient_hint(salt, public_key));
+        self.set_extension(EXT_RECIPIENT_HINT, value);
+    }
+
+    pub fn has_recipient_hint(&self) -> bool {
+        self.extension(EXT_RECIPIENT_HINT).is_some()
+    }
+
+    /// Length of the nonce stored in each chunk frame (zero with counter nonces)
+    pub fn frame_nonce_len(&self) -> usize {
+        if self.nonce_prefix().is_some() { 0 } else { self.aead.nonce_len() }
+    }
+
+    /// Bytes authenticated by the key-wrap AEAD: everything before the wrapped key
+    pub fn wrap_aad(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self.version {
+            FormatVersion::V1 => {
+                // v1 never authenticated its header; kept for completeness
+                out.extend_from_slice(MAGIC);
+                out.extend_from_slice(&(self.kem_ct.len() as u16).to_be_bytes());
+                out.extend_from_slice(&self.kem_ct);
+                out.extend_from_slice(&self.wrap_nonce);
+            }
+            FormatVersion::V2 => {
+                out.extend_from_slice(MAGIC);
+                out.push(CURRENT_VERSION);
+                out.push(self.kem.as_u8());
+                out.push(self.aead.as_u8());
+                let ext = self.encode_extensions();
+                out.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+                out.extend_from_slice(&ext);
+                out.extend_from_slice(&(self.kem_ct.len() as u16).to_be_bytes());
+                out.extend_from_slice(&self.kem_ct);
+                out.push(self.wrap_nonce.len() as u8);
+                out.extend_from_slice(&self.wrap_nonce);
+            }
+        }
+        out
+    }
+
+    /// Serialize the full header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.wrap_aad();
+        out.extend_from_slice(&(self.wrapped_key.len() as u16).to_be_bytes());
+        out.extend_from_slice(&self.wrapped_key);
+        out
+    }
+
+    /// Write the full header
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Read a header of either version, returning it together with its raw bytes
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(r: &mut R) -> Result<(Self, Vec<u8>)> {
+        let mut raw = Vec::new();
+
+        let mut magic = [0u8; 5];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC { bail!(PitlinkError::BadMagic); }
+        raw.extend_from_slice(&magic);
+
+        let mut first = [0u8; 1];
+        r.read_exact(&mut first)?;
+        raw.push(first[0]);
+
+        let (version, kem, aead, extensions, ct_len) = match first[0] {
+            2 => {
+                let mut ids = [0u8; 2];
+                r.read_exact(&mut ids)?;
+                raw.extend_from_slice(&ids);
+                let kem = KemId::from_u8(ids[0])?;
+                let aead = AeadId::from_u8(ids[1])?;
+
+                let ext_len = read_u16(r, &mut raw)? as usize;
+                if ext_len > MAX_EXTENSIONS_LEN { bail!("header extensions too large ({} bytes)", ext_len); }
+                let ext_bytes = read_vec(r, ext_len, &mut raw)?;
+                let extensions = decode_extensions(&ext_bytes)?;
+
+                let ct_len = read_u16(r, &mut raw)? as usize;
+                (FormatVersion::V2, kem, aead, extensions, ct_len)
+            }
+            V1_CT_LEN_HI => {
+                // v1: the byte we consumed is the high byte of the KEM ciphertext length
+                let mut lo = [0u8; 1];
+                r.read_exact(&mut lo)?;
+                raw.push(lo[0]);
+                let ct_len = u16::from_be_bytes([first[0], lo[0]]) as usize;
+                (FormatVersion::V1, KemId::Kyber768, AeadId::XChaCha20Poly1305, Vec::new(), ct_len)
+            }
+            other => bail!(PitlinkError::UnsupportedVersion(other)),
+        };
+
+        let kem_ct = read_vec(r, ct_len, &mut raw)?;
+
+        let nonce_len = match version {
+            FormatVersion::V1 => 24,
+            FormatVersion::V2 => {
+                let mut nl = [0u8; 1];
+                r.read_exact(&mut nl)?;
+                raw.push(nl[0]);
+                nl[0] as usize
+            }
+        };
+        let wrap_nonce = read_vec(r, nonce_len, &mut raw)?;
+
+        let wrap_len = read_u16(r, &mut raw)? as usize;
+        let wrapped_key = read_vec(r, wrap_len, &mut raw)?;
+
+        Ok((Self { version, kem, aead, extensions, kem_ct, wrap_nonce, wrapped_key }, raw))
+    }
+
+    fn encode_extensions(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for e in &self.extensions {
+            out.push(e.tag);
+            out.extend_from_slice(&(e.value.len() as u16).to_be_bytes());
+            out.extend_from_slice(&e.value);
+        }
+        out
+    }
+}
+
+/// One encrypted chunk as stored in the container
+#[derive(Debug, Clone)]
+pub struct ChunkFrame {
+    /// Stored nonce; empty when the nonce is derived from the header prefix
+    pub nonce: Vec<u8>,
+    pub flags: u8,
+    pub ciphertext: Vec<u8>,
+}
+
+impl ChunkFrame {
+    pub fn is_final(&self) -> bool {
+        self.flags & CHUNK_FLAG_FINAL != 0
+    }
+
+    pub fn is_signature(&self) -> bool {
+        self.flags & CHUNK_FLAG_SIGNATURE != 0
+    }
+
+    /// Size of the frame on disk in the given layout
+    pub fn encoded_len(&self, version: FormatVersion) -> usize {
+        let flags_len = if version == FormatVersion::V2 { 1 } else { 0 };
+        self.nonce.len() + flags_len + 4 + self.ciphertext.len()
+    }
+
+    /// Append the frame in the v2 layout to `out`
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.reserve(self.encoded_len(FormatVersion::V2));
+        out.extend_from_slice(&self.nonce);
+        out.push(self.flags);
+        out.extend_from_slice(&(self.ciphertext.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.ciphertext);
+    }
+
+    /// Write the frame in the v2 layout
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.nonce)?;
+        w.write_all(&[self.flags])?;
+        w.write_all(&(self.ciphertext.len() as u32).to_be_bytes())?;
+        w.write_all(&self.ciphertext)?;
+        Ok(())
+    }
+
+    /// Read the next frame, returning `None` at a clean end of stream.
+    /// `max_len` bounds the ciphertext allocation.
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(r: &mut R, header: &Header, max_len: usize) -> Result<Option<Self>> {
+        let mut frame = Self { nonce: Vec::new(), flags: 0, ciphertext: Vec::new() };
+        Ok(frame.read_into(r, header, max_len)?.then_some(frame))
+    }
+
+    /// Read the next frame into `self`, reusing its buffers; returns false at a
+    /// clean end of stream
+    #[cfg(feature = "std")]
+    pub fn read_into<R: Read>(&mut self, r: &mut R, header: &Header, max_len: usize) -> Result<bool> {
+        self.nonce.resize(header.frame_nonce_len(), 0);
+        let mut flags = [0u8; 1];
+        // The first field of the frame is the nonce, or the flags byte when nonces
+        // are derived from the header; EOF there is a clean end of stream.
+        let first: &mut [u8] = if self.nonce.is_empty() { &mut flags[..] } else { &mut self.nonce[..] };
+        if !read_exact_or_eof(r, first)? { return Ok(false); }
+        if !self.nonce.is_empty() && header.version == FormatVersion::V2 {
+            r.read_exact(&mut flags)?;
+        }
+        self.flags = flags[0];
+
+        let mut cl_b = [0u8; 4];
+        r.read_exact(&mut cl_b)?;
+        let cl = u32::from_be_bytes(cl_b) as usize;
+        if cl > max_len { bail!("chunk length {} exceeds maximum", cl); }
+        self.ciphertext.resize(cl, 0);
+        r.read_exact(&mut self.ciphertext)?;
+        Ok(true)
+    }
+}
+
+/// Where each chunk starts, stored encrypted in the index frame of a seekable package
+/// as `chunk_count u64 | plaintext_len u64 | (frame_offset u64 | plaintext_offset u64) * chunk_count`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkIndex {
+    pub entries: Vec<IndexEntry>,
+    pub plaintext_len: u64,
+}
+
+/// Position of one chunk: its frame in the package and its first plaintext byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub frame_offset: u64,
+    pub plaintext_offset: u64,
+}
+
+impl ChunkIndex {
+    /// Size of the encoded index of a package with `chunk_count` chunks
+    pub fn encoded_len(chunk_count: u64) -> u64 {
+        16 + 16 * chunk_count
+    }
+
+    /// Record the next chunk, whose frame starts at `frame_offset`, holding `len` plaintext bytes
+    pub fn push(&mut self, frame_offset: u64, len: u64) {
+        self.entries.push(IndexEntry { frame_offset, plaintext_offset: self.plaintext_len });
+        self.plaintext_len += len;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::encoded_len(self.entries.len() as u64) as usize);
+        out.extend_from_slice(&(self.entries.len() as u64).to_be_bytes());
+        out.extend_from_slice(&self.plaintext_len.to_be_bytes());
+        for e in &self.entries {
+            out.extend_from_slice(&e.frame_offset.to_be_bytes());
+            out.extend_from_slice(&e.plaintext_offset.to_be_bytes());
+        }
+        out
+    }
+
+    /// Parse an index; offsets must start at zero plaintext and never go backwards
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let field = |i: usize| u64::from_be_bytes(bytes[i * 8..i * 8 + 8].try_into().expect("8 bytes"));
+        if bytes.len() < 16 || !(bytes.len() - 16).is_multiple_of(16) { bail!("malformed chunk index ({} bytes)", bytes.len()); }
+        let (count, plaintext_len) = (field(0), field(1));
+        if (bytes.len() as u64 - 16) / 16 != count { bail!("chunk index length does not match its {} entries", count); }
+        let entries: Vec<IndexEntry> = (0..count as usize)
+            .map(|i| IndexEntry { frame_offset: field(2 + 2 * i), plaintext_offset: field(3 + 2 * i) })
+            .collect();
+        let ordered = entries.windows(2).all(|w| w[0].frame_offset < w[1].frame_offset && w[0].plaintext_offset <= w[1].plaintext_offset);
+        if entries.first().is_none_or(|e| e.plaintext_offset != 0) || !ordered
+            || entries.last().is_some_and(|e| e.plaintext_offset > plaintext_len) {
+            bail!("chunk index offsets are out of order");
+        }
+        Ok(Self { entries, plaintext_len })
+    }
+
+    /// Index of the chunk holding plaintext byte `offset`, if it is inside the plaintext
+    pub fn chunk_at(&self, offset: u64) -> Option<usize> {
+        if offset >= self.plaintext_len { return None; }
+        Some(self.entries.partition_point(|e| e.plaintext_offset <= offset) - 1)
+    }
+
+    /// Plaintext bytes held by chunk `i`
+    pub fn chunk_len(&self, i: usize) -> u64 {
+        let end = self.entries.get(i + 1).map_or(self.plaintext_len, |e| e.plaintext_offset);
+        end - self.entries[i].plaintext_offset
+    }
+}
+
+/// What the plaintext was before encryption, stored encrypted in the metadata frame as
+/// `name_len u16 | name | mtime u64 | size u64`. `name` is a bare file name and
+/// `mtime` is in seconds since the epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub name: String,
+    pub mtime: u64,
+    pub size: u64,
+}
+
+impl FileMetadata {
+    /// Largest encoding `from_bytes` accepts
+    pub const MAX_ENCODED_LEN: usize = 2 + u16::MAX as usize + 16;
+
+    /// Name, modification time and size of the file at `path`
+    #[cfg(feature = "std")]
+    pub fn from_path(path: &std::path::Path) -> Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        if !meta.is_file() { bail!("{} is not a regular file", path.display()); }
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else { bail!("file name of {} is not UTF-8", path.display()) };
+        let mtime = meta.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+        Ok(Self { name: name.into(), mtime, size: meta.len() })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let Ok(name_len) = u16::try_from(self.name.len()) else { bail!("file name is too long ({} bytes)", self.name.len()) };
+        let mut out = Vec::with_capacity(2 + self.name.len() + 16);
+        out.extend_from_slice(&name_len.to_be_bytes());
+        out.extend_from_slice(self.name.as_bytes());
+        out.extend_from_slice(&self.mtime.to_be_bytes());
+        out.extend_from_slice(&self.size.to_be_bytes());
+        Ok(out)
+    }
+
+    /// Parse the metadata; the name must be a single, non-empty path component
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let name_len = match bytes {
+            [hi, lo, ..] => u16::from_be_bytes([*hi, *lo]) as usize,
+            _ => bail!("malformed file metadata"),
+        };
+        if bytes.len() != 2 + name_len + 16 { bail!("malformed file metadata"); }
+        let Ok(name) = core::str::from_utf8(&bytes[2..2 + name_len]) else { bail!("file name in the metadata is not UTF-8") };
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+            bail!("file name {:?} in the metadata is not a plain file name", name);
+        }
+        let tail = &bytes[2 + name_len..];
+        let mtime = u64::from_be_bytes(tail[..8].try_into().expect("8 bytes"));
+        let size = u64::from_be_bytes(tail[8..].try_into().expect("8 bytes"));
+        Ok(Self { name: name.into(), mtime, size })
+    }
+}
+
+/// Whole-package trailer: totals plus a MAC over everything before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trailer {
+    pub plaintext_len: u64,
+    pub chunk_count: u64,
+    pub mac: [u8; 32],
+}
+
+impl Trailer {
+    /// The trailer fields covered by the MAC
+    fn totals(plaintext_len: u64, chunk_count: u64) -> Vec<u8> {
+        let mut out = Vec::with_capacity(TRAILER_LEN - 32);
+        out.extend_from_slice(TRAILER_MAGIC);
+        out.extend_from_slice(&plaintext_len.to_be_bytes());
+        out.extend_from_slice(&chunk_count.to_be_bytes());
+        out
+    }
+
+    /// The trailer as stored at the end of the package
+    pub fn to_bytes(&self) -> [u8; TRAILER_LEN] {
+        let mut out = [0u8; TRAILER_LEN];
+        out[..TRAILER_LEN - 32].copy_from_slice(&Self::totals(self.plaintext_len, self.chunk_count));
+        out[TRAILER_LEN - 32..].copy_from_slice(&self.mac);
+        out
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let mut buf = [0u8; TRAILER_LEN];
+        r.read_exact(&mut buf).map_err(|_| anyhow::Error::new(PitlinkError::TruncatedPackage).context("missing trailer"))?;
+        if &buf[..TRAILER_MAGIC.len()] != TRAILER_MAGIC {
+            bail!("package trailer is corrupted (bad magic)");
+        }
+        let plaintext_len = u64::from_be_bytes(buf[5..13].try_into().expect("8 bytes"));
+        let chunk_count = u64::from_be_bytes(buf[13..21].try_into().expect("8 bytes"));
+        let mut mac = [0u8; 32];
+        mac.copy_from_slice(&buf[21..]);
+        Ok(Self { plaintext_len, chunk_count, mac })
+    }
+}
+
+/// Running keyed BLAKE3 MAC over the package bytes, finished into a `Trailer`
+pub struct PackageMac(blake3::Hasher);
+
+impl PackageMac {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self(blake3::Hasher::new_keyed(key))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// MAC the totals and produce the trailer
+    pub fn finish(mut self, plaintext_len: u64, chunk_count: u64) -> Trailer {
+        self.0.update(&Trailer::totals(plaintext_len, chunk_count));
+        Trailer { plaintext_len, chunk_count, mac: self.0.finalize().into() }
+    }
+
+    /// Check a trailer read from disk against the bytes seen so far (constant time)
+    pub fn verify(self, trailer: &Trailer) -> bool {
+        let expected = self.finish(trailer.plaintext_len, trailer.chunk_count);
+        blake3::Hash::from(expected.mac) == blake3::Hash::from(trailer.mac)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Write for PackageMac {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Associated data for a v2 chunk: header hash, sequence number and frame flags.
+/// Binding the position into the tag rejects reordered, duplicated and
+/// truncated chunk streams.
+pub fn chunk_aad(header_hash: &[u8], seq: u64, flags: u8) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header_hash.len() + 9);
+    aad.extend_from_slice(header_hash);
+    aad.extend_from_slice(&seq.to_be_bytes());
+    aad.push(flags);
+    aad
+}
+
+/// Key of the whole-package trailer MAC, derived from the file key
+pub fn trailer_key(file_key: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    derive_key(file_key, b"pitlink-trailer-v1")
+}
+
+/// Key of the package's chunk Merkle tree, derived from the file key
+pub fn merkle_key(file_key: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    derive_key(file_key, b"pitlink-merkle-v1")
+}
+
+/// Derivation of the per-frame subkeys of a package with the chunk KDF extension
+///
+/// No two frames share a key, so a nonce repeated across frames never repeats
+/// under one key, and a single chunk can be re-encrypted without touching the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkKdf {
+    /// HKDF-SHA256 of the file key with info `pitlink-chunk-v1 | seq u64 BE`
+    HkdfSha256,
+}
+
+impl ChunkKdf {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            ChunkKdf::HkdfSha256 => 1,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            1 => Ok(ChunkKdf::HkdfSha256),
+            other => bail!("unsupported chunk KDF id {}", other),
+        }
+    }
+
+    /// Key of the frame at `seq` (a chunk number, or the position of the
+    /// metadata, signature or index frame)
+    pub fn frame_key(self, file_key: &[u8], seq: u64) -> Result<Zeroizing<[u8; 32]>> {
+        match self {
+            ChunkKdf::HkdfSha256 => {
+                let mut label = b"pitlink-chunk-v1".to_vec();
+                label.extend_from_slice(&seq.to_be_bytes());
+                derive_key(file_key, &label)
+            }
+        }
+    }
+}
+
+fn derive_key(file_key: &[u8], label: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let okm = crate::hkdf_derive(file_key, label, 32)?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&okm);
+    Ok(key)
+}
+
+#[cfg(feature = "std")]
+fn decode_extensions(mut bytes: &[u8]) -> Result<Vec<Extension>> {
+    let mut out = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < 3 { bail!("truncated header extension"); }
+        let tag = bytes[0];
+        let len = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        if bytes.len() < 3 + len { bail!("truncated header extension 0x{:02x}", tag); }
+        out.push(Extension { tag, value: bytes[3..3 + len].to_vec() });
+        bytes = &bytes[3 + len..];
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "std")]
+fn read_u16<R: Read>(r: &mut R, raw: &mut Vec<u8>) -> Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    raw.extend_from_slice(&b);
+    Ok(u16::from_be_bytes(b))
+}
+
+#[cfg(feature = "std")]
+fn read_vec<R: Read>(r: &mut R, len: usize, raw: &mut Vec<u8>) -> Result<Vec<u8>> {
+    let mut v = vec![0u8; len];
+    r.read_exact(&mut v)?;
+    raw.extend_from_slice(&v);
+    Ok(v)
+}
+
+/// Short salted hash identifying the recipient public key. It covers only the key
+/// bytes, so Kyber and ML-KEM keys of the same level (same encoding) both match.
+pub fn recipient_hint(salt: &[u8], public_key: &[u8]) -> [u8; 8] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"pitlink-recipient-hint-v1");
+    hasher.update(salt);
+    hasher.update(public_key);
+    let digest = hasher.finalize();
+    let mut hint = [0u8; 8];
+    hint.copy_from_slice(&digest[..8]);
+    hint
+}
+
+/// What the next member of an appendable package records as its previous member:
+/// a hash of this member's trailer, which its MAC ties to every byte of the member
+pub fn member_link(trailer: &Trailer) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"pitlink-member-link-v1");
+    hasher.update(trailer.to_bytes());
+    hasher.finalize().into()
+}
+
+/// Read the `count` Merkle leaf hashes that follow the header. The count comes
+/// from the header, so the table grows as leaves arrive rather than up front.
+#[cfg(feature = "std")]
+pub fn read_merkle_leaves<R: Read>(r: &mut R, count: u64) -> Result<Vec<[u8; 32]>> {
+    let mut leaves = Vec::with_capacity(count.min(4096) as usize);
+    for _ in 0..count {
+        let mut leaf = [0u8; 32];
+        r.read_exact(&mut leaf)?;
+        leaves.push(leaf);
+    }
+    Ok(leaves)
+}
+
+/// Fill `buf` completely, returning `Ok(false)` if the reader is already at EOF.
+/// A partial read followed by EOF is reported as an `UnexpectedEof` error.
+#[cfg(feature = "std")]
+pub fn read_exact_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> std::io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Read until `buf` is full or the reader hits EOF, returning the byte count.
+#[cfg(feature = "std")]
+pub fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v2_header_roundtrip() {
+        let mut header = Header::new(KemId::Kyber768, AeadId::XChaCha20Poly1305, vec![7u8; 1088], vec![1u8; 24]);
+        header.set_extension(0x10, vec![1, 2, 3]);
+        header.wrapped_key = vec![9u8; 48];
+
+        let bytes = header.to_bytes();
+        let (parsed, raw) = Header::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(raw, bytes);
+        assert_eq!(parsed.version, FormatVersion::V2);
+        assert_eq!(parsed.extension(0x10), Some(&[1u8, 2, 3][..]));
+        assert_eq!(parsed.kem_ct, header.kem_ct);
+        assert_eq!(parsed.wrapped_key, header.wrapped_key);
+    }
+
+    #[test]
+    fn test_chunk_size_extension() {
+        let mut header = Header::new(KemId::Kyber768, AeadId::XChaCha20Poly1305, vec![7u8; 1088], vec![1u8; 24]);
+        assert_eq!(header.chunk_size().unwrap(), CHUNK_SIZE);
+        header.set_chunk_size(64 * 1024);
+        assert_eq!(header.chunk_size().unwrap(), 64 * 1024);
+        header.set_chunk_size(MAX_CHUNK_SIZE + 1);
+        assert!(header.chunk_size().is_err());
+        header.set_extension(EXT_CHUNK_SIZE, vec![0, 1]);
+        assert!(header.chunk_size().is_err());
+    }
+
+    #[test]
+    fn test_chunk_kdf_extension() {
+        let mut header = Header::new(KemId::MlKem768, AeadId::XChaCha20Poly1305, vec![7u8; 1088], vec![1u8; 24]);
+        assert_eq!(header.chunk_kdf().unwrap(), None);
+        header.set_chunk_kdf(ChunkKdf::HkdfSha256);
+        assert_eq!(header.chunk_kdf().unwrap(), Some(ChunkKdf::HkdfSha256));
+        header.set_extension(EXT_CHUNK_KDF, vec![9]);
+        assert!(header.chunk_kdf().is_err());
+
+        let file_key = [3u8; 32];
+        let first = ChunkKdf::HkdfSha256.frame_key(&file_key, 0).unwrap();
+        assert_eq!(first, ChunkKdf::HkdfSha256.frame_key(&file_key, 0).unwrap());
+        assert_ne!(first, ChunkKdf::HkdfSha256.frame_key(&file_key, 1).unwrap());
+        assert_ne!(*first, file_key);
+    }
+
+    #[test]
+    fn test_lengths_beyond_32_bits() {
+        let len = 5 * (1u64 << 30) + 17;
+        let mut header = Header::new(KemId::Kyber768, AeadId::XChaCha20Poly1305, vec![7u8; 1088], vec![1u8; 24]);
+        assert_eq!(header.plaintext_len().unwrap(), None);
+        header.set_plaintext_len(len);
+        let bytes = header.to_bytes();
+        let (parsed, _) = Header::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(parsed.plaintext_len().unwrap(), Some(len));
+
+        let trailer = PackageMac::new(&[3u8; 32]).finish(len, len / CHUNK_SIZE as u64 + 1);
+        let mut out = Vec::new();
+        trailer.write_to(&mut out).unwrap();
+        assert_eq!(Trailer::read_from(&mut &out[..]).unwrap(), trailer);
+    }
+
+    #[test]
+    fn test_alloc_encoders_match_writers() {
+        let frame = ChunkFrame { nonce: Vec::new(), flags: CHUNK_FLAG_FINAL, ciphertext: vec![5u8; 40] };
+        let (mut written, mut encoded) = (Vec::new(), Vec::new());
+        frame.write_to(&mut written).unwrap();
+        frame.encode_into(&mut encoded);
+        assert_eq!(encoded, written);
+        assert_eq!(encoded.len(), frame.encoded_len(FormatVersion::V2));
+
+        let trailer = PackageMac::new(&[3u8; 32]).finish(40, 1);
+        assert_eq!(Trailer::read_from(&mut &trailer.to_bytes()[..]).unwrap(), trailer);
+    }
+
+    #[test]
+    fn test_chunk_index_roundtrip() {
+        let mut index = ChunkIndex::default();
+        index.push(200, 4096);
+        index.push(4317, 4096);
+        index.push(8434, 10);
+        let parsed = ChunkIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(parsed, index);
+        assert_eq!(index.to_bytes().len() as u64, ChunkIndex::encoded_len(3));
+        assert_eq!((index.chunk_at(0), index.chunk_at(4096), index.chunk_at(8201), index.chunk_at(8202)), (Some(0), Some(1), Some(2), None));
+        assert_eq!(index.chunk_len(2), 10);
+
+        index.entries.swap(0, 1);
+        assert!(ChunkIndex::from_bytes(&index.to_bytes()).is_err());
+        assert!(ChunkIndex::from_bytes(&[0u8; 24]).is_err());
+    }
+
+    #[test]
+    fn test_file_metadata_roundtrip() {
+        let meta = FileMetadata { name: "lap-12.mcap".into(), mtime: 1_760_000_000, size: 5 << 20 };
+        assert_eq!(FileMetadata::from_bytes(&meta.to_bytes().unwrap()).unwrap(), meta);
+        for name in ["", "..", "a/b", "a\\b"] {
+            let bytes = FileMetadata { name: name.into(), ..meta.clone() }.to_bytes().unwrap();
+            assert!(FileMetadata::from_bytes(&bytes).is_err(), "{:?}", name);
+        }
+        assert!(FileMetadata::from_bytes(&[0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_merkle_leaves_truncated() {
+        let table = [[3u8; 32]; 2].concat();
+        assert_eq!(read_merkle_leaves(&mut &table[..], 2).unwrap(), [[3u8; 32]; 2]);
+        // A header claiming the most leaves cannot make the reader allocate for them
+        let err = read_merkle_leaves(&mut &table[..], MAX_MERKLE_LEAVES).unwrap_err();
+        assert!(err.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_v1_header_is_detected() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&1088u16.to_be_bytes());
+        bytes.extend_from_slice(&[7u8; 1088]);
+        bytes.extend_from_slice(&[1u8; 24]);
+        bytes.extend_from_slice(&48u16.to_be_bytes());
+        bytes.extend_from_slice(&[9u8; 48]);
+
+        let (parsed, raw) = Header::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(raw, bytes);
+        assert_eq!(parsed.version, FormatVersion::V1);
+        assert_eq!(parsed.kem_ct.len(), 1088);
+        assert_eq!(parsed.wrapped_key.len(), 48);
+    }
+}
@@ -0,0 +1,123 @@
+//! Typed errors for the failures callers need to tell apart
+//!
+//! Functions still return `anyhow::Error`. Where one of these failures happens the
+//! error is (or wraps) a `PitlinkError`, usually under context naming the chunk or
+//! offset, and [`PitlinkError::classify`] recovers it. Each variant has a stable
+//! code, used by the CLI as its exit code and in `--json` error output.
+
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PitlinkError {
+    /// The input does not start with the package magic
+    BadMagic,
+    /// The header names a container version this build cannot read
+    UnsupportedVersion(u8),
+    /// The package was encrypted to a different key
+    WrongRecipient,
+    /// A chunk failed authentication: corrupted, reordered or tampered
+    ChunkAuthFailed { index: u64 },
+    /// The package ends before its final chunk, signature or trailer
+    TruncatedPackage,
+    /// A key file could not be parsed or holds the wrong kind of key
+    KeyFormat(String),
+    /// Reading or writing failed
+    #[cfg(feature = "std")]
+    Io(io::ErrorKind),
+}
+
+impl PitlinkError {
+    /// Stable numeric code. Codes are never reused; 1 is left for untyped errors
+    /// and 2 for command-line usage errors.
+    pub fn code(&self) -> i32 {
+        match self {
+            PitlinkError::BadMagic => 3,
+            PitlinkError::UnsupportedVersion(_) => 4,
+            PitlinkError::WrongRecipient => 5,
+            PitlinkError::ChunkAuthFailed { .. } => 6,
+            PitlinkError::TruncatedPackage => 7,
+            PitlinkError::KeyFormat(_) => 8,
+            #[cfg(feature = "std")]
+            PitlinkError::Io(_) => 9,
+        }
+    }
+
+    /// Stable snake_case name, as in JSON output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PitlinkError::BadMagic => "bad_magic",
+            PitlinkError::UnsupportedVersion(_) => "unsupported_version",
+            PitlinkError::WrongRecipient => "wrong_recipient",
+            PitlinkError::ChunkAuthFailed { .. } => "chunk_auth_failed",
+            PitlinkError::TruncatedPackage => "truncated_package",
+            PitlinkError::KeyFormat(_) => "key_format",
+            #[cfg(feature = "std")]
+            PitlinkError::Io(_) => "io",
+        }
+    }
+
+    /// The typed error behind `err`: the first `PitlinkError` in its chain, or an
+    /// I/O error, where running out of input counts as a truncated package
+    #[cfg(feature = "std")]
+    pub fn classify(err: &anyhow::Error) -> Option<Self> {
+        // Also finds one attached with `.context()`, which the chain walk below cannot
+        if let Some(e) = err.downcast_ref::<PitlinkError>() {
+            return Some(e.clone());
+        }
+        for cause in err.chain() {
+            if let Some(e) = cause.downcast_ref::<PitlinkError>() {
+                return Some(e.clone());
+            }
+            if let Some(e) = cause.downcast_ref::<io::Error>() {
+                // Stream adapters carry typed errors inside io::Error
+                if let Some(inner) = e.get_ref().and_then(|inner| inner.downcast_ref::<PitlinkError>()) {
+                    return Some(inner.clone());
+                }
+                return Some(match e.kind() {
+                    io::ErrorKind::UnexpectedEof => PitlinkError::TruncatedPackage,
+                    kind => PitlinkError::Io(kind),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for PitlinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PitlinkError::BadMagic => write!(f, "not a pitlink package (bad magic)"),
+            PitlinkError::UnsupportedVersion(v) => write!(f, "unsupported container version {}", v),
+            PitlinkError::WrongRecipient => write!(f, "this file was not encrypted to this key"),
+            PitlinkError::ChunkAuthFailed { index } => write!(f, "chunk {} failed authentication (corrupted, reordered or tampered)", index),
+            PitlinkError::TruncatedPackage => write!(f, "package truncated"),
+            PitlinkError::KeyFormat(msg) => write!(f, "invalid key file: {}", msg),
+            #[cfg(feature = "std")]
+            PitlinkError::Io(kind) => write!(f, "I/O error: {}", kind),
+        }
+    }
+}
+
+impl core::error::Error for PitlinkError {}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_through_context_and_io() {
+        let err = anyhow::Error::new(PitlinkError::ChunkAuthFailed { index: 4 }).context("at offset 99");
+        assert_eq!(PitlinkError::classify(&err), Some(PitlinkError::ChunkAuthFailed { index: 4 }));
+
+        let eof = anyhow::Error::new(io::Error::from(io::ErrorKind::UnexpectedEof)).context("reading header");
+        assert_eq!(PitlinkError::classify(&eof).map(|e| e.code()), Some(7));
+
+        let wrapped = anyhow::Error::new(io::Error::new(io::ErrorKind::InvalidData, PitlinkError::WrongRecipient));
+        assert_eq!(PitlinkError::classify(&wrapped), Some(PitlinkError::WrongRecipient));
+
+        assert_eq!(PitlinkError::classify(&anyhow::anyhow!("something else")), None);
+    }
+}
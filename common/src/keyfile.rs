@@ -0,0 +1,117 @@
+//! Binary key file framing
+//!
+//! Key files are `KEY_MAGIC | kind u8 | alg u8 | key bytes`, where the algorithm
+//! byte holds a `KemId` for encryption keys and a `SigId` for signing keys. With
+//! `KEY_FLAG_METADATA` set in the kind byte, `meta_len u16 | TLV entries` sits
+//! between the algorithm byte and the key bytes. Armor, legacy raw keys, key
+//! lengths and the meaning of the metadata entries are left to the readers.
+
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+
+/// Magic prefix of key files
+pub const KEY_MAGIC: &[u8] = b"RKPQK";
+
+/// Kind-byte flag marking a metadata block after the algorithm byte
+pub const KEY_FLAG_METADATA: u8 = 0x80;
+
+/// Whether a key file holds a public or a private key, for encryption or signing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    Public,
+    Private,
+    SigningPublic,
+    SigningPrivate,
+}
+
+impl KeyKind {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            KeyKind::Public => 0x01,
+            KeyKind::Private => 0x02,
+            KeyKind::SigningPublic => 0x03,
+            KeyKind::SigningPrivate => 0x04,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            0x01 => Ok(KeyKind::Public),
+            0x02 => Ok(KeyKind::Private),
+            0x03 => Ok(KeyKind::SigningPublic),
+            0x04 => Ok(KeyKind::SigningPrivate),
+            other => bail!("unknown key kind 0x{:02x}", other),
+        }
+    }
+
+    /// Whether this kind holds secret key material
+    pub fn is_private(self) -> bool {
+        matches!(self, KeyKind::Private | KeyKind::SigningPrivate)
+    }
+}
+
+/// The fields of a binary key file, borrowed from its bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEnvelope<'a> {
+    pub kind: KeyKind,
+    pub alg: u8,
+    /// Encoded metadata entries; empty when the key has none
+    pub meta: &'a [u8],
+    pub key: &'a [u8],
+}
+
+impl<'a> KeyEnvelope<'a> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(KEY_MAGIC.len() + 4 + self.meta.len() + self.key.len());
+        out.extend_from_slice(KEY_MAGIC);
+        if self.meta.is_empty() {
+            out.push(self.kind.as_u8());
+            out.push(self.alg);
+        } else {
+            out.push(self.kind.as_u8() | KEY_FLAG_METADATA);
+            out.push(self.alg);
+            out.extend_from_slice(&(self.meta.len() as u16).to_be_bytes());
+            out.extend_from_slice(self.meta);
+        }
+        out.extend_from_slice(self.key);
+        out
+    }
+
+    /// Parse a `KEY_MAGIC` key file; `None` if the bytes are not in this format
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Option<Self>> {
+        if !bytes.starts_with(KEY_MAGIC) || bytes.len() < KEY_MAGIC.len() + 2 {
+            return Ok(None);
+        }
+        let kind_byte = bytes[KEY_MAGIC.len()];
+        let kind = KeyKind::from_u8(kind_byte & !KEY_FLAG_METADATA)?;
+        let alg = bytes[KEY_MAGIC.len() + 1];
+        let mut key = &bytes[KEY_MAGIC.len() + 2..];
+        let mut meta: &[u8] = &[];
+        if kind_byte & KEY_FLAG_METADATA != 0 {
+            if key.len() < 2 { bail!("truncated key metadata"); }
+            let len = u16::from_be_bytes([key[0], key[1]]) as usize;
+            if key.len() < 2 + len { bail!("truncated key metadata"); }
+            meta = &key[2..2 + len];
+            key = &key[2 + len..];
+        }
+        Ok(Some(Self { kind, alg, meta, key }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_roundtrip() {
+        for meta in [&b""[..], &b"\x03\x00\x02hi"[..]] {
+            let envelope = KeyEnvelope { kind: KeyKind::SigningPrivate, alg: 0x07, meta, key: b"key bytes" };
+            let bytes = envelope.to_bytes();
+            assert_eq!(bytes[KEY_MAGIC.len()] & KEY_FLAG_METADATA != 0, !meta.is_empty());
+            assert_eq!(KeyEnvelope::from_bytes(&bytes).unwrap(), Some(envelope));
+        }
+        assert_eq!(KeyEnvelope::from_bytes(b"RKPQ").unwrap(), None);
+        assert!(KeyEnvelope::from_bytes(b"RKPQK\x09\x01").is_err());
+        assert!(KeyEnvelope::from_bytes(b"RKPQK\x81\x01\x00\x05abc").is_err());
+    }
+}
@@ -1,19 +1,51 @@
+//! Package format shared by the pitlink crates
+//!
+//! Builds without the default `std` feature as `no_std` + `alloc` (Rust 1.81 or
+//! later, for `core::error::Error`). That keeps header encoding, key derivation,
+//! chunk framing, key files and the trailer MAC, so a sender without an OS can
+//! produce packages the CLI opens; reading packages and the file helpers need
+//! `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::fs::File;
+#[cfg(feature = "std")]
 use std::io::{Read, Write};
 use anyhow::Result;
 use sha2::Sha256;
-use blake3;
 use hkdf::Hkdf;
+use zeroize::Zeroizing;
+
+pub mod cdc;
+pub mod compress;
+pub mod container;
+pub mod error;
+pub mod keyfile;
+pub mod manifest;
+pub mod merkle;
+
+pub use compress::Compression;
+pub use error::PitlinkError;
+pub use manifest::{Manifest, ManifestChunk, MANIFEST_VERSION};
+pub use container::{Header, ChunkFrame, FormatVersion, KemId, AeadId, SigId, CURRENT_VERSION, CHUNK_FLAG_FINAL};
 
 pub const CHUNK_SIZE: usize = 1024 * 1024; // 1 MiB
 pub const MAGIC: &[u8] = b"RKPQ1";
 
+#[cfg(feature = "std")]
 pub fn write_all<P: AsRef<std::path::Path>>(path: P, data: &[u8]) -> Result<()> {
     let mut f = File::create(path)?;
     f.write_all(data)?;
     Ok(())
 }
 
+#[cfg(feature = "std")]
 pub fn read_all<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<u8>> {
     let mut f = File::open(path)?;
     let mut buf = Vec::new();
@@ -21,9 +53,10 @@ pub fn read_all<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<u8>> {
     Ok(buf)
 }
 
-pub fn hkdf_derive(shared: &[u8], info: &[u8], out_len: usize) -> Result<Vec<u8>> {
+/// HKDF-SHA256 expand of `shared`; the output is wiped when dropped
+pub fn hkdf_derive(shared: &[u8], info: &[u8], out_len: usize) -> Result<Zeroizing<Vec<u8>>> {
     let hk = Hkdf::<Sha256>::new(None, shared);
-    let mut okm = vec![0u8; out_len];
+    let mut okm = Zeroizing::new(vec![0u8; out_len]);
     hk.expand(info, &mut okm).map_err(|e| anyhow::anyhow!("hkdf expand failed: {:?}", e))?;
     Ok(okm)
 }
@@ -0,0 +1,233 @@
+//! Manifest of a package split into chunk files
+//!
+//! One layout for everything that describes a cut-up input: the LZ4 chunker's
+//! segments, the encryptor's split pipeline parts, the transfer protocol and the
+//! dashboard's view of a package. A manifest names the run (`package_id`), the
+//! algorithms used, and every chunk file in order with its offset, sizes and hash:
+//!
+//! ```json
+//! {"version":2,"package_id":"5c0e...","input":"lap.csv",
+//!  "algorithms":{"kem":"MlKem768","aead":"XChaCha20Poly1305","compression":"Lz4","hash":"sha256"},
+//!  "recipient_fingerprint":"3f2a:...","chunk_size":1048576,"total_bytes":3145728,
+//!  "cdc":{"min":262144,"avg":1048576,"max":4194304},
+//!  "chunks":[{"index":1,"file":"lap.0001.pqc","offset":0,"size":1048576,"stored_size":412310,"frames":1,"hash":"9b1c..."}],
+//!  "signature":{"algorithm":"MlDsa65","value":"a1..."}}
+//! ```
+//!
+//! The signature covers [`Manifest::signing_bytes`]: the JSON encoding of the
+//! manifest without its signature, behind a domain separator. Signing and
+//! verifying are left to the caller, which holds the signature scheme.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cdc::Cdc;
+
+/// Layout version written to [`Manifest::version`]. Version 1 was the pipeline's
+/// own `parts` list and is not read any more.
+pub const MANIFEST_VERSION: u32 = 2;
+
+/// Prefix of the bytes a manifest signature covers
+const SIGNING_CONTEXT: &[u8] = b"PITLINK-MANIFEST-v2\0";
+
+/// An input cut into chunk files, in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// 128-bit id of the run in hex, random or derived from the input
+    pub package_id: String,
+    /// Name of the input the chunks were cut from
+    pub input: String,
+    pub algorithms: Algorithms,
+    /// Fingerprint of the recipient key when the chunks are encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_fingerprint: Option<String>,
+    /// Target chunk size in bytes; the last chunk may be shorter
+    pub chunk_size: u64,
+    /// Bounds of content-defined chunking, when the boundaries came from the
+    /// content instead of every `chunk_size` bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cdc: Option<Cdc>,
+    /// Bytes of the input, the sum of every chunk's `size`
+    pub total_bytes: u64,
+    pub chunks: Vec<ManifestChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ManifestSignature>,
+}
+
+/// Algorithms applied to the chunks, by their `Debug` names (e.g. `MlKem768`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Algorithms {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kem: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aead: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// Hash of [`ManifestChunk::hash`], e.g. `sha256`
+    pub hash: String,
+}
+
+/// One chunk file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestChunk {
+    /// Position in the input, from 1
+    pub index: u64,
+    /// File name of the chunk, next to the manifest
+    pub file: String,
+    /// Offset of the chunk's content in the input
+    pub offset: u64,
+    /// Bytes of the input the chunk holds
+    pub size: u64,
+    /// Bytes of the chunk file (compressed and/or encrypted)
+    pub stored_size: u64,
+    /// Chunk frames inside an encrypted chunk file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<u64>,
+    /// Hash of the chunk file, in hex
+    pub hash: String,
+    /// BLAKE3 of the chunk's input bytes in hex, equal for equal content however
+    /// it was stored, for deduplication and delta sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+}
+
+/// Signature over [`Manifest::signing_bytes`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestSignature {
+    /// Signature algorithm, e.g. `MlDsa65`
+    pub algorithm: String,
+    /// Signature bytes, in hex
+    pub value: String,
+}
+
+impl Manifest {
+    /// An empty manifest for `input`; chunks are pushed as they are written
+    pub fn new(package_id: String, input: String, algorithms: Algorithms, chunk_size: u64) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            package_id,
+            input,
+            algorithms,
+            recipient_fingerprint: None,
+            chunk_size,
+            cdc: None,
+            total_bytes: 0,
+            chunks: Vec::new(),
+            signature: None,
+        }
+    }
+
+    /// Append the next chunk and count its bytes into `total_bytes`
+    pub fn push(&mut self, chunk: ManifestChunk) {
+        self.total_bytes += chunk.size;
+        self.chunks.push(chunk);
+    }
+
+    /// The bytes a signature covers: everything but the signature itself
+    pub fn signing_bytes(&self) -> Result<Vec<u8>> {
+        let unsigned = Manifest { signature: None, ..self.clone() };
+        let mut bytes = SIGNING_CONTEXT.to_vec();
+        bytes.extend(serde_json::to_vec(&unsigned)?);
+        Ok(bytes)
+    }
+
+    /// Attach a signature made over [`Manifest::signing_bytes`]
+    pub fn set_signature(&mut self, algorithm: String, signature: &[u8]) {
+        self.signature = Some(ManifestSignature { algorithm, value: to_hex(signature) });
+    }
+
+    /// The raw signature bytes and algorithm name, if the manifest is signed
+    pub fn signature(&self) -> Result<Option<(&str, Vec<u8>)>> {
+        match &self.signature {
+            Some(sig) => Ok(Some((sig.algorithm.as_str(), from_hex(&sig.value)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Check that the chunks are numbered from 1, contiguous and add up to `total_bytes`
+    pub fn validate(&self) -> Result<()> {
+        if self.version != MANIFEST_VERSION {
+            bail!("unsupported manifest version {} (expected {})", self.version, MANIFEST_VERSION);
+        }
+        let mut offset = 0u64;
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if chunk.index != i as u64 + 1 || chunk.offset != offset {
+                bail!("manifest chunk {} is out of order", chunk.index);
+            }
+            offset += chunk.size;
+        }
+        if offset != self.total_bytes {
+            bail!("manifest chunks hold {} bytes but it records {}", offset, self.total_bytes);
+        }
+        Ok(())
+    }
+
+    /// Encode as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Decode and [`validate`](Manifest::validate) a JSON manifest
+    pub fn from_json(bytes: &[u8]) -> Result<Self> {
+        let manifest: Manifest = serde_json::from_slice(bytes)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+}
+
+/// Lowercase hex of `bytes`
+pub fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        out.push(DIGITS[(b >> 4) as usize] as char);
+        out.push(DIGITS[(b & 0x0f) as usize] as char);
+    }
+    out
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>> {
+    let pairs = text.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        bail!("hex string has odd length");
+    }
+    pairs
+        .map(|pair| {
+            let digit = |c: u8| (c as char).to_digit(16).ok_or_else(|| anyhow::anyhow!("invalid hex digit '{}'", c as char));
+            Ok((digit(pair[0])? << 4 | digit(pair[1])?) as u8)
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn chunk(index: u64, offset: u64, size: u64) -> ManifestChunk {
+        ManifestChunk { index, file: format!("lap.{:04}.pqc", index), offset, size, stored_size: size / 2, frames: Some(1), hash: "00".repeat(32), content_hash: None }
+    }
+
+    #[test]
+    fn roundtrips_and_validates() {
+        let algorithms = Algorithms { compression: Some("Lz4".to_string()), hash: "sha256".to_string(), ..Default::default() };
+        let mut manifest = Manifest::new("5c0e".to_string(), "lap.csv".to_string(), algorithms, 1024);
+        manifest.push(chunk(1, 0, 1024));
+        manifest.push(chunk(2, 1024, 100));
+        assert_eq!(manifest.total_bytes, 1124);
+
+        let unsigned = manifest.signing_bytes().unwrap();
+        manifest.set_signature("MlDsa65".to_string(), &[0xde, 0xad, 0x01]);
+        assert_eq!(manifest.signing_bytes().unwrap(), unsigned);
+        assert_eq!(manifest.signature().unwrap(), Some(("MlDsa65", vec![0xde, 0xad, 0x01])));
+
+        let parsed = Manifest::from_json(manifest.to_json().unwrap().as_bytes()).unwrap();
+        assert_eq!(parsed, manifest);
+
+        manifest.chunks[1].offset = 1000;
+        assert!(Manifest::from_json(manifest.to_json().unwrap().as_bytes()).is_err());
+    }
+}
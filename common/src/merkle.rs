@@ -0,0 +1,118 @@
+//! BLAKE3 Merkle tree over chunk hashes
+//!
+//! Leaves are `H(0x00 || chunk)` and interior nodes `H(0x01 || left || right)`,
+//! where `H` is BLAKE3 keyed with a per-package key. A node without a sibling is
+//! promoted to the next level unchanged. The root commits to every chunk and its
+//! position, so a single chunk can be checked against the root with a proof of
+//! `log2(n)` sibling hashes.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Hash of a single chunk
+pub fn leaf_hash(key: &[u8; 32], chunk: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_keyed(key);
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(chunk);
+    hasher.finalize().into()
+}
+
+fn node_hash(key: &[u8; 32], left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_keyed(key);
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// A complete tree, stored level by level from the leaves up
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Build the tree over `leaves`; at least one leaf is required
+    pub fn from_leaves(key: &[u8; 32], leaves: Vec<[u8; 32]>) -> Result<Self> {
+        if leaves.is_empty() { bail!("Merkle tree needs at least one leaf"); }
+        let mut levels = vec![leaves];
+        while levels.last().is_some_and(|l| l.len() > 1) {
+            let level = levels.last().unwrap();
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(key, left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Ok(Self { levels })
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.levels.last().unwrap()[0]
+    }
+
+    pub fn leaves(&self) -> &[[u8; 32]] {
+        &self.levels[0]
+    }
+
+    /// Sibling hashes needed to check leaf `index` against the root
+    pub fn proof(&self, index: usize) -> Result<Vec<[u8; 32]>> {
+        if index >= self.levels[0].len() { bail!("leaf index {} out of range", index); }
+        let mut proof = Vec::new();
+        let mut i = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = i ^ 1;
+            if sibling < level.len() {
+                proof.push(level[sibling]);
+            }
+            i /= 2;
+        }
+        Ok(proof)
+    }
+}
+
+/// Check that `leaf` sits at `index` of a tree with `leaf_count` leaves and `root`
+pub fn verify_proof(key: &[u8; 32], root: &[u8; 32], index: usize, leaf_count: usize, leaf: &[u8; 32], proof: &[[u8; 32]]) -> bool {
+    if index >= leaf_count { return false; }
+    let mut hash = *leaf;
+    let mut i = index;
+    let mut width = leaf_count;
+    let mut siblings = proof.iter();
+    while width > 1 {
+        let sibling = i ^ 1;
+        if sibling < width {
+            let Some(s) = siblings.next() else { return false };
+            hash = if i.is_multiple_of(2) { node_hash(key, &hash, s) } else { node_hash(key, s, &hash) };
+        }
+        i /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && &hash == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proofs_verify_for_every_leaf() {
+        let key = [7u8; 32];
+        for n in 1..=9 {
+            let leaves: Vec<_> = (0..n).map(|i| leaf_hash(&key, &[i as u8; 10])).collect();
+            let tree = MerkleTree::from_leaves(&key, leaves.clone()).unwrap();
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(verify_proof(&key, &tree.root(), i, n, leaf, &proof));
+                assert!(!verify_proof(&key, &tree.root(), i, n, &leaf_hash(&key, b"other"), &proof));
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Reporting agents
+//!
+//! Every machine sending reports is an agent, known by the `node_id` its reports
+//! carry. An agent registers with `POST /api/agents/register` to say which host
+//! and tool version it is; reports from a node that never registered are still
+//! accepted and make it known too. `GET /api/agents` lists them with when each
+//! was last seen, and the history and summary endpoints take a `node` filter to
+//! tell them apart. Agents are kept in the `agents` table of the metrics database.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::validate_node_id;
+use crate::store::MetricsStore;
+
+/// An agent seen this recently is online
+pub const ONLINE_WITHIN: Duration = Duration::from_secs(300);
+
+/// Body of `POST /api/agents/register`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registration {
+    /// The `node_id` of the agent's reports
+    pub node_id: String,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Version of the reporting tool
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Free text, e.g. "pit wall, left bench"
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl Registration {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_node_id(&self.node_id)?;
+        for (field, value, max) in [("hostname", &self.hostname, 255), ("version", &self.version, 64), ("description", &self.description, 256)] {
+            if value.as_ref().is_some_and(|value| value.len() > max || value.chars().any(char::is_control)) {
+                return Err(format!("{} must be at most {} bytes without control characters", field, max));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A reporting node as `GET /api/agents` shows it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Agent {
+    pub node_id: String,
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// When it last registered; absent for a node that only reports
+    pub registered: Option<DateTime<Utc>>,
+    /// When it last registered or reported
+    pub last_seen: DateTime<Utc>,
+}
+
+impl Agent {
+    /// "online" when seen within [`ONLINE_WITHIN`] of `now`, else "offline"
+    pub fn status(&self, now: DateTime<Utc>) -> &'static str {
+        match (now - self.last_seen).to_std() {
+            Ok(age) if age > ONLINE_WITHIN => "offline",
+            _ => "online",
+        }
+    }
+}
+
+/// The agents known, by node id
+pub struct Agents {
+    agents: RwLock<BTreeMap<String, Agent>>,
+    store: Option<Arc<MetricsStore>>,
+}
+
+impl Agents {
+    /// The agents in `store`, which keeps them from then on
+    pub fn new(store: Option<Arc<MetricsStore>>) -> Result<Self> {
+        let agents = match &store {
+            Some(store) => store.agents()?.into_iter().map(|agent| (agent.node_id.clone(), agent)).collect(),
+            None => BTreeMap::new(),
+        };
+        Ok(Self { agents: RwLock::new(agents), store })
+    }
+
+    /// Register, or re-register, a validated agent; true when it was not known before
+    pub fn register(&self, registration: Registration) -> Result<(Agent, bool)> {
+        let now = Utc::now();
+        let agent = Agent {
+            node_id: registration.node_id,
+            hostname: registration.hostname,
+            version: registration.version,
+            description: registration.description,
+            registered: Some(now),
+            last_seen: now,
+        };
+        let mut agents = self.agents.write();
+        if let Some(store) = &self.store {
+            store.save_agent(&agent)?;
+        }
+        let new = agents.insert(agent.node_id.clone(), agent.clone()).is_none();
+        Ok((agent, new))
+    }
+
+    /// Note a report from `node_id`, registered or not
+    pub fn seen(&self, node_id: &str) -> Result<()> {
+        let mut agents = self.agents.write();
+        let agent = agents.entry(node_id.to_string()).or_insert_with(|| Agent {
+            node_id: node_id.to_string(),
+            hostname: None,
+            version: None,
+            description: None,
+            registered: None,
+            last_seen: Utc::now(),
+        });
+        agent.last_seen = Utc::now();
+        match &self.store {
+            Some(store) => store.save_agent(agent),
+            None => Ok(()),
+        }
+    }
+
+    /// The agents known, by node id
+    pub fn list(&self) -> Vec<Agent> {
+        self.agents.read().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_and_tracks_agents() {
+        let store = Arc::new(MetricsStore::in_memory().unwrap());
+        store.insert(&serde_json::from_value(serde_json::json!({
+            "version": 1, "node_id": "car-12", "operation": "encrypt", "duration_ms": 10, "timestamp": "2026-01-01T00:00:00Z",
+        })).unwrap()).unwrap();
+
+        let agents = Agents::new(Some(store.clone())).unwrap();
+        let registration = |node_id: &str| Registration { node_id: node_id.to_string(), hostname: Some("pit-a".to_string()), version: Some("0.4.0".to_string()), description: None };
+        assert!(registration("pit a").validate().is_err());
+        assert!(Registration { description: Some("a\nb".to_string()), ..registration("pit-a") }.validate().is_err());
+        let (pit, new) = agents.register(registration("pit-a")).unwrap();
+        assert!(new && !agents.register(registration("pit-a")).unwrap().1);
+        agents.seen("pit-b").unwrap();
+
+        // Reloaded, with the node that reported before agents were kept
+        let reloaded = Agents::new(Some(store)).unwrap().list();
+        let ids: Vec<_> = reloaded.iter().map(|agent| agent.node_id.as_str()).collect();
+        assert_eq!(ids, ["car-12", "pit-a", "pit-b"]);
+        let now = Utc::now();
+        assert_eq!(reloaded[0].status(now), "offline");
+        assert!(reloaded[0].registered.is_none() && reloaded[2].registered.is_none());
+        assert_eq!(reloaded[1].hostname, pit.hostname);
+        assert_eq!(reloaded[1].status(now), "online");
+    }
+}
@@ -0,0 +1,426 @@
+//! Threshold alerts
+//!
+//! An alert rule watches one quantity of the recent reports, such as the average
+//! encrypt throughput over the last five minutes or the number of failed
+//! decrypts, optionally of one operation and node. Rules are managed under
+//! `/api/alerts` and kept in the metrics database. They are evaluated every
+//! [`EVALUATE_INTERVAL`] off the request path, from counts and averages the
+//! database works out, so ingesting stays cheap however busy the window is and an
+//! alert also resolves once its window has moved past the reports that tripped
+//! it. When a rule starts or stops firing, each of its webhooks gets a POST: the
+//! alert as JSON, or a message for a Slack incoming webhook.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::metrics::{validate_node_id, MetricsCollector, OperationAggregate, OperationFilter};
+use crate::store::MetricsStore;
+
+/// Time between evaluations
+pub const EVALUATE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Longest a webhook may take to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest window a rule may look back over
+const MAX_WINDOW: Duration = Duration::from_secs(86_400);
+
+/// What a rule measures over the reports in its window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    /// Average throughput, in Mbit/s
+    ThroughputMbps,
+    /// Average duration, in milliseconds
+    DurationMs,
+    /// Number of failed operations
+    Failures,
+    /// Share of the operations that failed, 0 to 1
+    FailureRate,
+}
+
+impl AlertMetric {
+    /// The value over the reports `aggregate` sums up; None for an average or
+    /// rate without reports
+    fn measure(self, aggregate: &OperationAggregate) -> Option<f64> {
+        match self {
+            Self::ThroughputMbps => aggregate.avg_throughput_mbps,
+            Self::DurationMs => aggregate.avg_duration_ms,
+            Self::Failures => Some(aggregate.failed as f64),
+            Self::FailureRate => (aggregate.count > 0).then(|| aggregate.failed as f64 / aggregate.count as f64),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::ThroughputMbps => "throughput_mbps",
+            Self::DurationMs => "duration_ms",
+            Self::Failures => "failures",
+            Self::FailureRate => "failure_rate",
+        }
+    }
+}
+
+/// Which side of the threshold trips a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    Above,
+    Below,
+}
+
+impl Condition {
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Above => value > threshold,
+            Self::Below => value < threshold,
+        }
+    }
+}
+
+/// What a webhook is sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The alert as JSON
+    #[default]
+    Json,
+    /// `{"text": ...}` for a Slack incoming webhook
+    Slack,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+}
+
+/// Body of `POST /api/alerts` and `PUT /api/alerts/{id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    /// Shown in notifications
+    pub name: String,
+    pub metric: AlertMetric,
+    /// Only reports of this operation; all of them when absent
+    #[serde(default)]
+    pub operation: Option<String>,
+    /// Only reports from this node; all of them when absent
+    #[serde(default)]
+    pub node: Option<String>,
+    pub condition: Condition,
+    pub threshold: f64,
+    /// How far back the metric is taken, e.g. "5m" (the default)
+    #[serde(default = "default_window")]
+    pub window: String,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+}
+
+fn default_window() -> String {
+    "5m".to_string()
+}
+
+impl AlertRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() || self.name.len() > 64 || self.name.chars().any(char::is_control) {
+            return Err("name must be 1-64 bytes without control characters".to_string());
+        }
+        if !self.threshold.is_finite() {
+            return Err("threshold must be a finite number".to_string());
+        }
+        self.window()?;
+        if let Some(node) = &self.node {
+            validate_node_id(node)?;
+        }
+        if self.operation.as_ref().is_some_and(|operation| operation.is_empty() || operation.len() > 32) {
+            return Err("operation must be 1-32 bytes".to_string());
+        }
+        if self.webhooks.len() > 8 {
+            return Err("at most 8 webhooks per rule".to_string());
+        }
+        if let Some(webhook) = self.webhooks.iter().find(|webhook| !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://")) {
+            return Err(format!("webhook {:?} is not an http:// or https:// URL", webhook.url));
+        }
+        Ok(())
+    }
+
+    fn window(&self) -> Result<chrono::Duration, String> {
+        let window = crate::config::parse_age(&self.window).map_err(|e| format!("{:#}", e))?;
+        if window > MAX_WINDOW {
+            return Err("window must be at most 1d".to_string());
+        }
+        chrono::Duration::from_std(window).map_err(|e| e.to_string())
+    }
+
+    /// The reports of the window ending at `now`
+    fn filter(&self, now: DateTime<Utc>) -> Result<OperationFilter, String> {
+        Ok(OperationFilter {
+            from: Some(now - self.window()?),
+            to: None,
+            operations: self.operation.iter().cloned().collect(),
+            nodes: self.node.iter().cloned().collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Ok,
+    Firing,
+}
+
+/// A rule and where it stands, as the API shows it
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub id: u64,
+    #[serde(flatten)]
+    pub rule: AlertRule,
+    pub state: AlertState,
+    /// The metric at the last evaluation; null for an average without reports
+    pub value: Option<f64>,
+    /// When the rule started firing
+    pub firing_since: Option<DateTime<Utc>>,
+    pub evaluated: Option<DateTime<Utc>>,
+}
+
+impl Alert {
+    fn new(id: u64, rule: AlertRule) -> Self {
+        Self { id, rule, state: AlertState::Ok, value: None, firing_since: None, evaluated: None }
+    }
+
+    /// One line for chat, e.g. "🚨 slow encrypt: throughput_mbps is 310.5, below 400 over the last 5m (encrypt on pit-a)"
+    fn message(&self) -> String {
+        let rule = &self.rule;
+        let value = self.value.map_or_else(|| "without reports".to_string(), |value| format!("is {}", (value * 1000.0).round() / 1000.0));
+        let scope = match (&rule.operation, &rule.node) {
+            (Some(operation), Some(node)) => format!(" ({} on {})", operation, node),
+            (Some(scope), None) | (None, Some(scope)) => format!(" ({})", scope),
+            (None, None) => String::new(),
+        };
+        match self.state {
+            AlertState::Firing => format!("🚨 {}: {} {}, {} {} over the last {}{}", rule.name, rule.metric.name(), value,
+                if rule.condition == Condition::Above { "above" } else { "below" }, rule.threshold, rule.window, scope),
+            AlertState::Ok => format!("✅ {} resolved: {} {} over the last {}{}", rule.name, rule.metric.name(), value, rule.window, scope),
+        }
+    }
+
+    /// What `webhook` is sent about this alert; never the webhooks themselves
+    fn payload(&self, format: WebhookFormat) -> Value {
+        match format {
+            WebhookFormat::Slack => json!({ "text": self.message() }),
+            WebhookFormat::Json => json!({
+                "id": self.id,
+                "name": self.rule.name,
+                "status": if self.state == AlertState::Firing { "firing" } else { "resolved" },
+                "metric": self.rule.metric,
+                "operation": self.rule.operation,
+                "node": self.rule.node,
+                "condition": self.rule.condition,
+                "threshold": self.rule.threshold,
+                "window": self.rule.window,
+                "value": self.value,
+                "firing_since": self.firing_since,
+                "at": self.evaluated,
+                "message": self.message(),
+            }),
+        }
+    }
+}
+
+/// The alert rules, by id. Ids are never reused, so a webhook consumer cannot
+/// mistake a new rule for a deleted one.
+pub struct Alerts {
+    alerts: RwLock<BTreeMap<u64, Alert>>,
+    store: Option<Arc<MetricsStore>>,
+    /// Ids when there is no store, which hands them out otherwise
+    next_id: AtomicU64,
+}
+
+impl Alerts {
+    /// The rules in `store`, which keeps them from then on
+    pub fn new(store: Option<Arc<MetricsStore>>) -> Result<Self> {
+        let mut alerts = BTreeMap::new();
+        for (id, rule) in store.as_ref().map(|store| store.alerts()).transpose()?.unwrap_or_default() {
+            let rule = serde_json::from_str(&rule).with_context(|| format!("invalid alert rule {} in the metrics database", id))?;
+            alerts.insert(id, Alert::new(id, rule));
+        }
+        Ok(Self { alerts: RwLock::new(alerts), store, next_id: AtomicU64::new(1) })
+    }
+
+    pub fn list(&self) -> Vec<Alert> {
+        self.alerts.read().values().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Alert> {
+        self.alerts.read().get(&id).cloned()
+    }
+
+    /// Add a validated rule
+    pub fn create(&self, rule: AlertRule) -> Result<Alert> {
+        let mut alerts = self.alerts.write();
+        let id = match &self.store {
+            Some(store) => store.insert_alert(&serde_json::to_string(&rule)?)?,
+            None => self.next_id.fetch_add(1, Ordering::Relaxed),
+        };
+        let alert = Alert::new(id, rule);
+        alerts.insert(id, alert.clone());
+        Ok(alert)
+    }
+
+    /// Replace the rule with this id by a validated one, which starts out not
+    /// firing; None when there is no such rule
+    pub fn replace(&self, id: u64, rule: AlertRule) -> Result<Option<Alert>> {
+        let mut alerts = self.alerts.write();
+        let Some(alert) = alerts.get_mut(&id) else { return Ok(None) };
+        let replaced = Alert::new(id, rule);
+        self.save(&replaced)?;
+        *alert = replaced.clone();
+        Ok(Some(replaced))
+    }
+
+    /// Delete the rule with this id; false when there is none
+    pub fn delete(&self, id: u64) -> Result<bool> {
+        let mut alerts = self.alerts.write();
+        if let Some(store) = &self.store {
+            store.delete_alert(id)?;
+        }
+        Ok(alerts.remove(&id).is_some())
+    }
+
+    fn save(&self, alert: &Alert) -> Result<()> {
+        match &self.store {
+            Some(store) => store.save_alert(alert.id, &serde_json::to_string(&alert.rule)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluate every rule against the reports `metrics` holds and notify the
+    /// webhooks of those that started or stopped firing. This reads the
+    /// database, so call it off the async workers.
+    pub fn check(&self, metrics: &MetricsCollector) {
+        for alert in self.evaluate(metrics, Utc::now()) {
+            for webhook in &alert.rule.webhooks {
+                let (url, payload) = (webhook.url.clone(), alert.payload(webhook.format));
+                let id = alert.id;
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = post(&url, &payload) {
+                        eprintln!("⚠️  Cannot notify alert {}: {:#}", id, e);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Evaluate every rule at `now`; returns the alerts whose state changed
+    fn evaluate(&self, metrics: &MetricsCollector, now: DateTime<Utc>) -> Vec<Alert> {
+        // Query without holding the lock, so the API is not kept waiting
+        let rules: Vec<_> = self.alerts.read().values().map(|alert| (alert.id, alert.rule.clone())).collect();
+        let mut values = Vec::new();
+        for (id, rule) in rules {
+            let aggregate = rule.filter(now)
+                .map_err(anyhow::Error::msg)
+                .and_then(|filter| metrics.operation_aggregate(&filter));
+            match aggregate {
+                Ok(aggregate) => values.push((id, rule.metric.measure(&aggregate), rule)),
+                Err(e) => eprintln!("⚠️  Cannot evaluate alert {}: {:#}", id, e),
+            }
+        }
+
+        let mut changed = Vec::new();
+        let mut alerts = self.alerts.write();
+        for (id, value, rule) in values {
+            // Skip a rule deleted or replaced in the meantime
+            let Some(alert) = alerts.get_mut(&id).filter(|alert| alert.rule == rule) else { continue };
+            alert.value = value;
+            alert.evaluated = Some(now);
+            let firing = alert.value.is_some_and(|value| alert.rule.condition.holds(value, alert.rule.threshold));
+            match (alert.state, firing) {
+                (AlertState::Ok, true) => {
+                    alert.state = AlertState::Firing;
+                    alert.firing_since = Some(now);
+                }
+                (AlertState::Firing, false) => {
+                    alert.state = AlertState::Ok;
+                    alert.firing_since = None;
+                }
+                _ => continue,
+            }
+            changed.push(alert.clone());
+        }
+        changed
+    }
+}
+
+/// POST `payload` as JSON to `url`
+fn post(url: &str, payload: &Value) -> Result<()> {
+    ureq::AgentBuilder::new()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&payload.to_string())
+        .with_context(|| format!("webhook {} failed", url))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_and_resolves_rules() {
+        let store = Arc::new(MetricsStore::in_memory().unwrap());
+        let collector = MetricsCollector::with_store(100, store.clone()).unwrap();
+        let alerts = Alerts::new(Some(store.clone())).unwrap();
+        let rule = |json: Value| -> AlertRule { serde_json::from_value(json).unwrap() };
+        let slow = alerts.create(rule(json!({
+            "name": "slow encrypt", "metric": "throughput_mbps", "operation": "encrypt", "condition": "below", "threshold": 400,
+            "webhooks": [{"url": "https://hooks.slack.com/services/T0/B0/x", "format": "slack"}],
+        }))).unwrap();
+        let failing = alerts.create(rule(json!({"name": "decrypt failures", "metric": "failures", "operation": "decrypt", "condition": "above", "threshold": 0, "window": "10m"}))).unwrap();
+        assert!(rule(json!({"name": "x", "metric": "failures", "condition": "above", "threshold": 0, "window": "2d"})).validate().is_err());
+        assert!(rule(json!({"name": "x", "metric": "failures", "condition": "above", "threshold": 0, "webhooks": [{"url": "file:///etc/passwd"}]})).validate().is_err());
+
+        let now = Utc::now();
+        let ingest = |operation: &str, ok: bool, duration_ms: u64, minutes_ago: i64| {
+            collector.ingest(serde_json::from_value(json!({
+                "version": 1, "node_id": "pit-a", "operation": operation, "ok": ok, "bytes": 10_000_000, "duration_ms": duration_ms,
+                "timestamp": now - chrono::Duration::minutes(minutes_ago),
+            })).unwrap()).unwrap();
+        };
+        // 80 and 800 Mbit/s average 440: not below 400 until the fast one leaves the window
+        ingest("encrypt", true, 1000, 1);
+        ingest("encrypt", true, 100, 4);
+        ingest("decrypt", true, 10, 2);
+        assert!(alerts.evaluate(&collector, now).is_empty());
+        let changed = alerts.evaluate(&collector, now + chrono::Duration::minutes(2));
+        assert_eq!(changed.iter().map(|alert| (alert.id, alert.state)).collect::<Vec<_>>(), [(slow.id, AlertState::Firing)]);
+        assert_eq!(changed[0].payload(WebhookFormat::Slack)["text"], "🚨 slow encrypt: throughput_mbps is 80, below 400 over the last 5m (encrypt)");
+
+        ingest("decrypt", false, 10, 0);
+        let changed = alerts.evaluate(&collector, now + chrono::Duration::minutes(3));
+        assert_eq!(changed.iter().map(|alert| (alert.id, alert.state)).collect::<Vec<_>>(), [(failing.id, AlertState::Firing)]);
+        assert_eq!(changed[0].payload(WebhookFormat::Json)["status"], "firing");
+        let changed = alerts.evaluate(&collector, now + chrono::Duration::minutes(11));
+        assert_eq!(changed.iter().map(|alert| (alert.id, alert.state)).collect::<Vec<_>>(), [(slow.id, AlertState::Ok), (failing.id, AlertState::Ok)]);
+
+        // Rules outlive a restart, without their state, and a deleted rule's id
+        // is never handed out again
+        assert!(alerts.delete(failing.id).unwrap() && !alerts.delete(failing.id).unwrap());
+        let reloaded = Alerts::new(Some(store)).unwrap();
+        assert_eq!(reloaded.list().iter().map(|alert| (alert.id, alert.rule.name.as_str())).collect::<Vec<_>>(), [(slow.id, "slow encrypt")]);
+        assert_eq!(reloaded.create(rule(json!({"name": "x", "metric": "failures", "condition": "above", "threshold": 0}))).unwrap().id, failing.id + 1);
+    }
+}
@@ -2,10 +2,24 @@
 
 use actix_web::{web, HttpResponse, Result as ActixResult};
 use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use crate::agents::{Agent, Agents, Registration};
+use crate::alerts::{AlertRule, Alerts};
+use crate::host::HostMonitor;
+use crate::jobs::{Cancel, EncryptRequest, JobManager};
+use crate::metrics::{OperationFilter, OperationReport};
 use crate::state::DashboardState;
+use crate::store::{HistoryCursor, Order};
 use trackshift::*;
 use std::sync::Arc;
 
+/// Liveness probe; the only endpoint that needs no API key
+pub async fn liveness() -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+    })))
+}
+
 /// Get system status snapshot
 pub async fn status(state: web::Data<Arc<DashboardState>>) -> ActixResult<HttpResponse> {
     let snapshot = state.monitor.get_status_snapshot();
@@ -119,6 +133,354 @@ pub async fn metrics_current(state: web::Data<Arc<DashboardState>>) -> ActixResu
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// Largest report body `POST /api/metrics` accepts
+pub const MAX_REPORT_BYTES: usize = 64 * 1024;
+
+/// JSON extractor settings for `POST /api/metrics`: bodies over
+/// [`MAX_REPORT_BYTES`] get 413 and malformed ones 400, both with a JSON error
+pub fn report_json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_REPORT_BYTES)
+        .error_handler(|err, _req| {
+            let response = match &err {
+                actix_web::error::JsonPayloadError::Overflow { .. } | actix_web::error::JsonPayloadError::OverflowKnownLength { .. } => {
+                    HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                        "error": format!("report larger than {} bytes", MAX_REPORT_BYTES),
+                    }))
+                }
+                _ => HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("invalid report: {}", err),
+                })),
+            };
+            actix_web::error::InternalError::from_response(err, response).into()
+        })
+}
+
+/// Ingest an operation report from a tool (`rust_pqc --metrics-url`)
+pub async fn metrics_ingest(
+    state: web::Data<Arc<DashboardState>>,
+    agents: web::Data<Agents>,
+    report: web::Json<OperationReport>,
+) -> ActixResult<HttpResponse> {
+    let report = report.into_inner();
+    if let Err(e) = report.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e,
+        })));
+    }
+    let node_id = report.node_id.clone();
+    if let Err(e) = state.metrics.ingest(report) {
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("{:#}", e),
+        })));
+    }
+    // The report is stored; a node that cannot be marked seen does not undo that
+    if let Err(e) = agents.seen(&node_id) {
+        eprintln!("⚠️  Cannot record agent {}: {:#}", node_id, e);
+    }
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "status": "accepted",
+    })))
+}
+
+/// Query for `GET /api/metrics/history`
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// Newest reports to return (default 100, at most 10000)
+    pub limit: Option<usize>,
+    /// Start of the range: RFC 3339, or an age such as "15m" for that long ago
+    pub from: Option<String>,
+    /// End of the range, in the same forms
+    pub to: Option<String>,
+    /// Comma-separated operations, e.g. "encrypt,decrypt"
+    pub operation: Option<String>,
+    /// Comma-separated node ids, e.g. "pit-a,pit-b"
+    pub node: Option<String>,
+    /// Page instead: the `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    /// Page instead: reports per page (default 100, at most 1000)
+    pub page_size: Option<usize>,
+}
+
+impl HistoryQuery {
+    fn filter(&self) -> Result<OperationFilter, String> {
+        parse_filter(self.from.as_deref(), self.to.as_deref(), self.operation.as_deref(), self.node.as_deref())
+    }
+}
+
+/// The history filter of the `from`, `to`, `operation` and `node` query parameters
+pub(crate) fn parse_filter(from: Option<&str>, to: Option<&str>, operation: Option<&str>, node: Option<&str>) -> Result<OperationFilter, String> {
+    let from = from.map(parse_time).transpose()?;
+    let to = to.map(parse_time).transpose()?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return Err("from is after to".to_string());
+        }
+    }
+    Ok(OperationFilter {
+        from,
+        to,
+        operations: parse_list(operation),
+        nodes: parse_list(node),
+    })
+}
+
+/// Operations or nodes from a comma-separated list; none means all of them
+pub(crate) fn parse_list(text: Option<&str>) -> Vec<String> {
+    text.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// An RFC 3339 time, or an age such as "15m" counted back from now
+fn parse_time(text: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let age = crate::config::parse_age(text)
+        .map_err(|_| format!("invalid time {:?}: use RFC 3339 or an age such as 15m", text))?;
+    Ok(Utc::now() - chrono::Duration::from_std(age).map_err(|e| e.to_string())?)
+}
+
+/// Get reported operations, newest first, optionally only those in a time range
+/// or of some operations; older ones come from the database. With `cursor` or
+/// `page_size` the result is one page, with the `next_cursor` to continue from
+/// (null on the last page).
+pub async fn metrics_history(
+    state: web::Data<Arc<DashboardState>>,
+    query: web::Query<HistoryQuery>,
+) -> ActixResult<HttpResponse> {
+    let bad_request = |e: String| HttpResponse::BadRequest().json(serde_json::json!({
+        "error": e,
+    }));
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(e) => return Ok(bad_request(e)),
+    };
+    let internal = |e: anyhow::Error| actix_web::error::ErrorInternalServerError(format!("{:#}", e));
+    if query.cursor.is_none() && query.page_size.is_none() {
+        let limit = query.limit.unwrap_or(100).min(10_000);
+        // Past a trimmed memory this reads the store, which blocks
+        let metrics = state.metrics.clone();
+        let operations = web::block(move || metrics.operation_history(&filter, limit)).await?.map_err(internal)?;
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "count": operations.len(),
+            "operations": operations,
+        })));
+    }
+
+    let cursor = match query.cursor.as_deref().map(str::parse::<HistoryCursor>).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => return Ok(bad_request(e.to_string())),
+    };
+    let page_size = query.page_size.unwrap_or(100).clamp(1, 1000);
+    let (operations, next) = state.metrics.operation_page(&filter, cursor, page_size, Order::NewestFirst).map_err(internal)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": operations.len(),
+        "operations": operations,
+        "next_cursor": next.map(|cursor| cursor.to_string()),
+    })))
+}
+
+/// Query for `GET /api/metrics/summary`
+#[derive(Deserialize)]
+pub struct SummaryQuery {
+    /// How far back to summarize, e.g. "1h" (default); at most 31 days
+    pub window: Option<String>,
+    /// Width of each bucket, e.g. "1m" (default)
+    pub bucket: Option<String>,
+    /// Comma-separated operations, e.g. "encrypt"
+    pub operation: Option<String>,
+    /// Comma-separated node ids, e.g. "pit-a"
+    pub node: Option<String>,
+}
+
+/// Throughput and duration aggregates per time bucket over the recent window,
+/// oldest bucket first
+pub async fn metrics_summary(
+    state: web::Data<Arc<DashboardState>>,
+    query: web::Query<SummaryQuery>,
+) -> ActixResult<HttpResponse> {
+    let bad_request = |e: String| HttpResponse::BadRequest().json(serde_json::json!({
+        "error": e,
+    }));
+    let age = |text: Option<&str>, default: &str| -> Result<chrono::Duration, String> {
+        let age = crate::config::parse_age(text.unwrap_or(default)).map_err(|e| format!("{:#}", e))?;
+        chrono::Duration::from_std(age).map_err(|e| e.to_string())
+    };
+    let (window, bucket) = match (age(query.window.as_deref(), "1h"), age(query.bucket.as_deref(), "1m")) {
+        (Ok(window), Ok(bucket)) => (window, bucket),
+        (Err(e), _) | (_, Err(e)) => return Ok(bad_request(e)),
+    };
+    if window.num_seconds() > crate::summary::MAX_WINDOW_SECONDS {
+        return Ok(bad_request(format!("window must be at most {}d", crate::summary::MAX_WINDOW_SECONDS / 86_400)));
+    }
+    if window.num_milliseconds() / bucket.num_milliseconds() >= crate::summary::MAX_BUCKETS {
+        return Ok(bad_request(format!("window / bucket must be under {} buckets", crate::summary::MAX_BUCKETS)));
+    }
+
+    let now = Utc::now();
+    let start = crate::summary::first_bucket(now, window, bucket);
+    let filter = OperationFilter {
+        from: Some(start),
+        to: Some(now),
+        operations: parse_list(query.operation.as_deref()),
+        nodes: parse_list(query.node.as_deref()),
+    };
+    // The store blocks, so scan it off the async workers
+    let metrics = state.metrics.clone();
+    let buckets = web::block(move || {
+        let mut summarizer = crate::summary::Summarizer::new(start, now, bucket);
+        metrics.for_each_operation(&filter, |report| summarizer.add(report)).map(|()| summarizer.finish())
+    })
+    .await?
+    .map_err(|e| actix_web::error::ErrorInternalServerError(format!("{:#}", e)))?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "window_seconds": window.num_seconds(),
+        "bucket_seconds": bucket.num_seconds(),
+        "buckets": buckets,
+    })))
+}
+
+/// Register a reporting agent; 201 the first time, 200 when it registers again
+pub async fn agents_register(agents: web::Data<Agents>, registration: web::Json<Registration>) -> ActixResult<HttpResponse> {
+    let registration = registration.into_inner();
+    if let Err(e) = registration.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e,
+        })));
+    }
+    match agents.register(registration) {
+        Ok((agent, true)) => Ok(HttpResponse::Created().json(agent)),
+        Ok((agent, false)) => Ok(HttpResponse::Ok().json(agent)),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("{:#}", e),
+        }))),
+    }
+}
+
+/// An agent with its status, for `GET /api/agents`
+#[derive(Serialize)]
+struct AgentStatus {
+    #[serde(flatten)]
+    agent: Agent,
+    /// "online" or "offline"
+    status: &'static str,
+}
+
+/// Every agent that registered or reported, with when it was last seen
+pub async fn agents_list(agents: web::Data<Agents>) -> ActixResult<HttpResponse> {
+    let now = Utc::now();
+    let agents: Vec<_> = agents.list()
+        .into_iter()
+        .map(|agent| AgentStatus { status: agent.status(now), agent })
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": agents.len(),
+        "online_within_seconds": crate::agents::ONLINE_WITHIN.as_secs(),
+        "agents": agents,
+    })))
+}
+
+fn no_such_alert(id: u64) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": format!("no alert {}", id),
+    }))
+}
+
+fn invalid_rule(rule: &AlertRule) -> Option<HttpResponse> {
+    let e = rule.validate().err()?;
+    Some(HttpResponse::BadRequest().json(serde_json::json!({
+        "error": e,
+    })))
+}
+
+/// Every alert rule, with whether it is firing
+pub async fn alerts_list(alerts: web::Data<Alerts>) -> ActixResult<HttpResponse> {
+    let alerts = alerts.list();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": alerts.len(),
+        "alerts": alerts,
+    })))
+}
+
+/// Add an alert rule; answers 201 with it
+pub async fn alerts_create(alerts: web::Data<Alerts>, rule: web::Json<AlertRule>) -> ActixResult<HttpResponse> {
+    let rule = rule.into_inner();
+    if let Some(response) = invalid_rule(&rule) {
+        return Ok(response);
+    }
+    let alert = alerts.create(rule).map_err(|e| actix_web::error::ErrorInternalServerError(format!("{:#}", e)))?;
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", format!("/api/alerts/{}", alert.id)))
+        .json(alert))
+}
+
+/// An alert rule and its state
+pub async fn alert_get(alerts: web::Data<Alerts>, id: web::Path<u64>) -> ActixResult<HttpResponse> {
+    let id = id.into_inner();
+    Ok(match alerts.get(id) {
+        Some(alert) => HttpResponse::Ok().json(alert),
+        None => no_such_alert(id),
+    })
+}
+
+/// Replace an alert rule; it starts over as not firing
+pub async fn alert_update(alerts: web::Data<Alerts>, id: web::Path<u64>, rule: web::Json<AlertRule>) -> ActixResult<HttpResponse> {
+    let (id, rule) = (id.into_inner(), rule.into_inner());
+    if let Some(response) = invalid_rule(&rule) {
+        return Ok(response);
+    }
+    match alerts.replace(id, rule) {
+        Ok(Some(alert)) => Ok(HttpResponse::Ok().json(alert)),
+        Ok(None) => Ok(no_such_alert(id)),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(format!("{:#}", e))),
+    }
+}
+
+/// Delete an alert rule; 204, or 404 when there is none
+pub async fn alert_delete(alerts: web::Data<Alerts>, id: web::Path<u64>) -> ActixResult<HttpResponse> {
+    let id = id.into_inner();
+    match alerts.delete(id) {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(no_such_alert(id)),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(format!("{:#}", e))),
+    }
+}
+
+/// Query for `GET /api/metrics/host`
+#[derive(Deserialize)]
+pub struct HostQuery {
+    /// Oldest sample: RFC 3339, or an age such as "15m" (default: all kept)
+    pub from: Option<String>,
+}
+
+/// Resource use of the dashboard host: the latest sample and those since `from`,
+/// oldest first
+pub async fn metrics_host(host: web::Data<HostMonitor>, query: web::Query<HostQuery>) -> ActixResult<HttpResponse> {
+    let from = match query.from.as_deref().map(parse_time).transpose() {
+        Ok(from) => from,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e,
+        }))),
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "interval_seconds": crate::host::SAMPLE_INTERVAL.as_secs(),
+        "latest": host.latest(),
+        "samples": host.since(from),
+    })))
+}
+
+/// Metrics in the Prometheus text format, for scraping
+pub async fn prometheus_metrics(state: web::Data<Arc<DashboardState>>, host: web::Data<HostMonitor>) -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type(crate::prometheus::CONTENT_TYPE)
+        .body(crate::prometheus::render(&state.metrics, host.latest().as_ref())))
+}
+
 /// Get all transfers
 pub async fn transfers(state: web::Data<Arc<DashboardState>>) -> ActixResult<HttpResponse> {
     let snapshot = state.monitor.get_status_snapshot();
@@ -433,3 +795,71 @@ pub async fn stats(state: web::Data<Arc<DashboardState>>) -> ActixResult<HttpRes
         },
     })))
 }
+
+/// Where `GET /api/benchmarks` reads the report from (`--bench-report`)
+pub struct BenchReport(pub std::path::PathBuf);
+
+/// Get the latest crypto benchmark report (`rust_pqc bench-report --export json`)
+pub async fn benchmarks(report: web::Data<BenchReport>) -> ActixResult<HttpResponse> {
+    let path = report.0.display();
+    let report = match std::fs::read_to_string(&report.0) {
+        Ok(text) => text,
+        Err(_) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("no benchmark report at {}", path),
+            })));
+        }
+    };
+    match serde_json::from_str::<serde_json::Value>(&report) {
+        Ok(value) => Ok(HttpResponse::Ok().json(value)),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("invalid benchmark report {}: {}", path, e),
+        }))),
+    }
+}
+
+/// Queue the encryption of a file in the jobs directory; the job runs in the
+/// background and is known by the returned id
+pub async fn jobs_encrypt(
+    jobs: web::Data<JobManager>,
+    request: web::Json<EncryptRequest>,
+) -> ActixResult<HttpResponse> {
+    match jobs.submit(request.into_inner()) {
+        Ok(job) => Ok(HttpResponse::Accepted()
+            .insert_header(("Location", format!("/api/jobs/{}", job.id)))
+            .json(job)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("{:#}", e),
+        }))),
+    }
+}
+
+fn no_such_job(id: u64) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": format!("no job {}", id),
+    }))
+}
+
+/// State and progress of an encryption job
+pub async fn job_status(jobs: web::Data<JobManager>, id: web::Path<u64>) -> ActixResult<HttpResponse> {
+    let id = id.into_inner();
+    match jobs.get(id) {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Ok(no_such_job(id)),
+    }
+}
+
+/// Cancel an encryption job: 200 when it was still queued, 202 while a running
+/// one stops, 409 when it has already finished
+pub async fn job_cancel(jobs: web::Data<JobManager>, id: web::Path<u64>) -> ActixResult<HttpResponse> {
+    let id = id.into_inner();
+    match jobs.cancel(id) {
+        Some(Cancel::Cancelled(job)) => Ok(HttpResponse::Ok().json(job)),
+        Some(Cancel::Stopping(job)) => Ok(HttpResponse::Accepted().json(job)),
+        Some(Cancel::Finished(job)) => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("job {} has already finished", id),
+            "job": job,
+        }))),
+        None => Ok(no_such_job(id)),
+    }
+}
@@ -0,0 +1,180 @@
+//! API key authentication
+//!
+//! Everything under `/api/` and the Prometheus `/metrics` needs a key, sent as
+//! `Authorization: Bearer <key>` or `X-API-Key: <key>`; only `/health` and the
+//! static UI are open. A browser's `EventSource` and plain download links cannot
+//! set headers, so the live stream and upload downloads also take the key as an
+//! `api_key` query parameter. Keys are the static ones from `--api-key` (or
+//! `DASHBOARD_API_KEYS`, comma-separated, or `api_keys` in the config file) plus
+//! those in the `api_keys` table of the metrics database, which
+//! `dashboard add-key NAME` creates. The table holds SHA-256 hashes only.
+
+use std::sync::Arc;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::store::MetricsStore;
+
+/// Accepted API keys
+pub struct ApiKeys {
+    fixed: Vec<[u8; 32]>,
+    store: Option<Arc<MetricsStore>>,
+}
+
+impl ApiKeys {
+    /// Accept `keys` and any key in the `api_keys` table of `store`
+    pub fn new(keys: impl IntoIterator<Item = String>, store: Option<Arc<MetricsStore>>) -> Self {
+        let fixed = keys.into_iter()
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty())
+            .map(|key| hash_key(&key))
+            .collect();
+        Self { fixed, store }
+    }
+
+    /// Whether no key at all would be accepted
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        Ok(self.fixed.is_empty() && self.store.as_ref().map_or(Ok(0), |store| store.api_key_count())? == 0)
+    }
+
+    /// Whether `key` is one of the static keys, which needs no database lookup
+    pub fn is_static(&self, key: &str) -> bool {
+        self.fixed.contains(&hash_key(key))
+    }
+
+    /// Whether `key` is accepted; blocks on the store for keys that are not static
+    pub fn check(&self, key: &str) -> anyhow::Result<bool> {
+        if self.is_static(key) {
+            return Ok(true);
+        }
+        match &self.store {
+            Some(store) => store.has_api_key(&hash_key(key)),
+            None => Ok(false),
+        }
+    }
+}
+
+/// SHA-256 of a key, as stored
+pub fn hash_key(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// A new random key: `plk_` and 32 random bytes in hex
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("plk_{}", hex)
+}
+
+/// Whether requests for `path` need a key
+fn needs_key(path: &str) -> bool {
+    path.starts_with("/api/") || path == "/api" || path == "/metrics"
+}
+
+/// Whether `path` may take the key as an `api_key` query parameter, for
+/// browsers that open it without a way to set headers
+fn takes_query_key(path: &str) -> bool {
+    path == "/api/metrics/stream" || path.starts_with("/api/uploads/")
+}
+
+#[derive(serde::Deserialize)]
+struct KeyQuery {
+    api_key: String,
+}
+
+/// The key a request carries, if any
+fn presented_key(req: &ServiceRequest) -> Option<String> {
+    let headers = req.headers();
+    if let Some(value) = headers.get("x-api-key") {
+        return value.to_str().ok().map(str::to_string);
+    }
+    if let Some(value) = headers.get("authorization") {
+        return value.to_str().ok()?.strip_prefix("Bearer ").map(str::to_string);
+    }
+    if req.method() == actix_web::http::Method::GET && takes_query_key(req.path()) {
+        return web::Query::<KeyQuery>::from_query(req.query_string()).ok().map(|query| query.into_inner().api_key);
+    }
+    None
+}
+
+/// Middleware rejecting requests for protected paths without an accepted key;
+/// needs `web::Data<ApiKeys>` in the app data
+pub async fn require_key(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if !needs_key(req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+    let keys = req.app_data::<web::Data<ApiKeys>>().cloned();
+    let verdict = match (keys, presented_key(&req)) {
+        (Some(keys), Some(key)) if keys.is_static(&key) => Ok(true),
+        // The store blocks, so look the key up off the async workers
+        (Some(keys), Some(key)) => web::block(move || keys.check(&key)).await.unwrap_or_else(|e| Err(anyhow::anyhow!("{}", e))),
+        _ => Ok(false),
+    };
+    let response = match verdict {
+        Ok(true) => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+        Ok(false) => HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer"))
+            .json(serde_json::json!({
+                "error": "missing or unknown API key",
+            })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("cannot check the API key: {:#}", e),
+        })),
+    };
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware, test, App};
+
+    #[actix_web::test]
+    async fn protects_the_api_only() {
+        let store = Arc::new(MetricsStore::in_memory().unwrap());
+        let stored = generate_key();
+        store.add_api_key("ci", &hash_key(&stored)).unwrap();
+        let keys = web::Data::new(ApiKeys::new(["static-key".to_string()], Some(store)));
+        assert!(!keys.is_empty().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(keys)
+                .wrap(middleware::from_fn(require_key))
+                .route("/health", web::get().to(HttpResponse::Ok))
+                .route("/api/status", web::get().to(HttpResponse::Ok))
+                .route("/api/metrics/stream", web::get().to(HttpResponse::Ok))
+                .route("/api/uploads/{id}/{name}", web::get().to(HttpResponse::Ok))
+                .route("/metrics", web::get().to(HttpResponse::Ok)),
+        ).await;
+
+        let status = |uri: &str, header: Option<(&str, String)>| {
+            let mut req = test::TestRequest::get().uri(uri);
+            if let Some(header) = header {
+                req = req.insert_header(header);
+            }
+            req.to_request()
+        };
+        for (uri, header, expected) in [
+            ("/health", None, 200),
+            ("/api/status", None, 401),
+            ("/metrics", None, 401),
+            ("/api/status", Some(("Authorization", "Bearer wrong".to_string())), 401),
+            ("/api/status", Some(("Authorization", "Bearer static-key".to_string())), 200),
+            ("/metrics", Some(("X-API-Key", stored.clone())), 200),
+            // Only the stream, which EventSource opens, and download links take the key in the query
+            ("/api/metrics/stream?operation=encrypt&api_key=static-key", None, 200),
+            ("/api/metrics/stream?api_key=wrong", None, 401),
+            ("/api/uploads/0123/run.csv.pqc?api_key=static-key", None, 200),
+            ("/api/status?api_key=static-key", None, 401),
+        ] {
+            let response = test::call_service(&app, status(uri, header.clone())).await;
+            assert_eq!(response.status().as_u16(), expected, "{} {:?}", uri, header);
+        }
+    }
+}
@@ -1,5 +1,216 @@
-/// Configuration management
+//! Server configuration: where to listen, the metrics database and TLS
+//!
+//! Runtime settings the UI changes (compression, routing, weights) live in
+//! `state.rs`; these are fixed for the life of the process. Each comes from a
+//! command-line flag, else its `DASHBOARD_*` variable, else the config file
+//! (`--config`), else the default:
+//!
+//! ```toml
+//! bind = "0.0.0.0"
+//! port = 8443
+//! static_dir = "/usr/share/pitlink/dashboard"
+//! db_path = "/var/lib/pitlink/dashboard-metrics.db"
+//! retention = "30d"                  # drop reports older than this
+//! jobs_dir = "/srv/pitlink/jobs"      # files encryption jobs may read and write
+//! max_upload = "4G"                  # largest file POST /api/encrypt takes
+//! upload_retention = "2d"            # delete encrypted uploads older than this
+//! bench_report = "bench.json"        # served at GET /api/benchmarks
+//! tls_cert = "/etc/pitlink/dashboard.crt"
+//! tls_key = "/etc/pitlink/dashboard.key"
+//! http_redirect_port = 8080          # with TLS: plain HTTP redirects to HTTPS
+//! api_keys = ["..."]                 # static keys, besides `dashboard add-key`
+//! ```
 
-// Configuration is handled in state.rs
-// This module can be extended for config file loading/saving
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+/// Settings from one source (flags or the config file); unset ones fall through
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub bind: Option<String>,
+    pub port: Option<u16>,
+    pub static_dir: Option<PathBuf>,
+    pub db_path: Option<PathBuf>,
+    /// Age after which reports are deleted, e.g. "90d" or "12h"
+    pub retention: Option<String>,
+    pub jobs_dir: Option<PathBuf>,
+    /// Largest upload to encrypt, e.g. "512M" or "4G"
+    pub max_upload: Option<String>,
+    /// Age after which encrypted uploads are deleted, e.g. "2d"
+    pub upload_retention: Option<String>,
+    /// `rust_pqc bench-report --export json` output to serve
+    pub bench_report: Option<PathBuf>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub http_redirect_port: Option<u16>,
+    pub api_keys: Vec<String>,
+}
+
+impl Settings {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// These settings, with the unset ones taken from `fallback`
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            bind: self.bind.or(fallback.bind),
+            port: self.port.or(fallback.port),
+            static_dir: self.static_dir.or(fallback.static_dir),
+            db_path: self.db_path.or(fallback.db_path),
+            retention: self.retention.or(fallback.retention),
+            jobs_dir: self.jobs_dir.or(fallback.jobs_dir),
+            max_upload: self.max_upload.or(fallback.max_upload),
+            upload_retention: self.upload_retention.or(fallback.upload_retention),
+            bench_report: self.bench_report.or(fallback.bench_report),
+            tls_cert: self.tls_cert.or(fallback.tls_cert),
+            tls_key: self.tls_key.or(fallback.tls_key),
+            http_redirect_port: self.http_redirect_port.or(fallback.http_redirect_port),
+            api_keys: if self.api_keys.is_empty() { fallback.api_keys } else { self.api_keys },
+        }
+    }
+}
+
+/// Certificate and key for HTTPS
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    pub static_dir: PathBuf,
+    pub db_path: PathBuf,
+    /// Reports older than this are deleted; kept forever when unset
+    pub retention: Option<Duration>,
+    /// Encryption jobs read and write files under this directory only
+    pub jobs_dir: PathBuf,
+    /// Largest upload `POST /api/encrypt` accepts, in bytes
+    pub max_upload: u64,
+    /// Packages made from uploads are deleted once older than this
+    pub upload_retention: Duration,
+    /// Benchmark report `GET /api/benchmarks` serves
+    pub bench_report: PathBuf,
+    pub tls: Option<TlsConfig>,
+    pub redirect_http_port: Option<u16>,
+    pub api_keys: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0".to_string(),
+            port: 8080,
+            static_dir: PathBuf::from("./dashboard/static"),
+            db_path: PathBuf::from("dashboard-metrics.db"),
+            retention: None,
+            jobs_dir: PathBuf::from("dashboard-jobs"),
+            max_upload: crate::upload::DEFAULT_MAX_UPLOAD,
+            upload_retention: crate::upload::DEFAULT_RETENTION,
+            bench_report: PathBuf::from("target/pitlink-bench.json"),
+            tls: None,
+            redirect_http_port: None,
+            api_keys: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Defaults overridden by `settings`
+    pub fn from_settings(settings: Settings) -> Result<Self> {
+        let defaults = Self::default();
+        let config = Self {
+            bind: settings.bind.unwrap_or(defaults.bind),
+            port: settings.port.unwrap_or(defaults.port),
+            static_dir: settings.static_dir.unwrap_or(defaults.static_dir),
+            db_path: settings.db_path.unwrap_or(defaults.db_path),
+            retention: settings.retention.as_deref().map(parse_age).transpose()?,
+            jobs_dir: settings.jobs_dir.unwrap_or(defaults.jobs_dir),
+            max_upload: settings.max_upload.as_deref().map(pitlink_pqc::parse_size).transpose()?.unwrap_or(defaults.max_upload),
+            upload_retention: settings.upload_retention.as_deref().map(parse_age).transpose()?.unwrap_or(defaults.upload_retention),
+            bench_report: settings.bench_report.unwrap_or(defaults.bench_report),
+            tls: match (settings.tls_cert, settings.tls_key) {
+                (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+                (None, None) => None,
+                _ => bail!("a TLS certificate and key must be given together"),
+            },
+            redirect_http_port: settings.http_redirect_port,
+            api_keys: settings.api_keys.into_iter().map(|key| key.trim().to_string()).filter(|key| !key.is_empty()).collect(),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(redirect) = self.redirect_http_port {
+            if self.tls.is_none() {
+                bail!("an HTTP to HTTPS redirect needs a TLS certificate and key");
+            }
+            if redirect == self.port {
+                bail!("the redirect port must differ from the HTTPS port {}", self.port);
+            }
+        }
+        Ok(())
+    }
+
+    /// Base URL the dashboard is reached at
+    pub fn url(&self) -> String {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!("{}://localhost:{}", scheme, self.port)
+    }
+}
+
+/// Parse an age such as "90d", "12h", "30m", "2w" or "3600s"
+pub fn parse_age(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().with_context(|| format!("invalid age {:?}", text))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        _ => bail!("invalid age {:?}: use a number with s, m, h, d or w", text),
+    };
+    if number == 0 {
+        bail!("age must be longer than zero");
+    }
+    let seconds = number.checked_mul(seconds).with_context(|| format!("age {:?} is too long", text))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_the_file() {
+        let file: Settings = toml::from_str("port = 9000\nretention = \"2w\"\ndb_path = \"/var/lib/m.db\"\napi_keys = [\"a\"]\nmax_upload = \"64M\"\nupload_retention = \"12h\"\nbench_report = \"bench.json\"").unwrap();
+        let flags = Settings { port: Some(9100), ..Default::default() };
+        let config = ServerConfig::from_settings(flags.or(file)).unwrap();
+        assert_eq!((config.port, config.bind.as_str()), (9100, "0.0.0.0"));
+        assert_eq!(config.db_path, PathBuf::from("/var/lib/m.db"));
+        assert_eq!(config.retention, Some(Duration::from_secs(14 * 86_400)));
+        assert_eq!(config.api_keys, ["a"]);
+        assert_eq!(config.max_upload, 64 << 20);
+        assert_eq!(config.upload_retention, Duration::from_secs(12 * 3600));
+        assert_eq!(config.bench_report, PathBuf::from("bench.json"));
+        assert_eq!(ServerConfig::from_settings(Settings::default()).unwrap().upload_retention, crate::upload::DEFAULT_RETENTION);
+
+        assert!(toml::from_str::<Settings>("prot = 1").is_err());
+        assert!(ServerConfig::from_settings(Settings { tls_cert: Some("c.pem".into()), ..Default::default() }).is_err());
+        assert!(ServerConfig::from_settings(Settings { http_redirect_port: Some(80), ..Default::default() }).is_err());
+        assert!(parse_age("12h").is_ok() && parse_age("12").is_err() && parse_age("0d").is_err() && parse_age("d").is_err());
+        assert!(parse_age(&format!("{}w", u64::MAX / 86_400)).unwrap_err().to_string().contains("too long"));
+    }
+}
@@ -0,0 +1,158 @@
+//! The metrics history as CSV
+//!
+//! `GET /api/metrics/export?format=csv` sends the reports that `from`, `to`,
+//! `operation` and `node` select, oldest first, for spreadsheets and notebooks.
+//! Rows are read from the metrics database a page at a time while the response
+//! is written, so a whole season of reports never sits in memory at once.
+
+use std::sync::Arc;
+
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpResponse, Result as ActixResult};
+use chrono::Utc;
+use futures::StreamExt;
+use serde::Deserialize;
+
+use crate::api::parse_filter;
+use crate::metrics::{MetricsCollector, OperationFilter, OperationReport};
+use crate::state::DashboardState;
+use crate::store::{HistoryCursor, Order};
+
+/// Reports read from the store per chunk of the response
+const PAGE_SIZE: usize = 1000;
+
+/// First line of the file
+const HEADER: &str = "timestamp,node_id,operation,ok,bytes,duration_ms,throughput_mbps,algorithm\n";
+
+/// Query for `GET /api/metrics/export`
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// Only "csv" (default)
+    pub format: Option<String>,
+    /// Start of the range: RFC 3339, or an age such as "15m" for that long ago
+    pub from: Option<String>,
+    /// End of the range, in the same forms
+    pub to: Option<String>,
+    /// Comma-separated operations, e.g. "encrypt,decrypt"
+    pub operation: Option<String>,
+    /// Comma-separated node ids, e.g. "pit-a,pit-b"
+    pub node: Option<String>,
+}
+
+/// A report as a CSV row
+fn row(report: &OperationReport) -> String {
+    let fields = [
+        report.timestamp.map(|time| time.to_rfc3339()).unwrap_or_default(),
+        field(&report.node_id),
+        field(&report.operation),
+        report.ok.to_string(),
+        report.bytes.to_string(),
+        report.duration_ms.to_string(),
+        report.throughput_mbps().map(|mbps| format!("{:.3}", mbps)).unwrap_or_default(),
+        field(report.algorithm.as_deref().unwrap_or_default()),
+    ];
+    fields.join(",") + "\n"
+}
+
+/// A text cell, quoted when it has to be and kept from being read as a
+/// spreadsheet formula
+fn field(text: &str) -> String {
+    let text = if text.starts_with(['=', '+', '-', '@']) { format!("'{}", text) } else { text.to_string() };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// Where the export has got to
+enum Progress {
+    Page(Option<HistoryCursor>),
+    Done,
+}
+
+/// The next page of rows; None once all were sent
+fn next_page(metrics: &MetricsCollector, filter: &OperationFilter, progress: Progress) -> Option<anyhow::Result<(String, Progress)>> {
+    let Progress::Page(cursor) = progress else {
+        return None;
+    };
+    Some(metrics.operation_page(filter, cursor, PAGE_SIZE, Order::OldestFirst).map(|(reports, next)| {
+        (reports.iter().map(row).collect(), next.map_or(Progress::Done, |next| Progress::Page(Some(next))))
+    }))
+}
+
+/// Stream the selected history as a CSV download, oldest report first
+pub async fn metrics_export(
+    state: web::Data<Arc<DashboardState>>,
+    query: web::Query<ExportQuery>,
+) -> ActixResult<HttpResponse> {
+    let bad_request = |e: String| HttpResponse::BadRequest().json(serde_json::json!({
+        "error": e,
+    }));
+    match query.format.as_deref() {
+        None | Some("csv") => {}
+        Some(format) => return Ok(bad_request(format!("unsupported format {:?}: use csv", format))),
+    }
+    let filter = match parse_filter(query.from.as_deref(), query.to.as_deref(), query.operation.as_deref(), query.node.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(bad_request(e)),
+    };
+
+    // Read the first page before answering, so a failure is still an error
+    // status. The store blocks, so read it off the async workers
+    let metrics = state.metrics.clone();
+    let (first, progress) = {
+        let (metrics, filter) = (metrics.clone(), filter.clone());
+        web::block(move || next_page(&metrics, &filter, Progress::Page(None))).await?
+    }
+    .expect("a first page is always read")
+    .map_err(|e| actix_web::error::ErrorInternalServerError(format!("{:#}", e)))?;
+    let rest = futures::stream::unfold(progress, move |progress| {
+        let (metrics, filter) = (metrics.clone(), filter.clone());
+        async move {
+            match web::block(move || next_page(&metrics, &filter, progress)).await {
+                Ok(Some(Ok((rows, progress)))) => Some((Ok(Bytes::from(rows)), progress)),
+                Ok(None) => None,
+                Ok(Some(Err(e))) => {
+                    // The status is sent already: cut the file short
+                    eprintln!("⚠️  Metrics export failed: {:#}", e);
+                    Some((Err(actix_web::error::ErrorInternalServerError(format!("{:#}", e))), Progress::Done))
+                }
+                Err(e) => Some((Err(e.into()), Progress::Done)),
+            }
+        }
+    });
+
+    let filename = format!("pitlink-metrics-{}.csv", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    let head = Bytes::from(HEADER.to_string() + &first);
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+        .streaming(futures::stream::once(futures::future::ready(Ok::<_, actix_web::Error>(head))).chain(rest)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_reports_oldest_first() {
+        let metrics = MetricsCollector::with_store(10, Arc::new(crate::store::MetricsStore::in_memory().unwrap())).unwrap();
+        for (i, algorithm) in ["MlKem768/XChaCha20Poly1305", "=HYPERLINK(\"x\"), a"].into_iter().enumerate() {
+            metrics.ingest(serde_json::from_value(serde_json::json!({
+                "version": 1, "node_id": "pit-a", "operation": "encrypt", "bytes": 1_000_000, "duration_ms": 10 * (i + 1),
+                "algorithm": algorithm, "timestamp": format!("2026-01-01T00:00:0{}Z", i),
+            })).unwrap()).unwrap();
+        }
+
+        let (rows, progress) = next_page(&metrics, &OperationFilter::default(), Progress::Page(None)).unwrap().unwrap();
+        assert!(matches!(progress, Progress::Done));
+        assert_eq!(rows, concat!(
+            "2026-01-01T00:00:00+00:00,pit-a,encrypt,true,1000000,10,800.000,MlKem768/XChaCha20Poly1305\n",
+            "2026-01-01T00:00:01+00:00,pit-a,encrypt,true,1000000,20,400.000,\"'=HYPERLINK(\"\"x\"\"), a\"\n",
+        ));
+        assert_eq!(HEADER.matches(',').count(), rows.lines().next().unwrap().matches(',').count());
+        let nobody = OperationFilter { nodes: vec!["pit-b".to_string()], ..Default::default() };
+        assert_eq!(next_page(&metrics, &nobody, Progress::Page(None)).unwrap().unwrap().0, "");
+    }
+}
@@ -0,0 +1,165 @@
+//! Resource use of the dashboard host
+//!
+//! A background task samples CPU, memory, disk I/O and network throughput with
+//! `sysinfo` every [`SAMPLE_INTERVAL`] and keeps the last hour of samples in
+//! memory. They are served at `GET /api/metrics/host` and as `pitlink_host_*`
+//! gauges on `/metrics`, next to the operation metrics, so a slow transfer can be
+//! set against what the host was doing at the time.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::Serialize;
+use sysinfo::{Disks, Networks, System};
+
+/// Time between samples
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Samples kept: one hour at [`SAMPLE_INTERVAL`]
+const MAX_SAMPLES: usize = 720;
+
+/// Resource use over one sample interval; rates are averages over it
+#[derive(Debug, Clone, Serialize)]
+pub struct HostSample {
+    pub timestamp: DateTime<Utc>,
+    /// Busy share of all CPUs, 0 to 100
+    pub cpu_percent: f32,
+    pub memory_used_bytes: u64,
+    pub memory_total_bytes: u64,
+    pub disk_read_bytes_per_sec: f64,
+    pub disk_written_bytes_per_sec: f64,
+    /// All interfaces but loopback
+    pub network_received_bytes_per_sec: f64,
+    pub network_transmitted_bytes_per_sec: f64,
+}
+
+/// The samples taken so far, oldest first
+#[derive(Default)]
+pub struct HostMonitor {
+    samples: RwLock<VecDeque<HostSample>>,
+}
+
+impl HostMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sample the host every [`SAMPLE_INTERVAL`] in the background
+    pub fn start(self: Arc<Self>) {
+        actix_web::rt::spawn(async move {
+            let mut sampler = Sampler::new();
+            let mut interval = actix_web::rt::time::interval(SAMPLE_INTERVAL);
+            // The first tick is immediate and rates need a whole interval
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                self.push(sampler.sample());
+            }
+        });
+    }
+
+    /// The newest sample, once one has been taken
+    pub fn latest(&self) -> Option<HostSample> {
+        self.samples.read().back().cloned()
+    }
+
+    /// Samples taken at or after `from`, oldest first
+    pub fn since(&self, from: Option<DateTime<Utc>>) -> Vec<HostSample> {
+        self.samples.read()
+            .iter()
+            .filter(|sample| from.is_none_or(|from| sample.timestamp >= from))
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, sample: HostSample) {
+        let mut samples = self.samples.write();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+}
+
+/// sysinfo state; CPU use and I/O are counted from one refresh to the next
+struct Sampler {
+    system: System,
+    disks: Disks,
+    networks: Networks,
+    last: Instant,
+}
+
+impl Sampler {
+    fn new() -> Self {
+        let mut system = System::new();
+        system.refresh_cpu_usage();
+        Self {
+            system,
+            disks: Disks::new_with_refreshed_list(),
+            networks: Networks::new_with_refreshed_list(),
+            last: Instant::now(),
+        }
+    }
+
+    fn sample(&mut self) -> HostSample {
+        self.system.refresh_cpu_usage();
+        self.system.refresh_memory();
+        self.disks.refresh(true);
+        self.networks.refresh(true);
+        let seconds = self.last.elapsed().as_secs_f64().max(0.001);
+        self.last = Instant::now();
+
+        // A device mounted more than once is listed once per mount point
+        let mut devices = HashSet::new();
+        let (mut read, mut written) = (0, 0);
+        for disk in self.disks.list().iter().filter(|disk| devices.insert(disk.name())) {
+            let usage = disk.usage();
+            read += usage.read_bytes;
+            written += usage.written_bytes;
+        }
+        let (mut received, mut transmitted) = (0, 0);
+        for (_, network) in self.networks.list().iter().filter(|(name, _)| !matches!(name.as_str(), "lo" | "lo0")) {
+            received += network.received();
+            transmitted += network.transmitted();
+        }
+
+        HostSample {
+            timestamp: Utc::now(),
+            cpu_percent: self.system.global_cpu_usage(),
+            memory_used_bytes: self.system.used_memory(),
+            memory_total_bytes: self.system.total_memory(),
+            disk_read_bytes_per_sec: read as f64 / seconds,
+            disk_written_bytes_per_sec: written as f64 / seconds,
+            network_received_bytes_per_sec: received as f64 / seconds,
+            network_transmitted_bytes_per_sec: transmitted as f64 / seconds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_hour_of_samples() {
+        let mut sampler = Sampler::new();
+        let sample = sampler.sample();
+        assert!(sample.memory_total_bytes > 0 && sample.memory_used_bytes <= sample.memory_total_bytes);
+        assert!((0.0..=100.0).contains(&sample.cpu_percent));
+
+        let monitor = HostMonitor::new();
+        assert!(monitor.latest().is_none());
+        let start = sample.timestamp;
+        for i in 0..MAX_SAMPLES as i64 + 10 {
+            monitor.push(HostSample { timestamp: start + chrono::Duration::seconds(5 * i), ..sample.clone() });
+        }
+        let samples = monitor.since(None);
+        assert_eq!(samples.len(), MAX_SAMPLES);
+        assert_eq!(samples[0].timestamp, start + chrono::Duration::seconds(50));
+        assert_eq!(monitor.latest().unwrap().timestamp, start + chrono::Duration::seconds(5 * (MAX_SAMPLES as i64 + 9)));
+        assert_eq!(monitor.since(Some(start + chrono::Duration::seconds(5 * MAX_SAMPLES as i64))).len(), 10);
+    }
+}
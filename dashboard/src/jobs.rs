@@ -0,0 +1,316 @@
+//! Encryption jobs run on the dashboard host
+//!
+//! `POST /api/jobs/encrypt` names a file under the jobs directory (`--jobs-dir`)
+//! and a recipient in the host's keyring. The file is encrypted with
+//! `pitlink_pqc::encrypt_file` on a blocking thread, at most [`MAX_RUNNING`] jobs
+//! at a time, and the job is known by its id from then on. Jobs are kept in
+//! memory only and are forgotten on restart.
+//!
+//! `GET /api/jobs/{id}` follows a job through the library's progress callback,
+//! and `DELETE /api/jobs/{id}` cancels it: a queued job never starts, a running
+//! one stops after its current chunk and leaves no package behind.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use pitlink_pqc::keyfile::KeyKind;
+use pitlink_pqc::keyring::Keyring;
+use pitlink_pqc::progress::{ProgressCallback, ProgressUpdate};
+use pitlink_pqc::EncryptOptions;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+/// Jobs encrypting at the same time; the rest wait queued
+pub const MAX_RUNNING: usize = 2;
+
+/// Finished jobs remembered; the oldest are dropped beyond this
+const MAX_FINISHED: usize = 1000;
+
+/// Body of `POST /api/jobs/encrypt`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptRequest {
+    /// Plaintext file, relative to the jobs directory
+    pub input: PathBuf,
+    /// Package to write, relative to the jobs directory; `<input>.pqc` by default
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    /// Name or fingerprint of the recipient's public key in the keyring
+    pub recipient: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Done | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+/// An encryption job as the API shows it; paths are relative to the jobs directory
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub state: JobState,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub recipient: String,
+    /// Plaintext bytes encrypted so far
+    pub bytes_processed: u64,
+    /// Size of the input, once the job has started
+    pub total_bytes: Option<u64>,
+    /// Average since the job started, in Mbit/s
+    pub throughput_mbps: Option<f64>,
+    pub created: DateTime<Utc>,
+    pub started: Option<DateTime<Utc>>,
+    pub finished: Option<DateTime<Utc>>,
+    /// Why the job failed
+    pub error: Option<String>,
+    #[serde(skip)]
+    cancel: Arc<AtomicBool>,
+}
+
+/// What [`JobManager::cancel`] did
+pub enum Cancel {
+    /// The job was queued and will never run
+    Cancelled(Job),
+    /// The job is running and stops after its current chunk
+    Stopping(Job),
+    /// The job had already finished
+    Finished(Job),
+}
+
+/// Queues and runs encryption jobs
+pub struct JobManager {
+    root: PathBuf,
+    keyring: Keyring,
+    jobs: Arc<RwLock<HashMap<u64, Job>>>,
+    next_id: AtomicU64,
+    slots: Arc<Semaphore>,
+}
+
+impl JobManager {
+    /// Run jobs on files under `root` (created if missing), for recipients in `keyring`
+    pub fn new(root: impl AsRef<Path>, keyring: Keyring) -> Result<Self> {
+        let root = root.as_ref();
+        std::fs::create_dir_all(root).with_context(|| format!("cannot create the jobs directory {}", root.display()))?;
+        let root = root.canonicalize().with_context(|| format!("cannot resolve the jobs directory {}", root.display()))?;
+        Ok(Self {
+            root,
+            keyring,
+            jobs: Arc::new(RwLock::new(HashMap::new())),
+            next_id: AtomicU64::new(1),
+            slots: Arc::new(Semaphore::new(MAX_RUNNING)),
+        })
+    }
+
+    /// Check `request` and queue it; the job runs in the background
+    pub fn submit(&self, request: EncryptRequest) -> Result<Job> {
+        let output = match request.output {
+            Some(output) => output,
+            None => {
+                let mut name = request.input.clone().into_os_string();
+                name.push(".pqc");
+                PathBuf::from(name)
+            }
+        };
+        let (input_path, output_path) = (self.resolve(&request.input)?, self.resolve(&output)?);
+        if !input_path.is_file() {
+            bail!("no file {} in the jobs directory", request.input.display());
+        }
+        if output_path.exists() {
+            bail!("{} already exists in the jobs directory", output.display());
+        }
+        let pubkey = self.recipient_key(&request.recipient)?;
+
+        let job = Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            state: JobState::Queued,
+            input: request.input,
+            output,
+            recipient: request.recipient,
+            bytes_processed: 0,
+            total_bytes: None,
+            throughput_mbps: None,
+            created: Utc::now(),
+            started: None,
+            finished: None,
+            error: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        self.insert(job.clone());
+
+        let (jobs, slots, id, cancel) = (self.jobs.clone(), self.slots.clone(), job.id, job.cancel.clone());
+        actix_web::rt::spawn(async move {
+            // The semaphore is never closed
+            let _slot = slots.acquire_owned().await;
+            let total_bytes = std::fs::metadata(&input_path).ok().map(|metadata| metadata.len());
+            let started = update(&jobs, id, |job| {
+                if job.state == JobState::Cancelled {
+                    return false;
+                }
+                job.state = JobState::Running;
+                job.total_bytes = total_bytes;
+                job.started = Some(Utc::now());
+                true
+            });
+            if started != Some(true) {
+                return;
+            }
+
+            let progress_jobs = jobs.clone();
+            let progress: ProgressCallback = Arc::new(move |progress: ProgressUpdate| {
+                update(&progress_jobs, id, |job| {
+                    job.bytes_processed = progress.bytes_processed;
+                    job.throughput_mbps = throughput_mbps(progress.bytes_processed, progress.elapsed);
+                });
+            });
+            let opts = EncryptOptions { progress: Some(progress), cancel: Some(cancel.clone()), ..Default::default() };
+            let result = tokio::task::spawn_blocking(move || pitlink_pqc::encrypt_file(input_path, output_path, pubkey, opts)).await;
+            let result = result.map_err(anyhow::Error::from).and_then(|result| result);
+            update(&jobs, id, |job| {
+                job.state = match &result {
+                    Ok(()) => JobState::Done,
+                    Err(_) if cancel.load(Ordering::Relaxed) => JobState::Cancelled,
+                    Err(e) => {
+                        job.error = Some(format!("{:#}", e));
+                        JobState::Failed
+                    }
+                };
+                job.finished = Some(Utc::now());
+            });
+        });
+        Ok(job)
+    }
+
+    /// The job with this id, if it is still remembered
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.jobs.read().get(&id).cloned()
+    }
+
+    /// Cancel the job with this id; None when there is no such job
+    pub fn cancel(&self, id: u64) -> Option<Cancel> {
+        let mut jobs = self.jobs.write();
+        let job = jobs.get_mut(&id)?;
+        Some(match job.state {
+            JobState::Queued => {
+                job.state = JobState::Cancelled;
+                job.finished = Some(Utc::now());
+                Cancel::Cancelled(job.clone())
+            }
+            JobState::Running => {
+                job.cancel.store(true, Ordering::Relaxed);
+                Cancel::Stopping(job.clone())
+            }
+            _ => Cancel::Finished(job.clone()),
+        })
+    }
+
+    fn insert(&self, job: Job) {
+        let mut jobs = self.jobs.write();
+        jobs.insert(job.id, job);
+        let mut finished: Vec<u64> = jobs.values().filter(|job| job.state.is_finished()).map(|job| job.id).collect();
+        if finished.len() > MAX_FINISHED {
+            finished.sort_unstable();
+            for id in &finished[..finished.len() - MAX_FINISHED] {
+                jobs.remove(id);
+            }
+        }
+    }
+
+    /// `path` under the jobs directory; it must be relative and stay inside it,
+    /// also once symlinks are followed. A path that does not exist yet is
+    /// checked through its nearest existing ancestor.
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        if path.as_os_str().is_empty() || !path.components().all(|part| matches!(part, Component::Normal(_))) {
+            bail!("{} must be a relative path inside the jobs directory", path.display());
+        }
+        let joined = self.root.join(path);
+        let existing = joined.ancestors()
+            .find(|ancestor| std::fs::symlink_metadata(ancestor).is_ok())
+            .context("the jobs directory is gone")?;
+        let real = existing.canonicalize().with_context(|| format!("cannot resolve {}", path.display()))?;
+        if !real.starts_with(&self.root) {
+            bail!("{} leads outside the jobs directory", path.display());
+        }
+        Ok(joined)
+    }
+
+    /// Public key file of the recipient named, or with the fingerprint, `recipient`
+    pub fn recipient_key(&self, recipient: &str) -> Result<PathBuf> {
+        if let Ok(path) = self.keyring.resolve(recipient, KeyKind::Public) {
+            return Ok(path);
+        }
+        let entry = self.keyring.list()?
+            .into_iter()
+            .find(|entry| entry.fingerprint.eq_ignore_ascii_case(recipient))
+            .with_context(|| format!("no recipient key {:?} in the keyring", recipient))?;
+        self.keyring.resolve(&entry.name, KeyKind::Public)
+    }
+}
+
+/// Apply `change` to the job with this id, if it is still remembered
+fn update<T>(jobs: &RwLock<HashMap<u64, Job>>, id: u64, change: impl FnOnce(&mut Job) -> T) -> Option<T> {
+    jobs.write().get_mut(&id).map(change)
+}
+
+fn throughput_mbps(bytes: u64, elapsed: Duration) -> Option<f64> {
+    let seconds = elapsed.as_secs_f64();
+    (seconds > 0.0).then(|| bytes as f64 * 8.0 / 1_000_000.0 / seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_jobs_inside_their_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let manager = JobManager::new(dir.join("jobs"), Keyring::open(dir.join("keys")).unwrap()).unwrap();
+        let root = dir.join("jobs").canonicalize().unwrap();
+        assert_eq!(manager.resolve(Path::new("laps/run-3.csv")).unwrap(), root.join("laps/run-3.csv"));
+        for path in ["", "/etc/passwd", "../keys/car.key", "laps/../../x", "./run.csv"] {
+            assert!(manager.resolve(Path::new(path)).is_err(), "{} accepted", path);
+        }
+
+        std::fs::write(dir.join("jobs/run.csv"), b"lap,time").unwrap();
+        let request = |input: &str, recipient: &str| EncryptRequest { input: input.into(), output: None, recipient: recipient.to_string() };
+        let missing = manager.submit(request("run.csv", "nobody")).unwrap_err();
+        assert!(format!("{:#}", missing).contains("no recipient key"), "{:#}", missing);
+        assert!(manager.submit(request("lap.csv", "nobody")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_when_checking_paths() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let manager = JobManager::new(dir.join("jobs"), Keyring::open(dir.join("keys")).unwrap()).unwrap();
+        let root = dir.join("jobs").canonicalize().unwrap();
+        symlink(dir.join("keys"), root.join("keys")).unwrap();
+        symlink(dir.join("keys/car.key"), root.join("car.key")).unwrap();
+        std::fs::create_dir(root.join("laps")).unwrap();
+        symlink(root.join("laps"), root.join("latest")).unwrap();
+        for path in ["keys", "keys/car.key", "keys/new/run.pqc", "car.key"] {
+            assert!(manager.resolve(Path::new(path)).is_err(), "{} accepted", path);
+        }
+        assert_eq!(manager.resolve(Path::new("latest/run-4.csv")).unwrap(), root.join("latest/run-4.csv"));
+    }
+}
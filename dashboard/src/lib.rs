@@ -7,9 +7,23 @@
 //! - Compression statistics
 //! - System performance
 
+pub mod agents;
+pub mod alerts;
 pub mod api;
+pub mod auth;
+pub mod config;
+pub mod export;
+pub mod host;
+pub mod jobs;
 pub mod metrics;
+pub mod prometheus;
 pub mod server;
+pub mod shutdown;
+pub mod store;
+pub mod stream;
+pub mod summary;
+pub mod tls;
+pub mod upload;
 pub mod integration;
 pub mod control;
 
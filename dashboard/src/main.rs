@@ -6,32 +6,250 @@
 /// - Method selection (compression, integrity, routing)
 /// - Configuration management
 
-use actix_web::{web, App, HttpServer, HttpResponse, Result as ActixResult};
+use actix_web::{middleware, web, App, HttpServer, HttpResponse, Result as ActixResult};
 use actix_files::Files;
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use parking_lot::RwLock;
 use trackshift::*;
 use std::collections::HashMap;
+use std::path::PathBuf;
 
+mod agents;
+mod alerts;
 mod api;
+mod auth;
+mod config;
+mod export;
+mod host;
+mod jobs;
+mod metrics;
+mod prometheus;
+mod shutdown;
 mod state;
+mod store;
+mod stream;
+mod summary;
+mod tls;
+mod upload;
 
+use agents::Agents;
+use alerts::Alerts;
+use auth::ApiKeys;
+use config::{ServerConfig, Settings};
+use host::HostMonitor;
+use jobs::JobManager;
+use metrics::MetricsCollector;
 use state::DashboardState;
+use store::MetricsStore;
+
+#[derive(Parser)]
+#[command(about = "PitlinkPQC web dashboard")]
+struct Cli {
+    /// TOML config file with the same settings; flags and environment variables override it
+    #[arg(long, env = "DASHBOARD_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on [default: 0.0.0.0]
+    #[arg(long, env = "DASHBOARD_BIND")]
+    bind: Option<String>,
+    /// Port to listen on [default: 8080]
+    #[arg(long, env = "DASHBOARD_PORT")]
+    port: Option<u16>,
+    /// Directory the UI is served from [default: ./dashboard/static]
+    #[arg(long, env = "DASHBOARD_STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+    /// SQLite database for reports and API keys [default: dashboard-metrics.db]
+    #[arg(long, env = "DASHBOARD_DB")]
+    db_path: Option<PathBuf>,
+    /// Delete reports older than this, e.g. 30d or 12h [default: keep all]
+    #[arg(long, env = "DASHBOARD_RETENTION", value_name = "AGE")]
+    retention: Option<String>,
+    /// Directory encryption jobs read and write files in [default: dashboard-jobs]
+    #[arg(long, env = "DASHBOARD_JOBS_DIR")]
+    jobs_dir: Option<PathBuf>,
+    /// Largest file POST /api/encrypt accepts, e.g. 512M or 4G [default: 1G]
+    #[arg(long, env = "DASHBOARD_MAX_UPLOAD", value_name = "SIZE")]
+    max_upload: Option<String>,
+    /// Delete packages made from uploads after this long, e.g. 12h [default: 7d]
+    #[arg(long, env = "DASHBOARD_UPLOAD_RETENTION", value_name = "AGE")]
+    upload_retention: Option<String>,
+    /// Benchmark report served at /api/benchmarks [default: target/pitlink-bench.json]
+    #[arg(long, env = "DASHBOARD_BENCH_REPORT")]
+    bench_report: Option<PathBuf>,
+    /// PEM certificate chain; with --tls-key serves HTTPS
+    #[arg(long, env = "DASHBOARD_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "DASHBOARD_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// With TLS, also listen on this plain-HTTP port and redirect to HTTPS
+    #[arg(long, env = "DASHBOARD_HTTP_REDIRECT_PORT")]
+    http_redirect_port: Option<u16>,
+    /// Static API key, repeatable (besides the keys from add-key)
+    #[arg(long = "api-key", value_name = "KEY", env = "DASHBOARD_API_KEYS", value_delimiter = ',', hide_env_values = true)]
+    api_keys: Vec<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create an API key called NAME and print it (only the hash is stored)
+    AddKey { name: String },
+    /// Delete the API key called NAME
+    RemoveKey { name: String },
+}
+
+impl Cli {
+    /// The flags as settings, over those of the config file
+    fn settings(&self) -> anyhow::Result<Settings> {
+        let flags = Settings {
+            bind: self.bind.clone(),
+            port: self.port,
+            static_dir: self.static_dir.clone(),
+            db_path: self.db_path.clone(),
+            retention: self.retention.clone(),
+            jobs_dir: self.jobs_dir.clone(),
+            max_upload: self.max_upload.clone(),
+            upload_retention: self.upload_retention.clone(),
+            bench_report: self.bench_report.clone(),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            http_redirect_port: self.http_redirect_port,
+            api_keys: self.api_keys.clone(),
+        };
+        let file = self.config.as_deref().map(Settings::read).transpose()?.unwrap_or_default();
+        Ok(flags.or(file))
+    }
+}
+
+/// How often reports and uploads past their retention are deleted
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let to_io = |e: anyhow::Error| std::io::Error::other(format!("{:#}", e));
+    let config = cli.settings().and_then(ServerConfig::from_settings).map_err(to_io)?;
+    
+    // Operation reports and API keys persist across restarts
+    let store = Arc::new(MetricsStore::open(&config.db_path).map_err(to_io)?);
+    
+    match &cli.command {
+        Some(Command::AddKey { name }) => {
+            let key = auth::generate_key();
+            store.add_api_key(name, &auth::hash_key(&key)).map_err(to_io)?;
+            println!("{}", key);
+            return Ok(());
+        }
+        Some(Command::RemoveKey { name }) => {
+            if !store.remove_api_key(name).map_err(to_io)? {
+                return Err(std::io::Error::other(format!("no API key called {}", name)));
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    
     println!("🚀 Starting PitlinkPQC Dashboard...");
-    println!("   Access at: http://localhost:8080");
+    println!("   Access at: {}", config.url());
+    println!("   Metrics database: {}", config.db_path.display());
+    let metrics = Arc::new(MetricsCollector::with_store(1000, store.clone()).map_err(to_io)?);
+    let agents = web::Data::new(Agents::new(Some(store.clone())).map_err(to_io)?);
+    let alerts = web::Data::new(Alerts::new(Some(store.clone())).map_err(to_io)?);
+    let api_keys = web::Data::new(ApiKeys::new(config.api_keys.clone(), Some(store)));
+    if api_keys.is_empty().map_err(to_io)? {
+        println!("⚠️  No API keys configured: /api and /metrics will refuse every request.");
+        println!("   Create one with `dashboard add-key NAME` or pass --api-key.");
+    }
+    
+    // Delete reports past the retention now and every hour
+    if let Some(retention) = config.retention {
+        let metrics = metrics.clone();
+        let retention = chrono::Duration::from_std(retention).map_err(|e| std::io::Error::other(e.to_string()))?;
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                match metrics.prune(chrono::Utc::now() - retention) {
+                    Ok(0) => {}
+                    Ok(deleted) => println!("   Deleted {} reports past the retention", deleted),
+                    Err(e) => eprintln!("⚠️  Cannot delete old reports: {:#}", e),
+                }
+            }
+        });
+    }
+    
+    // Alerts are checked on a timer, off the async workers since that reads the database
+    {
+        let (alerts, metrics) = (alerts.clone(), metrics.clone());
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(alerts::EVALUATE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let (alerts, metrics) = (alerts.clone(), metrics.clone());
+                if let Err(e) = web::block(move || alerts.check(&metrics)).await {
+                    eprintln!("⚠️  Cannot evaluate alerts: {}", e);
+                }
+            }
+        });
+    }
+    
+    // Encryption jobs, for recipients in this host's keyring
+    let keyring = pitlink_pqc::keyring::Keyring::open_default().map_err(to_io)?;
+    let jobs = web::Data::new(JobManager::new(&config.jobs_dir, keyring).map_err(to_io)?);
+    let uploads = web::Data::new(upload::Uploads::new(config.jobs_dir.join("uploads"), config.max_upload, config.upload_retention));
+    println!("   Encryption jobs in: {}", config.jobs_dir.display());
+
+    // Delete packages made from uploads past their retention now and every hour
+    {
+        let uploads = uploads.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let uploads = uploads.clone();
+                match web::block(move || uploads.prune()).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(deleted)) => println!("   Deleted {} uploads past the retention", deleted),
+                    Ok(Err(e)) => eprintln!("⚠️  Cannot delete old uploads: {:#}", e),
+                    Err(e) => eprintln!("⚠️  Cannot delete old uploads: {}", e),
+                }
+            }
+        });
+    }
+    
+    // Resource use of this host, sampled next to the reported operations
+    let host = web::Data::new(HostMonitor::new());
+    let bench_report = web::Data::new(api::BenchReport(config.bench_report.clone()));
+    host.clone().into_inner().start();
     
     // Initialize dashboard state
-    let state = Arc::new(DashboardState::new());
+    let state = Arc::new(DashboardState::with_metrics(metrics.clone()));
+    let static_dir = config.static_dir.clone();
     
-    // Start HTTP server
-    HttpServer::new(move || {
+    // Start HTTP(S) server
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(state.clone()))
+            .app_data(api_keys.clone())
+            .app_data(agents.clone())
+            .app_data(alerts.clone())
+            .app_data(jobs.clone())
+            .app_data(uploads.clone())
+            .app_data(host.clone())
+            .app_data(bench_report.clone())
+            .wrap(middleware::from_fn(auth::require_key))
+            .service(web::resource("/health").route(web::get().to(api::liveness)))
+            .service(web::resource("/metrics").route(web::get().to(api::prometheus_metrics)))
             .service(web::resource("/api/status").route(web::get().to(api::status)))
+            .service(web::resource("/api/metrics").app_data(api::report_json_config()).route(web::post().to(api::metrics_ingest)))
+            .service(web::resource("/api/metrics/history").route(web::get().to(api::metrics_history)))
+            .service(web::resource("/api/metrics/summary").route(web::get().to(api::metrics_summary)))
+            .service(web::resource("/api/metrics/stream").route(web::get().to(stream::metrics_stream)))
+            .service(web::resource("/api/metrics/export").route(web::get().to(export::metrics_export)))
+            .service(web::resource("/api/metrics/host").route(web::get().to(api::metrics_host)))
             .service(web::resource("/api/metrics/current").route(web::get().to(api::metrics_current)))
             .service(web::resource("/api/transfers").route(web::get().to(api::transfers)))
             .service(web::resource("/api/network").route(web::get().to(api::network)))
@@ -40,9 +258,62 @@ async fn main() -> std::io::Result<()> {
             .service(web::resource("/api/control").route(web::post().to(api::control)))
             .service(web::resource("/api/methods").route(web::get().to(api::methods)))
             .service(web::resource("/api/stats").route(web::get().to(api::stats)))
-            .service(Files::new("/", "./dashboard/static").index_file("index.html"))
-    })
-    .bind("0.0.0.0:8080")?
-    .run()
-    .await
+            .service(web::resource("/api/benchmarks").route(web::get().to(api::benchmarks)))
+            .service(web::resource("/api/agents").route(web::get().to(api::agents_list)))
+            .service(web::resource("/api/agents/register").route(web::post().to(api::agents_register)))
+            .service(web::resource("/api/alerts").route(web::get().to(api::alerts_list)).route(web::post().to(api::alerts_create)))
+            .service(web::resource("/api/alerts/{id}")
+                .route(web::get().to(api::alert_get))
+                .route(web::put().to(api::alert_update))
+                .route(web::delete().to(api::alert_delete)))
+            .service(web::resource("/api/jobs/encrypt").route(web::post().to(api::jobs_encrypt)))
+            .service(web::resource("/api/encrypt").route(web::post().to(upload::encrypt)))
+            .service(web::resource("/api/uploads/{id}/{name}").route(web::get().to(upload::download)))
+            .service(web::resource("/api/jobs/{id}").route(web::get().to(api::job_status)).route(web::delete().to(api::job_cancel)))
+            .service(Files::new("/", &static_dir).index_file("index.html"))
+    });
+    let address = (config.bind.as_str(), config.port);
+    let server = match &config.tls {
+        Some(tls) => server.bind_rustls_0_23(address, tls::server_config(tls).map_err(to_io)?)?,
+        None => server.bind(address)?,
+    };
+    
+    let server = server.shutdown_timeout(shutdown::DRAIN_TIMEOUT.as_secs()).disable_signals().run();
+    let mut handles = vec![server.handle()];
+    
+    // Plain HTTP only sends clients over to HTTPS
+    let redirect = match config.redirect_http_port {
+        Some(redirect_port) => {
+            println!("   Redirecting http://localhost:{} to HTTPS", redirect_port);
+            let https_port = config.port;
+            let redirect = HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::new(https_port))
+                    .default_service(web::to(tls::redirect))
+            })
+            .bind((config.bind.as_str(), redirect_port))?
+            .disable_signals()
+            .run();
+            handles.push(redirect.handle());
+            Some(redirect)
+        }
+        None => None,
+    };
+    
+    // On SIGINT/SIGTERM stop accepting and give in-flight requests time to finish
+    actix_web::rt::spawn(async move {
+        shutdown::signal().await;
+        println!("🛑 Shutting down, draining in-flight requests (up to {}s)...", shutdown::DRAIN_TIMEOUT.as_secs());
+        futures::future::join_all(handles.iter().map(|handle| handle.stop(true))).await;
+    });
+    match redirect {
+        Some(redirect) => {
+            tokio::try_join!(server, redirect)?;
+        }
+        None => server.await?,
+    }
+    
+    metrics.flush().map_err(to_io)?;
+    println!("   Metrics flushed to {}", config.db_path.display());
+    Ok(())
 }
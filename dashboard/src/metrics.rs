@@ -4,7 +4,186 @@ use serde::{Serialize, Deserialize};
 use std::sync::Arc;
 use parking_lot::RwLock;
 use chrono::{DateTime, Utc};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::broadcast;
+use crate::store::{HistoryCursor, MetricsStore, Order};
+
+/// Payload version `POST /api/metrics` accepts
+pub const REPORT_VERSION: u32 = 1;
+
+/// One operation reported by a tool (`rust_pqc --metrics-url`), version 1:
+///
+/// ```json
+/// {"version":1,"node_id":"car-12","operation":"encrypt","ok":true,
+///  "bytes":1048576,"duration_ms":12,"algorithm":"MlKem768/XChaCha20Poly1305",
+///  "timestamp":"2026-...Z"}
+/// ```
+///
+/// Fields the dashboard does not use (such as the full `result`) are ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationReport {
+    pub version: u32,
+    /// Reporting machine, 1-64 of `A-Z a-z 0-9 . _ - :`
+    pub node_id: String,
+    /// Command that ran, e.g. "encrypt" or "benchmark-kem"
+    pub operation: String,
+    #[serde(default = "default_ok")]
+    pub ok: bool,
+    /// Plaintext bytes processed
+    #[serde(default)]
+    pub bytes: u64,
+    pub duration_ms: u64,
+    /// KEM and AEAD, e.g. "MlKem768/XChaCha20Poly1305"
+    #[serde(default)]
+    pub algorithm: Option<String>,
+    /// When the operation finished; the time of receipt when absent
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// An ingested report with its event id, for `GET /api/metrics/stream`. With a
+/// store the id is the report's row, so ids keep growing across restarts.
+#[derive(Debug, Clone)]
+pub struct ReportEvent {
+    pub id: u64,
+    pub report: OperationReport,
+}
+
+/// Reports a subscriber may fall behind by before it has to catch up from the store
+pub const EVENT_BUFFER: usize = 1024;
+
+/// Reports read from the store at a time when scanning the history
+const SCAN_PAGE: usize = 1000;
+
+/// Upper bounds, in milliseconds, of the operation duration histogram buckets
+pub const DURATION_BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1_000, 5_000, 30_000];
+
+/// Running totals for one operation since the dashboard started; unlike the
+/// history these are never trimmed
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OperationTotals {
+    pub succeeded: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    /// Operations per duration bucket (not cumulative); the last slot counts
+    /// those slower than every bound
+    pub duration_buckets: [u64; DURATION_BUCKETS_MS.len() + 1],
+    pub duration_sum_ms: u64,
+}
+
+impl OperationTotals {
+    pub(crate) fn record(&mut self, ok: bool, bytes: u64, duration_ms: u64) {
+        if ok {
+            self.succeeded += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.bytes += bytes;
+        let bucket = DURATION_BUCKETS_MS.iter().position(|&bound| duration_ms <= bound).unwrap_or(DURATION_BUCKETS_MS.len());
+        self.duration_buckets[bucket] += 1;
+        self.duration_sum_ms += duration_ms;
+    }
+}
+
+/// Which reports a history query selects
+#[derive(Debug, Clone, Default)]
+pub struct OperationFilter {
+    /// Only reports at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Only reports at or before this time
+    pub to: Option<DateTime<Utc>>,
+    /// Only these operations; all of them when empty
+    pub operations: Vec<String>,
+    /// Only reports from these nodes; all of them when empty
+    pub nodes: Vec<String>,
+}
+
+impl OperationFilter {
+    pub fn matches(&self, report: &OperationReport) -> bool {
+        report.timestamp >= self.from
+            && (self.to.is_none() || report.timestamp <= self.to)
+            && (self.operations.is_empty() || self.operations.contains(&report.operation))
+            && (self.nodes.is_empty() || self.nodes.contains(&report.node_id))
+    }
+}
+
+/// Counts and averages over the reports a filter selects
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OperationAggregate {
+    pub count: u64,
+    pub failed: u64,
+    /// Over the reports that took measurable time; None without any
+    pub avg_throughput_mbps: Option<f64>,
+    pub avg_duration_ms: Option<f64>,
+}
+
+impl OperationAggregate {
+    fn of<'a>(reports: impl Iterator<Item = &'a OperationReport>) -> Self {
+        let (mut count, mut failed, mut timed, mut throughput, mut duration) = (0u64, 0u64, 0u64, 0.0, 0.0);
+        for report in reports {
+            count += 1;
+            failed += u64::from(!report.ok);
+            duration += report.duration_ms as f64;
+            if let Some(mbps) = report.throughput_mbps() {
+                timed += 1;
+                throughput += mbps;
+            }
+        }
+        Self {
+            count,
+            failed,
+            avg_throughput_mbps: (timed > 0).then(|| throughput / timed as f64),
+            avg_duration_ms: (count > 0).then(|| duration / count as f64),
+        }
+    }
+}
+
+fn default_ok() -> bool {
+    true
+}
+
+/// Check a node id is 1-64 of `A-Z a-z 0-9 . _ - :`
+pub fn validate_node_id(node_id: &str) -> Result<(), String> {
+    let id_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | ':');
+    if node_id.is_empty() || node_id.len() > 64 || !node_id.chars().all(id_char) {
+        return Err("node_id must be 1-64 of A-Z a-z 0-9 . _ - :".to_string());
+    }
+    Ok(())
+}
+
+impl OperationReport {
+    /// Check the report is one this dashboard can store
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version != REPORT_VERSION {
+            return Err(format!("unsupported payload version {} (this dashboard accepts {})", self.version, REPORT_VERSION));
+        }
+        validate_node_id(&self.node_id)?;
+        let op_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | ' ');
+        if self.operation.is_empty() || self.operation.len() > 32 || !self.operation.chars().all(op_char) {
+            return Err("operation must be 1-32 of a-z 0-9 - _ and spaces".to_string());
+        }
+        if let Some(algorithm) = &self.algorithm {
+            if algorithm.len() > 64 || !algorithm.chars().all(|c| c.is_ascii_graphic()) {
+                return Err("algorithm must be at most 64 printable characters".to_string());
+            }
+        }
+        if self.duration_ms > 86_400_000 {
+            return Err("duration_ms is more than a day".to_string());
+        }
+        if let Some(timestamp) = self.timestamp {
+            if timestamp > Utc::now() + chrono::Duration::minutes(5) {
+                return Err("timestamp is in the future".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Throughput in Mbit/s, when the operation took measurable time
+    pub fn throughput_mbps(&self) -> Option<f64> {
+        (self.duration_ms > 0).then(|| self.bytes as f64 * 8.0 / 1000.0 / self.duration_ms as f64)
+    }
+}
 
 /// System-wide metrics
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -167,6 +346,12 @@ impl Default for SystemMetrics {
 pub struct MetricsCollector {
     metrics: Arc<RwLock<SystemMetrics>>,
     history: Arc<RwLock<VecDeque<SystemMetrics>>>,
+    operations: Arc<RwLock<VecDeque<OperationReport>>>,
+    totals: Arc<RwLock<BTreeMap<String, OperationTotals>>>,
+    store: Option<Arc<MetricsStore>>,
+    events: broadcast::Sender<ReportEvent>,
+    /// Event ids when there is no store
+    next_event_id: AtomicU64,
     max_history: usize,
     start_time: DateTime<Utc>,
 }
@@ -177,11 +362,43 @@ impl MetricsCollector {
         Self {
             metrics: Arc::new(RwLock::new(SystemMetrics::default())),
             history: Arc::new(RwLock::new(VecDeque::with_capacity(max_history))),
+            operations: Arc::new(RwLock::new(VecDeque::with_capacity(max_history))),
+            totals: Arc::new(RwLock::new(BTreeMap::new())),
+            store: None,
+            events: broadcast::channel(EVENT_BUFFER).0,
+            next_event_id: AtomicU64::new(1),
             max_history,
             start_time: Utc::now(),
         }
     }
 
+    /// Create a collector that persists reports to `store`, with the newest
+    /// `max_history` of them and the totals of all of them loaded from it
+    pub fn with_store(max_history: usize, store: Arc<MetricsStore>) -> anyhow::Result<Self> {
+        let mut collector = Self::new(max_history);
+        let totals = store.totals()?;
+        let mut metrics = collector.metrics.write();
+        let performance = &mut metrics.performance;
+        for (operation, t) in &totals {
+            let count = t.succeeded + t.failed;
+            if operation == "decrypt" {
+                performance.total_bytes_received += t.bytes;
+            } else {
+                performance.total_bytes_sent += t.bytes;
+            }
+            performance.chunks_processed += count;
+            performance.avg_processing_time_ms += t.duration_sum_ms as f32;
+        }
+        if performance.chunks_processed > 0 {
+            performance.avg_processing_time_ms /= performance.chunks_processed as f32;
+        }
+        drop(metrics);
+        *collector.totals.write() = totals;
+        collector.operations.write().extend(store.history(&OperationFilter::default(), max_history)?.into_iter().rev());
+        collector.store = Some(store);
+        Ok(collector)
+    }
+
     /// Update current metrics
     pub fn update(&self, metrics: SystemMetrics) {
         let mut current = self.metrics.write();
@@ -197,6 +414,148 @@ impl MetricsCollector {
         }
     }
 
+    /// Store a validated operation report and fold it into the performance metrics:
+    /// every operation counts as processed, decrypts add to the bytes received and
+    /// everything else to the bytes sent, and the report goes out to the
+    /// subscribers. Nothing changes when it cannot be persisted.
+    pub fn ingest(&self, mut report: OperationReport) -> anyhow::Result<()> {
+        let timestamp = *report.timestamp.get_or_insert_with(Utc::now);
+        let id = match &self.store {
+            Some(store) => store.insert(&report)?,
+            None => self.next_event_id.fetch_add(1, Ordering::Relaxed),
+        };
+        let mut metrics = self.metrics.read().clone();
+        let performance = &mut metrics.performance;
+        let count = performance.chunks_processed as f32;
+        performance.avg_processing_time_ms = (performance.avg_processing_time_ms * count + report.duration_ms as f32) / (count + 1.0);
+        performance.chunks_processed += 1;
+        if report.operation == "decrypt" {
+            performance.total_bytes_received += report.bytes;
+        } else {
+            performance.total_bytes_sent += report.bytes;
+        }
+        metrics.timestamp = timestamp;
+        self.update(metrics);
+        self.totals.write().entry(report.operation.clone()).or_default().record(report.ok, report.bytes, report.duration_ms);
+        // Nobody may be subscribed
+        let _ = self.events.send(ReportEvent { id, report: report.clone() });
+
+        let mut operations = self.operations.write();
+        operations.push_back(report);
+        while operations.len() > self.max_history {
+            operations.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Forget the reports older than `before`, in memory and in the store;
+    /// returns how many were deleted from the store. The running totals keep
+    /// counting them until the next restart.
+    pub fn prune(&self, before: DateTime<Utc>) -> anyhow::Result<usize> {
+        self.operations.write().retain(|report| report.timestamp >= Some(before));
+        match &self.store {
+            Some(store) => store.prune(before),
+            None => Ok(0),
+        }
+    }
+
+    /// Make sure every ingested report is durably on disk; for shutdown
+    pub fn flush(&self) -> anyhow::Result<()> {
+        match &self.store {
+            Some(store) => store.checkpoint(),
+            None => Ok(()),
+        }
+    }
+
+    /// Get reported operations held in memory, newest first
+    pub fn get_operations(&self, limit: Option<usize>) -> Vec<OperationReport> {
+        let operations = self.operations.read();
+        let limit = limit.unwrap_or(operations.len());
+        operations.iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Get the newest `limit` reported operations that `filter` selects, newest
+    /// first by timestamp: from memory while it holds every report, else from
+    /// the store
+    pub fn operation_history(&self, filter: &OperationFilter, limit: usize) -> anyhow::Result<Vec<OperationReport>> {
+        let operations = self.operations.read();
+        // Reports carry their own timestamps and arrive in any order, so once
+        // memory has been trimmed it cannot tell which ones a range or a limit
+        // selects
+        if let Some(store) = self.store.as_ref().filter(|_| operations.len() >= self.max_history) {
+            drop(operations);
+            return store.history(filter, limit);
+        }
+        let mut found: Vec<_> = operations.iter()
+            .rev()
+            .filter(|report| filter.matches(report))
+            .cloned()
+            .collect();
+        // Stable, so equal timestamps stay latest arrival first, as in the store
+        found.sort_by_key(|report| std::cmp::Reverse(report.timestamp));
+        found.truncate(limit);
+        Ok(found)
+    }
+
+    /// Get a page of the reported operations that `filter` selects, in `order`,
+    /// starting after `cursor`, and the cursor of the next page. Pages always come
+    /// from the store, whose rows give them a stable order.
+    pub fn operation_page(&self, filter: &OperationFilter, cursor: Option<HistoryCursor>, page_size: usize, order: Order) -> anyhow::Result<(Vec<OperationReport>, Option<HistoryCursor>)> {
+        match &self.store {
+            Some(store) => store.page(filter, cursor, page_size, order),
+            None => anyhow::bail!("paging through the history needs the metrics database"),
+        }
+    }
+
+    /// Call `f` with every reported operation that `filter` selects, oldest first:
+    /// a page at a time from the store when there is one, so they are never all
+    /// in memory at once
+    pub fn for_each_operation(&self, filter: &OperationFilter, mut f: impl FnMut(&OperationReport)) -> anyhow::Result<()> {
+        let Some(store) = &self.store else {
+            self.operations.read().iter().filter(|report| filter.matches(report)).for_each(f);
+            return Ok(());
+        };
+        let mut cursor = None;
+        loop {
+            let (reports, next) = store.page(filter, cursor, SCAN_PAGE, Order::OldestFirst)?;
+            reports.iter().for_each(&mut f);
+            match next {
+                Some(next) => cursor = Some(next),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Counts and averages over the reported operations that `filter` selects:
+    /// from the store when there is one, without loading the reports
+    pub fn operation_aggregate(&self, filter: &OperationFilter) -> anyhow::Result<OperationAggregate> {
+        match &self.store {
+            Some(store) => store.aggregate(filter),
+            None => Ok(OperationAggregate::of(self.operations.read().iter().filter(|report| filter.matches(report)))),
+        }
+    }
+
+    /// Receive every report ingested from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ReportEvent> {
+        self.events.subscribe()
+    }
+
+    /// Up to `limit` reports ingested after the event `id`, oldest first; none
+    /// without a store to find them in
+    pub fn events_after(&self, id: u64, limit: usize) -> anyhow::Result<Vec<ReportEvent>> {
+        let Some(store) = &self.store else { return Ok(Vec::new()) };
+        Ok(store.inserted_after(id, limit)?.into_iter().map(|(id, report)| ReportEvent { id, report }).collect())
+    }
+
+    /// Get the running totals per operation
+    pub fn get_totals(&self) -> BTreeMap<String, OperationTotals> {
+        self.totals.read().clone()
+    }
+
     /// Get current metrics
     pub fn get_current(&self) -> SystemMetrics {
         let mut metrics = self.metrics.read().clone();
@@ -233,3 +592,108 @@ impl Default for MetricsCollector {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(json: serde_json::Value) -> OperationReport {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn ingests_valid_reports_only() {
+        let good = serde_json::json!({
+            "version": 1, "node_id": "car-12", "operation": "encrypt", "bytes": 4_000_000u64,
+            "duration_ms": 20, "algorithm": "MlKem768/XChaCha20Poly1305", "result": {"chunks": 4},
+        });
+        let ok = report(good.clone());
+        assert_eq!(ok.validate(), Ok(()));
+        assert_eq!(ok.throughput_mbps(), Some(1600.0));
+
+        for (field, value) in [("version", serde_json::json!(2)), ("node_id", "".into()), ("node_id", "car 12".into()), ("operation", "Encrypt".into()), ("duration_ms", serde_json::json!(90_000_000u64))] {
+            let mut bad = good.clone();
+            bad[field] = value;
+            assert!(report(bad).validate().is_err(), "{} accepted", field);
+        }
+
+        let collector = MetricsCollector::new(2);
+        for _ in 0..3 {
+            collector.ingest(ok.clone()).unwrap();
+        }
+        collector.ingest(report(serde_json::json!({"version": 1, "node_id": "base", "operation": "decrypt", "bytes": 10, "duration_ms": 50}))).unwrap();
+        let current = collector.get_current();
+        assert_eq!(current.performance.chunks_processed, 4);
+        assert_eq!((current.performance.total_bytes_sent, current.performance.total_bytes_received), (12_000_000, 10));
+        assert_eq!(current.performance.avg_processing_time_ms, 27.5);
+        let operations = collector.get_operations(None);
+        assert_eq!(operations.len(), 2);
+        assert!(operations[0].timestamp.is_some() && operations[0].operation == "decrypt");
+        let totals = collector.get_totals();
+        assert_eq!((totals["encrypt"].succeeded, totals["encrypt"].bytes, totals["encrypt"].duration_buckets[2]), (3, 12_000_000, 3));
+        assert_eq!(totals["decrypt"].duration_buckets[3], 1);
+    }
+
+    #[test]
+    fn restores_history_and_totals_from_the_store() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let path = dir.join("metrics.db");
+        let collector = MetricsCollector::with_store(2, Arc::new(MetricsStore::open(&path).unwrap())).unwrap();
+        for (i, operation) in ["encrypt", "encrypt", "decrypt"].into_iter().enumerate() {
+            let timestamp = Utc::now() - chrono::Duration::seconds(10 - i as i64);
+            collector.ingest(report(serde_json::json!({"version": 1, "node_id": "car-12", "operation": operation, "bytes": 100, "duration_ms": 10, "timestamp": timestamp}))).unwrap();
+        }
+        drop(collector);
+
+        let collector = MetricsCollector::with_store(2, Arc::new(MetricsStore::open(&path).unwrap())).unwrap();
+        assert_eq!(collector.get_operations(None).len(), 2);
+        let history = collector.operation_history(&OperationFilter::default(), 10).unwrap();
+        let operations: Vec<_> = history.iter().map(|r| r.operation.as_str()).collect();
+        assert_eq!(operations, ["decrypt", "encrypt", "encrypt"]);
+        assert_eq!(history[2].node_id, "car-12");
+        assert_eq!(collector.get_totals()["encrypt"].succeeded, 2);
+        let performance = collector.get_current().performance;
+        assert_eq!((performance.chunks_processed, performance.total_bytes_sent, performance.total_bytes_received), (3, 200, 100));
+    }
+
+    #[test]
+    fn filters_history_by_time_operation_and_node() {
+        let collector = MetricsCollector::with_store(2, Arc::new(MetricsStore::in_memory().unwrap())).unwrap();
+        let start = Utc::now() - chrono::Duration::minutes(10);
+        for (minute, operation) in ["encrypt", "decrypt", "encrypt", "encrypt", "decrypt"].into_iter().enumerate() {
+            let timestamp = start + chrono::Duration::minutes(minute as i64);
+            collector.ingest(report(serde_json::json!({"version": 1, "node_id": "car-12", "operation": operation, "bytes": minute, "duration_ms": 10, "timestamp": timestamp}))).unwrap();
+        }
+        let bytes = |filter: OperationFilter, limit| -> Vec<u64> {
+            collector.operation_history(&filter, limit).unwrap().iter().map(|r| r.bytes).collect()
+        };
+        let encrypts = || vec!["encrypt".to_string()];
+        // Only the last two are still in memory; the rest come from the store
+        assert_eq!(bytes(OperationFilter { operations: encrypts(), ..Default::default() }, 10), [3, 2, 0]);
+        assert_eq!(bytes(OperationFilter { operations: encrypts(), ..Default::default() }, 1), [3]);
+        assert_eq!(bytes(OperationFilter { from: Some(start + chrono::Duration::minutes(1)), to: Some(start + chrono::Duration::minutes(3)), ..Default::default() }, 10), [3, 2, 1]);
+        assert_eq!(bytes(OperationFilter { from: Some(start + chrono::Duration::seconds(210)), ..Default::default() }, 10), [4]);
+        assert_eq!(bytes(OperationFilter { to: Some(start), operations: vec!["decrypt".to_string()], ..Default::default() }, 10), Vec::<u64>::new());
+        collector.ingest(report(serde_json::json!({"version": 1, "node_id": "pit-2", "operation": "encrypt", "bytes": 5, "duration_ms": 10}))).unwrap();
+        let pit = |node: &str| OperationFilter { nodes: vec![node.to_string()], ..Default::default() };
+        assert_eq!(bytes(pit("pit-2"), 10), [5]);
+        assert_eq!(bytes(pit("car-12"), 10), [4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn orders_history_by_timestamp_not_arrival() {
+        let collector = MetricsCollector::new(10);
+        let start = Utc::now() - chrono::Duration::minutes(10);
+        // A node that was offline reports its older operations last
+        for minute in [5, 6, 1, 2] {
+            let timestamp = start + chrono::Duration::minutes(minute);
+            collector.ingest(report(serde_json::json!({"version": 1, "node_id": "car-12", "operation": "encrypt", "bytes": minute, "duration_ms": 10, "timestamp": timestamp}))).unwrap();
+        }
+        let bytes = |filter: OperationFilter, limit| -> Vec<u64> {
+            collector.operation_history(&filter, limit).unwrap().iter().map(|r| r.bytes).collect()
+        };
+        assert_eq!(bytes(OperationFilter::default(), 10), [6, 5, 2, 1]);
+        assert_eq!(bytes(OperationFilter::default(), 1), [6]);
+        assert_eq!(bytes(OperationFilter { to: Some(start + chrono::Duration::minutes(5)), ..Default::default() }, 2), [5, 2]);
+    }
+}
@@ -0,0 +1,107 @@
+//! Prometheus text exposition of the collected metrics
+//!
+//! Served at `GET /metrics` for scraping:
+//!
+//! - `pitlink_operations_total{operation,result}` counter, result "ok" or "error"
+//! - `pitlink_bytes_total{operation}` counter of plaintext bytes
+//! - `pitlink_operation_duration_seconds{operation}` histogram
+//! - `pitlink_uptime_seconds` gauge
+//! - `pitlink_host_*` gauges of the dashboard host's resource use, once sampled
+
+use std::fmt::Write;
+
+use crate::host::HostSample;
+use crate::metrics::{MetricsCollector, DURATION_BUCKETS_MS};
+
+/// Content type of the exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Render everything the collector knows, and the latest host sample, in the
+/// text exposition format
+pub fn render(collector: &MetricsCollector, host: Option<&HostSample>) -> String {
+    let totals = collector.get_totals();
+    let mut out = String::new();
+
+    out.push_str("# HELP pitlink_operations_total Operations reported by the tools.\n");
+    out.push_str("# TYPE pitlink_operations_total counter\n");
+    for (operation, t) in &totals {
+        let _ = writeln!(out, "pitlink_operations_total{{operation=\"{}\",result=\"ok\"}} {}", label(operation), t.succeeded);
+        let _ = writeln!(out, "pitlink_operations_total{{operation=\"{}\",result=\"error\"}} {}", label(operation), t.failed);
+    }
+
+    out.push_str("# HELP pitlink_bytes_total Plaintext bytes processed by reported operations.\n");
+    out.push_str("# TYPE pitlink_bytes_total counter\n");
+    for (operation, t) in &totals {
+        let _ = writeln!(out, "pitlink_bytes_total{{operation=\"{}\"}} {}", label(operation), t.bytes);
+    }
+
+    out.push_str("# HELP pitlink_operation_duration_seconds Duration of reported operations.\n");
+    out.push_str("# TYPE pitlink_operation_duration_seconds histogram\n");
+    for (operation, t) in &totals {
+        let operation = label(operation);
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS_MS.iter().zip(&t.duration_buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "pitlink_operation_duration_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}", operation, *bound as f64 / 1000.0, cumulative);
+        }
+        let count = t.succeeded + t.failed;
+        let _ = writeln!(out, "pitlink_operation_duration_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}", operation, count);
+        let _ = writeln!(out, "pitlink_operation_duration_seconds_sum{{operation=\"{}\"}} {}", operation, t.duration_sum_ms as f64 / 1000.0);
+        let _ = writeln!(out, "pitlink_operation_duration_seconds_count{{operation=\"{}\"}} {}", operation, count);
+    }
+
+    out.push_str("# HELP pitlink_uptime_seconds Seconds since the dashboard started.\n");
+    out.push_str("# TYPE pitlink_uptime_seconds gauge\n");
+    let _ = writeln!(out, "pitlink_uptime_seconds {}", collector.get_current().performance.uptime_seconds);
+
+    if let Some(host) = host {
+        for (name, help, value) in [
+            ("cpu_usage_percent", "CPU use of the dashboard host, 0 to 100.", host.cpu_percent as f64),
+            ("memory_used_bytes", "Memory in use on the dashboard host.", host.memory_used_bytes as f64),
+            ("memory_total_bytes", "Memory of the dashboard host.", host.memory_total_bytes as f64),
+            ("disk_read_bytes_per_second", "Disk reads on the dashboard host.", host.disk_read_bytes_per_sec),
+            ("disk_written_bytes_per_second", "Disk writes on the dashboard host.", host.disk_written_bytes_per_sec),
+            ("network_received_bytes_per_second", "Network bytes received by the dashboard host.", host.network_received_bytes_per_sec),
+            ("network_transmitted_bytes_per_second", "Network bytes sent by the dashboard host.", host.network_transmitted_bytes_per_sec),
+        ] {
+            let _ = writeln!(out, "# HELP pitlink_host_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE pitlink_host_{} gauge", name);
+            let _ = writeln!(out, "pitlink_host_{} {}", name, value);
+        }
+    }
+    out
+}
+
+/// Escape a label value; ingested operation names never need it, but the format requires it
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::OperationReport;
+
+    #[test]
+    fn renders_counters_and_histograms() {
+        let collector = MetricsCollector::new(10);
+        for (ok, duration_ms) in [(true, 3), (true, 40), (false, 60_000)] {
+            let report = serde_json::json!({"version": 1, "node_id": "car-12", "operation": "encrypt", "ok": ok, "bytes": 1000, "duration_ms": duration_ms});
+            collector.ingest(serde_json::from_value::<OperationReport>(report).unwrap()).unwrap();
+        }
+        let text = render(&collector, None);
+        for line in [
+            "pitlink_operations_total{operation=\"encrypt\",result=\"ok\"} 2",
+            "pitlink_operations_total{operation=\"encrypt\",result=\"error\"} 1",
+            "pitlink_bytes_total{operation=\"encrypt\"} 3000",
+            "pitlink_operation_duration_seconds_bucket{operation=\"encrypt\",le=\"0.005\"} 1",
+            "pitlink_operation_duration_seconds_bucket{operation=\"encrypt\",le=\"0.05\"} 2",
+            "pitlink_operation_duration_seconds_bucket{operation=\"encrypt\",le=\"30\"} 2",
+            "pitlink_operation_duration_seconds_bucket{operation=\"encrypt\",le=\"+Inf\"} 3",
+            "pitlink_operation_duration_seconds_sum{operation=\"encrypt\"} 60.043",
+            "pitlink_operation_duration_seconds_count{operation=\"encrypt\"} 3",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {}", line);
+        }
+    }
+}
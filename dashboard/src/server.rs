@@ -11,6 +11,7 @@ use anyhow::Result;
 use crate::api::create_api_router;
 use crate::metrics::MetricsCollector;
 use crate::control::DashboardController;
+use crate::shutdown;
 use quic_fec::FallbackManager;
 
 /// Dashboard server
@@ -50,7 +51,7 @@ impl DashboardServer {
         self.controller.clone()
     }
 
-    /// Start the dashboard server
+    /// Start the dashboard server; returns after a graceful shutdown
     pub async fn start(&self) -> Result<()> {
         let app = self.create_app();
         
@@ -60,8 +61,26 @@ impl DashboardServer {
         println!("🚀 Dashboard server starting on http://{}", addr);
         println!("📊 Open http://localhost:{} in your browser", self.port);
         
-        axum::serve(listener, app).await?;
+        // On SIGINT/SIGTERM stop accepting, then wait for in-flight requests,
+        // but no longer than the drain timeout
+        let stopping = Arc::new(tokio::sync::Notify::new());
+        let notify = stopping.clone();
+        let serve = axum::serve(listener, app).with_graceful_shutdown(async move {
+            shutdown::signal().await;
+            println!("🛑 Shutting down, draining in-flight requests...");
+            notify.notify_one();
+        });
+        tokio::select! {
+            result = serve => result?,
+            _ = async {
+                stopping.notified().await;
+                tokio::time::sleep(shutdown::DRAIN_TIMEOUT).await;
+            } => {
+                println!("⚠️  Requests still in flight after {}s; stopping anyway", shutdown::DRAIN_TIMEOUT.as_secs());
+            }
+        }
         
+        self.collector.flush()?;
         Ok(())
     }
 
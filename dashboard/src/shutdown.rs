@@ -0,0 +1,34 @@
+//! Graceful shutdown on SIGINT or SIGTERM
+//!
+//! The server stops accepting connections when a signal arrives, lets requests
+//! already in flight finish for up to [`DRAIN_TIMEOUT`] and then flushes the
+//! metrics database, so a report is never cut off half-written.
+
+use std::time::Duration;
+
+/// Longest in-flight requests get to finish once shutdown starts
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Resolve on the first SIGINT (Ctrl-C) or SIGTERM
+pub async fn signal() {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
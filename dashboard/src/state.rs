@@ -5,6 +5,7 @@ use std::sync::Arc;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
+use crate::metrics::MetricsCollector;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DashboardConfig {
@@ -70,6 +71,9 @@ pub struct DashboardState {
     
     // Active transfers
     pub active_transfers: Arc<RwLock<HashMap<String, TransferInfo>>>,
+    
+    // Operations reported by the tools
+    pub metrics: Arc<MetricsCollector>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -88,6 +92,10 @@ pub struct TransferInfo {
 
 impl DashboardState {
     pub fn new() -> Self {
+        Self::with_metrics(Arc::new(MetricsCollector::default()))
+    }
+    
+    pub fn with_metrics(metrics: Arc<MetricsCollector>) -> Self {
         let scheduler = Arc::new(PriorityScheduler::new());
         let monitor = Arc::new(RealtimeStatusMonitor::with_scheduler(scheduler.clone()));
         
@@ -98,6 +106,7 @@ impl DashboardState {
             monitor,
             config: Arc::new(RwLock::new(DashboardConfig::default())),
             active_transfers: Arc::new(RwLock::new(HashMap::new())),
+            metrics,
         }
     }
     
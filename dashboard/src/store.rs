@@ -0,0 +1,388 @@
+//! SQLite persistence for ingested operation reports
+//!
+//! Every report accepted by `POST /api/metrics` is written here before it reaches
+//! the in-memory buffer, so history and the Prometheus totals survive a restart.
+//! The collector keeps the newest reports in memory and only reads the database
+//! for history beyond them. The database also holds the API keys (see `auth`)
+//! the reporting agents (see `agents`) and the alert rules (see `alerts`).
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use parking_lot::Mutex;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+
+use crate::agents::Agent;
+use crate::metrics::{OperationAggregate, OperationFilter, OperationReport, OperationTotals, REPORT_VERSION};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS operations (
+    id          INTEGER PRIMARY KEY,
+    timestamp   INTEGER NOT NULL,   -- milliseconds since the Unix epoch
+    node_id     TEXT    NOT NULL,
+    operation   TEXT    NOT NULL,
+    ok          INTEGER NOT NULL,
+    bytes       INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    algorithm   TEXT
+);
+CREATE INDEX IF NOT EXISTS operations_timestamp ON operations (timestamp);
+CREATE INDEX IF NOT EXISTS operations_node ON operations (node_id, timestamp);
+CREATE TABLE IF NOT EXISTS api_keys (
+    name     TEXT    PRIMARY KEY,
+    key_hash BLOB    NOT NULL UNIQUE,   -- SHA-256 of the key
+    created  INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS agents (
+    node_id     TEXT    PRIMARY KEY,
+    hostname    TEXT,
+    version     TEXT,
+    description TEXT,
+    registered  INTEGER,            -- NULL for a node that reports without registering
+    last_seen   INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS alerts (
+    id   INTEGER PRIMARY KEY AUTOINCREMENT,   -- never reused, not even after a delete
+    rule TEXT    NOT NULL                     -- the rule as its JSON
+);
+";
+
+/// Where a page of history ends: the timestamp and row of its last report.
+/// Clients see it as an opaque string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryCursor {
+    timestamp_ms: i64,
+    id: i64,
+}
+
+impl fmt::Display for HistoryCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.timestamp_ms, self.id)
+    }
+}
+
+impl FromStr for HistoryCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let parsed = text.split_once('_').and_then(|(timestamp_ms, id)| Some(Self {
+            timestamp_ms: timestamp_ms.parse().ok()?,
+            id: id.parse().ok()?,
+        }));
+        parsed.with_context(|| format!("invalid cursor {:?}", text))
+    }
+}
+
+/// Which end of the history pages start from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    NewestFirst,
+    OldestFirst,
+}
+
+/// Database of operation reports
+pub struct MetricsStore {
+    conn: Mutex<Connection>,
+}
+
+impl MetricsStore {
+    /// Open (or create) the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).with_context(|| format!("cannot open metrics database {}", path.display()))?;
+        // Concurrent readers while a report is being written
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn)
+    }
+
+    /// A database that lives only as long as the store
+    pub fn in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA).context("cannot create the metrics schema")?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// Append one report; its timestamp must be set. Returns its row id, which
+    /// grows with every report.
+    pub fn insert(&self, report: &OperationReport) -> Result<u64> {
+        let timestamp = report.timestamp.unwrap_or_else(Utc::now);
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT INTO operations (timestamp, node_id, operation, ok, bytes, duration_ms, algorithm) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![timestamp.timestamp_millis(), report.node_id, report.operation, report.ok, report.bytes as i64, report.duration_ms as i64, report.algorithm],
+        ).context("cannot store the report")?;
+        Ok(conn.last_insert_rowid() as u64)
+    }
+
+    /// Up to `limit` reports stored after the row `id`, oldest first, with their row ids
+    pub fn inserted_after(&self, id: u64, limit: usize) -> Result<Vec<(u64, OperationReport)>> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare(
+            "SELECT timestamp, node_id, operation, ok, bytes, duration_ms, algorithm, id FROM operations WHERE id > ?1 ORDER BY id LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![id as i64, limit.min(i64::MAX as usize) as i64], |row| {
+            Ok((row.get::<_, i64>(7)? as u64, report_from_row(row)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The newest `limit` reports that `filter` selects, newest first
+    pub fn history(&self, filter: &OperationFilter, limit: usize) -> Result<Vec<OperationReport>> {
+        Ok(self.select(filter, None, limit, Order::NewestFirst)?.into_iter().map(|(_, report)| report).collect())
+    }
+
+    /// Up to `page_size` reports that `filter` selects, in `order`, starting
+    /// after `after`; also the cursor of the next page, when there is one
+    pub fn page(&self, filter: &OperationFilter, after: Option<HistoryCursor>, page_size: usize, order: Order) -> Result<(Vec<OperationReport>, Option<HistoryCursor>)> {
+        let mut rows = self.select(filter, after, page_size.saturating_add(1), order)?;
+        let next = if rows.len() > page_size {
+            rows.truncate(page_size);
+            rows.last().map(|(cursor, _)| *cursor)
+        } else {
+            None
+        };
+        Ok((rows.into_iter().map(|(_, report)| report).collect(), next))
+    }
+
+    fn select(&self, filter: &OperationFilter, after: Option<HistoryCursor>, limit: usize, order: Order) -> Result<Vec<(HistoryCursor, OperationReport)>> {
+        let (mut conditions, mut values) = conditions(filter);
+        if let Some(after) = after {
+            let before = if order == Order::NewestFirst { "<" } else { ">" };
+            conditions.push(format!("(timestamp {0} ? OR (timestamp = ? AND id {0} ?))", before));
+            values.extend([Value::Integer(after.timestamp_ms), Value::Integer(after.timestamp_ms), Value::Integer(after.id)]);
+        }
+        let condition = where_clause(&conditions);
+        values.push(Value::Integer(limit.min(i64::MAX as usize) as i64));
+
+        let direction = if order == Order::NewestFirst { "DESC" } else { "ASC" };
+
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare(&format!(
+            "SELECT timestamp, node_id, operation, ok, bytes, duration_ms, algorithm, id FROM operations {} ORDER BY timestamp {1}, id {1} LIMIT ?",
+            condition, direction,
+        ))?;
+        let rows = statement.query_map(params_from_iter(values), |row| {
+            Ok((HistoryCursor { timestamp_ms: row.get(0)?, id: row.get(7)? }, report_from_row(row)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Counts and averages over the reports that `filter` selects
+    pub fn aggregate(&self, filter: &OperationFilter) -> Result<OperationAggregate> {
+        let (conditions, values) = conditions(filter);
+        let conn = self.conn.lock();
+        let aggregate = conn.query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM(1 - ok), 0),
+                        AVG(CASE WHEN duration_ms > 0 THEN bytes * 8.0 / 1000.0 / duration_ms END), AVG(duration_ms)
+                 FROM operations {}",
+                where_clause(&conditions),
+            ),
+            params_from_iter(values),
+            |row| Ok(OperationAggregate {
+                count: row.get::<_, i64>(0)? as u64,
+                failed: row.get::<_, i64>(1)? as u64,
+                avg_throughput_mbps: row.get(2)?,
+                avg_duration_ms: row.get(3)?,
+            }),
+        )?;
+        Ok(aggregate)
+    }
+
+    /// Running totals per operation over everything stored
+    pub fn totals(&self) -> Result<BTreeMap<String, OperationTotals>> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare("SELECT operation, ok, bytes, duration_ms FROM operations")?;
+        let mut rows = statement.query([])?;
+        let mut totals = BTreeMap::<String, OperationTotals>::new();
+        while let Some(row) = rows.next()? {
+            let (ok, bytes, duration_ms) = (row.get(1)?, row.get::<_, i64>(2)? as u64, row.get::<_, i64>(3)? as u64);
+            totals.entry(row.get(0)?).or_default().record(ok, bytes, duration_ms);
+        }
+        Ok(totals)
+    }
+
+    /// Delete the reports older than `before`; returns how many
+    pub fn prune(&self, before: DateTime<Utc>) -> Result<usize> {
+        let deleted = self.conn.lock().execute("DELETE FROM operations WHERE timestamp < ?1", [before.timestamp_millis()])?;
+        Ok(deleted)
+    }
+
+    /// Fold the write-ahead log back into the database file, so a copy of the
+    /// file alone holds every report
+    pub fn checkpoint(&self) -> Result<()> {
+        self.conn.lock().query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(())).context("cannot checkpoint the metrics database")?;
+        Ok(())
+    }
+
+    /// Add an API key under `name` by its hash
+    pub fn add_api_key(&self, name: &str, key_hash: &[u8; 32]) -> Result<()> {
+        self.conn.lock().execute(
+            "INSERT INTO api_keys (name, key_hash, created) VALUES (?1, ?2, ?3)",
+            params![name, &key_hash[..], Utc::now().timestamp_millis()],
+        ).with_context(|| format!("cannot add API key {} (names must be unique)", name))?;
+        Ok(())
+    }
+
+    /// Remove the API key called `name`; false when there is none
+    pub fn remove_api_key(&self, name: &str) -> Result<bool> {
+        Ok(self.conn.lock().execute("DELETE FROM api_keys WHERE name = ?1", [name])? > 0)
+    }
+
+    /// Whether a key with this hash is stored
+    pub fn has_api_key(&self, key_hash: &[u8; 32]) -> Result<bool> {
+        let count: i64 = self.conn.lock().query_row("SELECT COUNT(*) FROM api_keys WHERE key_hash = ?1", [&key_hash[..]], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+
+    pub fn api_key_count(&self) -> Result<u64> {
+        let count: i64 = self.conn.lock().query_row("SELECT COUNT(*) FROM api_keys", [], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+
+    /// Add or replace an agent
+    pub fn save_agent(&self, agent: &Agent) -> Result<()> {
+        self.conn.lock().execute(
+            "INSERT OR REPLACE INTO agents (node_id, hostname, version, description, registered, last_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![agent.node_id, agent.hostname, agent.version, agent.description, agent.registered.map(|t| t.timestamp_millis()), agent.last_seen.timestamp_millis()],
+        ).with_context(|| format!("cannot store agent {}", agent.node_id))?;
+        Ok(())
+    }
+
+    /// Every agent, including nodes whose reports predate the agents table
+    pub fn agents(&self) -> Result<Vec<Agent>> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare(
+            "SELECT node_id, hostname, version, description, registered, last_seen FROM agents
+             UNION ALL
+             SELECT node_id, NULL, NULL, NULL, NULL, MAX(timestamp) FROM operations
+             WHERE node_id NOT IN (SELECT node_id FROM agents) GROUP BY node_id",
+        )?;
+        let rows = statement.query_map([], |row| Ok(Agent {
+            node_id: row.get(0)?,
+            hostname: row.get(1)?,
+            version: row.get(2)?,
+            description: row.get(3)?,
+            registered: row.get::<_, Option<i64>>(4)?.map(from_millis),
+            last_seen: from_millis(row.get(5)?),
+        }))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Add an alert rule, given as JSON; returns its new id
+    pub fn insert_alert(&self, rule: &str) -> Result<u64> {
+        let conn = self.conn.lock();
+        conn.execute("INSERT INTO alerts (rule) VALUES (?1)", [rule]).context("cannot store the alert")?;
+        Ok(conn.last_insert_rowid() as u64)
+    }
+
+    /// Replace the alert rule with this id, given as JSON
+    pub fn save_alert(&self, id: u64, rule: &str) -> Result<()> {
+        self.conn.lock().execute("INSERT OR REPLACE INTO alerts (id, rule) VALUES (?1, ?2)", params![id as i64, rule])
+            .with_context(|| format!("cannot store alert {}", id))?;
+        Ok(())
+    }
+
+    /// Delete the alert rule with this id; false when there is none
+    pub fn delete_alert(&self, id: u64) -> Result<bool> {
+        Ok(self.conn.lock().execute("DELETE FROM alerts WHERE id = ?1", [id as i64])? > 0)
+    }
+
+    /// Every alert rule, as its id and JSON
+    pub fn alerts(&self) -> Result<Vec<(u64, String)>> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare("SELECT id, rule FROM alerts ORDER BY id")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// SQL conditions selecting the reports of `filter`, with their parameters
+fn conditions(filter: &OperationFilter) -> (Vec<String>, Vec<Value>) {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    if let Some(from) = filter.from {
+        conditions.push("timestamp >= ?".to_string());
+        values.push(Value::Integer(from.timestamp_millis()));
+    }
+    if let Some(to) = filter.to {
+        conditions.push("timestamp <= ?".to_string());
+        values.push(Value::Integer(to.timestamp_millis()));
+    }
+    if !filter.operations.is_empty() {
+        conditions.push(format!("operation IN ({})", vec!["?"; filter.operations.len()].join(", ")));
+        values.extend(filter.operations.iter().cloned().map(Value::Text));
+    }
+    if !filter.nodes.is_empty() {
+        conditions.push(format!("node_id IN ({})", vec!["?"; filter.nodes.len()].join(", ")));
+        values.extend(filter.nodes.iter().cloned().map(Value::Text));
+    }
+    (conditions, values)
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) }
+}
+
+/// The report in a row of `SELECT timestamp, node_id, operation, ok, bytes, duration_ms, algorithm`
+fn report_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<OperationReport> {
+    Ok(OperationReport {
+        version: REPORT_VERSION,
+        timestamp: Some(from_millis(row.get(0)?)),
+        node_id: row.get(1)?,
+        operation: row.get(2)?,
+        ok: row.get(3)?,
+        bytes: row.get::<_, i64>(4)? as u64,
+        duration_ms: row.get::<_, i64>(5)? as u64,
+        algorithm: row.get(6)?,
+    })
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis).single().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_through_history() {
+        let store = MetricsStore::in_memory().unwrap();
+        let start = Utc::now() - chrono::Duration::minutes(10);
+        // Two reports share each timestamp, so pages must split ties by row
+        for i in 0..7u64 {
+            let timestamp = start + chrono::Duration::seconds(i as i64 / 2);
+            let operation = if i == 3 { "decrypt" } else { "encrypt" };
+            store.insert(&serde_json::from_value(serde_json::json!({
+                "version": 1, "node_id": "car-12", "operation": operation, "bytes": i, "duration_ms": 10, "timestamp": timestamp,
+            })).unwrap()).unwrap();
+        }
+        let walk = |filter: &OperationFilter, page_size, order| {
+            let (mut pages, mut cursor) = (Vec::new(), None);
+            loop {
+                let (reports, next) = store.page(filter, cursor, page_size, order).unwrap();
+                pages.push(reports.iter().map(|r| r.bytes).collect::<Vec<_>>());
+                match next {
+                    Some(next) => cursor = Some(next.to_string().parse().unwrap()),
+                    None => return pages,
+                }
+            }
+        };
+        assert_eq!(walk(&OperationFilter::default(), 3, Order::NewestFirst), [vec![6, 5, 4], vec![3, 2, 1], vec![0]]);
+        assert_eq!(walk(&OperationFilter::default(), 7, Order::NewestFirst), [vec![6, 5, 4, 3, 2, 1, 0]]);
+        assert_eq!(walk(&OperationFilter::default(), 3, Order::OldestFirst), [vec![0, 1, 2], vec![3, 4, 5], vec![6]]);
+        let encrypts = OperationFilter { operations: vec!["encrypt".to_string()], ..Default::default() };
+        assert_eq!(walk(&encrypts, 3, Order::NewestFirst), [vec![6, 5, 4], vec![2, 1, 0]]);
+        assert_eq!(walk(&encrypts, 4, Order::OldestFirst), [vec![0, 1, 2, 4], vec![5, 6]]);
+        assert!("12".parse::<HistoryCursor>().is_err() && "a_1".parse::<HistoryCursor>().is_err());
+    }
+}
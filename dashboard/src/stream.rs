@@ -0,0 +1,266 @@
+//! Live reports as Server-Sent Events
+//!
+//! `GET /api/metrics/stream` keeps the response open and sends every report as
+//! it is ingested, for browsers and proxies where a plain HTTP response gets
+//! through more easily than a WebSocket:
+//!
+//! ```text
+//! id: 4812
+//! event: report
+//! data: {"version":1,"node_id":"pit-a","operation":"encrypt",...}
+//! ```
+//!
+//! A `heartbeat` event every [`HEARTBEAT_INTERVAL`] keeps idle connections from
+//! being timed out. A client that reconnects with `Last-Event-ID` (as
+//! `EventSource` does by itself) first gets the reports it missed from the
+//! metrics database. When that is more than [`MAX_REPLAY`], it gets a `reset`
+//! event instead, naming the last event it had (null when it had none), and
+//! should refetch the history from there before going on with the live
+//! reports. A client too slow for the live buffer catches up the same way.
+//! `operation` and `node`
+//! narrow the stream like they narrow the history. `EventSource` cannot send
+//! headers, so the stream also takes the API key as an `api_key` query parameter
+//! (see `auth`).
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult};
+use chrono::Utc;
+use futures::future::LocalBoxFuture;
+use futures::{FutureExt, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Interval;
+
+use crate::api::parse_list;
+use crate::metrics::{MetricsCollector, OperationFilter, ReportEvent};
+use crate::state::DashboardState;
+
+/// Time between heartbeats
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Most missed reports replayed to a client that reconnects
+pub const MAX_REPLAY: usize = 1000;
+
+/// How long `EventSource` waits before reconnecting, in milliseconds
+const RETRY_MS: u64 = 3000;
+
+/// Query for `GET /api/metrics/stream`
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    /// Comma-separated operations, e.g. "encrypt,decrypt"
+    pub operation: Option<String>,
+    /// Comma-separated node ids, e.g. "pit-a"
+    pub node: Option<String>,
+}
+
+/// One client's stream
+struct Subscription {
+    metrics: Arc<MetricsCollector>,
+    events: broadcast::Receiver<ReportEvent>,
+    filter: OperationFilter,
+    /// Replayed from the store and not sent yet
+    backlog: VecDeque<ReportEvent>,
+    /// Too much was missed after `last` to replay; sent before anything else
+    reset: bool,
+    /// Newest event replayed; live ones up to it were sent already
+    replayed: u64,
+    /// Newest event sent, to catch up from when falling behind
+    last: Option<u64>,
+    heartbeat: Interval,
+    shutdown: LocalBoxFuture<'static, ()>,
+}
+
+impl Subscription {
+    /// The next chunk of the response; None ends it
+    async fn next(&mut self) -> Option<Bytes> {
+        loop {
+            if std::mem::take(&mut self.reset) {
+                let after = self.last.map_or("null".to_string(), |last| last.to_string());
+                return Some(Bytes::from(format!("event: reset\ndata: {{\"after\":{},\"max_replay\":{}}}\n\n", after, MAX_REPLAY)));
+            }
+            if let Some(event) = self.backlog.pop_front() {
+                self.last = self.last.max(Some(event.id));
+                if self.filter.matches(&event.report) {
+                    return Some(frame(&event));
+                }
+                continue;
+            }
+            tokio::select! {
+                _ = &mut self.shutdown => return None,
+                _ = self.heartbeat.tick() => {
+                    return Some(Bytes::from(format!("event: heartbeat\ndata: {{\"time\":\"{}\"}}\n\n", Utc::now().to_rfc3339())));
+                }
+                received = self.events.recv() => match received {
+                    Ok(event) if event.id > self.replayed => self.backlog.push_back(event),
+                    Ok(_) => {}
+                    // Too slow for the live buffer: catch up from the store
+                    Err(RecvError::Lagged(_)) => self.replay().await,
+                    Err(RecvError::Closed) => return None,
+                },
+            }
+        }
+    }
+
+    /// Queue the reports after the last one sent, or a reset when there are
+    /// more than [`MAX_REPLAY`] of them or nothing was sent to count from
+    async fn replay(&mut self) {
+        let Some(after) = self.last else {
+            self.reset = true;
+            return;
+        };
+        // The store blocks, so read it off the async workers
+        let metrics = self.metrics.clone();
+        match web::block(move || metrics.events_after(after, MAX_REPLAY + 1)).await.map_err(anyhow::Error::from) {
+            Ok(Ok(events)) if events.len() > MAX_REPLAY => self.reset = true,
+            Ok(Ok(events)) => {
+                self.replayed = self.replayed.max(events.last().map_or(after, |event| event.id));
+                self.backlog.extend(events);
+            }
+            Ok(Err(e)) | Err(e) => eprintln!("⚠️  Cannot replay reports after {}: {:#}", after, e),
+        }
+    }
+}
+
+/// A report as an SSE event
+fn frame(event: &ReportEvent) -> Bytes {
+    let data = serde_json::to_string(&event.report).unwrap_or_default();
+    Bytes::from(format!("id: {}\nevent: report\ndata: {}\n\n", event.id, data))
+}
+
+/// Stream the ingested reports as Server-Sent Events until the client leaves
+/// or the dashboard shuts down
+pub async fn metrics_stream(
+    req: HttpRequest,
+    state: web::Data<Arc<DashboardState>>,
+    query: web::Query<StreamQuery>,
+) -> ActixResult<HttpResponse> {
+    let last_event_id = match req.headers().get("last-event-id") {
+        None => None,
+        Some(value) => match value.to_str().ok().and_then(|id| id.trim().parse::<u64>().ok()) {
+            Some(id) => Some(id),
+            None => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Last-Event-ID must be the id of an earlier event",
+                })));
+            }
+        },
+    };
+    let filter = OperationFilter {
+        operations: parse_list(query.operation.as_deref()),
+        nodes: parse_list(query.node.as_deref()),
+        ..Default::default()
+    };
+
+    // Subscribe before replaying, so nothing falls between the two
+    let metrics = state.metrics.clone();
+    let mut subscription = Subscription {
+        events: metrics.subscribe(),
+        metrics,
+        filter,
+        backlog: VecDeque::new(),
+        reset: false,
+        replayed: 0,
+        last: last_event_id,
+        heartbeat: tokio::time::interval(HEARTBEAT_INTERVAL),
+        shutdown: crate::shutdown::signal().boxed_local(),
+    };
+    if last_event_id.is_some() {
+        subscription.replay().await;
+    }
+
+    let retry = Bytes::from(format!("retry: {}\n\n", RETRY_MS));
+    let events = futures::stream::unfold(subscription, |mut subscription| async move {
+        let chunk = subscription.next().await?;
+        Some((Ok::<_, actix_web::Error>(chunk), subscription))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // Keep nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(futures::stream::once(futures::future::ready(Ok(retry))).chain(events)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn streams_and_replays_reports() {
+        let metrics = Arc::new(MetricsCollector::with_store(10, Arc::new(crate::store::MetricsStore::in_memory().unwrap())).unwrap());
+        let ingest = |node_id: &str| {
+            metrics.ingest(serde_json::from_value(serde_json::json!({"version": 1, "node_id": node_id, "operation": "encrypt", "duration_ms": 3})).unwrap()).unwrap();
+        };
+        for node_id in ["pit-a", "pit-b", "pit-a"] {
+            ingest(node_id);
+        }
+
+        // Back from event 1: the two missed, then live ones, with the same event
+        // arriving both ways sent once
+        let mut subscription = Subscription {
+            events: metrics.subscribe(),
+            metrics: metrics.clone(),
+            filter: OperationFilter::default(),
+            backlog: VecDeque::new(),
+            reset: false,
+            replayed: 0,
+            last: Some(1),
+            heartbeat: tokio::time::interval(Duration::from_secs(3600)),
+            shutdown: futures::future::pending().boxed_local(),
+        };
+        subscription.heartbeat.tick().await;
+        ingest("pit-b");
+        subscription.replay().await;
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let chunk = subscription.next().await.unwrap();
+            let text = std::str::from_utf8(&chunk).unwrap();
+            assert!(text.starts_with("id: ") && text.contains("\nevent: report\ndata: {") && text.ends_with("}\n\n"), "{}", text);
+            ids.push(text[4..text.find('\n').unwrap()].parse::<u64>().unwrap());
+        }
+        assert_eq!(ids, [2, 3, 4]);
+        assert!(subscription.backlog.is_empty());
+
+        subscription.filter.nodes = vec!["pit-a".to_string()];
+        ingest("pit-b");
+        ingest("pit-a");
+        assert!(std::str::from_utf8(&subscription.next().await.unwrap()).unwrap().starts_with("id: 6\n"));
+
+        // Too far behind to replay: a reset, then the live reports
+        for _ in 0..=MAX_REPLAY {
+            ingest("pit-a");
+        }
+        subscription.replay().await;
+        assert_eq!(&subscription.next().await.unwrap()[..], b"event: reset\ndata: {\"after\":6,\"max_replay\":1000}\n\n");
+        assert!(subscription.backlog.is_empty());
+        ingest("pit-a");
+        assert!(std::str::from_utf8(&subscription.next().await.unwrap()).unwrap().starts_with("id: 7\n"));
+    }
+
+    #[actix_web::test]
+    async fn resets_a_client_that_lags_before_its_first_report() {
+        let metrics = Arc::new(MetricsCollector::with_store(10, Arc::new(crate::store::MetricsStore::in_memory().unwrap())).unwrap());
+        let mut subscription = Subscription {
+            events: metrics.subscribe(),
+            metrics: metrics.clone(),
+            filter: OperationFilter::default(),
+            backlog: VecDeque::new(),
+            reset: false,
+            replayed: 0,
+            last: None,
+            heartbeat: tokio::time::interval(Duration::from_secs(3600)),
+            shutdown: futures::future::pending().boxed_local(),
+        };
+        subscription.heartbeat.tick().await;
+        for _ in 0..=crate::metrics::EVENT_BUFFER {
+            metrics.ingest(serde_json::from_value(serde_json::json!({"version": 1, "node_id": "pit-a", "operation": "encrypt", "duration_ms": 3})).unwrap()).unwrap();
+        }
+        assert_eq!(&subscription.next().await.unwrap()[..], b"event: reset\ndata: {\"after\":null,\"max_replay\":1000}\n\n");
+        // Then the live reports the buffer still holds
+        assert!(std::str::from_utf8(&subscription.next().await.unwrap()).unwrap().starts_with("id: 2\n"));
+    }
+}
@@ -0,0 +1,147 @@
+//! Time-bucketed aggregates of the reported operations
+//!
+//! Served at `GET /api/metrics/summary?window=1h&bucket=1m`, so charts get one
+//! point per bucket instead of every report: the count, failures and bytes, and
+//! the average and p50/p95/p99 of throughput (Mbit/s) and duration (ms).
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::metrics::OperationReport;
+
+/// Most buckets one summary may have
+pub const MAX_BUCKETS: i64 = 1440;
+
+/// Longest window one summary may cover, in seconds
+pub const MAX_WINDOW_SECONDS: i64 = 31 * 86_400;
+
+/// Average and percentiles of one quantity; absent for a bucket without reports
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub avg: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Stats {
+    fn of(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        // Nearest rank: the smallest value at or above the fraction of them
+        let rank = |p: f64| values[((p * values.len() as f64).ceil() as usize).clamp(1, values.len()) - 1];
+        Some(Self {
+            avg: values.iter().sum::<f64>() / values.len() as f64,
+            p50: rank(0.50),
+            p95: rank(0.95),
+            p99: rank(0.99),
+        })
+    }
+}
+
+/// Aggregates of the reports in `[start, start + bucket)`
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketSummary {
+    pub start: DateTime<Utc>,
+    pub count: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    pub throughput_mbps: Option<Stats>,
+    pub duration_ms: Option<Stats>,
+}
+
+/// Start of the first of the buckets covering the `window` up to `now`; bucket
+/// edges are multiples of `bucket` since the epoch, so they do not move between
+/// requests
+pub fn first_bucket(now: DateTime<Utc>, window: Duration, bucket: Duration) -> DateTime<Utc> {
+    let bucket_ms = bucket.num_milliseconds().max(1);
+    let start_ms = (now - window).timestamp_millis();
+    Utc.timestamp_millis_opt(start_ms - start_ms.rem_euclid(bucket_ms)).single().unwrap_or_default()
+}
+
+/// Sorts reports into buckets as they come, keeping only the figures each
+/// bucket needs, so a summary of a long history never holds the reports
+pub struct Summarizer {
+    start: DateTime<Utc>,
+    bucket_ms: i64,
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Clone, Default)]
+struct Bucket {
+    count: u64,
+    failed: u64,
+    bytes: u64,
+    throughput_mbps: Vec<f64>,
+    duration_ms: Vec<f64>,
+}
+
+impl Summarizer {
+    /// Buckets of `bucket` from `start` up to `end`
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>, bucket: Duration) -> Self {
+        let bucket_ms = bucket.num_milliseconds().max(1);
+        let buckets = ((end - start).num_milliseconds() / bucket_ms + 1).max(0) as usize;
+        Self { start, bucket_ms, buckets: vec![Bucket::default(); buckets] }
+    }
+
+    /// Count `report` in its bucket; reports outside them all are skipped
+    pub fn add(&mut self, report: &OperationReport) {
+        let Some(timestamp) = report.timestamp else { return };
+        let offset = (timestamp - self.start).num_milliseconds();
+        if offset < 0 {
+            return;
+        }
+        let Some(bucket) = self.buckets.get_mut((offset / self.bucket_ms) as usize) else { return };
+        bucket.count += 1;
+        bucket.failed += u64::from(!report.ok);
+        bucket.bytes += report.bytes;
+        bucket.throughput_mbps.extend(report.throughput_mbps());
+        bucket.duration_ms.push(report.duration_ms as f64);
+    }
+
+    /// The buckets, oldest first, including the empty ones
+    pub fn finish(self) -> Vec<BucketSummary> {
+        let (start, bucket_ms) = (self.start, self.bucket_ms);
+        self.buckets.into_iter()
+            .enumerate()
+            .map(|(i, bucket)| BucketSummary {
+                start: start + Duration::milliseconds(i as i64 * bucket_ms),
+                count: bucket.count,
+                failed: bucket.failed,
+                bytes: bucket.bytes,
+                throughput_mbps: Stats::of(bucket.throughput_mbps),
+                duration_ms: Stats::of(bucket.duration_ms),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_per_bucket() {
+        let now = Utc.timestamp_millis_opt(1_800_000_000_000 + 150_000).unwrap();
+        let start = first_bucket(now, Duration::minutes(3), Duration::minutes(1));
+        assert_eq!(start.timestamp_millis(), 1_800_000_000_000 - 60_000);
+        let reports: Vec<OperationReport> = (1..=100u64)
+            .map(|i| serde_json::from_value(serde_json::json!({
+                "version": 1, "node_id": "car-12", "operation": "encrypt", "ok": i != 7, "bytes": 1_000_000, "duration_ms": i,
+                "timestamp": start + Duration::seconds(60 + (i as i64 % 60)),
+            })).unwrap())
+            .collect();
+        let mut summarizer = Summarizer::new(start, now, Duration::minutes(1));
+        reports.iter().for_each(|report| summarizer.add(report));
+        let buckets = summarizer.finish();
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(buckets.iter().map(|b| b.count).collect::<Vec<_>>(), [0, 100, 0, 0]);
+        assert!(buckets[0].duration_ms.is_none());
+        let busy = &buckets[1];
+        assert_eq!((busy.failed, busy.bytes), (1, 100_000_000));
+        assert_eq!(busy.duration_ms, Some(Stats { avg: 50.5, p50: 50.0, p95: 95.0, p99: 99.0 }));
+        assert_eq!(busy.throughput_mbps.as_ref().unwrap().p50, 8000.0 / 51.0);
+    }
+}
@@ -0,0 +1,70 @@
+//! HTTPS: rustls server config from PEM files, and the plain-HTTP redirect
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::{Context, Result};
+
+use crate::config::TlsConfig;
+
+/// Build the rustls server config from the certificate chain and private key
+pub fn server_config(tls: &TlsConfig) -> Result<rustls::ServerConfig> {
+    let open = |path: &std::path::Path| -> Result<BufReader<File>> {
+        Ok(BufReader::new(File::open(path).with_context(|| format!("cannot open {}", path.display()))?))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&tls.cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid certificate file {}", tls.cert.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificate in {}", tls.cert.display());
+    }
+    let key = rustls_pemfile::private_key(&mut open(&tls.key)?)
+        .with_context(|| format!("invalid key file {}", tls.key.display()))?
+        .with_context(|| format!("no private key in {}", tls.key.display()))?;
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("the certificate does not match the private key")?;
+    Ok(config)
+}
+
+/// Redirect any plain-HTTP request to the same path on the HTTPS port
+pub async fn redirect(req: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let info = req.connection_info();
+    let host = info.host();
+    // Drop the plain-HTTP port; keep IPv6 brackets intact
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let port = match **https_port {
+        443 => String::new(),
+        port => format!(":{}", port),
+    };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    HttpResponse::PermanentRedirect()
+        .insert_header(("Location", format!("https://{}{}{}", host, port, path)))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn redirects_to_the_https_port() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(8443u16))
+                .default_service(web::to(redirect)),
+        ).await;
+        let req = test::TestRequest::get().uri("/api/status?limit=5").insert_header(("Host", "pit.example:8080")).to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status().as_u16(), 308);
+        assert_eq!(response.headers().get("Location").unwrap(), "https://pit.example:8443/api/status?limit=5");
+    }
+}
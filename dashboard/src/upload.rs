@@ -0,0 +1,269 @@
+//! Encrypt a file uploaded to `POST /api/encrypt`
+//!
+//! The multipart form has a `recipient` field (name or fingerprint of a public
+//! key in the host's keyring) followed by a `file` field. The upload is streamed
+//! straight through `pitlink_pqc::encrypt`, so the plaintext never touches the
+//! disk, into a package under `uploads/` in the jobs directory; the response
+//! links to it at `GET /api/uploads/{id}/{name}`, which takes the API key as an
+//! `api_key` query parameter too so a browser can open it. Packages are deleted
+//! once older than the upload retention. Like `rust_pqc encrypt -i -`, a
+//! streamed package carries no Merkle tree.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use actix_files::NamedFile;
+use actix_multipart::{Field, Multipart};
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpResponse, Result as ActixResult};
+use anyhow::Context;
+use futures::StreamExt;
+use pitlink_pqc::keyfile::{KeyFile, KeyKind};
+use pitlink_pqc::EncryptOptions;
+use rand::RngCore;
+use tokio::sync::mpsc;
+
+use crate::jobs::JobManager;
+
+/// Largest upload accepted when none is configured: 1 GiB
+pub const DEFAULT_MAX_UPLOAD: u64 = 1 << 30;
+
+/// How long packages made from uploads are kept when nothing is configured
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 86_400);
+
+/// Where uploads are encrypted to, how large they may be and how long they are kept
+pub struct Uploads {
+    dir: PathBuf,
+    max_bytes: u64,
+    retention: Duration,
+}
+
+impl Uploads {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64, retention: Duration) -> Self {
+        Self { dir: dir.into(), max_bytes, retention }
+    }
+
+    /// Path of a package written earlier, if `id` and `name` could be one
+    fn package(&self, id: &str, name: &str) -> Option<PathBuf> {
+        let name_ok = name.ends_with(".pqc") && matches!(Path::new(name).components().collect::<Vec<_>>()[..], [Component::Normal(_)]);
+        (is_upload_id(id) && name_ok).then(|| self.dir.join(id).join(name))
+    }
+
+    /// Delete the uploads older than the retention; returns how many there were.
+    /// Blocks on the file system.
+    pub fn prune(&self) -> anyhow::Result<usize> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("cannot list {}", self.dir.display()))),
+        };
+        let cutoff = SystemTime::now().checked_sub(self.retention).unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut deleted = 0;
+        for entry in entries {
+            let entry = entry?;
+            let is_upload = entry.file_name().to_str().is_some_and(is_upload_id) && entry.file_type()?.is_dir();
+            if is_upload && entry.metadata()?.modified()? < cutoff {
+                std::fs::remove_dir_all(entry.path()).with_context(|| format!("cannot delete {}", entry.path().display()))?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+/// Whether `id` is the name an upload's directory gets: 32 hex digits
+fn is_upload_id(id: &str) -> bool {
+    id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// A file name from the upload that is safe to write under: its last component,
+/// with anything but `A-Z a-z 0-9 . _ -` replaced
+fn package_name(file_name: Option<&str>) -> String {
+    let base = file_name.unwrap_or_default().rsplit(['/', '\\']).next().unwrap_or_default();
+    let clean: String = base.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .take(100)
+        .collect();
+    let clean = clean.trim_start_matches('.');
+    format!("{}.pqc", if clean.is_empty() { "upload" } else { clean })
+}
+
+/// Blocking reader over the chunks the request handler forwards
+struct ChannelReader {
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+    current: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current[..n]);
+        self.current = self.current.slice(n..);
+        Ok(n)
+    }
+}
+
+fn bad_request(error: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": error.to_string(),
+    }))
+}
+
+/// Encrypt the uploaded `file` to `recipient`; answers 201 with a link to the package
+pub async fn encrypt(jobs: web::Data<JobManager>, uploads: web::Data<Uploads>, mut form: Multipart) -> ActixResult<HttpResponse> {
+    let mut recipient = None;
+    while let Some(field) = form.next().await {
+        let mut field = field?;
+        match field.name() {
+            Some("recipient") => {
+                let mut text = Vec::new();
+                while let Some(chunk) = field.next().await {
+                    text.extend_from_slice(&chunk?);
+                    if text.len() > 256 {
+                        return Ok(bad_request("recipient is too long"));
+                    }
+                }
+                recipient = Some(String::from_utf8_lossy(&text).trim().to_string());
+            }
+            Some("file") => {
+                let Some(recipient) = recipient.take() else {
+                    return Ok(bad_request("send the recipient field before the file"));
+                };
+                let pubkey = match jobs.recipient_key(&recipient) {
+                    Ok(pubkey) => pubkey,
+                    Err(e) => return Ok(bad_request(format!("{:#}", e))),
+                };
+                let name = package_name(field.content_disposition().and_then(|cd| cd.get_filename()));
+                return encrypt_field(&uploads, field, pubkey, name).await;
+            }
+            Some(other) => return Ok(bad_request(format!("unexpected field {}", other))),
+            None => return Ok(bad_request("form field without a name")),
+        }
+    }
+    Ok(bad_request("no file field"))
+}
+
+async fn encrypt_field(uploads: &Uploads, mut field: Field, pubkey: PathBuf, name: String) -> ActixResult<HttpResponse> {
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let id: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+    let dir = uploads.dir.join(&id);
+    let path = dir.join(&name);
+
+    let (chunks, rx) = mpsc::channel(16);
+    let (package_dir, package) = (dir.clone(), path.clone());
+    let encryption = tokio::task::spawn_blocking(move || -> anyhow::Result<u64> {
+        let recipient = KeyFile::read(&pubkey, KeyKind::Public)?;
+        std::fs::create_dir_all(&package_dir)?;
+        let out = BufWriter::new(File::create(&package).with_context(|| format!("cannot create {}", package.display()))?);
+        let input = ChannelReader { chunks: rx, current: Bytes::new() };
+        Ok(pitlink_pqc::encrypt(input, out, &recipient, None, &EncryptOptions::default())?.plaintext_len)
+    });
+
+    // Forward the upload until it ends, breaks the limit or the encryptor stops
+    let mut received = 0u64;
+    let mut too_large = false;
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| io::Error::other(e.to_string()));
+        if let Ok(chunk) = &chunk {
+            received += chunk.len() as u64;
+            too_large = received > uploads.max_bytes;
+        }
+        let failed = chunk.is_err() || too_large;
+        let chunk = if too_large { Err(io::Error::other("upload too large")) } else { chunk };
+        if chunks.send(chunk).await.is_err() || failed {
+            break;
+        }
+    }
+    drop(chunks);
+
+    let result = encryption.await.map_err(anyhow::Error::from).and_then(|result| result);
+    let plaintext_bytes = match result {
+        Ok(bytes) if !too_large => bytes,
+        result => {
+            let _ = std::fs::remove_dir_all(&dir);
+            if too_large {
+                return Ok(HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                    "error": format!("upload larger than {} bytes", uploads.max_bytes),
+                })));
+            }
+            let e = result.err().map(|e| format!("{:#}", e)).unwrap_or_default();
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("cannot encrypt the upload: {}", e),
+            })));
+        }
+    };
+    let url = format!("/api/uploads/{}/{}", id, name);
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", url.clone()))
+        .json(serde_json::json!({
+            "id": id,
+            "name": name,
+            "url": url,
+            "plaintext_bytes": plaintext_bytes,
+            "package_bytes": std::fs::metadata(&path).map(|metadata| metadata.len()).ok(),
+        })))
+}
+
+/// Download a package made by [`encrypt`]
+pub async fn download(uploads: web::Data<Uploads>, path: web::Path<(String, String)>) -> ActixResult<NamedFile> {
+    let (id, name) = path.into_inner();
+    let package = uploads.package(&id, &name).ok_or_else(|| actix_web::error::ErrorNotFound("no such package"))?;
+    Ok(NamedFile::open(package)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_streams_uploads() {
+        assert_eq!(package_name(Some("lap 3/run.csv")), "run.csv.pqc");
+        assert_eq!(package_name(Some("C:\\data\\..\\tel€m.bin")), "tel_m.bin.pqc");
+        assert_eq!(package_name(Some("..")), "upload.pqc");
+        assert_eq!(package_name(None), "upload.pqc");
+
+        let uploads = Uploads::new("/srv/jobs/uploads", 10, DEFAULT_RETENTION);
+        let id = "0123456789abcdef0123456789abcdef";
+        assert_eq!(uploads.package(id, "run.csv.pqc"), Some(PathBuf::from(format!("/srv/jobs/uploads/{}/run.csv.pqc", id))));
+        assert!(uploads.package(id, "../x.pqc").is_none() && uploads.package(id, "run.csv").is_none() && uploads.package("..", "run.pqc").is_none());
+
+        let (tx, rx) = mpsc::channel(4);
+        for chunk in [&b"lap,"[..], b"", b"time"] {
+            tx.try_send(Ok(Bytes::from_static(chunk))).unwrap();
+        }
+        drop(tx);
+        let mut text = String::new();
+        ChannelReader { chunks: rx, current: Bytes::new() }.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "lap,time");
+    }
+
+    #[test]
+    fn prunes_old_uploads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let uploads = Uploads::new(tmp.path().join("uploads"), 10, Duration::from_secs(3600));
+        assert_eq!(uploads.prune().unwrap(), 0);
+
+        let (old, new) = ("0123456789abcdef0123456789abcdef", "fedcba9876543210fedcba9876543210");
+        for dir in [old, new, "notes"] {
+            std::fs::create_dir_all(tmp.path().join("uploads").join(dir)).unwrap();
+            std::fs::write(tmp.path().join("uploads").join(dir).join("run.csv.pqc"), b"package").unwrap();
+        }
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(7200);
+        for dir in [old, "notes"] {
+            File::open(tmp.path().join("uploads").join(dir)).unwrap().set_modified(two_hours_ago).unwrap();
+        }
+        assert_eq!(uploads.prune().unwrap(), 1);
+        assert!(uploads.package(old, "run.csv.pqc").is_some_and(|path| !path.exists()));
+        assert!(uploads.package(new, "run.csv.pqc").is_some_and(|path| path.exists()));
+        assert!(tmp.path().join("uploads/notes").exists());
+    }
+}
@@ -1,14 +1,9 @@
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
 
-#[derive(Clone, Debug)]
-pub struct CompressedChunkInfo {
-    pub index: u64,
-    pub byte_offset: u64,
-    pub compressed_size: usize,
-    pub uncompressed_estimate: Option<usize>,
-}
+use common::manifest::{self, Algorithms, Manifest, ManifestChunk};
 
 /// Read 4 bytes as little-endian u32
 fn read_u32_le(data: &[u8]) -> u32 {
@@ -32,7 +27,8 @@ fn calculate_chunk_size(file_size: usize) -> usize {
 
 /// Chunk an LZ4 file with size-prepended blocks (compress_prepend_size format)
 /// Uses dynamic chunk sizing based on input file size
-pub fn chunk_lz4_file(input: &str, out_prefix: &str) -> Result<Vec<CompressedChunkInfo>, Box<dyn Error>> {
+/// Returns the manifest of the chunk files, which is also written to `<out_prefix>.manifest.json`
+pub fn chunk_lz4_file(input: &str, out_prefix: &str) -> Result<Manifest, Box<dyn Error>> {
     let input_file = File::open(input)?;
     let mut reader = BufReader::new(input_file);
     
@@ -49,7 +45,10 @@ pub fn chunk_lz4_file(input: &str, out_prefix: &str) -> Result<Vec<CompressedChu
     eprintln!("File size: {} MB", file_size / (1024 * 1024));
     eprintln!("Dynamic chunk size: {} MB", target_chunk_size / (1024 * 1024));
     
-    let mut chunks = Vec::new();
+    // The id is derived from the content, so rechunking the same file gives the same id
+    let package_id = manifest::to_hex(&common::blake3_hash(&all_data)[..16]);
+    let algorithms = Algorithms { compression: Some("Lz4".to_string()), hash: "blake3".to_string(), ..Default::default() };
+    let mut manifest = Manifest::new(package_id, input.to_string(), algorithms, target_chunk_size as u64);
     let mut chunk_index = 1u64;
     let mut offset = 0;
     let mut chunk_start = 0;
@@ -78,12 +77,7 @@ pub fn chunk_lz4_file(input: &str, out_prefix: &str) -> Result<Vec<CompressedChu
                 &chunk_blocks,
             )?;
             
-            chunks.push(CompressedChunkInfo {
-                index: chunk_index,
-                byte_offset: chunk_start as u64,
-                compressed_size: chunk_compressed,
-                uncompressed_estimate: None,
-            });
+            manifest.push(chunk_entry(&out_file, chunk_index, chunk_start, chunk_compressed, &all_data, &chunk_blocks));
             
             chunk_index += 1;
             chunk_start = offset;
@@ -101,15 +95,29 @@ pub fn chunk_lz4_file(input: &str, out_prefix: &str) -> Result<Vec<CompressedChu
             &chunk_blocks,
         )?;
         
-        chunks.push(CompressedChunkInfo {
-            index: chunk_index,
-            byte_offset: chunk_start as u64,
-            compressed_size: chunk_compressed,
-            uncompressed_estimate: None,
-        });
+        manifest.push(chunk_entry(&out_file, chunk_index, chunk_start, chunk_compressed, &all_data, &chunk_blocks));
     }
     
-    Ok(chunks)
+    std::fs::write(format!("{}.manifest.json", out_prefix), manifest.to_json()? + "\n")?;
+    Ok(manifest)
+}
+
+/// Manifest entry of a chunk file written from `blocks`
+fn chunk_entry(out_file: &str, index: u64, start: usize, size: usize, data: &[u8], blocks: &[(usize, usize)]) -> ManifestChunk {
+    let mut hasher = blake3::Hasher::new();
+    for &(block_start, block_size) in blocks {
+        hasher.update(&data[block_start..block_start + block_size]);
+    }
+    ManifestChunk {
+        index,
+        file: Path::new(out_file).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        offset: start as u64,
+        size: size as u64,
+        stored_size: size as u64,
+        frames: None,
+        hash: hasher.finalize().to_hex().to_string(),
+        content_hash: None,
+    }
 }
 
 /// Write a single chunk file (just concatenate blocks verbatim)
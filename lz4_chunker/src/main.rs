@@ -44,7 +44,7 @@ fn chunk_command(input: &str, prefix: &str) -> Result<(), Box<dyn Error>> {
     println!("───────────────────────────────────────────────────────────");
     
     let start = std::time::Instant::now();
-    let chunks = chunk_lz4_file(input, prefix)?;
+    let manifest = chunk_lz4_file(input, prefix)?;
     let total_elapsed = start.elapsed();
     
     let timestamp_end = get_timestamp();
@@ -52,14 +52,15 @@ fn chunk_command(input: &str, prefix: &str) -> Result<(), Box<dyn Error>> {
     
     println!();
     println!("✓ CHUNKING COMPLETE");
-    println!("  Chunks Created:   {}", chunks.len());
+    println!("  Chunks Created:   {}", manifest.chunks.len());
     println!("  Duration:         {} ms ({:.3} sec)", total_elapsed.as_millis(), total_elapsed.as_secs_f64());
     println!();
     
-    for chunk in chunks {
+    for chunk in &manifest.chunks {
         println!("  [Chunk {}] offset={:10} size={:10} bytes", 
-                 chunk.index, chunk.byte_offset, chunk.compressed_size);
+                 chunk.index, chunk.offset, chunk.size);
     }
+    println!("  Manifest:         {}.manifest.json", prefix);
     
     println!();
     println!("[CHUNKING END]   Timestamp: {} | Time (ms): {}", timestamp_end, timestamp_ms_end);
@@ -0,0 +1,91 @@
+//! Criterion suite for the package primitives and the full encrypt path
+//!
+//! `cargo bench -p pitlink_pqc` writes results under `target/criterion`;
+//! `rust_pqc bench-report --export json` turns them into the dashboard's format.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use common::container::{AeadId, KemId};
+use common::hkdf_derive;
+use pitlink_pqc::cipher::Cipher;
+use pitlink_pqc::{kem, EncryptOptions};
+
+const KEMS: [KemId; 4] = [KemId::Kyber768, KemId::MlKem512, KemId::MlKem768, KemId::MlKem1024];
+const AEADS: [(AeadId, &str); 2] = [(AeadId::XChaCha20Poly1305, "xchacha20poly1305"), (AeadId::Aes256Gcm, "aes256gcm")];
+const AEAD_SIZES: [usize; 4] = [64, 1024, 64 * 1024, 1024 * 1024];
+const FILE_SIZES: [usize; 2] = [1024 * 1024, 16 * 1024 * 1024];
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    getrandom::getrandom(&mut buf).expect("getrandom");
+    buf
+}
+
+fn bench_kem(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kem");
+    for kem_id in KEMS {
+        let name = format!("{:?}", kem_id);
+        let (pk, sk) = kem::generate_keypair(kem_id);
+        let (ct, _) = kem::encapsulate(kem_id, &pk).expect("encapsulate");
+        group.bench_function(BenchmarkId::new("keygen", &name), |b| b.iter(|| kem::generate_keypair(kem_id)));
+        group.bench_function(BenchmarkId::new("encapsulate", &name), |b| b.iter(|| kem::encapsulate(kem_id, black_box(&pk)).expect("encapsulate")));
+        group.bench_function(BenchmarkId::new("decapsulate", &name), |b| b.iter(|| kem::decapsulate(kem_id, black_box(&sk), black_box(&ct)).expect("decapsulate")));
+    }
+    group.finish();
+}
+
+fn bench_hkdf(c: &mut Criterion) {
+    let shared = random_bytes(32);
+    c.bench_function("hkdf/session_key", |b| b.iter(|| hkdf_derive(black_box(&shared), b"kyber-session-v1", 32).expect("hkdf")));
+}
+
+fn bench_aead(c: &mut Criterion) {
+    let key = random_bytes(32);
+    let aad = random_bytes(41);
+    let mut group = c.benchmark_group("aead");
+    for (aead, name) in AEADS {
+        let cipher = Cipher::new(aead, &key).expect("cipher");
+        let nonce = random_bytes(aead.nonce_len());
+        for size in AEAD_SIZES {
+            let msg = random_bytes(size);
+            let ct = cipher.encrypt(&nonce, &msg, &aad).expect("encrypt");
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(format!("{}/encrypt", name), size), &msg, |b, msg| {
+                b.iter(|| cipher.encrypt(&nonce, black_box(msg), &aad).expect("encrypt"))
+            });
+            group.bench_with_input(BenchmarkId::new(format!("{}/decrypt", name), size), &ct, |b, ct| {
+                b.iter(|| cipher.decrypt(&nonce, black_box(ct), &aad).expect("decrypt"))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_file_encrypt(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("pitlink-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("temp dir");
+    pitlink_pqc::keygen(dir.clone(), KemId::MlKem768, false, None, Default::default(), true).expect("keygen");
+    let pubkey = dir.join("kyber_public.key");
+
+    let mut group = c.benchmark_group("file_encrypt");
+    group.sample_size(10);
+    for size in FILE_SIZES {
+        let input = dir.join(format!("plain-{}", size));
+        std::fs::write(&input, random_bytes(size)).expect("write input");
+        let output = dir.join(format!("package-{}", size));
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(BenchmarkId::new("ml-kem-768", size), |b| {
+            b.iter(|| {
+                let opts = EncryptOptions { force: true, ..Default::default() };
+                pitlink_pqc::encrypt_file(input.clone(), output.clone(), pubkey.clone(), opts).expect("encrypt")
+            })
+        });
+    }
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, bench_kem, bench_hkdf, bench_aead, bench_file_encrypt);
+criterion_main!(benches);
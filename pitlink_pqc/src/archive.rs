@@ -0,0 +1,324 @@
+//! Directory trees packed into a single plaintext stream for `encrypt --recursive`
+//!
+//! One record per entry, parents before children, then an end marker:
+//!
+//! `kind u8 | path_len u16 | path | mode u32 | mtime u64 | size u64 | data[size]`
+//!
+//! Paths are relative, UTF-8 and `/`-separated; `mode` holds the Unix permission
+//! bits and `mtime` is in seconds since the epoch. Symlinks and special files are
+//! skipped when packing. Extraction refuses absolute paths, `..` components and
+//! paths that would pass through a symlink.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use tracing::{debug, warn};
+
+const KIND_END: u8 = 0;
+const KIND_DIR: u8 = 1;
+const KIND_FILE: u8 = 2;
+
+/// `mode u32 | mtime u64 | size u64` following the path
+const RECORD_TAIL_LEN: usize = 4 + 8 + 8;
+const MAX_PATH_LEN: usize = u16::MAX as usize;
+
+struct Entry {
+    kind: u8,
+    path: String,
+    source: PathBuf,
+    mode: u32,
+    mtime: u64,
+    size: u64,
+}
+
+impl Entry {
+    fn record_header(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(3 + self.path.len() + RECORD_TAIL_LEN);
+        out.push(self.kind);
+        out.extend_from_slice(&(self.path.len() as u16).to_be_bytes());
+        out.extend_from_slice(self.path.as_bytes());
+        out.extend_from_slice(&self.mode.to_be_bytes());
+        out.extend_from_slice(&self.mtime.to_be_bytes());
+        out.extend_from_slice(&self.size.to_be_bytes());
+        out
+    }
+}
+
+/// Streams the archive of a directory tree; the tree is listed up front and file
+/// contents are read as the stream is consumed
+pub struct ArchiveReader {
+    entries: Vec<Entry>,
+    next: usize,
+    pending: Vec<u8>,
+    pending_pos: usize,
+    file: Option<(File, u64)>,
+    finished: bool,
+}
+
+impl ArchiveReader {
+    pub fn new(root: &Path) -> Result<Self> {
+        if !root.is_dir() {
+            bail!("{} is not a directory", root.display());
+        }
+        let mut entries = Vec::new();
+        walk(root, "", &mut entries)?;
+        Ok(Self { entries, next: 0, pending: Vec::new(), pending_pos: 0, file: None, finished: false })
+    }
+
+    /// Number of files and directories in the archive
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Total length of the stream, end marker included
+    pub fn stream_len(&self) -> u64 {
+        let records: u64 = self.entries.iter().map(|e| (3 + e.path.len() + RECORD_TAIL_LEN) as u64 + e.size).sum();
+        records + 1
+    }
+
+    /// Queue the next record header, opening its file; false once the end marker is queued
+    fn advance(&mut self) -> std::io::Result<bool> {
+        let Some(entry) = self.entries.get(self.next) else {
+            if self.finished { return Ok(false); }
+            self.finished = true;
+            self.pending = vec![KIND_END];
+            self.pending_pos = 0;
+            return Ok(true);
+        };
+        self.next += 1;
+        self.pending = entry.record_header();
+        self.pending_pos = 0;
+        if entry.kind == KIND_FILE && entry.size > 0 {
+            self.file = Some((File::open(&entry.source)?, entry.size));
+        }
+        Ok(true)
+    }
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.pending_pos < self.pending.len() {
+                let n = buf.len().min(self.pending.len() - self.pending_pos);
+                buf[..n].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + n]);
+                self.pending_pos += n;
+                return Ok(n);
+            }
+            if let Some((file, remaining)) = &mut self.file {
+                // Compare in u64: a file over 4 GiB would wrap `remaining` on 32-bit targets
+                let want = (*remaining).min(buf.len() as u64) as usize;
+                let n = file.read(&mut buf[..want])?;
+                if n == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "file shrank while archiving"));
+                }
+                *remaining -= n as u64;
+                if *remaining == 0 { self.file = None; }
+                return Ok(n);
+            }
+            if !self.advance()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// List `dir` depth-first in name order so both encrypt passes see the same stream
+fn walk(dir: &Path, prefix: &str, entries: &mut Vec<Entry>) -> Result<()> {
+    let mut children: Vec<_> = std::fs::read_dir(dir)?.collect::<std::io::Result<_>>()?;
+    children.sort_by_key(|e| e.file_name());
+    for child in children {
+        let name = child.file_name();
+        let Some(name) = name.to_str() else {
+            warn!(target: "io", "skipping non-UTF-8 name in {}", dir.display());
+            continue;
+        };
+        let path = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+        if path.len() > MAX_PATH_LEN { bail!("path too long: {}", path); }
+        let meta = std::fs::symlink_metadata(child.path())?;
+        let kind = if meta.is_dir() {
+            KIND_DIR
+        } else if meta.is_file() {
+            KIND_FILE
+        } else {
+            warn!(target: "io", "skipping {} (not a regular file or directory)", child.path().display());
+            continue;
+        };
+        let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+        let size = if kind == KIND_FILE { meta.len() } else { 0 };
+        entries.push(Entry { kind, path: path.clone(), source: child.path(), mode: mode_of(&meta, kind), mtime, size });
+        if kind == KIND_DIR {
+            walk(&child.path(), &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode_of(meta: &std::fs::Metadata, _kind: u8) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(meta: &std::fs::Metadata, kind: u8) -> u32 {
+    let base = if kind == KIND_DIR { 0o755 } else { 0o644 };
+    if meta.permissions().readonly() { base & !0o222 } else { base }
+}
+
+/// Resolve an archive path under `root`, refusing anything that could escape it
+fn safe_join(root: &Path, path: &str) -> Result<PathBuf> {
+    if path.is_empty() || path.contains(['\\', '\0']) {
+        bail!("unsafe path in archive: {:?}", path);
+    }
+    let mut out = root.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) if !part.to_string_lossy().contains(':') => out.push(part),
+            _ => bail!("unsafe path in archive: {:?}", path),
+        }
+        // An existing symlink (e.g. when extracting into an existing directory) could redirect writes
+        if std::fs::symlink_metadata(&out).is_ok_and(|m| m.file_type().is_symlink()) {
+            bail!("refusing to extract through symlink {}", out.display());
+        }
+    }
+    Ok(out)
+}
+
+enum State {
+    Header,
+    Data { file: File, path: PathBuf, remaining: u64, mode: u32, mtime: u64 },
+    End,
+}
+
+/// Restores an archive stream under a directory as plaintext is written to it
+pub struct Extractor {
+    root: PathBuf,
+    buf: Vec<u8>,
+    state: State,
+    /// Directory permissions and mtimes, applied once their contents are in place
+    dirs: Vec<(PathBuf, u32, u64)>,
+    entries: u64,
+}
+
+impl Extractor {
+    pub fn new(root: &Path) -> Result<Self> {
+        std::fs::create_dir_all(root)?;
+        Ok(Self { root: root.to_path_buf(), buf: Vec::new(), state: State::Header, dirs: Vec::new(), entries: 0 })
+    }
+
+    /// Check the archive ended cleanly and apply directory metadata; returns the entry count
+    pub fn finish(mut self) -> Result<u64> {
+        if !matches!(self.state, State::End) {
+            bail!("directory archive is truncated");
+        }
+        for (path, mode, mtime) in self.dirs.drain(..).rev() {
+            apply_metadata(&path, mode, mtime, true);
+        }
+        Ok(self.entries)
+    }
+
+    fn process(&mut self) -> Result<()> {
+        loop {
+            match &mut self.state {
+                State::Header => {
+                    let Some(&kind) = self.buf.first() else { return Ok(()) };
+                    if kind == KIND_END {
+                        self.buf.drain(..1);
+                        self.state = State::End;
+                        continue;
+                    }
+                    if self.buf.len() < 3 { return Ok(()); }
+                    let path_len = u16::from_be_bytes([self.buf[1], self.buf[2]]) as usize;
+                    let total = 3 + path_len + RECORD_TAIL_LEN;
+                    if self.buf.len() < total { return Ok(()); }
+                    let path = std::str::from_utf8(&self.buf[3..3 + path_len]).context("archive path is not UTF-8")?.to_string();
+                    let tail = &self.buf[3 + path_len..total];
+                    let mode = u32::from_be_bytes(tail[0..4].try_into().expect("4 bytes"));
+                    let mtime = u64::from_be_bytes(tail[4..12].try_into().expect("8 bytes"));
+                    let size = u64::from_be_bytes(tail[12..20].try_into().expect("8 bytes"));
+                    self.buf.drain(..total);
+
+                    let target = safe_join(&self.root, &path)?;
+                    self.entries += 1;
+                    match kind {
+                        KIND_DIR => {
+                            std::fs::create_dir_all(&target)?;
+                            self.dirs.push((target, mode, mtime));
+                        }
+                        KIND_FILE => {
+                            if let Some(parent) = target.parent() { std::fs::create_dir_all(parent)?; }
+                            debug!(target: "io", "extracting {} ({} bytes)", path, size);
+                            let file = File::create(&target).with_context(|| format!("cannot create {}", target.display()))?;
+                            self.state = State::Data { file, path: target, remaining: size, mode, mtime };
+                        }
+                        other => bail!("unknown archive entry kind {}", other),
+                    }
+                }
+                State::Data { file, path, remaining, mode, mtime } => {
+                    let n = (*remaining).min(self.buf.len() as u64) as usize;
+                    file.write_all(&self.buf[..n])?;
+                    self.buf.drain(..n);
+                    *remaining -= n as u64;
+                    if *remaining > 0 { return Ok(()); }
+                    let (path, mode, mtime) = (path.clone(), *mode, *mtime);
+                    self.state = State::Header;
+                    apply_metadata(&path, mode, mtime, false);
+                }
+                State::End => {
+                    if !self.buf.is_empty() { bail!("unexpected data after the end of the directory archive"); }
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl Write for Extractor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        self.process().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:#}", e)))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Best-effort restore of permissions and mtime
+fn apply_metadata(path: &Path, mode: u32, mtime: u64, is_dir: bool) {
+    // Directories can only be opened (and so timestamped) this way on Unix
+    if !is_dir || cfg!(unix) {
+        if let Ok(f) = File::options().write(!is_dir).read(is_dir).open(path) {
+            let _ = f.set_modified(UNIX_EPOCH + Duration::from_secs(mtime));
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777));
+    }
+    #[cfg(not(unix))]
+    if let Ok(meta) = std::fs::metadata(path) {
+        let mut perms = meta.permissions();
+        perms.set_readonly(mode & 0o222 == 0);
+        let _ = std::fs::set_permissions(path, perms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_join_rejects_escapes() {
+        let root = Path::new("out");
+        assert_eq!(safe_join(root, "a/b.txt").unwrap(), root.join("a").join("b.txt"));
+        for bad in ["../x", "a/../../x", "/etc/passwd", "", "a\\..\\x", "C:/x", "./a"] {
+            assert!(safe_join(root, bad).is_err(), "{:?} accepted", bad);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! PEM-style ASCII armor
+//!
+//! ```text
+//! -----BEGIN <LABEL>-----
+//! Key: value
+//!
+//! base64 body wrapped at 64 columns
+//! -----END <LABEL>-----
+//! ```
+
+use anyhow::{bail, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+const LINE_WIDTH: usize = 64;
+
+/// A decoded armor block
+#[derive(Debug, Clone)]
+pub struct Armored {
+    pub label: String,
+    pub headers: Vec<(String, String)>,
+    pub data: Vec<u8>,
+}
+
+impl Armored {
+    /// Look up a header value
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Whether `bytes` look like an armor block (ignoring leading whitespace)
+pub fn is_armored(bytes: &[u8]) -> bool {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    bytes[start..].starts_with(b"-----BEGIN ")
+}
+
+/// Encode `data` as an armor block
+pub fn encode(label: &str, headers: &[(&str, String)], data: &[u8]) -> String {
+    let body = STANDARD.encode(data);
+    let mut out = format!("-----BEGIN {}-----\n", label);
+    for (k, v) in headers {
+        out.push_str(&format!("{}: {}\n", k, v));
+    }
+    if !headers.is_empty() {
+        out.push('\n');
+    }
+    for line in body.as_bytes().chunks(LINE_WIDTH) {
+        out.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+/// Decode the first armor block in `bytes`
+pub fn decode(bytes: &[u8]) -> Result<Armored> {
+    let text = std::str::from_utf8(bytes).map_err(|_| anyhow::anyhow!("armored data is not UTF-8"))?;
+    let mut lines = text.lines().map(str::trim).skip_while(|l| l.is_empty());
+
+    let begin = lines.next().unwrap_or_default();
+    let label = begin
+        .strip_prefix("-----BEGIN ")
+        .and_then(|l| l.strip_suffix("-----"))
+        .ok_or_else(|| anyhow::anyhow!("missing armor BEGIN line"))?
+        .to_string();
+    let end = format!("-----END {}-----", label);
+
+    let mut headers = Vec::new();
+    let mut body = String::new();
+    let mut in_headers = true;
+    let mut ended = false;
+    for line in lines {
+        if line == end {
+            ended = true;
+            break;
+        }
+        if in_headers {
+            if line.is_empty() {
+                in_headers = false;
+                continue;
+            }
+            if let Some((k, v)) = line.split_once(": ") {
+                headers.push((k.to_string(), v.to_string()));
+                continue;
+            }
+            in_headers = false;
+        }
+        body.push_str(line);
+    }
+    if !ended { bail!("missing armor END line for {}", label); }
+
+    let data = STANDARD.decode(body.as_bytes()).map_err(|e| anyhow::anyhow!("invalid armor body: {}", e))?;
+    Ok(Armored { label, headers, data })
+}
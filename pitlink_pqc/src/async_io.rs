@@ -0,0 +1,73 @@
+//! tokio `AsyncRead`/`AsyncWrite` versions of [`encrypt`](crate::encrypt) and
+//! [`decrypt`](crate::decrypt), behind the `async` feature
+//!
+//! The package code runs on tokio's blocking pool and reaches the async streams
+//! through `SyncIoBridge`, so neither the AEAD work nor the chunk pipeline holds up
+//! the runtime's worker threads. Must be called from within a tokio runtime.
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+use crate::keyfile::{KeyFile, SigningKeyFile};
+use crate::{EncryptOptions, PackageSummary};
+
+/// Encrypt everything read from `input` to `recipient`, writing the package to
+/// `output`, which is flushed and handed back. See [`crate::encrypt`].
+pub async fn encrypt<R, W>(input: R, output: W, recipient: KeyFile, signer: Option<SigningKeyFile>, opts: EncryptOptions) -> Result<(W, PackageSummary)>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut output = SyncIoBridge::new(output);
+        let summary = crate::encrypt(SyncIoBridge::new(input), &mut output, &recipient, signer.as_ref(), &opts)?;
+        Ok((output.into_inner(), summary))
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!("encryption task failed: {}", e))?
+}
+
+/// Decrypt the package read from `input` with `identity`, writing the plaintext to
+/// `output`, which is flushed and handed back. As with [`crate::decrypt`], discard
+/// whatever reached `output` when an error is returned.
+pub async fn decrypt<R, W>(input: R, output: W, identity: KeyFile, sender: Option<SigningKeyFile>) -> Result<(W, PackageSummary)>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut output = SyncIoBridge::new(output);
+        let summary = crate::decrypt(SyncIoBridge::new(input), &mut output, &identity, sender.as_ref())?;
+        Ok((output.into_inner(), summary))
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!("decryption task failed: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::keyfile::KeyMetadata;
+    use crate::{generate_keypair, KemId};
+    use common::container::MIN_CHUNK_SIZE;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, KeyMetadata::default()).unwrap();
+        let plaintext: Vec<u8> = (0..2 * MIN_CHUNK_SIZE + 11).map(|i| i as u8).collect();
+        let opts = EncryptOptions { chunk_size: MIN_CHUNK_SIZE, ..Default::default() };
+
+        let (package, summary) = encrypt(Cursor::new(plaintext.clone()), Vec::new(), public, None, opts).await.unwrap();
+        assert_eq!(summary.chunks, 3);
+        let (decrypted, _) = decrypt(Cursor::new(package.clone()), Vec::new(), private.clone(), None).await.unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        let mut tampered = package;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(decrypt(Cursor::new(tampered), Vec::new(), private, None).await.is_err());
+    }
+}
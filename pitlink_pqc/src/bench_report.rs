@@ -0,0 +1,115 @@
+//! Summaries of criterion results for `bench-report`
+//!
+//! `cargo bench -p pitlink_pqc` leaves one `new/benchmark.json` + `new/estimates.json`
+//! pair per benchmark under `target/criterion`. The report flattens them into a
+//! single document the dashboard serves from `/api/benchmarks`:
+//!
+//! ```json
+//! { "source": "criterion", "generated": "...", "benchmarks": [
+//!   { "group": "aead", "function": "xchacha20poly1305/encrypt", "parameter": "1024",
+//!     "mean_ns": 812.4, "median_ns": 809.1, "std_dev_ns": 11.2,
+//!     "throughput_bytes": 1024, "mib_per_s": 1202.1 } ] }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+struct BenchmarkId {
+    group_id: String,
+    function_id: Option<String>,
+    value_str: Option<String>,
+    throughput: Option<CriterionThroughput>,
+}
+
+#[derive(Deserialize)]
+enum CriterionThroughput {
+    Bytes(u64),
+    BytesDecimal(u64),
+    Elements(u64),
+}
+
+#[derive(Deserialize)]
+struct Estimates {
+    mean: Estimate,
+    median: Estimate,
+    std_dev: Estimate,
+}
+
+#[derive(Deserialize)]
+struct Estimate {
+    point_estimate: f64,
+}
+
+/// One benchmark's timing per iteration
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchResult {
+    pub group: String,
+    pub function: Option<String>,
+    pub parameter: Option<String>,
+    pub mean_ns: f64,
+    pub median_ns: f64,
+    pub std_dev_ns: f64,
+    /// Bytes processed per iteration, for throughput benchmarks
+    pub throughput_bytes: Option<u64>,
+    pub mib_per_s: Option<f64>,
+}
+
+impl BenchResult {
+    pub fn name(&self) -> String {
+        [Some(self.group.as_str()), self.function.as_deref(), self.parameter.as_deref()]
+            .into_iter().flatten().collect::<Vec<_>>().join("/")
+    }
+}
+
+/// Collect the latest result of every benchmark under a criterion output directory
+pub fn collect(dir: &Path) -> Result<Vec<BenchResult>> {
+    if !dir.is_dir() {
+        anyhow::bail!("{} not found (run `cargo bench -p pitlink_pqc` first)", dir.display());
+    }
+    let mut runs = Vec::new();
+    find_runs(dir, &mut runs)?;
+    let mut results = runs.iter().map(|run| read_run(run)).collect::<Result<Vec<_>>>()?;
+    results.sort_by_key(|r| r.name());
+    Ok(results)
+}
+
+/// Every `new` directory holding a benchmark description and its estimates
+fn find_runs(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() { continue; }
+        if path.file_name().is_some_and(|n| n == "new") {
+            if path.join("benchmark.json").is_file() && path.join("estimates.json").is_file() {
+                out.push(path);
+            }
+        } else {
+            find_runs(&path, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_run(dir: &Path) -> Result<BenchResult> {
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).with_context(|| format!("cannot read {}", dir.join(name).display()));
+    let id: BenchmarkId = serde_json::from_str(&read("benchmark.json")?).with_context(|| format!("invalid benchmark.json in {}", dir.display()))?;
+    let est: Estimates = serde_json::from_str(&read("estimates.json")?).with_context(|| format!("invalid estimates.json in {}", dir.display()))?;
+    let throughput_bytes = match id.throughput {
+        Some(CriterionThroughput::Bytes(n) | CriterionThroughput::BytesDecimal(n)) => Some(n),
+        Some(CriterionThroughput::Elements(_)) | None => None,
+    };
+    let mean_ns = est.mean.point_estimate;
+    let mib_per_s = throughput_bytes.filter(|_| mean_ns > 0.0).map(|n| n as f64 / (1024.0 * 1024.0) / (mean_ns / 1e9));
+    Ok(BenchResult {
+        group: id.group_id,
+        function: id.function_id,
+        parameter: id.value_str,
+        mean_ns,
+        median_ns: est.median.point_estimate,
+        std_dev_ns: est.std_dev.point_estimate,
+        throughput_bytes,
+        mib_per_s,
+    })
+}
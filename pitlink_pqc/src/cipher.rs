@@ -0,0 +1,126 @@
+//! AEAD cipher dispatch
+//!
+//! The AEAD id in the package header selects the cipher used for both the key wrap
+//! and the chunk stream.
+
+use std::borrow::Cow;
+
+use anyhow::Result;
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, AeadInPlace, KeyInit, Payload};
+
+use common::container::{AeadId, ChunkKdf};
+use zeroize::Zeroizing;
+
+/// An initialized AEAD for one of the supported ciphers
+#[derive(Clone)]
+pub enum Cipher {
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    Aes256Gcm(Aes256Gcm),
+}
+
+impl Cipher {
+    /// Create a cipher from a 32-byte key
+    pub fn new(aead: AeadId, key: &[u8]) -> Result<Self> {
+        Ok(match aead {
+            AeadId::XChaCha20Poly1305 => Cipher::XChaCha20Poly1305(
+                XChaCha20Poly1305::new_from_slice(key).map_err(|e| anyhow::anyhow!("invalid key: {}", e))?,
+            ),
+            AeadId::Aes256Gcm => Cipher::Aes256Gcm(
+                Aes256Gcm::new_from_slice(key).map_err(|e| anyhow::anyhow!("invalid key: {}", e))?,
+            ),
+        })
+    }
+
+    /// Parse a `--cipher` value
+    pub fn parse_id(name: &str) -> Result<AeadId> {
+        match name {
+            "xchacha20poly1305" => Ok(AeadId::XChaCha20Poly1305),
+            "aes256gcm" => Ok(AeadId::Aes256Gcm),
+            other => anyhow::bail!("unsupported cipher '{}' (expected xchacha20poly1305 or aes256gcm)", other),
+        }
+    }
+
+    /// The header id of this cipher
+    pub fn id(&self) -> AeadId {
+        match self {
+            Cipher::XChaCha20Poly1305(_) => AeadId::XChaCha20Poly1305,
+            Cipher::Aes256Gcm(_) => AeadId::Aes256Gcm,
+        }
+    }
+
+    pub fn encrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        let payload = Payload { msg, aad };
+        match self {
+            Cipher::XChaCha20Poly1305(c) => c.encrypt(nonce.into(), payload),
+            Cipher::Aes256Gcm(c) => c.encrypt(nonce.into(), payload),
+        }
+        .map_err(|e| anyhow::anyhow!("AEAD encrypt: {}", e))
+    }
+
+    pub fn decrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        let payload = Payload { msg, aad };
+        match self {
+            Cipher::XChaCha20Poly1305(c) => c.decrypt(nonce.into(), payload),
+            Cipher::Aes256Gcm(c) => c.decrypt(nonce.into(), payload),
+        }
+        .map_err(|e| anyhow::anyhow!("AEAD decrypt: {}", e))
+    }
+
+    /// Encrypt `buf` in place, appending the tag
+    pub fn encrypt_in_place(&self, nonce: &[u8], aad: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+        self.check_nonce(nonce)?;
+        match self {
+            Cipher::XChaCha20Poly1305(c) => c.encrypt_in_place(nonce.into(), aad, buf),
+            Cipher::Aes256Gcm(c) => c.encrypt_in_place(nonce.into(), aad, buf),
+        }
+        .map_err(|e| anyhow::anyhow!("AEAD encrypt: {}", e))
+    }
+
+    /// Decrypt `buf` in place, leaving the plaintext without the tag
+    pub fn decrypt_in_place(&self, nonce: &[u8], aad: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+        self.check_nonce(nonce)?;
+        match self {
+            Cipher::XChaCha20Poly1305(c) => c.decrypt_in_place(nonce.into(), aad, buf),
+            Cipher::Aes256Gcm(c) => c.decrypt_in_place(nonce.into(), aad, buf),
+        }
+        .map_err(|e| anyhow::anyhow!("AEAD decrypt: {}", e))
+    }
+
+    fn check_nonce(&self, nonce: &[u8]) -> Result<()> {
+        if nonce.len() != self.id().nonce_len() {
+            anyhow::bail!("nonce has length {}, expected {} for {:?}", nonce.len(), self.id().nonce_len(), self.id());
+        }
+        Ok(())
+    }
+}
+
+/// Frame keys of a package: the file key for every frame, or a subkey per frame
+/// position when the header records a [`ChunkKdf`]
+pub enum FrameKeys {
+    Shared(Cipher),
+    PerFrame { aead: AeadId, kdf: ChunkKdf, file_key: Zeroizing<[u8; 32]> },
+}
+
+impl FrameKeys {
+    pub fn new(aead: AeadId, file_key: &[u8], kdf: Option<ChunkKdf>) -> Result<Self> {
+        Ok(match kdf {
+            None => FrameKeys::Shared(Cipher::new(aead, file_key)?),
+            Some(kdf) => {
+                let file_key = <[u8; 32]>::try_from(file_key).map_err(|_| anyhow::anyhow!("file key has length {}, expected 32", file_key.len()))?;
+                FrameKeys::PerFrame { aead, kdf, file_key: Zeroizing::new(file_key) }
+            }
+        })
+    }
+
+    /// Cipher of the frame at `seq`
+    pub fn at(&self, seq: u64) -> Result<Cow<'_, Cipher>> {
+        match self {
+            FrameKeys::Shared(cipher) => Ok(Cow::Borrowed(cipher)),
+            FrameKeys::PerFrame { aead, kdf, file_key } => Ok(Cow::Owned(Cipher::new(*aead, &kdf.frame_key(&file_key[..], seq)?[..])?)),
+        }
+    }
+}
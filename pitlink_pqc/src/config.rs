@@ -0,0 +1,124 @@
+//! User defaults from `~/.config/pitlink/config.toml` and `PITLINK_*` variables
+//!
+//! Command-line flags take precedence over environment variables, which take
+//! precedence over the config file.
+//!
+//! ```toml
+//! identity = "laptop"                 # keyring name or private key path
+//! recipient = "keys/kyber_public.key" # keyring name or public key path
+//! cipher = "aes256gcm"
+//! chunk_size = "4M"
+//! peers = ["3f2a:9c41:..."]           # signing keys send/receive --auth-key accept
+//! metrics_url = "http://dashboard:8080/api/metrics"
+//! metrics_key = "plk_..."            # dashboard API key (dashboard add-key NAME)
+//! node_id = "car-12"                  # reported to the dashboard (default: host name)
+//! s3_endpoint = "https://minio.pit:9000" # S3-compatible gateway for --upload/--download
+//! s3_region = "eu-west-1"
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Decryption key used when neither --privkey nor --identity is given
+    pub identity: Option<String>,
+    /// Encryption key used when neither --pubkey nor --recipient is given
+    pub recipient: Option<String>,
+    /// AEAD used when encrypt gets no --cipher
+    pub cipher: Option<String>,
+    /// Chunk size used when encrypt gets no --chunk-size, e.g. "64K"
+    pub chunk_size: Option<String>,
+    /// Peer signing-key fingerprints used when send/receive --auth-key get no --peer
+    pub peers: Vec<String>,
+    /// Dashboard endpoint encrypt/decrypt/benchmark results are POSTed to when --metrics-url is not given
+    pub metrics_url: Option<String>,
+    /// API key sent with metrics reports
+    pub metrics_key: Option<String>,
+    /// Name this machine reports metrics under (default: the host name)
+    pub node_id: Option<String>,
+    /// S3-compatible endpoint for --upload/--download (default: AWS)
+    pub s3_endpoint: Option<String>,
+    /// S3 region (default: AWS_REGION, else us-east-1)
+    pub s3_region: Option<String>,
+}
+
+impl Config {
+    /// `$PITLINK_CONFIG`, else `$XDG_CONFIG_HOME/pitlink/config.toml` or `~/.config/pitlink/config.toml`
+    pub fn default_path() -> Result<PathBuf> {
+        if let Some(path) = std::env::var_os("PITLINK_CONFIG") {
+            return Ok(PathBuf::from(path));
+        }
+        let base = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => dirs::home_dir().context("cannot determine home directory")?.join(".config"),
+        };
+        Ok(base.join("pitlink").join("config.toml"))
+    }
+
+    /// Load the config file (absent is fine) and apply environment overrides
+    pub fn load() -> Result<Self> {
+        let path = Self::default_path()?;
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(text) => Self::from_toml(&text).with_context(|| format!("invalid config file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+        };
+        config.apply_env();
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    fn apply_env(&mut self) {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        if let Some(v) = var("PITLINK_IDENTITY") { self.identity = Some(v); }
+        if let Some(v) = var("PITLINK_RECIPIENT") { self.recipient = Some(v); }
+        if let Some(v) = var("PITLINK_CIPHER") { self.cipher = Some(v); }
+        if let Some(v) = var("PITLINK_CHUNK_SIZE") { self.chunk_size = Some(v); }
+        if let Some(v) = var("PITLINK_METRICS_URL") { self.metrics_url = Some(v); }
+        if let Some(v) = var("PITLINK_METRICS_KEY") { self.metrics_key = Some(v); }
+        if let Some(v) = var("PITLINK_NODE_ID") { self.node_id = Some(v); }
+        if let Some(v) = var("PITLINK_S3_ENDPOINT") { self.s3_endpoint = Some(v); }
+        if let Some(v) = var("PITLINK_S3_REGION") { self.s3_region = Some(v); }
+    }
+
+    /// The default identity as `(path, keyring name)`
+    pub fn identity_key(&self) -> (Option<PathBuf>, Option<String>) {
+        key_ref(self.identity.as_deref())
+    }
+
+    /// The default recipient as `(path, keyring name)`
+    pub fn recipient_key(&self) -> (Option<PathBuf>, Option<String>) {
+        key_ref(self.recipient.as_deref())
+    }
+}
+
+/// A config key reference is a path when it contains a separator or names an
+/// existing file, otherwise a keyring name
+fn key_ref(value: Option<&str>) -> (Option<PathBuf>, Option<String>) {
+    match value {
+        Some(v) if v.contains(['/', '\\']) || Path::new(v).is_file() => (Some(PathBuf::from(v)), None),
+        Some(v) => (None, Some(v.to_string())),
+        None => (None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_as_paths_or_names() {
+        let config = Config::from_toml("identity = \"laptop\"\nrecipient = \"keys/kyber_public.key\"\ncipher = \"aes256gcm\"\n").unwrap();
+        assert_eq!(config.identity_key(), (None, Some("laptop".to_string())));
+        assert_eq!(config.recipient_key(), (Some(PathBuf::from("keys/kyber_public.key")), None));
+        assert_eq!(config.cipher.as_deref(), Some("aes256gcm"));
+        assert!(Config::from_toml("chunk = 1").is_err());
+    }
+}
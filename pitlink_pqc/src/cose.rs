@@ -0,0 +1,119 @@
+//! COSE_Encrypt (RFC 9052) envelopes for packages
+//!
+//! The envelope carries the key-establishment half of a package so standards-based
+//! tooling can find and unwrap the content-encryption key (CEK):
+//!
+//! ```text
+//! COSE_Encrypt (tag 96)
+//!   protected    { alg: content AEAD }
+//!   unprotected  { content type: application/vnd.pitlink.package }
+//!   ciphertext   nil (detached: the package's chunk frames)
+//!   recipients   [ protected   { alg: pitlink KEM }
+//!                  unprotected { IV: wrap nonce, kid: recipient hint,
+//!                                -65537: KEM ciphertext, -65538: wrap AAD }
+//!                  ciphertext  wrapped CEK ] ]
+//! ```
+//!
+//! Decapsulating the KEM ciphertext gives the KEK; the CEK is the wrapped key opened
+//! with the content AEAD under the KEK, using the wrap AAD (the package header up to
+//! the wrapped key) rather than a COSE Enc_structure. XChaCha20-Poly1305 and the
+//! post-quantum KEMs have no IANA COSE algorithms yet, so they use private-use values.
+
+use anyhow::{anyhow, Result};
+use coset::cbor::value::Value;
+use coset::{iana, Algorithm, CoseEncryptBuilder, CoseRecipientBuilder, HeaderBuilder, TaggedCborSerializable};
+
+use common::container::{AeadId, FormatVersion, Header, KemId, EXT_RECIPIENT_HINT};
+
+/// Content type of the detached ciphertext
+pub const CONTENT_TYPE: &str = "application/vnd.pitlink.package";
+
+/// Recipient header label holding the KEM ciphertext
+pub const LABEL_KEM_CIPHERTEXT: i64 = -65537;
+
+/// Recipient header label holding the AAD the CEK was wrapped with
+pub const LABEL_WRAP_AAD: i64 = -65538;
+
+/// Private-use algorithm for XChaCha20-Poly1305
+pub const ALG_XCHACHA20_POLY1305: i64 = -65537;
+
+/// Private-use KEM algorithms are this value minus the pitlink KEM id
+pub const ALG_KEM_BASE: i64 = -65792;
+
+/// COSE algorithm of an AEAD
+pub fn aead_alg(aead: AeadId) -> Algorithm {
+    match aead {
+        AeadId::Aes256Gcm => Algorithm::Assigned(iana::Algorithm::A256GCM),
+        AeadId::XChaCha20Poly1305 => Algorithm::PrivateUse(ALG_XCHACHA20_POLY1305),
+    }
+}
+
+/// COSE algorithm of a KEM
+pub fn kem_alg(kem: KemId) -> Algorithm {
+    Algorithm::PrivateUse(ALG_KEM_BASE - i64::from(kem.as_u8()))
+}
+
+/// Tagged COSE_Encrypt bytes for the package whose header is `header`
+pub fn envelope(header: &Header) -> Result<Vec<u8>> {
+    let mut content = HeaderBuilder::new().build();
+    content.alg = Some(aead_alg(header.aead));
+
+    let mut recipient = HeaderBuilder::new().build();
+    recipient.alg = Some(kem_alg(header.kem));
+    // v1 wrapped the CEK without AAD
+    let wrap_aad = if header.version == FormatVersion::V1 { Vec::new() } else { header.wrap_aad() };
+    let mut unprotected = HeaderBuilder::new()
+        .iv(header.wrap_nonce.clone())
+        .value(LABEL_KEM_CIPHERTEXT, Value::Bytes(header.kem_ct.clone()))
+        .value(LABEL_WRAP_AAD, Value::Bytes(wrap_aad));
+    if let Some(hint) = header.extension(EXT_RECIPIENT_HINT) {
+        unprotected = unprotected.key_id(hint.to_vec());
+    }
+
+    CoseEncryptBuilder::new()
+        .protected(content)
+        .unprotected(HeaderBuilder::new().content_type(CONTENT_TYPE.to_string()).build())
+        .add_recipient(
+            CoseRecipientBuilder::new()
+                .protected(recipient)
+                .unprotected(unprotected.build())
+                .ciphertext(header.wrapped_key.clone())
+                .build(),
+        )
+        .build()
+        .to_tagged_vec()
+        .map_err(|e| anyhow!("COSE encoding: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use coset::{CoseEncrypt, Label};
+
+    use super::*;
+    use crate::cipher::Cipher;
+    use crate::{encrypt, generate_keypair, kem, EncryptOptions, ExposeSecret};
+
+    #[test]
+    fn envelope_unwraps_the_cek() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let mut package = Vec::new();
+        encrypt(&b"partner handoff"[..], &mut package, &public, None, &EncryptOptions::default()).unwrap();
+        let (header, _) = Header::read_from(&mut &package[..]).unwrap();
+
+        let cose = CoseEncrypt::from_tagged_slice(&envelope(&header).unwrap()).unwrap();
+        assert_eq!(cose.protected.header.alg, Some(aead_alg(header.aead)));
+        assert!(cose.ciphertext.is_none());
+        let [recipient] = cose.recipients.as_slice() else { panic!("expected one recipient") };
+        assert_eq!(recipient.protected.header.alg, Some(kem_alg(KemId::MlKem768)));
+        let label = |l: i64| match recipient.unprotected.rest.iter().find(|(k, _)| *k == Label::Int(l)) {
+            Some((_, Value::Bytes(b))) => b.clone(),
+            other => panic!("label {} missing: {:?}", l, other),
+        };
+
+        let kek = kem::decapsulate(KemId::MlKem768, private.key.expose_secret(), &label(LABEL_KEM_CIPHERTEXT)).unwrap();
+        let cek = Cipher::new(header.aead, kek.expose_secret()).unwrap()
+            .decrypt(&recipient.unprotected.iv, recipient.ciphertext.as_ref().unwrap(), &label(LABEL_WRAP_AAD))
+            .unwrap();
+        assert_eq!(cek.len(), 32);
+    }
+}
@@ -0,0 +1,514 @@
+//! Datagram mode: every UDP payload sealed on its own (`datagram send` / `datagram receive`)
+//!
+//! For radio-modem links where loss makes TCP collapse. Nothing is retransmitted
+//! and no datagram depends on another, so losing one loses only its own payload:
+//!
+//! ```text
+//! INIT  0x01 | key_id[8] | kem u8 | aead u8 | ct_len u16 | KEM ciphertext
+//! DATA  0x02 | key_id[8] | seq u64 | AEAD(payload)
+//! ```
+//!
+//! The traffic key comes from the KEM ciphertext in INIT; `key_id` is the start of
+//! the SHA-256 of `kem | aead | ciphertext`, so a receiver knows which key a DATA
+//! datagram needs, and an INIT with an altered algorithm byte names another key
+//! rather than taking the place of the real one. The sender repeats INIT every
+//! `init_every` datagrams, so a receiver that missed it or started late catches
+//! up. The nonce is the explicit sequence number and the AAD binds type, key id
+//! and sequence number. A sliding window of [`WINDOW`] sequence numbers drops
+//! replays and datagrams too old to tell apart from one, while reordered ones
+//! inside the window are accepted.
+//!
+//! ML-KEM decapsulates any ciphertext of the right length, so an INIT proves
+//! nothing by itself. Its key is held as provisional until a DATA datagram under
+//! it authenticates; only then may it push out a key already in use, so a flood
+//! of forged INITs cannot make the receiver forget a real sender.
+//!
+//! With `--fec K:M` the sender also adds Reed-Solomon PARITY datagrams after every
+//! K DATA datagrams (see [`fec`]), so a receiver rebuilds up to M lost ones per
+//! group without a way back to the sender. Receivers always understand them.
+
+mod fec;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use common::hkdf_derive;
+use common::container::{AeadId, KemId};
+
+use crate::cipher::Cipher;
+use crate::keyfile::{KeyFile, KeyKind};
+use self::fec::{ParityEncoder, Recovery, PACKET_PARITY};
+use crate::{check_overwrite, create_output, kem, open_input, output, perms, secret_bytes, ExposeSecret, SecretBytes};
+
+pub use self::fec::Fec;
+
+const PACKET_INIT: u8 = 1;
+const PACKET_DATA: u8 = 2;
+const KEY_ID_LEN: usize = 8;
+const DATA_HEADER_LEN: usize = 1 + KEY_ID_LEN + 8;
+const TRAFFIC_LABEL: &[u8] = b"pitlink-datagram-traffic-v1";
+/// Sequence numbers the replay window remembers below the highest one seen
+pub const WINDOW: u64 = 128;
+/// Payload bytes per datagram that stay under a 1280-byte path MTU
+pub const DEFAULT_MAX_PAYLOAD: usize = 1200;
+/// Senders whose keys a receiver keeps at once
+const MAX_KEYS: usize = 16;
+/// Keys from INITs that no DATA datagram has confirmed yet, kept at once
+const MAX_PENDING_KEYS: usize = 16;
+
+/// Which sequence numbers have been seen, for the last [`WINDOW`] of them
+#[derive(Debug, Default, Clone)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `i` is set when `highest - i` has been accepted
+    seen: u128,
+}
+
+impl ReplayWindow {
+    /// Whether `seq` is neither a replay nor older than the window
+    pub fn check(&self, seq: u64) -> bool {
+        let Some(highest) = self.highest else { return true };
+        if seq > highest {
+            return true;
+        }
+        let age = highest - seq;
+        age < WINDOW && self.seen & (1 << age) == 0
+    }
+
+    /// Record `seq`; call only once its datagram has authenticated
+    pub fn accept(&mut self, seq: u64) {
+        match self.highest {
+            Some(highest) if seq <= highest => self.seen |= 1 << (highest - seq),
+            Some(highest) => {
+                let shift = seq - highest;
+                self.seen = if shift >= WINDOW { 0 } else { self.seen << shift };
+                self.seen |= 1;
+                self.highest = Some(seq);
+            }
+            None => {
+                self.seen = 1;
+                self.highest = Some(seq);
+            }
+        }
+    }
+}
+
+fn traffic_cipher(aead: AeadId, shared: &[u8]) -> Result<Cipher> {
+    Cipher::new(aead, &hkdf_derive(shared, TRAFFIC_LABEL, 32)?)
+}
+
+fn nonce_aad(aead: AeadId, key_id: &[u8; KEY_ID_LEN], seq: u64) -> (Vec<u8>, [u8; DATA_HEADER_LEN]) {
+    let mut nonce = vec![0u8; aead.nonce_len()];
+    let at = nonce.len() - 8;
+    nonce[at..].copy_from_slice(&seq.to_be_bytes());
+    let mut header = [0u8; DATA_HEADER_LEN];
+    header[0] = PACKET_DATA;
+    header[1..1 + KEY_ID_LEN].copy_from_slice(key_id);
+    header[1 + KEY_ID_LEN..].copy_from_slice(&seq.to_be_bytes());
+    (nonce, header)
+}
+
+fn key_id(kem: u8, aead: u8, ct: &[u8]) -> [u8; KEY_ID_LEN] {
+    Sha256::new().chain_update([kem, aead]).chain_update(ct).finalize()[..KEY_ID_LEN].try_into().expect("8 bytes")
+}
+
+/// Seals payloads into independent datagrams under one encapsulated key
+pub struct DatagramSealer {
+    key_id: [u8; KEY_ID_LEN],
+    cipher: Cipher,
+    init: Vec<u8>,
+    seq: u64,
+}
+
+impl DatagramSealer {
+    /// Encapsulate a fresh traffic key to `recipient`
+    pub fn new(recipient: &KeyFile, aead: AeadId) -> Result<Self> {
+        let (ct, shared) = kem::encapsulate(recipient.kem, recipient.key.expose_secret())?;
+        let key_id = key_id(recipient.kem.as_u8(), aead.as_u8(), &ct);
+        let mut init = vec![PACKET_INIT];
+        init.extend_from_slice(&key_id);
+        init.extend_from_slice(&[recipient.kem.as_u8(), aead.as_u8()]);
+        init.extend_from_slice(&(ct.len() as u16).to_be_bytes());
+        init.extend_from_slice(&ct);
+        Ok(Self { key_id, cipher: traffic_cipher(aead, shared.expose_secret())?, init, seq: 0 })
+    }
+
+    /// The INIT datagram that lets a receiver derive the traffic key
+    pub fn init_datagram(&self) -> &[u8] {
+        &self.init
+    }
+
+    /// Seal `payload` into the next DATA datagram
+    pub fn seal(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let (nonce, header) = nonce_aad(self.cipher.id(), &self.key_id, self.seq);
+        let sealed = self.cipher.encrypt(&nonce, payload, &header)?;
+        self.seq = self.seq.checked_add(1).context("datagram sequence numbers exhausted")?;
+        let mut datagram = header.to_vec();
+        datagram.extend_from_slice(&sealed);
+        Ok(datagram)
+    }
+}
+
+struct ReceiveKey {
+    cipher: Cipher,
+    window: ReplayWindow,
+    last_used: Instant,
+}
+
+/// Opens datagrams from any number of senders to one private key
+pub struct DatagramOpener {
+    kem: KemId,
+    identity: SecretBytes,
+    /// Keys a DATA datagram has authenticated under
+    keys: HashMap<[u8; KEY_ID_LEN], ReceiveKey>,
+    /// Keys from INITs alone, until a DATA datagram confirms them
+    pending: HashMap<[u8; KEY_ID_LEN], ReceiveKey>,
+    recovery: Recovery,
+}
+
+/// What one received datagram turned out to be
+#[derive(Debug, PartialEq, Eq)]
+pub enum Opened {
+    /// An INIT; `true` when it brought a key not seen before
+    Init(bool),
+    Data { seq: u64, payload: Vec<u8> },
+    /// A PARITY datagram that let lost DATA datagrams be rebuilt, in sequence order
+    Recovered(Vec<(u64, Vec<u8>)>),
+}
+
+impl DatagramOpener {
+    pub fn new(identity: &KeyFile) -> Self {
+        Self { kem: identity.kem, identity: secret_bytes(identity.key.expose_secret().clone()), keys: HashMap::new(), pending: HashMap::new(), recovery: Recovery::default() }
+    }
+
+    /// Authenticate and decrypt one datagram. Errors mean the datagram should be
+    /// dropped (forged, replayed, too old, or for a key whose INIT has not arrived);
+    /// the opener itself is unaffected.
+    pub fn open(&mut self, datagram: &[u8]) -> Result<Opened> {
+        match datagram.first() {
+            Some(&PACKET_INIT) => self.open_init(&datagram[1..]).map(Opened::Init),
+            Some(&PACKET_DATA) => {
+                let (seq, payload) = self.open_data(datagram)?;
+                let id: [u8; KEY_ID_LEN] = datagram[1..1 + KEY_ID_LEN].try_into().expect("8 bytes");
+                self.recovery.keep(id, seq, datagram);
+                Ok(Opened::Data { seq, payload })
+            }
+            Some(&PACKET_PARITY) => {
+                let rebuilt = self.recovery.add_parity(datagram)?;
+                // A rebuilt datagram that fails here was forged or already arrived
+                Ok(Opened::Recovered(rebuilt.iter().filter_map(|datagram| self.open_data(datagram)
+                    .map_err(|e| debug!(target: "aead", "rebuilt datagram dropped: {:#}", e)).ok()).collect()))
+            }
+            _ => bail!("not a pitlink datagram"),
+        }
+    }
+
+    fn open_data(&mut self, datagram: &[u8]) -> Result<(u64, Vec<u8>)> {
+        if datagram.first() != Some(&PACKET_DATA) || datagram.len() < DATA_HEADER_LEN {
+            bail!("not a pitlink datagram");
+        }
+        let id: [u8; KEY_ID_LEN] = datagram[1..1 + KEY_ID_LEN].try_into().expect("8 bytes");
+        let seq = u64::from_be_bytes(datagram[1 + KEY_ID_LEN..DATA_HEADER_LEN].try_into().expect("8 bytes"));
+        let confirmed = self.keys.contains_key(&id);
+        let key = if confirmed { self.keys.get_mut(&id) } else { self.pending.get_mut(&id) };
+        let Some(key) = key else { bail!("datagram for an unknown key (INIT not received yet)") };
+        if !key.window.check(seq) {
+            bail!("datagram {} is a replay or older than the replay window", seq);
+        }
+        let (nonce, header) = nonce_aad(key.cipher.id(), &id, seq);
+        let payload = key.cipher.decrypt(&nonce, &datagram[DATA_HEADER_LEN..], &header)
+            .with_context(|| format!("datagram {} failed authentication", seq))?;
+        key.window.accept(seq);
+        key.last_used = Instant::now();
+        if !confirmed {
+            // A real sender holds the key now: it may take the place of another
+            let key = self.pending.remove(&id).expect("pending key");
+            make_room(&mut self.keys, MAX_KEYS);
+            self.keys.insert(id, key);
+        }
+        Ok((seq, payload))
+    }
+
+    fn open_init(&mut self, body: &[u8]) -> Result<bool> {
+        if body.len() < KEY_ID_LEN + 4 {
+            bail!("truncated INIT datagram");
+        }
+        let (id, rest) = body.split_at(KEY_ID_LEN);
+        let ct_len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        let ct = rest.get(4..4 + ct_len).context("truncated INIT datagram")?;
+        // The key id hashes everything the key depends on, so a repeat needs no
+        // decapsulation
+        if key_id(rest[0], rest[1], ct)[..] != id[..] {
+            bail!("INIT key id does not match its algorithms and ciphertext");
+        }
+        let id: [u8; KEY_ID_LEN] = id.try_into().expect("8 bytes");
+        if self.keys.contains_key(&id) || self.pending.contains_key(&id) {
+            return Ok(false);
+        }
+        let kem_id = KemId::from_u8(rest[0])?;
+        if !kem::key_compatible(self.kem, kem_id) {
+            bail!("sender encrypts with {:?} but the private key is {:?}", kem_id, self.kem);
+        }
+        let shared = kem::decapsulate(kem_id, self.identity.expose_secret(), ct)?;
+        let cipher = traffic_cipher(AeadId::from_u8(rest[1])?, shared.expose_secret())?;
+        make_room(&mut self.pending, MAX_PENDING_KEYS);
+        self.pending.insert(id, ReceiveKey { cipher, window: ReplayWindow::default(), last_used: Instant::now() });
+        Ok(true)
+    }
+}
+
+/// Drop the least recently used of `keys` when it holds `max` already
+fn make_room(keys: &mut HashMap<[u8; KEY_ID_LEN], ReceiveKey>, max: usize) {
+    if keys.len() >= max {
+        let stalest = keys.iter().min_by_key(|(_, key)| key.last_used).map(|(id, _)| *id);
+        if let Some(stalest) = stalest {
+            keys.remove(&stalest);
+        }
+    }
+}
+
+/// How `datagram send` cuts its input into datagrams
+#[derive(Clone, Debug)]
+pub struct DatagramOptions {
+    pub aead: AeadId,
+    /// Largest payload per datagram; longer lines are split
+    pub max_payload: usize,
+    /// Repeat the INIT datagram after this many DATA datagrams
+    pub init_every: u64,
+    /// Add Reed-Solomon parity across groups of DATA datagrams
+    pub fec: Option<Fec>,
+}
+
+impl Default for DatagramOptions {
+    fn default() -> Self {
+        Self { aead: AeadId::XChaCha20Poly1305, max_payload: DEFAULT_MAX_PAYLOAD, init_every: 32, fec: None }
+    }
+}
+
+/// Send each line of `input` (`-` for stdin) to `to` as its own datagram,
+/// sealed for the public key at `pubkey_path`
+pub fn datagram_send(to: &str, input: PathBuf, pubkey_path: PathBuf, opts: DatagramOptions) -> Result<()> {
+    if opts.max_payload == 0 {
+        bail!("max_payload must be at least 1 byte");
+    }
+    if opts.init_every == 0 {
+        bail!("init_every must be at least 1");
+    }
+    let recipient = KeyFile::read(pubkey_path, KeyKind::Public)?;
+    let socket = UdpSocket::bind(if to.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" })?;
+    socket.connect(to).with_context(|| format!("cannot reach {}", to))?;
+    let mut sealer = DatagramSealer::new(&recipient, opts.aead)?;
+    let mut input_reader = BufReader::new(open_input(&input)?);
+    let start = Instant::now();
+    let mut parity = opts.fec.map(ParityEncoder::new);
+    let (mut datagrams, mut parity_datagrams, mut bytes) = (0u64, 0u64, 0u64);
+    let mut line = Vec::new();
+    socket.send(sealer.init_datagram())?;
+    loop {
+        line.clear();
+        if input_reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        for payload in line.chunks(opts.max_payload) {
+            if datagrams > 0 && datagrams % opts.init_every == 0 {
+                socket.send(sealer.init_datagram())?;
+            }
+            // A full socket buffer or an unreachable port loses this datagram only
+            let datagram = sealer.seal(payload)?;
+            if let Err(e) = socket.send(&datagram) {
+                debug!(target: "io", "datagram dropped on send: {}", e);
+            }
+            datagrams += 1;
+            bytes += payload.len() as u64;
+            if let Some(encoder) = &mut parity {
+                parity_datagrams += send_parity(&socket, encoder.push(datagram)?);
+            }
+        }
+    }
+    if let Some(encoder) = &mut parity {
+        parity_datagrams += send_parity(&socket, encoder.finish()?);
+    }
+    info!(target: "io", "Sent {} datagrams ({} bytes) to {}", datagrams, bytes, to);
+    output::record(json!({
+        "input": input, "to": to, "recipient_fingerprint": recipient.fingerprint()?, "kem": format!("{:?}", recipient.kem),
+        "aead": format!("{:?}", opts.aead), "datagrams": datagrams, "parity_datagrams": parity_datagrams, "plaintext_bytes": bytes, "elapsed_ms": start.elapsed().as_millis() as u64,
+    }));
+    Ok(())
+}
+
+fn send_parity(socket: &UdpSocket, datagrams: Vec<Vec<u8>>) -> u64 {
+    for datagram in &datagrams {
+        if let Err(e) = socket.send(datagram) {
+            debug!(target: "io", "parity datagram dropped on send: {}", e);
+        }
+    }
+    datagrams.len() as u64
+}
+
+/// Receive datagrams on `listen` and write each authenticated payload to `output`
+/// (`-` for stdout) as it arrives. Runs until no datagram has arrived for
+/// `idle_timeout`, or forever without one.
+pub fn datagram_receive(listen: &str, output: PathBuf, privkey_path: PathBuf, allow_insecure_key: bool, idle_timeout: Option<Duration>, force: bool) -> Result<()> {
+    check_overwrite(&output, force)?;
+    perms::check_private(&privkey_path, allow_insecure_key)?;
+    let identity = KeyFile::read(&privkey_path, KeyKind::Private)?;
+    let socket = UdpSocket::bind(listen).with_context(|| format!("cannot listen on {}", listen))?;
+    socket.set_read_timeout(idle_timeout)?;
+    info!(target: "io", "Waiting for datagrams on {}", socket.local_addr()?);
+    let mut opener = DatagramOpener::new(&identity);
+    let mut out = create_output(&output)?;
+    let (mut datagrams, mut recovered, mut dropped, mut bytes) = (0u64, 0u64, 0u64, 0u64);
+    let mut buf = vec![0u8; 65536];
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        match opener.open(&buf[..n]) {
+            Ok(Opened::Init(true)) => info!(target: "kem", "New datagram key from {}", from),
+            Ok(Opened::Init(false)) => {}
+            Ok(Opened::Data { payload, .. }) => {
+                out.write_all(&payload)?;
+                out.flush()?;
+                datagrams += 1;
+                bytes += payload.len() as u64;
+            }
+            Ok(Opened::Recovered(payloads)) => {
+                for (seq, payload) in payloads {
+                    debug!(target: "aead", "rebuilt datagram {} from parity", seq);
+                    out.write_all(&payload)?;
+                    recovered += 1;
+                    bytes += payload.len() as u64;
+                }
+                out.flush()?;
+            }
+            Err(e) => {
+                dropped += 1;
+                debug!(target: "aead", "dropped datagram from {}: {:#}", from, e);
+            }
+        }
+    }
+    info!(target: "io", "Received {} datagrams ({} bytes), rebuilt {}, dropped {}", datagrams, bytes, recovered, dropped);
+    output::record(json!({
+        "output": output, "listen": listen, "kem": format!("{:?}", identity.kem),
+        "datagrams": datagrams, "recovered": recovered, "dropped": dropped, "plaintext_bytes": bytes,
+    }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn tolerates_loss_and_reorder_but_not_replay() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let mut sealer = DatagramSealer::new(&public, AeadId::XChaCha20Poly1305).unwrap();
+        let datagrams: Vec<_> = (0..200u32).map(|i| sealer.seal(&i.to_be_bytes()).unwrap()).collect();
+
+        let mut opener = DatagramOpener::new(&private);
+        assert!(opener.open(&datagrams[0]).is_err());
+        assert_eq!(opener.open(sealer.init_datagram()).unwrap(), Opened::Init(true));
+        assert_eq!(opener.open(sealer.init_datagram()).unwrap(), Opened::Init(false));
+        let seqs: Vec<u64> = [5, 3, 4, 150, 60, 10].iter().filter_map(|&i| match opener.open(&datagrams[i]) {
+            Ok(Opened::Data { seq, .. }) => Some(seq),
+            _ => None,
+        }).collect();
+        // 10 is more than WINDOW behind 150
+        assert_eq!(seqs, [5, 3, 4, 150, 60]);
+        assert!(opener.open(&datagrams[60]).is_err());
+
+        let mut forged = datagrams[151].clone();
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(opener.open(&forged).is_err());
+        assert!(opener.open(&datagrams[151]).is_ok());
+    }
+
+    #[test]
+    fn forged_inits_do_not_evict_senders() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let mut sealer = DatagramSealer::new(&public, AeadId::XChaCha20Poly1305).unwrap();
+        let mut opener = DatagramOpener::new(&private);
+        opener.open(sealer.init_datagram()).unwrap();
+        opener.open(&sealer.seal(b"lap 1").unwrap()).unwrap();
+
+        // Random ciphertexts decapsulate too, so each of these is a new key
+        let init_len = sealer.init_datagram().len();
+        for i in 0..MAX_KEYS as u8 * 2 {
+            let ct = vec![i; init_len - (1 + KEY_ID_LEN + 4)];
+            let mut init = sealer.init_datagram()[..init_len - ct.len()].to_vec();
+            init[1..1 + KEY_ID_LEN].copy_from_slice(&key_id(init[1 + KEY_ID_LEN], init[2 + KEY_ID_LEN], &ct));
+            init.extend_from_slice(&ct);
+            assert_eq!(opener.open(&init).unwrap(), Opened::Init(true));
+        }
+        assert_eq!(opener.open(&sealer.seal(b"lap 2").unwrap()).unwrap(), Opened::Data { seq: 1, payload: b"lap 2".to_vec() });
+
+        // A real new sender is confirmed by its first DATA datagram
+        let mut other = DatagramSealer::new(&public, AeadId::XChaCha20Poly1305).unwrap();
+        assert_eq!(opener.open(other.init_datagram()).unwrap(), Opened::Init(true));
+        assert!(opener.open(&other.seal(b"pit").unwrap()).is_ok());
+        assert_eq!(opener.open(other.init_datagram()).unwrap(), Opened::Init(false));
+        assert_eq!((opener.keys.len(), opener.pending.len()), (2, MAX_PENDING_KEYS - 1));
+    }
+
+    #[test]
+    fn tampered_init_does_not_shadow_the_real_one() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let mut sealer = DatagramSealer::new(&public, AeadId::XChaCha20Poly1305).unwrap();
+        let mut opener = DatagramOpener::new(&private);
+
+        // Same ciphertext and key id, other AEAD: the id no longer matches
+        let mut tampered = sealer.init_datagram().to_vec();
+        tampered[2 + KEY_ID_LEN] = AeadId::Aes256Gcm.as_u8();
+        assert!(opener.open(&tampered).is_err());
+
+        // With the id recomputed it is a key of its own, and the real INIT still lands
+        let ct = tampered[1 + KEY_ID_LEN + 4..].to_vec();
+        let id = key_id(tampered[1 + KEY_ID_LEN], tampered[2 + KEY_ID_LEN], &ct);
+        tampered[1..1 + KEY_ID_LEN].copy_from_slice(&id);
+        assert_eq!(opener.open(&tampered).unwrap(), Opened::Init(true));
+        assert_eq!(opener.open(sealer.init_datagram()).unwrap(), Opened::Init(true));
+        assert_eq!(opener.open(&sealer.seal(b"pit").unwrap()).unwrap(), Opened::Data { seq: 0, payload: b"pit".to_vec() });
+    }
+
+    #[test]
+    fn rebuilds_lost_datagrams_from_parity() {
+        assert!(Fec::parse("8:0").is_err());
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let mut sealer = DatagramSealer::new(&public, AeadId::XChaCha20Poly1305).unwrap();
+        let mut encoder = ParityEncoder::new(Fec::parse("4:2").unwrap());
+        let payloads: Vec<Vec<u8>> = (1..=5usize).map(|i| vec![i as u8; i * 10]).collect();
+        let mut datagrams = Vec::new();
+        let mut parity = Vec::new();
+        for payload in &payloads {
+            let datagram = sealer.seal(payload).unwrap();
+            datagrams.push(datagram.clone());
+            parity.extend(encoder.push(datagram).unwrap());
+        }
+        // A short last group still gets its parity
+        let tail = encoder.finish().unwrap();
+        assert_eq!((parity.len(), tail.len()), (2, 2));
+
+        let mut opener = DatagramOpener::new(&private);
+        opener.open(sealer.init_datagram()).unwrap();
+        for i in [0, 3] {
+            opener.open(&datagrams[i]).unwrap();
+        }
+        assert_eq!(opener.open(&parity[0]).unwrap(), Opened::Recovered(Vec::new()));
+        assert_eq!(opener.open(&parity[1]).unwrap(), Opened::Recovered(vec![(1, payloads[1].clone()), (2, payloads[2].clone())]));
+        // Parity cannot replay what already arrived
+        assert!(opener.open(&datagrams[1]).is_err());
+        assert_eq!(opener.open(&tail[1]).unwrap(), Opened::Recovered(vec![(4, payloads[4].clone())]));
+    }
+}
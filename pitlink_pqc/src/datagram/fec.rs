@@ -0,0 +1,198 @@
+//! Reed-Solomon parity across groups of DATA datagrams (`datagram send --fec K:M`)
+//!
+//! After every K DATA datagrams the sender adds M PARITY datagrams, computed over
+//! the sealed datagrams zero-padded to the longest in the group:
+//!
+//! ```text
+//! PARITY  0x03 | key_id[8] | first_seq u64 | count u8 | parity u8 | index u8
+//!         | count x datagram_len u16 | shard
+//! ```
+//!
+//! A receiver holding any `count` of a group's DATA and PARITY datagrams rebuilds
+//! the missing DATA datagrams without asking for them again, then authenticates
+//! them like any other. Parity covers ciphertext only, so a forged PARITY datagram
+//! can at worst rebuild something that fails authentication. The last group of an
+//! input may be short; its PARITY datagrams carry the smaller `count`.
+
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::{bail, Context, Result};
+use reed_solomon_erasure::{galois_8::Field, ReedSolomon};
+
+use super::{DATA_HEADER_LEN, KEY_ID_LEN};
+
+pub(super) const PACKET_PARITY: u8 = 3;
+const PARITY_HEADER_LEN: usize = 1 + KEY_ID_LEN + 8 + 3;
+/// Largest K and M; a whole group stays well inside the replay window
+const MAX_DATA: u8 = 64;
+const MAX_PARITY: u8 = 32;
+/// Authenticated DATA datagrams kept for rebuilding groups
+const KEEP_DATAGRAMS: usize = 1024;
+/// Groups kept while they wait for enough PARITY datagrams
+const KEEP_GROUPS: usize = 64;
+
+type GroupId = ([u8; KEY_ID_LEN], u64);
+
+/// Group shape: `data` DATA datagrams, then `parity` PARITY datagrams
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fec {
+    pub data: u8,
+    pub parity: u8,
+}
+
+impl Fec {
+    /// Parse `K:M`, e.g. `8:2` to survive any two losses in each ten datagrams
+    pub fn parse(s: &str) -> Result<Self> {
+        let parsed = s.split_once(':').and_then(|(k, m)| Some((k.trim().parse::<u8>().ok()?, m.trim().parse::<u8>().ok()?)));
+        match parsed {
+            Some((data, parity)) if (1..=MAX_DATA).contains(&data) && (1..=MAX_PARITY).contains(&parity) => Ok(Self { data, parity }),
+            _ => bail!("invalid FEC group {:?} (expected K:M with K up to {} and M up to {}, e.g. 8:2)", s, MAX_DATA, MAX_PARITY),
+        }
+    }
+}
+
+/// Collects sealed DATA datagrams into groups and computes their parity
+pub(super) struct ParityEncoder {
+    fec: Fec,
+    group: Vec<Vec<u8>>,
+}
+
+impl ParityEncoder {
+    pub(super) fn new(fec: Fec) -> Self {
+        Self { fec, group: Vec::with_capacity(fec.data as usize) }
+    }
+
+    /// Add the next sealed DATA datagram; returns the group's PARITY datagrams
+    /// once it is full
+    pub(super) fn push(&mut self, datagram: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        self.group.push(datagram);
+        if self.group.len() < self.fec.data as usize {
+            return Ok(Vec::new());
+        }
+        self.finish()
+    }
+
+    /// PARITY datagrams for whatever was pushed since the last full group
+    pub(super) fn finish(&mut self) -> Result<Vec<Vec<u8>>> {
+        let group = std::mem::take(&mut self.group);
+        let Some(first) = group.first() else { return Ok(Vec::new()) };
+        let (count, parity) = (group.len(), self.fec.parity as usize);
+        let shard_len = group.iter().map(Vec::len).max().unwrap_or(0);
+        let mut shards: Vec<Vec<u8>> = group.iter().map(|datagram| padded(datagram, shard_len)).collect();
+        shards.resize(count + parity, vec![0u8; shard_len]);
+        ReedSolomon::<Field>::new(count, parity)?.encode(&mut shards)?;
+
+        let mut header = vec![PACKET_PARITY];
+        // Key id and sequence number of the group's first DATA datagram
+        header.extend_from_slice(&first[1..DATA_HEADER_LEN]);
+        header.extend_from_slice(&[count as u8, self.fec.parity, 0]);
+        for datagram in &group {
+            header.extend_from_slice(&(datagram.len() as u16).to_be_bytes());
+        }
+        Ok(shards[count..].iter().enumerate().map(|(index, shard)| {
+            let mut datagram = header.clone();
+            datagram[PARITY_HEADER_LEN - 1] = index as u8;
+            datagram.extend_from_slice(shard);
+            datagram
+        }).collect())
+    }
+}
+
+fn padded(datagram: &[u8], len: usize) -> Vec<u8> {
+    let mut shard = datagram.to_vec();
+    shard.resize(len, 0);
+    shard
+}
+
+struct Group {
+    lens: Vec<usize>,
+    parity: Vec<Option<Vec<u8>>>,
+}
+
+/// Recent DATA datagrams and the groups still missing some, for rebuilding
+/// datagrams lost on the way
+#[derive(Default)]
+pub(super) struct Recovery {
+    datagrams: HashMap<GroupId, Vec<u8>>,
+    datagram_order: VecDeque<GroupId>,
+    groups: HashMap<GroupId, Group>,
+    group_order: VecDeque<GroupId>,
+}
+
+impl Recovery {
+    /// Remember an authenticated DATA datagram
+    pub(super) fn keep(&mut self, key_id: [u8; KEY_ID_LEN], seq: u64, datagram: &[u8]) {
+        if self.datagrams.insert((key_id, seq), datagram.to_vec()).is_none() {
+            self.datagram_order.push_back((key_id, seq));
+            if self.datagram_order.len() > KEEP_DATAGRAMS {
+                if let Some(oldest) = self.datagram_order.pop_front() {
+                    self.datagrams.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// Take a PARITY datagram; returns the DATA datagrams of its group that can
+    /// now be rebuilt, still sealed
+    pub(super) fn add_parity(&mut self, datagram: &[u8]) -> Result<Vec<Vec<u8>>> {
+        if datagram.len() < PARITY_HEADER_LEN {
+            bail!("truncated PARITY datagram");
+        }
+        let key_id: [u8; KEY_ID_LEN] = datagram[1..1 + KEY_ID_LEN].try_into().expect("8 bytes");
+        let first = u64::from_be_bytes(datagram[1 + KEY_ID_LEN..DATA_HEADER_LEN].try_into().expect("8 bytes"));
+        let (count, parity, index) = (datagram[DATA_HEADER_LEN] as usize, datagram[DATA_HEADER_LEN + 1] as usize, datagram[DATA_HEADER_LEN + 2] as usize);
+        if count == 0 || parity == 0 || index >= parity || count > MAX_DATA as usize || parity > MAX_PARITY as usize {
+            bail!("malformed PARITY datagram");
+        }
+        let lens_end = PARITY_HEADER_LEN + 2 * count;
+        let lens: Vec<usize> = datagram.get(PARITY_HEADER_LEN..lens_end).context("truncated PARITY datagram")?
+            .chunks(2).map(|len| u16::from_be_bytes([len[0], len[1]]) as usize).collect();
+        let shard = &datagram[lens_end..];
+        if lens.iter().any(|&len| len < DATA_HEADER_LEN || len > shard.len()) {
+            bail!("malformed PARITY datagram");
+        }
+
+        let id = (key_id, first);
+        if !self.groups.contains_key(&id) {
+            self.group_order.push_back(id);
+            if self.group_order.len() > KEEP_GROUPS {
+                if let Some(oldest) = self.group_order.pop_front() {
+                    self.groups.remove(&oldest);
+                }
+            }
+        }
+        let group = self.groups.entry(id).or_insert_with(|| Group { lens: lens.clone(), parity: vec![None; parity] });
+        let shard_len = group.parity.iter().flatten().next().map_or(shard.len(), Vec::len);
+        if group.lens != lens || group.parity.len() != parity || shard_len != shard.len() {
+            bail!("PARITY datagram does not match the others of its group");
+        }
+        group.parity[index] = Some(shard.to_vec());
+        self.rebuild(id, shard_len)
+    }
+
+    /// Rebuild the missing DATA datagrams of group `id` when enough of it is here
+    fn rebuild(&mut self, id: GroupId, shard_len: usize) -> Result<Vec<Vec<u8>>> {
+        let (key_id, first) = id;
+        let Some(group) = self.groups.get(&id) else { return Ok(Vec::new()) };
+        let count = group.lens.len();
+        let mut shards: Vec<Option<Vec<u8>>> = (0..count as u64)
+            .map(|i| self.datagrams.get(&(key_id, first.wrapping_add(i))).map(|datagram| padded(datagram, shard_len)))
+            .collect();
+        let missing: Vec<usize> = (0..count).filter(|&i| shards[i].is_none()).collect();
+        if !missing.is_empty() && count - missing.len() + group.parity.iter().flatten().count() < count {
+            return Ok(Vec::new());
+        }
+        let Some(group) = self.groups.remove(&id) else { return Ok(Vec::new()) };
+        self.group_order.retain(|other| *other != id);
+        if missing.is_empty() {
+            return Ok(Vec::new());
+        }
+        let parity = group.parity.len();
+        shards.extend(group.parity);
+        ReedSolomon::<Field>::new(count, parity)?.reconstruct_data(&mut shards)?;
+        Ok(missing.into_iter().filter_map(|i| shards[i].take().map(|mut datagram| {
+            datagram.truncate(group.lens[i]);
+            datagram
+        })).collect())
+    }
+}
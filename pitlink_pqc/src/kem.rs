@@ -0,0 +1,270 @@
+//! Key encapsulation dispatch
+//!
+//! Each `KemId` recorded in the package header and key files maps to a keypair
+//! generator and an encapsulate/decapsulate pair that yields the 32-byte KEK used
+//! to wrap the file key.
+//!
+//! ML-KEM (FIPS 203) is the default for new keys. Kyber round-3 remains supported so
+//! existing packages keep decrypting. Both use the same key encoding per parameter
+//! set, so a key pair can decapsulate packages of either flavour at its level.
+
+use anyhow::Result;
+use rand::rngs::OsRng;
+use pqcrypto_kyber::{kyber512, kyber768, kyber1024};
+use pqcrypto_mlkem::{mlkem512, mlkem768, mlkem1024};
+use pqcrypto_traits::kem::*;
+use ml_kem::{B32, EncodedSizeUser, KemCore, MlKem512, MlKem768, MlKem1024};
+use sha3::{Digest, Sha3_256};
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, StaticSecret};
+
+use common::hkdf_derive;
+use zeroize::Zeroizing;
+use common::container::KemId;
+
+use crate::{secret_bytes, SecretBytes};
+
+const X25519_LEN: usize = 32;
+
+macro_rules! kyber_keypair {
+    ($m:ident) => {{
+        let (pk, sk) = $m::keypair();
+        (pk.as_bytes().to_vec(), sk.as_bytes().to_vec())
+    }};
+}
+
+macro_rules! kyber_encapsulate {
+    ($m:ident, $pk:expr) => {{
+        let pk = $m::PublicKey::from_bytes($pk).map_err(|e| anyhow::anyhow!("PublicKey from_bytes: {}", e))?;
+        let (shared, ct) = $m::encapsulate(&pk);
+        (ct.as_bytes().to_vec(), Zeroizing::new(shared.as_bytes().to_vec()))
+    }};
+}
+
+macro_rules! kyber_decapsulate {
+    ($m:ident, $sk:expr, $ct:expr) => {{
+        let sk = $m::SecretKey::from_bytes($sk).map_err(|e| anyhow::anyhow!("SecretKey from_bytes: {}", e))?;
+        let ct = $m::Ciphertext::from_bytes($ct).map_err(|e| anyhow::anyhow!("Ciphertext from_bytes: {}", e))?;
+        Zeroizing::new($m::decapsulate(&ct, &sk).as_bytes().to_vec())
+    }};
+}
+
+/// Map a `--level` value to a KEM id, using ML-KEM unless `legacy_kyber` is set
+pub fn kem_for_level(level: u16, legacy_kyber: bool) -> Result<KemId> {
+    match (level, legacy_kyber) {
+        (512, false) => Ok(KemId::MlKem512),
+        (768, false) => Ok(KemId::MlKem768),
+        (1024, false) => Ok(KemId::MlKem1024),
+        (512, true) => Ok(KemId::Kyber512),
+        (768, true) => Ok(KemId::Kyber768),
+        (1024, true) => Ok(KemId::Kyber1024),
+        (other, _) => anyhow::bail!("unsupported security level {} (expected 512, 768 or 1024)", other),
+    }
+}
+
+/// Whether a key generated for `key_kem` can decapsulate a package using `package_kem`.
+///
+/// Kyber round-3 and ML-KEM keys share their encoding at each level, which lets old
+/// raw Kyber keys open ML-KEM packages and vice versa.
+pub fn key_compatible(key_kem: KemId, package_kem: KemId) -> bool {
+    key_kem == package_kem || matches!(
+        (key_kem, package_kem),
+        (KemId::Kyber512, KemId::MlKem512) | (KemId::MlKem512, KemId::Kyber512)
+            | (KemId::Kyber768, KemId::MlKem768) | (KemId::MlKem768, KemId::Kyber768)
+            | (KemId::Kyber1024, KemId::MlKem1024) | (KemId::MlKem1024, KemId::Kyber1024)
+    )
+}
+
+/// Public key length in bytes
+pub fn public_key_len(kem: KemId) -> usize {
+    match kem {
+        KemId::Kyber512 => kyber512::public_key_bytes(),
+        KemId::Kyber768 => kyber768::public_key_bytes(),
+        KemId::Kyber1024 => kyber1024::public_key_bytes(),
+        KemId::MlKem512 => mlkem512::public_key_bytes(),
+        KemId::MlKem768 => mlkem768::public_key_bytes(),
+        KemId::MlKem1024 => mlkem1024::public_key_bytes(),
+        KemId::X25519Kyber768 => kyber768::public_key_bytes() + X25519_LEN,
+    }
+}
+
+/// Private key length in bytes
+pub fn secret_key_len(kem: KemId) -> usize {
+    match kem {
+        KemId::Kyber512 => kyber512::secret_key_bytes(),
+        KemId::Kyber768 => kyber768::secret_key_bytes(),
+        KemId::Kyber1024 => kyber1024::secret_key_bytes(),
+        KemId::MlKem512 => mlkem512::secret_key_bytes(),
+        KemId::MlKem768 => mlkem768::secret_key_bytes(),
+        KemId::MlKem1024 => mlkem1024::secret_key_bytes(),
+        KemId::X25519Kyber768 => kyber768::secret_key_bytes() + X25519_LEN,
+    }
+}
+
+/// Length of a deterministic keygen seed
+pub const SEED_LEN: usize = 32;
+
+macro_rules! mlkem_from_seed {
+    ($k:ty, $d:expr, $z:expr) => {{
+        let (dk, ek) = <$k>::generate_deterministic(&$d, &$z);
+        (ek.as_bytes().to_vec(), dk.as_bytes().to_vec())
+    }};
+}
+
+/// Derive a keypair deterministically from a 32-byte seed, returning `(public, private)`.
+///
+/// The ML-KEM `d` and `z` inputs (and the X25519 scalar for hybrid keys) are expanded
+/// from the seed with HKDF, so the same seed always yields the same keypair. Kyber
+/// round-3 has no deterministic keygen interface and is rejected.
+pub fn keypair_from_seed(kem: KemId, seed: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    if seed.len() != SEED_LEN {
+        anyhow::bail!("seed must be {} bytes, got {}", SEED_LEN, seed.len());
+    }
+    let d = B32::try_from(&hkdf_derive(seed, b"pitlink-keygen-d-v1", 32)?[..]).expect("32-byte HKDF output");
+    let z = B32::try_from(&hkdf_derive(seed, b"pitlink-keygen-z-v1", 32)?[..]).expect("32-byte HKDF output");
+    match kem {
+        KemId::MlKem512 => Ok(mlkem_from_seed!(MlKem512, d, z)),
+        KemId::MlKem768 => Ok(mlkem_from_seed!(MlKem768, d, z)),
+        KemId::MlKem1024 => Ok(mlkem_from_seed!(MlKem1024, d, z)),
+        KemId::X25519Kyber768 => {
+            // Kyber-768 and ML-KEM-768 share their key encoding
+            let (mut public, mut private) = mlkem_from_seed!(MlKem768, d, z);
+            let x_sk = StaticSecret::from(to_array(&hkdf_derive(seed, b"pitlink-keygen-x25519-v1", 32)?));
+            public.extend_from_slice(X25519PublicKey::from(&x_sk).as_bytes());
+            private.extend_from_slice(&x_sk.to_bytes());
+            Ok((public, private))
+        }
+        KemId::Kyber512 | KemId::Kyber768 | KemId::Kyber1024 => {
+            anyhow::bail!("deterministic keygen is not supported for Kyber round-3; use ML-KEM")
+        }
+    }
+}
+
+/// Recover the public key from a private key.
+///
+/// Kyber and ML-KEM private keys embed the public key as
+/// `sk_pke || pk || H(pk) || z`; the embedded hash is checked so a corrupted key
+/// is rejected. The X25519 half of hybrid keys is recomputed from the secret scalar.
+pub fn public_from_secret(kem: KemId, sk_bytes: &[u8]) -> Result<Vec<u8>> {
+    if sk_bytes.len() != secret_key_len(kem) {
+        anyhow::bail!("{:?} private key has length {}, expected {}", kem, sk_bytes.len(), secret_key_len(kem));
+    }
+    match kem {
+        KemId::X25519Kyber768 => {
+            let (kyber_sk, x_sk) = split_hybrid(sk_bytes, kyber768::secret_key_bytes())?;
+            let mut public = embedded_public(KemId::Kyber768, kyber_sk)?;
+            let x_pk = X25519PublicKey::from(&StaticSecret::from(to_array(x_sk)));
+            public.extend_from_slice(x_pk.as_bytes());
+            Ok(public)
+        }
+        other => embedded_public(other, sk_bytes),
+    }
+}
+
+fn embedded_public(kem: KemId, sk_bytes: &[u8]) -> Result<Vec<u8>> {
+    let pk_len = public_key_len(kem);
+    // sk_pke is 384 * k bytes and pk is 384 * k + 32 bytes
+    let start = pk_len - 32;
+    let pk = &sk_bytes[start..start + pk_len];
+    let hash = &sk_bytes[start + pk_len..start + pk_len + 32];
+    if Sha3_256::digest(pk).as_slice() != hash {
+        anyhow::bail!("private key is corrupted (embedded public key hash mismatch)");
+    }
+    Ok(pk.to_vec())
+}
+
+/// Generate a keypair for `kem`, returning `(public, private)` key bytes.
+///
+/// Hybrid keys are stored as the Kyber key followed by the 32-byte X25519 key.
+pub fn generate_keypair(kem: KemId) -> (Vec<u8>, Vec<u8>) {
+    match kem {
+        KemId::Kyber512 => kyber_keypair!(kyber512),
+        KemId::Kyber768 => kyber_keypair!(kyber768),
+        KemId::Kyber1024 => kyber_keypair!(kyber1024),
+        KemId::MlKem512 => kyber_keypair!(mlkem512),
+        KemId::MlKem768 => kyber_keypair!(mlkem768),
+        KemId::MlKem1024 => kyber_keypair!(mlkem1024),
+        KemId::X25519Kyber768 => {
+            let (mut public, mut private) = kyber_keypair!(kyber768);
+            let x_sk = StaticSecret::random_from_rng(OsRng);
+            let x_pk = X25519PublicKey::from(&x_sk);
+            public.extend_from_slice(x_pk.as_bytes());
+            private.extend_from_slice(&x_sk.to_bytes());
+            (public, private)
+        }
+    }
+}
+
+/// Encapsulate to a recipient public key, returning `(kem_ciphertext, kek)`.
+/// Shared secrets and the KEK are wiped when dropped.
+pub fn encapsulate(kem: KemId, pk_bytes: &[u8]) -> Result<(Vec<u8>, SecretBytes)> {
+    let (ct, shared) = match kem {
+        KemId::Kyber512 => kyber_encapsulate!(kyber512, pk_bytes),
+        KemId::Kyber768 => kyber_encapsulate!(kyber768, pk_bytes),
+        KemId::Kyber1024 => kyber_encapsulate!(kyber1024, pk_bytes),
+        KemId::MlKem512 => kyber_encapsulate!(mlkem512, pk_bytes),
+        KemId::MlKem768 => kyber_encapsulate!(mlkem768, pk_bytes),
+        KemId::MlKem1024 => kyber_encapsulate!(mlkem1024, pk_bytes),
+        KemId::X25519Kyber768 => {
+            let (kyber_pk, x_pk) = split_hybrid(pk_bytes, kyber768::public_key_bytes())?;
+            let (mut ct, kyber_shared) = kyber_encapsulate!(kyber768, kyber_pk);
+
+            let x_pk = X25519PublicKey::from(to_array(x_pk));
+            let eph = EphemeralSecret::random_from_rng(OsRng);
+            let eph_pk = X25519PublicKey::from(&eph);
+            let x_shared = eph.diffie_hellman(&x_pk);
+
+            ct.extend_from_slice(eph_pk.as_bytes());
+            return Ok((ct, hybrid_kek(x_shared.as_bytes(), &kyber_shared)?));
+        }
+    };
+    Ok((ct, derive_kek(&shared, kem.kek_label())?))
+}
+
+/// Decapsulate a KEM ciphertext with the recipient private key, returning the KEK.
+pub fn decapsulate(kem: KemId, sk_bytes: &[u8], ct_bytes: &[u8]) -> Result<SecretBytes> {
+    let shared = match kem {
+        KemId::Kyber512 => kyber_decapsulate!(kyber512, sk_bytes, ct_bytes),
+        KemId::Kyber768 => kyber_decapsulate!(kyber768, sk_bytes, ct_bytes),
+        KemId::Kyber1024 => kyber_decapsulate!(kyber1024, sk_bytes, ct_bytes),
+        KemId::MlKem512 => kyber_decapsulate!(mlkem512, sk_bytes, ct_bytes),
+        KemId::MlKem768 => kyber_decapsulate!(mlkem768, sk_bytes, ct_bytes),
+        KemId::MlKem1024 => kyber_decapsulate!(mlkem1024, sk_bytes, ct_bytes),
+        KemId::X25519Kyber768 => {
+            let (kyber_sk, x_sk) = split_hybrid(sk_bytes, kyber768::secret_key_bytes())?;
+            let (kyber_ct, eph_pk) = split_hybrid(ct_bytes, kyber768::ciphertext_bytes())?;
+            let kyber_shared = kyber_decapsulate!(kyber768, kyber_sk, kyber_ct);
+
+            let x_sk = StaticSecret::from(to_array(x_sk));
+            let x_shared = x_sk.diffie_hellman(&X25519PublicKey::from(to_array(eph_pk)));
+            return hybrid_kek(x_shared.as_bytes(), &kyber_shared);
+        }
+    };
+    derive_kek(&shared, kem.kek_label())
+}
+
+/// KEK = HKDF(X25519 shared secret || Kyber shared secret)
+fn hybrid_kek(x_shared: &[u8], kyber_shared: &[u8]) -> Result<SecretBytes> {
+    let mut ikm = Zeroizing::new(Vec::with_capacity(x_shared.len() + kyber_shared.len()));
+    ikm.extend_from_slice(x_shared);
+    ikm.extend_from_slice(kyber_shared);
+    derive_kek(&ikm, KemId::X25519Kyber768.kek_label())
+}
+
+/// 32-byte KEK expanded from `ikm` under `label`
+fn derive_kek(ikm: &[u8], label: &[u8]) -> Result<SecretBytes> {
+    Ok(secret_bytes(hkdf_derive(ikm, label, 32)?.to_vec()))
+}
+
+/// Split `bytes` into a Kyber part of `kyber_len` bytes and a trailing X25519 part.
+fn split_hybrid(bytes: &[u8], kyber_len: usize) -> Result<(&[u8], &[u8])> {
+    if bytes.len() != kyber_len + X25519_LEN {
+        anyhow::bail!("hybrid key material has length {}, expected {}", bytes.len(), kyber_len + X25519_LEN);
+    }
+    Ok(bytes.split_at(kyber_len))
+}
+
+fn to_array(bytes: &[u8]) -> [u8; X25519_LEN] {
+    let mut out = [0u8; X25519_LEN];
+    out.copy_from_slice(bytes);
+    out
+}
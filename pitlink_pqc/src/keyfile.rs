@@ -0,0 +1,417 @@
+//! Key file container
+//!
+//! Key files are stored in the `common::keyfile` framing, `KEY_MAGIC | kind u8 |
+//! kem_id u8 | key bytes`, so the parameter set travels with the key, either in
+//! binary or ASCII-armored (`-----BEGIN PITLINK KYBER PUBLIC KEY-----`). Raw key
+//! files written by older versions (bare Kyber-768 or hybrid key bytes) are still
+//! accepted and identified by length. All readers auto-detect the encoding.
+//!
+//! ML-DSA signing keys use the same container with their own kinds; the
+//! algorithm byte then holds a `SigId` instead of a `KemId`.
+//!
+//! Keys with metadata (creation time, expiry, comment) set `KEY_FLAG_METADATA` in
+//! the kind byte and carry `meta_len u16 | TLV entries` between the kem id and the
+//! key bytes. Unknown TLV tags are skipped.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use secrecy::{ExposeSecret, ExposeSecretMut};
+use zeroize::{Zeroize, Zeroizing};
+
+use common::{read_all, write_all};
+use common::container::{KemId, SigId};
+use common::error::PitlinkError;
+use common::keyfile::KeyEnvelope;
+pub use common::keyfile::{KeyKind, KEY_FLAG_METADATA, KEY_MAGIC};
+
+use crate::armor;
+use crate::kem;
+use crate::perms;
+use crate::sig;
+use crate::{secret_bytes, SecretBytes};
+
+/// Version of the armored key encoding
+const ARMOR_VERSION: &str = "1";
+
+const META_CREATED: u8 = 0x01;
+const META_EXPIRES: u8 = 0x02;
+const META_COMMENT: u8 = 0x03;
+
+/// Optional key metadata; times are seconds since the Unix epoch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyMetadata {
+    pub created: Option<u64>,
+    pub expires: Option<u64>,
+    pub comment: Option<String>,
+}
+
+impl KeyMetadata {
+    /// Metadata for a key created now
+    pub fn now(expires: Option<u64>, comment: Option<String>) -> Self {
+        Self { created: Some(unix_now()), expires, comment }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.created.is_none() && self.expires.is_none() && self.comment.is_none()
+    }
+
+    /// Whether the key has passed its expiry time
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|t| t <= unix_now())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut push = |tag: u8, value: &[u8]| {
+            out.push(tag);
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(value);
+        };
+        if let Some(t) = self.created { push(META_CREATED, &t.to_be_bytes()); }
+        if let Some(t) = self.expires { push(META_EXPIRES, &t.to_be_bytes()); }
+        if let Some(c) = &self.comment { push(META_COMMENT, c.as_bytes()); }
+        out
+    }
+
+    fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let mut meta = Self::default();
+        while !bytes.is_empty() {
+            if bytes.len() < 3 { bail!("truncated key metadata"); }
+            let tag = bytes[0];
+            let len = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+            if bytes.len() < 3 + len { bail!("truncated key metadata"); }
+            let value = &bytes[3..3 + len];
+            match tag {
+                META_CREATED => meta.created = Some(read_time(value)?),
+                META_EXPIRES => meta.expires = Some(read_time(value)?),
+                META_COMMENT => meta.comment = Some(String::from_utf8(value.to_vec())?),
+                _ => {}
+            }
+            bytes = &bytes[3 + len..];
+        }
+        Ok(meta)
+    }
+}
+
+fn read_time(value: &[u8]) -> Result<u64> {
+    let arr: [u8; 8] = value.try_into().map_err(|_| anyhow::anyhow!("invalid timestamp in key metadata"))?;
+    Ok(u64::from_be_bytes(arr))
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Format a Unix timestamp as a UTC date (`YYYY-MM-DD`)
+pub fn format_date(secs: u64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| secs.to_string())
+}
+
+/// Parse an expiry given as a date (`YYYY-MM-DD`) or a lifetime in days (`90d`)
+pub fn parse_expiry(s: &str) -> Result<u64> {
+    if let Some(days) = s.strip_suffix('d') {
+        let days: u64 = days.parse().map_err(|_| anyhow::anyhow!("invalid expiry '{}'", s))?;
+        return Ok(unix_now() + days * 86_400);
+    }
+    let date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| anyhow::anyhow!("invalid expiry '{}' (use YYYY-MM-DD or <days>d)", s))?;
+    Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as u64)
+}
+
+/// Armor label of a key file of `kind`
+fn armor_label(kind: KeyKind) -> &'static str {
+    match kind {
+        KeyKind::Public => "PITLINK KYBER PUBLIC KEY",
+        KeyKind::Private => "PITLINK KYBER PRIVATE KEY",
+        KeyKind::SigningPublic => "PITLINK SIGNING PUBLIC KEY",
+        KeyKind::SigningPrivate => "PITLINK SIGNING PRIVATE KEY",
+    }
+}
+
+/// A KEM key together with its algorithm and metadata. The key bytes are wiped
+/// on drop and left out of `Debug` output.
+#[derive(Debug)]
+pub struct KeyFile {
+    pub kind: KeyKind,
+    pub kem: KemId,
+    pub key: SecretBytes,
+    pub meta: KeyMetadata,
+}
+
+impl KeyFile {
+    pub fn new(kind: KeyKind, kem: KemId, key: Vec<u8>) -> Self {
+        Self { kind, kem, key: secret_bytes(key), meta: KeyMetadata::default() }
+    }
+
+    pub fn with_metadata(mut self, meta: KeyMetadata) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    /// Serialize the key file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_envelope(self.kind, self.kem.as_u8(), &self.meta, self.key.expose_secret())
+    }
+
+    /// Encode the key file as an ASCII armor block
+    pub fn to_armored(&self) -> String {
+        encode_armored(self.kind, format!("{:?}", self.kem), &self.meta, &self.to_bytes())
+    }
+
+    /// Parse a key file, checking that it holds the expected kind of key
+    pub fn from_bytes(bytes: &[u8], expected: KeyKind) -> Result<Self> {
+        Self::parse(bytes, expected).map_err(key_format)
+    }
+
+    fn parse(bytes: &[u8], expected: KeyKind) -> Result<Self> {
+        let (bytes, armored_alg) = unarmor(bytes, expected)?;
+        let parsed = match decode_envelope(&bytes)? {
+            Some(env) => Self { kind: env.kind, kem: KemId::from_u8(env.alg)?, key: secret_bytes(env.key), meta: env.meta },
+            None => Self::from_raw(&bytes, expected)?,
+        };
+        if let Some(alg) = armored_alg {
+            if alg != format!("{:?}", parsed.kem) {
+                bail!("armor header says {} but key is {:?}", alg, parsed.kem);
+            }
+        }
+
+        if parsed.kind != expected {
+            bail!("expected a {:?} key but found a {:?} key", expected, parsed.kind);
+        }
+        let want = if parsed.kind.is_private() {
+            kem::secret_key_len(parsed.kem)
+        } else {
+            kem::public_key_len(parsed.kem)
+        };
+        if parsed.key.expose_secret().len() != want {
+            bail!("{:?} key has length {}, expected {}", parsed.kem, parsed.key.expose_secret().len(), want);
+        }
+        Ok(parsed)
+    }
+
+    /// Identify a legacy raw key file by its length. Raw files predate ML-KEM, so
+    /// they are always treated as Kyber round-3 keys.
+    fn from_raw(bytes: &[u8], kind: KeyKind) -> Result<Self> {
+        for kem in [KemId::Kyber512, KemId::Kyber768, KemId::Kyber1024, KemId::X25519Kyber768] {
+            let len = if kind.is_private() { kem::secret_key_len(kem) } else { kem::public_key_len(kem) };
+            if bytes.len() == len {
+                return Ok(Self::new(kind, kem, bytes.to_vec()));
+            }
+        }
+        bail!("unrecognized key file ({} bytes)", bytes.len())
+    }
+
+    /// Stable fingerprint of a public key: the first 16 bytes of
+    /// SHA-256(kem_id || key) as colon-separated hex groups
+    pub fn fingerprint(&self) -> Result<String> {
+        if self.kind != KeyKind::Public {
+            bail!("fingerprints are computed from public keys");
+        }
+        Ok(fingerprint(self.kem, self.key.expose_secret()))
+    }
+
+    /// Read a key file from disk
+    pub fn read<P: AsRef<Path>>(path: P, expected: KeyKind) -> Result<Self> {
+        Self::from_bytes(&Zeroizing::new(read_all(path)?), expected)
+    }
+
+    /// Write the key file to disk; private keys are made owner-only
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_bytes(path, &self.to_bytes())
+    }
+
+    /// Write the key file to disk in armored form
+    pub fn write_armored<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_bytes(path, self.to_armored().as_bytes())
+    }
+
+    fn write_bytes<P: AsRef<Path>>(&self, path: P, data: &[u8]) -> Result<()> {
+        write_key(self.kind, path.as_ref(), data)
+    }
+}
+
+impl Clone for KeyFile {
+    fn clone(&self) -> Self {
+        Self { kind: self.kind, kem: self.kem, key: secret_bytes(self.key.expose_secret().clone()), meta: self.meta.clone() }
+    }
+}
+
+impl Zeroize for KeyFile {
+    fn zeroize(&mut self) {
+        self.key.expose_secret_mut().zeroize();
+    }
+}
+
+/// A signing key together with its algorithm and metadata. The key bytes are
+/// wiped on drop and left out of `Debug` output.
+#[derive(Debug)]
+pub struct SigningKeyFile {
+    pub kind: KeyKind,
+    pub alg: SigId,
+    pub key: SecretBytes,
+    pub meta: KeyMetadata,
+}
+
+impl SigningKeyFile {
+    pub fn new(kind: KeyKind, alg: SigId, key: Vec<u8>) -> Self {
+        Self { kind, alg, key: secret_bytes(key), meta: KeyMetadata::default() }
+    }
+
+    pub fn with_metadata(mut self, meta: KeyMetadata) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    /// Serialize the key file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_envelope(self.kind, self.alg.as_u8(), &self.meta, self.key.expose_secret())
+    }
+
+    /// Encode the key file as an ASCII armor block
+    pub fn to_armored(&self) -> String {
+        encode_armored(self.kind, format!("{:?}", self.alg), &self.meta, &self.to_bytes())
+    }
+
+    /// Parse a signing key file, checking that it holds the expected kind of key
+    pub fn from_bytes(bytes: &[u8], expected: KeyKind) -> Result<Self> {
+        Self::parse(bytes, expected).map_err(key_format)
+    }
+
+    fn parse(bytes: &[u8], expected: KeyKind) -> Result<Self> {
+        let (bytes, armored_alg) = unarmor(bytes, expected)?;
+        let env = decode_envelope(&bytes)?.ok_or_else(|| anyhow::anyhow!("not a signing key file"))?;
+        if env.kind != expected {
+            bail!("expected a {:?} key but found a {:?} key", expected, env.kind);
+        }
+        let parsed = Self { kind: env.kind, alg: SigId::from_u8(env.alg)?, key: secret_bytes(env.key), meta: env.meta };
+        if let Some(alg) = armored_alg {
+            if alg != format!("{:?}", parsed.alg) {
+                bail!("armor header says {} but key is {:?}", alg, parsed.alg);
+            }
+        }
+        let want = if parsed.kind.is_private() {
+            sig::secret_key_len(parsed.alg)
+        } else {
+            sig::public_key_len(parsed.alg)
+        };
+        if parsed.key.expose_secret().len() != want {
+            bail!("{:?} key has length {}, expected {}", parsed.alg, parsed.key.expose_secret().len(), want);
+        }
+        Ok(parsed)
+    }
+
+    /// Fingerprint of a signing public key, computed like KEM fingerprints
+    pub fn fingerprint(&self) -> Result<String> {
+        if self.kind != KeyKind::SigningPublic {
+            bail!("fingerprints are computed from public keys");
+        }
+        Ok(fingerprint_bytes(self.alg.as_u8(), self.key.expose_secret()))
+    }
+
+    /// Read a signing key file from disk
+    pub fn read<P: AsRef<Path>>(path: P, expected: KeyKind) -> Result<Self> {
+        Self::from_bytes(&Zeroizing::new(read_all(path)?), expected)
+    }
+
+    /// Write the key file to disk; private keys are made owner-only
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        write_key(self.kind, path.as_ref(), &self.to_bytes())
+    }
+
+    /// Write the key file to disk in armored form
+    pub fn write_armored<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        write_key(self.kind, path.as_ref(), self.to_armored().as_bytes())
+    }
+}
+
+impl Clone for SigningKeyFile {
+    fn clone(&self) -> Self {
+        Self { kind: self.kind, alg: self.alg, key: secret_bytes(self.key.expose_secret().clone()), meta: self.meta.clone() }
+    }
+}
+
+impl Zeroize for SigningKeyFile {
+    fn zeroize(&mut self) {
+        self.key.expose_secret_mut().zeroize();
+    }
+}
+
+/// Binary key file fields shared by KEM and signing keys
+struct Envelope {
+    kind: KeyKind,
+    alg: u8,
+    meta: KeyMetadata,
+    key: Vec<u8>,
+}
+
+/// Report a key file parse failure as `PitlinkError::KeyFormat`
+fn key_format(e: anyhow::Error) -> anyhow::Error {
+    PitlinkError::KeyFormat(format!("{:#}", e)).into()
+}
+
+fn encode_envelope(kind: KeyKind, alg: u8, meta: &KeyMetadata, key: &[u8]) -> Vec<u8> {
+    KeyEnvelope { kind, alg, meta: &meta.to_bytes(), key }.to_bytes()
+}
+
+/// Parse a `KEY_MAGIC` key file; `None` if the bytes are not in the container format
+fn decode_envelope(bytes: &[u8]) -> Result<Option<Envelope>> {
+    let Some(env) = KeyEnvelope::from_bytes(bytes)? else {
+        return Ok(None);
+    };
+    Ok(Some(Envelope { kind: env.kind, alg: env.alg, meta: KeyMetadata::from_bytes(env.meta)?, key: env.key.to_vec() }))
+}
+
+fn encode_armored(kind: KeyKind, alg: String, meta: &KeyMetadata, data: &[u8]) -> String {
+    let mut headers = vec![("Version", ARMOR_VERSION.to_string()), ("Algorithm", alg)];
+    if let Some(comment) = &meta.comment {
+        headers.push(("Comment", comment.clone()));
+    }
+    armor::encode(armor_label(kind), &headers, data)
+}
+
+/// Strip ASCII armor if present, checking its label and version. Returns the
+/// binary key file and the armor's Algorithm header.
+fn unarmor(bytes: &[u8], expected: KeyKind) -> Result<(Zeroizing<Vec<u8>>, Option<String>)> {
+    if !armor::is_armored(bytes) {
+        return Ok((Zeroizing::new(bytes.to_vec()), None));
+    }
+    let block = armor::decode(bytes)?;
+    if block.label != armor_label(expected) {
+        bail!("expected '{}' armor but found '{}'", armor_label(expected), block.label);
+    }
+    if let Some(v) = block.header("Version") {
+        if v != ARMOR_VERSION { bail!("unsupported armored key version {}", v); }
+    }
+    let alg = block.header("Algorithm").map(str::to_string);
+    Ok((Zeroizing::new(block.data), alg))
+}
+
+fn write_key(kind: KeyKind, path: &Path, data: &[u8]) -> Result<()> {
+    if kind.is_private() {
+        perms::write_private(path, data)
+    } else {
+        write_all(path, data)
+    }
+}
+
+/// Fingerprint of a public key for `kem`
+pub fn fingerprint(kem: KemId, public_key: &[u8]) -> String {
+    fingerprint_bytes(kem.as_u8(), public_key)
+}
+
+fn fingerprint_bytes(alg: u8, public_key: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([alg]);
+    hasher.update(public_key);
+    let digest = hasher.finalize();
+    digest[..16]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(":")
+}
@@ -0,0 +1,189 @@
+//! Named key storage under `~/.pitlink/keys`
+//!
+//! Each entry `<name>` is stored as `<name>.pub` and, when the private half is
+//! held, `<name>.key`, both in the `keyfile` container format. Keys generated in
+//! the keyring are derived from a random seed kept in `<name>.seed`, which is what
+//! `keys backup` exports as a BIP39 mnemonic.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use zeroize::Zeroizing;
+
+use common::container::KemId;
+
+use crate::kem;
+use crate::{secret_bytes, SecretBytes};
+use crate::keyfile::{KeyFile, KeyKind, KeyMetadata};
+use crate::perms;
+
+/// A keyring entry as shown by `keys list`
+#[derive(Debug, Clone)]
+pub struct KeyEntry {
+    pub name: String,
+    pub kem: KemId,
+    pub fingerprint: String,
+    pub has_private: bool,
+    pub meta: KeyMetadata,
+}
+
+/// Directory of named keys
+pub struct Keyring {
+    root: PathBuf,
+}
+
+impl Keyring {
+    /// Open a keyring rooted at `root`, creating the directory if needed
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).with_context(|| format!("creating keyring {}", root.display()))?;
+        Ok(Self { root })
+    }
+
+    /// Open the default keyring at `~/.pitlink/keys`
+    pub fn open_default() -> Result<Self> {
+        Self::open(Self::default_path()?)
+    }
+
+    /// Location of the default keyring
+    pub fn default_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().context("cannot determine home directory")?;
+        Ok(home.join(".pitlink").join("keys"))
+    }
+
+    /// Path of the public key for `name`
+    pub fn public_path(&self, name: &str) -> Result<PathBuf> {
+        validate_name(name)?;
+        Ok(self.root.join(format!("{}.pub", name)))
+    }
+
+    /// Path of the private key for `name`
+    pub fn private_path(&self, name: &str) -> Result<PathBuf> {
+        validate_name(name)?;
+        Ok(self.root.join(format!("{}.key", name)))
+    }
+
+    /// Path of the keygen seed for `name`
+    pub fn seed_path(&self, name: &str) -> Result<PathBuf> {
+        validate_name(name)?;
+        Ok(self.root.join(format!("{}.seed", name)))
+    }
+
+    /// Resolve `name` to an existing key file of the given kind
+    pub fn resolve(&self, name: &str, kind: KeyKind) -> Result<PathBuf> {
+        let path = if kind.is_private() { self.private_path(name)? } else { self.public_path(name)? };
+        if !path.exists() {
+            bail!("no {:?} key named '{}' in keyring {}", kind, name, self.root.display());
+        }
+        Ok(path)
+    }
+
+    /// Generate and store a new keypair, returning its fingerprint.
+    /// KEMs with deterministic keygen are derived from a stored random seed.
+    pub fn generate(&self, name: &str, kem_id: KemId, meta: KeyMetadata) -> Result<String> {
+        let mut seed = Zeroizing::new([0u8; kem::SEED_LEN]);
+        getrandom::getrandom(&mut *seed)?;
+        match kem::keypair_from_seed(kem_id, &seed[..]) {
+            Ok(_) => self.store_from_seed(name, kem_id, &seed[..], meta),
+            Err(_) => {
+                let (pk, sk) = kem::generate_keypair(kem_id);
+                self.store(name, kem_id, pk, sk, meta)
+            }
+        }
+    }
+
+    /// Re-derive a keypair from its seed and store it together with the seed
+    pub fn store_from_seed(&self, name: &str, kem_id: KemId, seed: &[u8], meta: KeyMetadata) -> Result<String> {
+        let (pk, sk) = kem::keypair_from_seed(kem_id, seed)?;
+        let fp = self.store(name, kem_id, pk, sk, meta)?;
+        perms::write_private(self.seed_path(name)?, seed)?;
+        Ok(fp)
+    }
+
+    /// Read the keygen seed of `name`
+    pub fn seed(&self, name: &str) -> Result<SecretBytes> {
+        let path = self.seed_path(name)?;
+        if !path.exists() {
+            bail!("key '{}' has no stored seed (imported or Kyber round-3 key)", name);
+        }
+        Ok(secret_bytes(fs::read(path)?))
+    }
+
+    /// Store a keypair under `name`, returning its fingerprint
+    pub fn store(&self, name: &str, kem_id: KemId, pk: Vec<u8>, sk: Vec<u8>, meta: KeyMetadata) -> Result<String> {
+        let pub_path = self.public_path(name)?;
+        if pub_path.exists() { bail!("key '{}' already exists", name); }
+        let fp = crate::keyfile::fingerprint(kem_id, &pk);
+        KeyFile::new(KeyKind::Public, kem_id, pk).with_metadata(meta.clone()).write(&pub_path)?;
+        KeyFile::new(KeyKind::Private, kem_id, sk).with_metadata(meta).write(self.private_path(name)?)?;
+        Ok(fp)
+    }
+
+    /// Import a public key file, or a private key together with its public key
+    pub fn import(&self, name: &str, public: &Path, private: Option<&Path>) -> Result<String> {
+        let pub_path = self.public_path(name)?;
+        if pub_path.exists() { bail!("key '{}' already exists", name); }
+
+        let pk = KeyFile::read(public, KeyKind::Public)?;
+        if let Some(private) = private {
+            let sk = KeyFile::read(private, KeyKind::Private)?;
+            if sk.kem != pk.kem {
+                bail!("public key is {:?} but private key is {:?}", pk.kem, sk.kem);
+            }
+            sk.write(self.private_path(name)?)?;
+        }
+        pk.write(&pub_path)?;
+        pk.fingerprint()
+    }
+
+    /// Copy a stored key out of the keyring, optionally ASCII-armored
+    pub fn export(&self, name: &str, kind: KeyKind, out: &Path, armor: bool) -> Result<()> {
+        let key = KeyFile::read(self.resolve(name, kind)?, kind)?;
+        if armor { key.write_armored(out) } else { key.write(out) }
+    }
+
+    /// List all entries sorted by name
+    pub fn list(&self) -> Result<Vec<KeyEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("pub") { continue; }
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let key = KeyFile::read(&path, KeyKind::Public)?;
+            entries.push(KeyEntry {
+                has_private: self.private_path(&name)?.exists(),
+                fingerprint: key.fingerprint()?,
+                kem: key.kem,
+                meta: key.meta,
+                name,
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Remove a stored key, its private half and its seed
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let pub_path = self.public_path(name)?;
+        let priv_path = self.private_path(name)?;
+        if !pub_path.exists() && !priv_path.exists() {
+            bail!("no key named '{}'", name);
+        }
+        for path in [pub_path, priv_path, self.seed_path(name)?] {
+            if path.exists() { fs::remove_file(path)?; }
+        }
+        Ok(())
+    }
+}
+
+/// Key names become file names, so keep them to a safe character set
+fn validate_name(name: &str) -> Result<()> {
+    let ok = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !ok { bail!("invalid key name '{}' (use letters, digits, '-', '_' and '.')", name); }
+    Ok(())
+}
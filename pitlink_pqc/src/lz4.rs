@@ -0,0 +1,46 @@
+//! Decoding LZ4-compressed plaintext while it is decrypted (`decrypt --decompress`)
+//!
+//! Packages made with `--compress lz4` already decrypt to the raw input, because
+//! every chunk is decompressed as it is opened. This covers the other case: an
+//! `.lz4` file (from the `lz4` CLI or `lz4_flex::frame`) encrypted as it is. The
+//! decrypted plaintext is read through an LZ4 frame decoder and only the decoded
+//! bytes reach the output.
+
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+
+/// Decode the LZ4 frames read from `source` into `out`, returning the decoded length
+pub fn decode<R: Read, W: Write + ?Sized>(source: R, out: &mut W) -> Result<u64> {
+    let mut frames = lz4_flex::frame::FrameDecoder::new(source);
+    let written = std::io::copy(&mut frames, out).context("package plaintext is not a valid LZ4 frame")?;
+    Ok(written)
+}
+
+/// Strip a `.lz4` extension from a file name stored in the package metadata
+pub fn decoded_name(name: &str) -> &str {
+    match name.strip_suffix(".lz4") {
+        Some(stem) if !stem.is_empty() => stem,
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_frames_and_refuses_plain_text() {
+        let raw: Vec<u8> = b"ts,speed,rpm\n1712,287.4,11800\n".iter().copied().cycle().take(300_000).collect();
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(&raw).unwrap();
+        let packed = encoder.finish().unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(decode(&packed[..], &mut out).unwrap(), raw.len() as u64);
+        assert_eq!(out, raw);
+        assert!(decode(&raw[..], &mut Vec::new()).is_err());
+        assert!(decode(&packed[..packed.len() / 2], &mut Vec::new()).is_err());
+        assert_eq!((decoded_name("lap.csv.lz4"), decoded_name(".lz4"), decoded_name("lap.csv")), ("lap.csv", ".lz4", "lap.csv"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Reporting command results to the dashboard
+//!
+//! With `--metrics-url` (or `metrics_url` in the config) encrypt, decrypt, pipeline
+//! and the benchmark commands POST their recorded result to the dashboard's
+//! metrics endpoint, e.g. `http://host:8080/api/metrics`, as one version 1 report:
+//!
+//! ```json
+//! {"version":1,"source":"rust_pqc","node_id":"car-12","operation":"encrypt",
+//!  "ok":true,"timestamp":"2026-...Z","duration_ms":12,"bytes":1048576,
+//!  "package_bytes":1049211,"algorithm":"MlKem768/XChaCha20Poly1305",
+//!  "throughput_mbps":699.0,"result":{...}}
+//! ```
+//!
+//! `node_id` comes from `node_id` in the config (or `PITLINK_NODE_ID`), else the
+//! host name. `metrics_key` (or `PITLINK_METRICS_KEY`) is sent as the dashboard
+//! API key. Reporting is best effort: a dashboard that is down or slow only
+//! costs a warning, never the command's own result.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+/// Commands whose results are reported
+const REPORTED: &[&str] = &["encrypt", "decrypt", "pipeline", "benchmark-kem", "benchmark-file"];
+
+/// Longest the CLI waits for the dashboard before giving up on a report
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether `command` (a subcommand path such as "encrypt") reports metrics
+pub fn is_reported(command: &str) -> bool {
+    REPORTED.contains(&command)
+}
+
+/// Version of the report [`payload`] builds
+pub const VERSION: u32 = 1;
+
+/// The report for one run of `command` on `node_id`. `result` is the recorded
+/// command result, `elapsed` the wall-clock time of the whole run. Sizes,
+/// algorithms and throughput are taken from the result when it has them.
+pub fn payload(command: &str, node_id: &str, ok: bool, elapsed: Duration, result: Option<&Value>) -> Value {
+    let field = |name: &str| result.and_then(|r| r.get(name));
+    let count = |name: &str| field(name).and_then(Value::as_u64);
+    let duration_ms = count("elapsed_ms").unwrap_or(elapsed.as_millis() as u64);
+    let bytes = count("plaintext_bytes");
+    let throughput_mbps = bytes
+        .filter(|_| duration_ms > 0)
+        .map(|bytes| bytes as f64 * 8.0 / 1_000_000.0 / (duration_ms as f64 / 1000.0));
+    let algorithm = match (field("kem").and_then(Value::as_str), field("aead").and_then(Value::as_str)) {
+        (Some(kem), Some(aead)) => Some(format!("{}/{}", kem, aead)),
+        (kem, aead) => kem.or(aead).map(str::to_string),
+    };
+    json!({
+        "version": VERSION,
+        "source": "rust_pqc",
+        "node_id": node_id,
+        "operation": command,
+        "ok": ok,
+        "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "duration_ms": duration_ms,
+        "bytes": bytes.unwrap_or(0),
+        "package_bytes": count("package_bytes"),
+        "algorithm": algorithm,
+        "throughput_mbps": throughput_mbps,
+        "result": result,
+    })
+}
+
+/// This machine's host name, for reports without a configured node id
+pub fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "rust_pqc".to_string())
+}
+
+/// POST `payload` as JSON to `url`, with `api_key` as a bearer token when given
+pub fn post(url: &str, api_key: Option<&str>, payload: &Value) -> Result<()> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let mut request = agent.post(url).set("Content-Type", "application/json");
+    if let Some(key) = api_key {
+        request = request.set("Authorization", &format!("Bearer {}", key));
+    }
+    request
+        .send_string(&payload.to_string())
+        .with_context(|| format!("cannot report metrics to {}", url))?;
+    Ok(())
+}
+
+/// Report one run of `command` on `node_id` to `url`, logging instead of failing
+pub fn report(url: &str, api_key: Option<&str>, node_id: &str, command: &str, ok: bool, elapsed: Duration, result: Option<&Value>) {
+    match post(url, api_key, &payload(command, node_id, ok, elapsed, result)) {
+        Ok(()) => debug!(target: "io", "Reported {} metrics to {}", command, url),
+        Err(e) => warn!(target: "io", "{:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_derives_throughput_from_the_result() {
+        let result = json!({ "plaintext_bytes": 10_000_000u64, "package_bytes": 10_000_500u64, "elapsed_ms": 100, "kem": "MlKem768", "aead": "XChaCha20Poly1305" });
+        let report = payload("encrypt", "car-12", true, Duration::from_millis(250), Some(&result));
+        assert_eq!((report["version"].as_u64(), report["node_id"].as_str(), report["operation"].as_str()), (Some(1), Some("car-12"), Some("encrypt")));
+        assert_eq!((report["duration_ms"].as_u64(), report["bytes"].as_u64()), (Some(100), Some(10_000_000)));
+        assert_eq!(report["package_bytes"], 10_000_500u64);
+        assert_eq!(report["algorithm"], "MlKem768/XChaCha20Poly1305");
+        assert_eq!(report["throughput_mbps"].as_f64(), Some(800.0));
+        assert_eq!(report["result"], result);
+
+        let report = payload("benchmark-kem", "car-12", false, Duration::from_millis(7), None);
+        assert_eq!((report["duration_ms"].as_u64(), report["bytes"].as_u64()), (Some(7), Some(0)));
+        assert!(report["throughput_mbps"].is_null() && report["algorithm"].is_null());
+        assert!(is_reported("benchmark-kem") && !is_reported("keys list"));
+    }
+}
@@ -0,0 +1,50 @@
+//! Human-readable or JSON command output
+//!
+//! In JSON mode the human status lines move to stderr and each command records a
+//! structured result, which the CLI prints as a single JSON object on stdout.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde_json::Value;
+use zeroize::Zeroize;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static RESULT: Mutex<Option<Value>> = Mutex::new(None);
+
+pub fn set_json(enabled: bool) {
+    JSON.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Record the structured result of the current command, replacing any earlier one
+pub fn record(result: Value) {
+    *RESULT.lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+}
+
+/// Take the recorded result
+pub fn take() -> Option<Value> {
+    RESULT.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// Overwrite every string in a taken result, which may hold a secret such as
+/// a `keys backup` phrase
+pub fn wipe(value: &mut Value) {
+    match value {
+        Value::String(s) => s.zeroize(),
+        Value::Array(items) => items.iter_mut().for_each(wipe),
+        Value::Object(map) => map.values_mut().for_each(wipe),
+        _ => {}
+    }
+}
+
+/// Print a human status line: stdout normally, stderr in JSON mode
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::is_json() { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
@@ -0,0 +1,165 @@
+//! Owner-only permissions for secret files
+//!
+//! On Unix secrets are created with mode 0600 and reading a private key that is
+//! group/world accessible is refused. On Windows the equivalent is an ACL without
+//! inherited entries that grants access only to the current user, applied and
+//! inspected with `icacls`. The check reads the ACL as SDDL, so it matches the
+//! well-known SIDs rather than group names that change with the system language,
+//! and a file whose ACL cannot be read counts as accessible.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use anyhow::Result;
+
+/// Write a secret file readable only by its owner
+pub fn write_private<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let mut f = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+        // `mode` only applies to newly created files
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        f.write_all(data)?;
+    }
+    #[cfg(not(unix))]
+    {
+        // Restrict the file while it is still empty, so the secret is never
+        // readable under the inherited ACL
+        let mut f = fs::File::create(path)?;
+        #[cfg(windows)]
+        restrict_windows_acl(path)?;
+        f.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Describe why a secret file is too permissive, or `None` if it is owner-only
+pub fn insecure_reason<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+    let path = path.as_ref();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Ok(Some(format!("{} has mode {:o}; run `chmod 600 {}`", path.display(), mode, path.display())));
+        }
+        Ok(None)
+    }
+    #[cfg(windows)]
+    {
+        let fix = format!("run `icacls {} /inheritance:r /grant:r %USERNAME%:F`", path.display());
+        let sddl = match windows_sddl(path) {
+            Ok(sddl) => sddl,
+            Err(e) => return Ok(Some(format!("{:#}, so it may be readable by anyone; {}", e, fix))),
+        };
+        // Access-allowed entries look like `(A;flags;rights;;;SID)`
+        for ace in sddl.split('(').skip(1) {
+            let fields: Vec<&str> = ace.split(')').next().unwrap_or_default().split(';').collect();
+            let (Some(&kind), Some(&sid)) = (fields.first(), fields.get(5)) else { continue };
+            if !kind.starts_with('A') {
+                continue;
+            }
+            if let Some((name, ..)) = BROAD_PRINCIPALS.iter().find(|(_, alias, full)| sid == *alias || sid == *full) {
+                return Ok(Some(format!("{} grants access to {}; {}", path.display(), name, fix)));
+            }
+        }
+        Ok(None)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+/// Refuse (or with `allow_insecure`, warn about) a private key readable by others
+pub fn check_private<P: AsRef<Path>>(path: P, allow_insecure: bool) -> Result<()> {
+    if let Some(reason) = insecure_reason(path)? {
+        if !allow_insecure {
+            anyhow::bail!("private key is accessible by other users: {} (use --allow-insecure-key to override)", reason);
+        }
+        tracing::warn!(target: "perms", "private key is accessible by other users: {}", reason);
+    }
+    Ok(())
+}
+
+/// Groups a private key must not be readable by: name, SDDL alias and SID
+#[cfg(windows)]
+const BROAD_PRINCIPALS: [(&str, &str, &str); 3] = [
+    ("Everyone", "WD", "S-1-1-0"),
+    ("BUILTIN\\Users", "BU", "S-1-5-32-545"),
+    ("Authenticated Users", "AU", "S-1-5-11"),
+];
+
+/// The DACL of `path` as SDDL, from `icacls /save`
+#[cfg(windows)]
+fn windows_sddl(path: &Path) -> Result<String> {
+    use anyhow::Context;
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or_default();
+    let dump = std::env::temp_dir().join(format!("pitlink-acl-{}-{}", std::process::id(), nanos));
+    let status = std::process::Command::new("icacls").arg(path).arg("/save").arg(&dump).output()?.status;
+    let saved = fs::read(&dump);
+    let _ = fs::remove_file(&dump);
+    if !status.success() {
+        anyhow::bail!("icacls cannot read the ACL of {}", path.display());
+    }
+    // UTF-16LE: the file name on the first line and its SDDL on the second
+    let units: Vec<u16> = saved?.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect();
+    String::from_utf16_lossy(&units).lines().nth(1).map(str::to_string)
+        .with_context(|| format!("icacls returned no ACL for {}", path.display()))
+}
+
+#[cfg(windows)]
+fn restrict_windows_acl(path: &Path) -> Result<()> {
+    let user = std::env::var("USERNAME")?;
+    let status = std::process::Command::new("icacls")
+        .arg(path)
+        .args(["/inheritance:r", "/grant:r"])
+        .arg(format!("{}:F", user))
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("icacls failed to restrict {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn writes_secrets_owner_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let created = dir.path().join("new.key");
+        write_private(&created, b"secret").unwrap();
+        assert_eq!(mode(&created), 0o600);
+        assert_eq!(fs::read(&created).unwrap(), b"secret");
+
+        // An existing file keeps its mode when opened, so it is tightened too
+        let existing = dir.path().join("old.key");
+        fs::write(&existing, b"old").unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&existing, b"new").unwrap();
+        assert_eq!(mode(&existing), 0o600);
+        assert!(insecure_reason(&existing).unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_private_keys_others_can_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("id.key");
+        fs::write(&key, b"secret").unwrap();
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let error = check_private(&key, false).unwrap_err().to_string();
+        assert!(error.contains("mode 644"), "{}", error);
+        check_private(&key, true).unwrap();
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o600)).unwrap();
+        check_private(&key, false).unwrap();
+    }
+}
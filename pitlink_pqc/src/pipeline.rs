@@ -0,0 +1,278 @@
+//! Single-pass compress → chunk → encrypt pipeline (`pipeline`)
+//!
+//! Does in one streaming pass what `csv_lz4_tool`, `lz4_chunker` and `encrypt` do
+//! with intermediate files: the raw input is read once, and every chunk is
+//! LZ4-compressed and encrypted as it goes (see [`Compression`]). The result is one
+//! package, or with a split size one package per part of the input plus a manifest:
+//!
+//! ```text
+//! <prefix>.0001.pqc ... <prefix>.NNNN.pqc   one complete package per part
+//! <prefix>.manifest.json                    the parts in order, with offsets and SHA-256
+//! ```
+//!
+//! Parts end on chunk boundaries and decrypt on their own; their plaintexts in
+//! manifest order are the input. With [`Split::Cdc`] the part boundaries are
+//! content-defined instead (see [`common::cdc`]): an edit to the input only
+//! changes the parts around it, and the manifest records each part's content
+//! hash so unchanged parts can be recognised across runs. The manifest is a [`common::Manifest`], signed
+//! with the sign key when one is given. It is written last, so a prefix without
+//! one is an interrupted run.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use common::cdc::Cdc;
+use common::manifest::{self, Algorithms, Manifest, ManifestChunk};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::keyfile::{KeyFile, KeyKind, SigningKeyFile};
+use crate::sig;
+use crate::{check_overwrite, encrypt_file, is_stdio, open_input, output, write_package, AtomicOutput, Compression, EncryptOptions, ExposeSecret, Plaintext};
+
+/// Where a split pipeline run starts a new part
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    /// After this many plaintext bytes, rounded down to whole chunks
+    Fixed(u64),
+    /// At content-defined boundaries within these bounds
+    Cdc(Cdc),
+}
+
+/// `<prefix>.0001.pqc` for part 1
+pub fn part_path(prefix: &Path, index: u64) -> PathBuf {
+    suffixed(prefix, &format!(".{:04}.pqc", index))
+}
+
+/// `<prefix>.manifest.json`
+pub fn manifest_path(prefix: &Path) -> PathBuf {
+    suffixed(prefix, ".manifest.json")
+}
+
+fn suffixed(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Compress, chunk and encrypt `input` (`-` for stdin) for the public key at
+/// `pubkey_path` in one pass. Without `split` the result is one package at
+/// `output`; with it `output` is the prefix of one package per part and of their
+/// manifest. Content-defined parts need an input file, which is read once more
+/// to find the boundaries. Chunks are LZ4-compressed unless `opts.compress`
+/// already names a compression.
+pub fn pipeline(input: PathBuf, output: PathBuf, pubkey_path: PathBuf, split: Option<Split>, mut opts: EncryptOptions) -> Result<()> {
+    opts.compress.get_or_insert(Compression::Lz4);
+    let Some(split) = split else { return encrypt_file(input, output, pubkey_path, opts) };
+    if is_stdio(&output) {
+        bail!("a split pipeline writes several files; --output must be a path prefix, not -");
+    }
+    let manifest_file = manifest_path(&output);
+    check_overwrite(&manifest_file, opts.force)?;
+    let start = Instant::now();
+    let recipient = KeyFile::read(pubkey_path, KeyKind::Public)?;
+    let signer = opts.sign_key.as_ref().map(|path| SigningKeyFile::read(path, KeyKind::SigningPrivate)).transpose()?;
+    let chunk_size = opts.chunk_size as u64;
+    let mut package_id = [0u8; 16];
+    getrandom::getrandom(&mut package_id)?;
+    let algorithms = Algorithms {
+        kem: Some(format!("{:?}", recipient.kem)),
+        aead: Some(format!("{:?}", opts.aead)),
+        compression: opts.compress.map(|c| format!("{:?}", c)),
+        hash: "sha256".to_string(),
+    };
+    let mut manifest = Manifest::new(manifest::to_hex(&package_id), input.display().to_string(), algorithms, chunk_size);
+    manifest.recipient_fingerprint = Some(recipient.fingerprint()?);
+    let (fixed_len, mut cuts) = match split {
+        Split::Fixed(bytes) => ((bytes / chunk_size).max(1) * chunk_size, None),
+        Split::Cdc(cdc) => {
+            if is_stdio(&input) {
+                bail!("content-defined parts need an input file to scan, not stdin");
+            }
+            manifest.cdc = Some(cdc);
+            let cuts = cdc.scan(BufReader::new(File::open(&input)?))?;
+            info!(target: "io", "Found {} content-defined parts in {}", cuts.len().max(1), input.display());
+            (0, Some(cuts.into_iter()))
+        }
+    };
+
+    // Files are read twice per part for the Merkle tree; stdin once, without one
+    let (mut stream, input_len) = if is_stdio(&input) {
+        info!(target: "io", "Reading plaintext from stdin (parts will carry no Merkle tree)");
+        (Some(BufReader::new(open_input(&input)?)), 0)
+    } else {
+        (None, std::fs::metadata(&input)?.len())
+    };
+    let mut offset = 0u64;
+    loop {
+        let (part_len, content_hash) = match cuts.as_mut() {
+            Some(cuts) => match cuts.next() {
+                Some((len, hash)) => (len, Some(manifest::to_hex(&hash))),
+                // An empty input still gets one (empty) part
+                None if manifest.chunks.is_empty() => (0, None),
+                None => break,
+            },
+            None => (fixed_len, None),
+        };
+        let plaintext = match stream.as_mut() {
+            Some(stream) => {
+                if !manifest.chunks.is_empty() && stream.fill_buf()?.is_empty() {
+                    break;
+                }
+                Plaintext::Stream(Box::new(stream.by_ref().take(part_len)))
+            }
+            None => {
+                if !manifest.chunks.is_empty() && offset >= input_len {
+                    break;
+                }
+                let path = input.clone();
+                Plaintext::Reopen(part_reader(&input, offset, part_len)?, Box::new(move || part_reader(&path, offset, part_len)))
+            }
+        };
+        let index = manifest.chunks.len() as u64 + 1;
+        let path = part_path(&output, index);
+        check_overwrite(&path, opts.force)?;
+        let mut out = AtomicOutput::create(&path)?;
+        let mut hashed = HashingWriter { inner: &mut out, hasher: Sha256::new(), len: 0 };
+        let summary = write_package(plaintext, &mut hashed, &recipient, signer.as_ref(), &opts)?;
+        let (package_bytes, digest) = (hashed.len, hashed.hasher.finalize());
+        if content_hash.is_some() && summary.plaintext_len != part_len {
+            bail!("{} changed while it was being split", input.display());
+        }
+        out.commit()?;
+        info!(target: "io", "Wrote part {} ({} plaintext bytes) to {}", index, summary.plaintext_len, path.display());
+        manifest.push(ManifestChunk {
+            index,
+            file: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            offset,
+            size: summary.plaintext_len,
+            stored_size: package_bytes,
+            frames: Some(summary.chunks),
+            hash: manifest::to_hex(&digest),
+            content_hash,
+        });
+        offset += summary.plaintext_len;
+        // A short fixed-size part means the input ran out
+        if cuts.is_none() && summary.plaintext_len < part_len {
+            break;
+        }
+    }
+
+    if let Some(signer) = &signer {
+        let signature = sig::sign(signer.alg, signer.key.expose_secret(), &manifest.signing_bytes()?)?;
+        manifest.set_signature(format!("{:?}", signer.alg), &signature);
+    }
+    let mut out = AtomicOutput::create(&manifest_file)?;
+    out.write_all(manifest.to_json()?.as_bytes())?;
+    out.write_all(b"\n")?;
+    out.commit()?;
+    info!(target: "io", "Wrote {} parts and their manifest to {}", manifest.chunks.len(), manifest_file.display());
+    output::record(json!({
+        "input": input, "manifest": manifest_file, "package_id": manifest.package_id, "recipient_fingerprint": manifest.recipient_fingerprint, "kem": format!("{:?}", recipient.kem),
+        "aead": format!("{:?}", opts.aead), "chunk_size": opts.chunk_size, "compression": manifest.algorithms.compression, "parts": manifest.chunks.len(),
+        "plaintext_bytes": offset, "package_bytes": manifest.chunks.iter().map(|part| part.stored_size).sum::<u64>(),
+        "signed_with": signer.as_ref().map(|s| format!("{:?}", s.alg)), "elapsed_ms": start.elapsed().as_millis() as u64,
+    }));
+    Ok(())
+}
+
+/// `len` bytes of the file at `path` from `offset` on
+fn part_reader(path: &Path, offset: u64, len: u64) -> Result<Box<dyn Read + Send>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(Box::new(file.take(len)))
+}
+
+/// Writer that hashes and counts what passes through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decrypt_file, keygen, DecryptOptions, KemId, KeyMetadata};
+    use common::container::MIN_CHUNK_SIZE;
+
+    #[test]
+    fn splits_into_parts_that_decrypt_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        keygen(dir.clone(), KemId::MlKem768, false, None, KeyMetadata::default(), true).unwrap();
+        let input = dir.join("lap.csv");
+        let raw: Vec<u8> = b"ts,speed,rpm\n1712,287.4,11800\n".iter().copied().cycle().take(5 * MIN_CHUNK_SIZE / 2).collect();
+        std::fs::write(&input, &raw).unwrap();
+
+        let prefix = dir.join("lap");
+        let opts = EncryptOptions { chunk_size: MIN_CHUNK_SIZE, force: true, ..Default::default() };
+        // 1.5 chunks round down to one chunk per part
+        pipeline(input, prefix.clone(), dir.join("kyber_public.key"), Some(Split::Fixed(MIN_CHUNK_SIZE as u64 * 3 / 2)), opts).unwrap();
+        let manifest = Manifest::from_json(&std::fs::read(manifest_path(&prefix)).unwrap()).unwrap();
+        assert_eq!((manifest.chunks.len(), manifest.total_bytes), (3, raw.len() as u64));
+        assert_eq!(manifest.algorithms.compression.as_deref(), Some("Lz4"));
+        assert!(manifest.signature.is_none());
+
+        let mut joined = Vec::new();
+        for part in &manifest.chunks {
+            let package = dir.join(&part.file);
+            assert_eq!(part.offset, joined.len() as u64);
+            let out = dir.join(format!("part-{}", part.index));
+            decrypt_file(package, out.clone(), dir.join("kyber_private.key"), DecryptOptions { force: true, ..Default::default() }).unwrap();
+            joined.extend(std::fs::read(out).unwrap());
+        }
+        assert_eq!(joined, raw);
+    }
+
+    #[test]
+    fn content_defined_parts_survive_an_insertion() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        keygen(dir.clone(), KemId::MlKem768, false, None, KeyMetadata::default(), true).unwrap();
+        let mut state = 7u64;
+        let raw: Vec<u8> = (0..256 * 1024).map(|_| { state = state.wrapping_mul(6364136223846793005).wrapping_add(1); (state >> 56) as u8 }).collect();
+        let mut edited = raw[..5000].to_vec();
+        edited.extend_from_slice(b"new lap");
+        edited.extend_from_slice(&raw[5000..]);
+
+        let cdc = Cdc::new(4 * 1024, 16 * 1024, 64 * 1024).unwrap();
+        let run = |name: &str, data: &[u8]| {
+            let input = dir.join(format!("{}.bin", name));
+            std::fs::write(&input, data).unwrap();
+            let opts = EncryptOptions { chunk_size: MIN_CHUNK_SIZE, force: true, ..Default::default() };
+            pipeline(input, dir.join(name), dir.join("kyber_public.key"), Some(Split::Cdc(cdc)), opts).unwrap();
+            Manifest::from_json(&std::fs::read(manifest_path(&dir.join(name))).unwrap()).unwrap()
+        };
+        let (before, after) = (run("before", &raw), run("after", &edited));
+        assert_eq!((before.cdc, before.total_bytes, after.total_bytes), (Some(cdc), raw.len() as u64, edited.len() as u64));
+        assert!(before.chunks.len() > 4);
+        let shared = after.chunks.iter().filter(|c| before.chunks.iter().any(|b| b.content_hash == c.content_hash)).count();
+        assert!(shared + 2 >= before.chunks.len(), "{} of {} parts kept", shared, before.chunks.len());
+
+        let mut joined = Vec::new();
+        for part in &after.chunks {
+            let out = dir.join(format!("after-{}", part.index));
+            decrypt_file(dir.join(&part.file), out.clone(), dir.join("kyber_private.key"), DecryptOptions { force: true, ..Default::default() }).unwrap();
+            joined.extend(std::fs::read(out).unwrap());
+        }
+        assert_eq!(joined, edited);
+    }
+}
@@ -0,0 +1,101 @@
+//! Progress reporting for long-running encrypt and decrypt operations
+//!
+//! The core reports through a callback so library users (and the dashboard) can
+//! consume the same updates the CLI renders as a progress bar.
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Minimum interval between two callback invocations
+const REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Snapshot of an operation's progress
+#[derive(Debug, Clone)]
+pub struct ProgressUpdate {
+    pub bytes_processed: u64,
+    /// Unknown when reading from stdin
+    pub total_bytes: Option<u64>,
+    pub elapsed: Duration,
+    /// Set on the last update of an operation
+    pub done: bool,
+}
+
+impl ProgressUpdate {
+    pub fn percentage(&self) -> Option<f32> {
+        self.total_bytes.filter(|&t| t > 0).map(|t| self.bytes_processed as f32 / t as f32 * 100.0)
+    }
+
+    pub fn speed_mbps(&self) -> f32 {
+        let secs = self.elapsed.as_secs_f32();
+        if secs > 0.0 { self.bytes_processed as f32 / (1024.0 * 1024.0) / secs } else { 0.0 }
+    }
+
+    pub fn eta_seconds(&self) -> Option<u64> {
+        let total = self.total_bytes?;
+        let speed = self.speed_mbps();
+        if speed <= 0.0 { return None; }
+        let remaining = total.saturating_sub(self.bytes_processed);
+        Some((remaining as f32 / (1024.0 * 1024.0) / speed) as u64)
+    }
+}
+
+/// Callback receiving progress updates
+pub type ProgressCallback = Arc<dyn Fn(ProgressUpdate) + Send + Sync>;
+
+/// Rate-limited driver for an optional progress callback
+pub(crate) struct Progress {
+    callback: Option<ProgressCallback>,
+    total_bytes: Option<u64>,
+    start: Instant,
+    last_report: Option<Instant>,
+}
+
+impl Progress {
+    pub(crate) fn new(callback: Option<ProgressCallback>, total_bytes: Option<u64>) -> Self {
+        Self { callback, total_bytes, start: Instant::now(), last_report: None }
+    }
+
+    /// A tracker that reports nowhere
+    pub(crate) fn none() -> Self {
+        Self::new(None, None)
+    }
+
+    pub(crate) fn update(&mut self, bytes_processed: u64) {
+        if self.callback.is_none() { return; }
+        if self.last_report.is_some_and(|t| t.elapsed() < REPORT_INTERVAL) { return; }
+        self.last_report = Some(Instant::now());
+        self.report(bytes_processed, false);
+    }
+
+    pub(crate) fn finish(&mut self, bytes_processed: u64) {
+        self.report(bytes_processed, true);
+    }
+
+    fn report(&self, bytes_processed: u64, done: bool) {
+        if let Some(callback) = &self.callback {
+            callback(ProgressUpdate { bytes_processed, total_bytes: self.total_bytes, elapsed: self.start.elapsed(), done });
+        }
+    }
+}
+
+/// Progress bar rendered on stderr
+pub fn progress_bar() -> ProgressCallback {
+    Arc::new(|p: ProgressUpdate| {
+        const WIDTH: usize = 30;
+        let mb = |b: u64| b as f64 / (1024.0 * 1024.0);
+        let line = match (p.percentage(), p.total_bytes) {
+            (Some(pct), Some(total)) => {
+                let filled = ((pct / 100.0) * WIDTH as f32) as usize;
+                let eta = p.eta_seconds().map(|s| format!("{}:{:02}", s / 60, s % 60)).unwrap_or_else(|| "--:--".to_string());
+                format!("[{}{}] {:5.1}% {:.1}/{:.1} MiB {:.1} MiB/s ETA {}",
+                    "#".repeat(filled.min(WIDTH)), "-".repeat(WIDTH - filled.min(WIDTH)), pct, mb(p.bytes_processed), mb(total), p.speed_mbps(), eta)
+            }
+            _ => format!("{:.1} MiB {:.1} MiB/s", mb(p.bytes_processed), p.speed_mbps()),
+        };
+        let mut err = std::io::stderr().lock();
+        let _ = write!(err, "\r{:<90}", line);
+        if p.done { let _ = writeln!(err); }
+        let _ = err.flush();
+    })
+}
@@ -0,0 +1,400 @@
+//! S3-compatible object storage for `encrypt --upload` / `decrypt --download`
+//!
+//! The package is streamed straight into a multipart upload: it is cut into parts
+//! as it is written, each part is sent once it is full, and the upload is completed
+//! after the trailer. Only the part being filled is held in memory, so a package
+//! of any size never touches the uploader's disk. A download streams the object
+//! body into the decryptor the same way.
+//!
+//! ```text
+//! s3://bucket/prefix/      the package is stored as prefix/<input name>.pqc
+//! s3://bucket/lap-12.pqc   a key ending in .pqc is used as it is
+//! ```
+//!
+//! Requests are signed with AWS Signature Version 4 from `AWS_ACCESS_KEY_ID`,
+//! `AWS_SECRET_ACCESS_KEY` and optionally `AWS_SESSION_TOKEN`. The region comes
+//! from `s3_region` in the config, else `AWS_REGION` / `AWS_DEFAULT_REGION`, else
+//! `us-east-1`. Without an endpoint the bucket is addressed on AWS
+//! (`bucket.s3.<region>.amazonaws.com`); with `s3_endpoint` (or
+//! `AWS_ENDPOINT_URL`) such as a MinIO or Ceph gateway, path-style
+//! (`endpoint/bucket/key`).
+
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use common::manifest::to_hex;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+use zeroize::Zeroizing;
+
+use crate::keyfile::{KeyFile, KeyKind, SigningKeyFile};
+use crate::progress::Progress;
+use crate::{check_overwrite, dearmor_input, is_stdio, lz4, output, perms, streamed_plaintext, throttle, upload_name, write_package, AtomicOutput, DecryptOptions, EncryptOptions, OpenPackage};
+
+/// Size of the first upload parts; S3 wants at least 5 MiB in every part but the last
+const PART_SIZE: usize = 8 * 1024 * 1024;
+/// The part size doubles after this many parts, which keeps a package of any
+/// size under the limit of 10,000 parts
+const PARTS_PER_SIZE: usize = 1000;
+/// Longest a connection attempt, read or write may stall
+const IO_TIMEOUT: Duration = Duration::from_secs(120);
+/// SHA-256 of an empty body
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// An object location, `s3://bucket/key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Url {
+    pub bucket: String,
+    /// Object key, or a prefix ending in `/` (possibly empty)
+    pub key: String,
+}
+
+impl S3Url {
+    pub fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("s3://") else { bail!("{} is not an s3://bucket/key URL", url) };
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            bail!("{} names no bucket", url);
+        }
+        Ok(Self { bucket: bucket.to_string(), key: key.to_string() })
+    }
+}
+
+/// Endpoint, region and credentials for S3 requests
+pub struct Store {
+    /// `scheme://host[:port]` of a path-style endpoint; AWS when None
+    endpoint: Option<String>,
+    region: String,
+    access_key: String,
+    secret_key: Zeroizing<String>,
+    session_token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl Store {
+    /// Credentials from the `AWS_*` variables; `endpoint` and `region` override the
+    /// ones from the environment
+    pub fn from_env(endpoint: Option<String>, region: Option<String>) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let (Some(access_key), Some(secret_key)) = (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) else {
+            bail!("S3 needs credentials in AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY");
+        };
+        let region = region.or_else(|| var("AWS_REGION")).or_else(|| var("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = endpoint.or_else(|| var("AWS_ENDPOINT_URL")).map(|e| e.trim_end_matches('/').to_string());
+        if let Some(endpoint) = &endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                bail!("S3 endpoint {} must start with http:// or https://", endpoint);
+            }
+        }
+        let agent = ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(30)).timeout_read(IO_TIMEOUT).timeout_write(IO_TIMEOUT).build();
+        Ok(Self { endpoint, region, access_key, secret_key: Zeroizing::new(secret_key), session_token: var("AWS_SESSION_TOKEN"), agent })
+    }
+
+    /// Start a multipart upload of `key` in `bucket`
+    pub fn upload(&self, bucket: &str, key: &str) -> Result<MultipartUpload<'_>> {
+        let response = self.send("POST", bucket, key, &[("uploads", "")], b"")?;
+        let body = response.into_string()?;
+        let Some(upload_id) = xml_value(&body, "UploadId") else { bail!("S3 did not return an upload id for s3://{}/{}", bucket, key) };
+        debug!(target: "io", "Started multipart upload {} of s3://{}/{}", upload_id, bucket, key);
+        Ok(MultipartUpload { store: self, bucket: bucket.to_string(), key: key.to_string(), upload_id, part: Vec::with_capacity(PART_SIZE), etags: Vec::new(), bytes: 0, done: false })
+    }
+
+    /// The body of the object `key` in `bucket`, as it arrives
+    pub fn download(&self, bucket: &str, key: &str) -> Result<Box<dyn Read + Send>> {
+        let response = self.send("GET", bucket, key, &[], b"")?;
+        Ok(Box::new(response.into_reader()))
+    }
+
+    /// Sign and send one request, turning an S3 error response into an error
+    fn send(&self, method: &str, bucket: &str, key: &str, query: &[(&str, &str)], body: &[u8]) -> Result<ureq::Response> {
+        let payload_hash = if body.is_empty() { EMPTY_SHA256.to_string() } else { to_hex(&Sha256::digest(body)) };
+        let (url, request) = self.request(method, bucket, key, query, &payload_hash, chrono::Utc::now());
+        let result = if body.is_empty() && method != "POST" { request.call() } else { request.send_bytes(body) };
+        match result {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(code, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let detail = [xml_value(&body, "Code"), xml_value(&body, "Message")].into_iter().flatten().collect::<Vec<_>>().join(": ");
+                bail!("{} {} failed with HTTP {}{}", method, url, code, if detail.is_empty() { String::new() } else { format!(" ({})", detail) })
+            }
+            Err(e) => Err(anyhow::Error::new(e).context(format!("{} {} failed", method, url))),
+        }
+    }
+
+    /// The URL of the request and the request with its SigV4 headers
+    fn request(&self, method: &str, bucket: &str, key: &str, query: &[(&str, &str)], payload_hash: &str, now: chrono::DateTime<chrono::Utc>) -> (String, ureq::Request) {
+        let (base, host, path) = match &self.endpoint {
+            Some(endpoint) => {
+                let host = endpoint.split_once("://").map_or(endpoint.as_str(), |(_, host)| host).to_string();
+                (endpoint.clone(), host, format!("/{}/{}", uri_encode(bucket, false), uri_encode(key, true)))
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", bucket, self.region);
+                (format!("https://{}", host), host, format!("/{}", uri_encode(key, true)))
+            }
+        };
+        let mut query: Vec<(String, String)> = query.iter().map(|&(k, v)| (uri_encode(k, false), uri_encode(v, false))).collect();
+        query.sort();
+        let query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let mut headers = vec![("host", host.as_str()), ("x-amz-content-sha256", payload_hash), ("x-amz-date", amz_date.as_str())];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.as_str()));
+        }
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}", method, path, query, canonical_headers, signed_headers, payload_hash);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, to_hex(&Sha256::digest(canonical_request.as_bytes())));
+        let signature = to_hex(&hmac(&signing_key(&self.secret_key, date, &self.region, "s3"), string_to_sign.as_bytes()));
+        let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", self.access_key, scope, signed_headers, signature);
+
+        let url = if query.is_empty() { format!("{}{}", base, path) } else { format!("{}{}?{}", base, path, query) };
+        let mut request = self.agent.request(method, &url).set("Authorization", &authorization);
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.set(name, value);
+        }
+        (url, request)
+    }
+}
+
+/// A multipart upload written as a stream. `complete` finishes it; dropped
+/// without that, the upload is aborted so the parts do not linger in the bucket.
+pub struct MultipartUpload<'a> {
+    store: &'a Store,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    part: Vec<u8>,
+    etags: Vec<String>,
+    /// Bytes written so far
+    bytes: u64,
+    done: bool,
+}
+
+impl MultipartUpload<'_> {
+    /// Size the part being filled is sent at
+    fn part_size(&self) -> usize {
+        PART_SIZE << (self.etags.len() / PARTS_PER_SIZE).min(10)
+    }
+
+    fn send_part(&mut self) -> Result<()> {
+        let number = (self.etags.len() + 1).to_string();
+        let response = self.store.send("PUT", &self.bucket, &self.key, &[("partNumber", &number), ("uploadId", &self.upload_id)], &self.part)?;
+        let Some(etag) = response.header("ETag").map(str::to_string) else { bail!("S3 returned no ETag for part {}", number) };
+        debug!(target: "io", "Uploaded part {} ({} bytes)", number, self.part.len());
+        self.etags.push(etag);
+        self.part.clear();
+        Ok(())
+    }
+
+    /// Send the last part and complete the upload; returns the object size and
+    /// the number of parts
+    pub fn complete(mut self) -> Result<(u64, usize)> {
+        if !self.part.is_empty() || self.etags.is_empty() {
+            self.send_part()?;
+        }
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in self.etags.iter().enumerate() {
+            body.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let response = self.store.send("POST", &self.bucket, &self.key, &[("uploadId", &self.upload_id)], body.as_bytes())?;
+        // Completion can fail after the 200 status line has been sent
+        let reply = response.into_string()?;
+        if reply.contains("<Error>") {
+            bail!("S3 could not complete the upload of s3://{}/{}: {}", self.bucket, self.key, xml_value(&reply, "Message").unwrap_or(reply));
+        }
+        self.done = true;
+        Ok((self.bytes, self.etags.len()))
+    }
+}
+
+impl Write for MultipartUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.part_size() - self.part.len());
+        self.part.extend_from_slice(&buf[..n]);
+        self.bytes += n as u64;
+        if self.part.len() == self.part_size() {
+            self.send_part().map_err(|e| io::Error::other(format!("{:#}", e)))?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MultipartUpload<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(e) = self.store.send("DELETE", &self.bucket, &self.key, &[("uploadId", &self.upload_id)], b"") {
+                warn!(target: "io", "could not abort the upload of s3://{}/{}: {:#}", self.bucket, self.key, e);
+            }
+        }
+    }
+}
+
+/// Encrypt `input` (`-` for stdin, a directory with `opts.recursive`) like
+/// [`encrypt_file`](crate::encrypt_file) and upload the package to `url`
+/// (`s3://bucket/prefix/` or `s3://bucket/key.pqc`). `mmap` and `append` do not
+/// apply.
+pub fn upload_file(input: PathBuf, url: &str, pubkey_path: PathBuf, opts: EncryptOptions, store: &Store) -> Result<()> {
+    if opts.mmap || opts.append {
+        bail!("--upload streams the package; it cannot be combined with --mmap or --append");
+    }
+    let url = S3Url::parse(url)?;
+    let key = upload_name(&url.key, &input)?;
+    let start = Instant::now();
+    let recipient = KeyFile::read(pubkey_path, KeyKind::Public)?;
+    let signer = opts.sign_key.as_ref().map(|path| SigningKeyFile::read(path, KeyKind::SigningPrivate)).transpose()?;
+    let plaintext = streamed_plaintext(&input, &opts)?;
+
+    let mut upload = store.upload(&url.bucket, &key)?;
+    let summary = match opts.rate_limit {
+        Some(rate) => write_package(plaintext, &mut throttle::Throttled::new(&mut upload, rate), &recipient, signer.as_ref(), &opts)?,
+        None => write_package(plaintext, &mut upload, &recipient, signer.as_ref(), &opts)?,
+    };
+    let (package_bytes, parts) = upload.complete()?;
+    let location = format!("s3://{}/{}", url.bucket, key);
+    info!(target: "io", "Uploaded encrypted package to {} in {} parts", location, parts);
+    output::record(json!({
+        "input": input, "output": location, "recipient_fingerprint": recipient.fingerprint()?, "kem": format!("{:?}", recipient.kem),
+        "aead": format!("{:?}", opts.aead), "chunks": summary.chunks, "chunk_size": opts.chunk_size, "compression": opts.compress.map(|c| format!("{:?}", c)),
+        "plaintext_bytes": summary.plaintext_len, "package_bytes": package_bytes, "parts": parts,
+        "signed_with": signer.as_ref().map(|s| format!("{:?}", s.alg)), "elapsed_ms": start.elapsed().as_millis() as u64,
+    }));
+    Ok(())
+}
+
+/// Download the package at `url` (`s3://bucket/key`) and decrypt it as it arrives
+/// to `output` like [`decrypt_file`](crate::decrypt_file). `hybrid`,
+/// `allow_insecure_key`, `verify_sender`, `progress`, `force`, `restore_metadata`
+/// and `decompress` apply; extracting, ranges and members need a local package.
+pub fn download_file(url: &str, output: PathBuf, privkey_path: PathBuf, opts: DecryptOptions, store: &Store) -> Result<()> {
+    if opts.extract || opts.range.is_some() || opts.member.is_some() || opts.mmap {
+        bail!("--download decrypts the package as it streams; --extract, ranges and --mmap need a local package file");
+    }
+    let url = S3Url::parse(url)?;
+    if url.key.is_empty() || url.key.ends_with('/') {
+        bail!("s3://{}/{} names no object to download", url.bucket, url.key);
+    }
+    if opts.restore_metadata && is_stdio(&output) {
+        bail!("--restore-metadata needs an output file or directory, not stdout");
+    }
+    if !opts.restore_metadata {
+        check_overwrite(&output, opts.force)?;
+    }
+    perms::check_private(&privkey_path, opts.allow_insecure_key)?;
+    let identity = KeyFile::read(&privkey_path, KeyKind::Private)?;
+    let sender = opts.verify_sender.as_ref().map(|path| SigningKeyFile::read(path, KeyKind::SigningPublic)).transpose()?;
+    if sender.is_some() && is_stdio(&output) {
+        bail!("--verify-sender cannot hold back plaintext written to stdout; decrypt to a file instead");
+    }
+
+    let start = Instant::now();
+    let source = dearmor_input(store.download(&url.bucket, &url.key)?)?;
+    let mut package = OpenPackage::from_reader(source, &identity, opts.hybrid, sender.as_ref())?;
+    let decompress = opts.decompress && package.header.compression()?.is_none();
+    let output = match &package.metadata {
+        Some(metadata) if opts.restore_metadata => {
+            let name = if decompress { lz4::decoded_name(&metadata.name) } else { &metadata.name };
+            let target = if output.is_dir() { output.join(name) } else { output };
+            check_overwrite(&target, opts.force)?;
+            target
+        }
+        None if opts.restore_metadata => bail!("package carries no file metadata (encrypt it with --store-metadata)"),
+        _ => output,
+    };
+    let mut progress = Progress::new(opts.progress, None);
+    let mut out = AtomicOutput::create(&output)?;
+    let (summary, decompressed_bytes) = if decompress {
+        let (summary, decoded) = package.read_chunks_lz4(&mut out, sender.as_ref(), &mut progress)?;
+        (summary, Some(decoded))
+    } else {
+        (package.read_chunks(&mut out, sender.as_ref(), &mut progress)?, None)
+    };
+    out.commit()?;
+    if let Some(metadata) = package.metadata.as_ref().filter(|_| opts.restore_metadata) {
+        let mtime = std::time::UNIX_EPOCH + Duration::from_secs(metadata.mtime);
+        if let Err(e) = std::fs::File::options().write(true).open(&output).and_then(|f| f.set_modified(mtime)) {
+            warn!(target: "io", "could not restore the modification time of {}: {}", output.display(), e);
+        }
+    }
+    info!(target: "io", "Downloaded and decrypted s3://{}/{} in {} ms", url.bucket, url.key, start.elapsed().as_millis());
+    output::record(json!({
+        "input": format!("s3://{}/{}", url.bucket, url.key), "output": output, "kem": format!("{:?}", package.header.kem), "aead": format!("{:?}", package.header.aead),
+        "chunks": summary.chunks, "plaintext_bytes": summary.plaintext_len, "decompressed_bytes": decompressed_bytes,
+        "signed_with": package.signed_with.map(|alg| format!("{:?}", alg)), "sender_verified": sender.is_some(),
+        "metadata": package.metadata.as_ref().map(|m| json!({ "name": m.name, "mtime": m.mtime, "size": m.size })),
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    }));
+    Ok(())
+}
+
+/// SigV4 signing key for one day, region and service
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but the unreserved characters, and `/` when `keep_slash`
+fn uri_encode(text: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for &b in text.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Text of the first `<tag>` element of an XML response
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn signs_like_aws() {
+        // The signing key of the AWS SigV4 documentation example
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(to_hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+        assert_eq!(uri_encode("laps/2026 r1+q.pqc", true), "laps/2026%20r1%2Bq.pqc");
+        assert_eq!(xml_value("<R><UploadId>abc</UploadId></R>", "UploadId").as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn names_packages_under_a_prefix() {
+        let url = S3Url::parse("s3://telemetry/spa/fp2").unwrap();
+        assert_eq!((url.bucket.as_str(), url.key.as_str()), ("telemetry", "spa/fp2"));
+        assert_eq!(upload_name(&url.key, Path::new("data/lap.csv")).unwrap(), "spa/fp2/lap.csv.pqc");
+        assert_eq!(upload_name("", Path::new("lap.csv")).unwrap(), "lap.csv.pqc");
+        assert_eq!(upload_name("spa/lap-12.pqc", Path::new("-")).unwrap(), "spa/lap-12.pqc");
+        assert!(upload_name(&url.key, Path::new("-")).is_err());
+        assert!(S3Url::parse("https://telemetry/x").is_err() && S3Url::parse("s3:///x").is_err());
+    }
+}
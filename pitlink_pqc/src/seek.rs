@@ -0,0 +1,257 @@
+//! Byte-range reads from seekable packages
+//!
+//! `encrypt --seekable` ends the package with an encrypted [`ChunkIndex`] frame
+//! holding the offset of every chunk frame. [`SeekablePackage`] reads it from the
+//! end of the package, then decrypts only the chunks a range touches.
+//!
+//! Every chunk is still authenticated by its AEAD tag, which binds it to its
+//! position, and by its Merkle leaf when the package has a tree. The trailer MAC
+//! and the sender signature cover the whole package, so only a full
+//! [`decrypt`](crate::decrypt) checks them.
+//!
+//! `decrypt --range` and `decrypt --chunks` select the region with a [`DecryptRange`].
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use anyhow::{bail, Result};
+
+use common::container::{chunk_aad, ChunkFrame, ChunkIndex, Trailer, CHUNK_FLAG_INDEX, TRAILER_LEN};
+
+use crate::keyfile::KeyFile;
+use crate::{OpenPackage, PitlinkError};
+
+/// Plaintext region selected by `decrypt --range` or `decrypt --chunks`; ends are exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptRange {
+    /// Plaintext bytes `start..end`; `None` runs to the end of the plaintext
+    Bytes { start: u64, end: Option<u64> },
+    /// Chunks `start..end`; `None` runs to the final chunk
+    Chunks { start: u64, end: Option<u64> },
+}
+
+impl DecryptRange {
+    /// Parse a byte range such as `100M-200M` or `1G-`, with sizes as in [`parse_size`](crate::parse_size)
+    pub fn parse_bytes(s: &str) -> Result<Self> {
+        let Some((start, end)) = s.split_once('-') else { bail!("invalid byte range {:?} (expected e.g. 100M-200M or 1G-)", s) };
+        let start = crate::parse_size(start)?;
+        let end = (!end.trim().is_empty()).then(|| crate::parse_size(end)).transpose()?;
+        if end.is_some_and(|end| end < start) { bail!("byte range {:?} ends before it starts", s); }
+        Ok(Self::Bytes { start, end })
+    }
+
+    /// Parse a chunk range such as `5..10` or `5..`
+    pub fn parse_chunks(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("invalid chunk range {:?} (expected e.g. 5..10 or 5..)", s);
+        let (start, end) = s.split_once("..").ok_or_else(invalid)?;
+        let start = start.trim().parse::<u64>().map_err(|_| invalid())?;
+        let end = (!end.trim().is_empty()).then(|| end.trim().parse::<u64>()).transpose().map_err(|_| invalid())?;
+        if end.is_some_and(|end| end < start) { bail!("chunk range {:?} ends before it starts", s); }
+        Ok(Self::Chunks { start, end })
+    }
+}
+
+/// A seekable package opened for random-access reads
+pub struct SeekablePackage<R: Read + Seek> {
+    package: OpenPackage<R>,
+    index: ChunkIndex,
+    /// Chunk whose plaintext is in the package buffers
+    loaded: Option<usize>,
+}
+
+impl<R: Read + Seek> SeekablePackage<R> {
+    /// Unwrap the file key with `identity` and read the chunk index from the end of `inner`
+    pub fn open(inner: R, identity: &KeyFile) -> Result<Self> {
+        Self::from_package(OpenPackage::from_reader(inner, identity, false, None)?)
+    }
+
+    /// Read the chunk index of a package whose file key has been unwrapped
+    pub(crate) fn from_package(mut package: OpenPackage<R>) -> Result<Self> {
+        if !package.header.is_seekable() {
+            bail!("package has no chunk index (encrypt it with --seekable)");
+        }
+        let truncated = || anyhow::Error::new(PitlinkError::TruncatedPackage);
+        let end = package.reader.seek(SeekFrom::End(0))?;
+        let trailer_start = end.checked_sub(TRAILER_LEN as u64).ok_or_else(|| truncated().context("missing trailer"))?;
+        package.reader.seek(SeekFrom::Start(trailer_start))?;
+        let trailer = Trailer::read_from(&mut package.reader)?;
+
+        // The index frame has a fixed size for a given chunk count
+        let chunk_count = trailer.chunk_count;
+        let ct_len = ChunkIndex::encoded_len(chunk_count).saturating_add(package.header.aead.tag_len() as u64);
+        let frame_len = ct_len.saturating_add(package.header.frame_nonce_len() as u64 + 5);
+        let index_start = trailer_start.checked_sub(frame_len).filter(|&start| start >= package.offset)
+            .ok_or_else(|| truncated().context(format!("chunk index of {} chunks does not fit the package", chunk_count)))?;
+        package.reader.seek(SeekFrom::Start(index_start))?;
+        let frame = ChunkFrame::read_from(&mut package.reader, &package.header, ct_len as usize)?
+            .filter(|f| f.flags == CHUNK_FLAG_INDEX && f.ciphertext.len() as u64 == ct_len)
+            .ok_or_else(|| truncated().context(format!("missing chunk index (offset {})", index_start)))?;
+        let index_seq = package.header.index_seq(chunk_count);
+        let nonce = package.header.chunk_nonce(index_seq).unwrap_or_else(|| frame.nonce.clone());
+        let index_bytes = package.keys.at(index_seq)?
+            .decrypt(&nonce, &frame.ciphertext, &chunk_aad(&package.header_hash, index_seq, frame.flags))
+            .map_err(|_| anyhow::anyhow!("AEAD decrypt failed for the chunk index (offset {})", index_start))?;
+        let index = ChunkIndex::from_bytes(&index_bytes)?;
+
+        if index.entries.len() as u64 != chunk_count || index.plaintext_len != trailer.plaintext_len {
+            bail!("chunk index records {} bytes in {} chunks but the trailer records {} bytes in {} chunks",
+                index.plaintext_len, index.entries.len(), trailer.plaintext_len, chunk_count);
+        }
+        if let Some(len) = package.expected_len.filter(|&len| len != index.plaintext_len) {
+            bail!("chunk index records {} plaintext bytes but the header records {}", index.plaintext_len, len);
+        }
+        let in_bounds = index.entries.first().is_some_and(|e| e.frame_offset == package.offset)
+            && index.entries.last().is_some_and(|e| e.frame_offset < index_start);
+        if !in_bounds {
+            bail!("chunk index frame offsets fall outside the chunk stream");
+        }
+        Ok(Self { package, index, loaded: None })
+    }
+
+    /// Plaintext length of the package
+    pub fn len(&self) -> u64 {
+        self.index.plaintext_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of chunks in the package
+    pub fn chunk_count(&self) -> u64 {
+        self.index.entries.len() as u64
+    }
+
+    /// Plaintext offset of the first byte of `chunk`; the plaintext length for the
+    /// chunk after the final one
+    pub fn chunk_offset(&self, chunk: u64) -> Option<u64> {
+        match chunk.cmp(&self.chunk_count()) {
+            std::cmp::Ordering::Less => Some(self.index.entries[chunk as usize].plaintext_offset),
+            std::cmp::Ordering::Equal => Some(self.len()),
+            std::cmp::Ordering::Greater => None,
+        }
+    }
+
+    /// Plaintext bytes `start..end` selected by `range`. A byte range is cut off at
+    /// the end of the plaintext but must start inside it; a chunk range must name
+    /// existing chunks.
+    pub fn resolve(&self, range: DecryptRange) -> Result<(u64, u64)> {
+        match range {
+            DecryptRange::Bytes { start, end } => {
+                if start > self.len() { bail!("byte range starts past the {} byte plaintext", self.len()); }
+                Ok((start, end.unwrap_or(u64::MAX).min(self.len())))
+            }
+            DecryptRange::Chunks { start, end } => {
+                let end = end.unwrap_or(self.chunk_count());
+                match (self.chunk_offset(start), self.chunk_offset(end)) {
+                    (Some(start), Some(end)) => Ok((start, end)),
+                    _ => bail!("chunk range {}..{} is outside the package's {} chunks", start, end, self.chunk_count()),
+                }
+            }
+        }
+    }
+
+    /// Decrypt up to `len` plaintext bytes starting at `offset`. The result is
+    /// shorter when the range runs past the end of the plaintext.
+    pub fn read_range(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(offset.saturating_add(len).min(self.len()).saturating_sub(offset) as usize);
+        self.write_range(offset, len, &mut out)?;
+        Ok(out)
+    }
+
+    /// Like [`read_range`](Self::read_range), but writes the plaintext to `out`
+    /// chunk by chunk; returns the number of bytes written
+    pub fn write_range<W: Write>(&mut self, offset: u64, len: u64, out: &mut W) -> Result<u64> {
+        let end = offset.saturating_add(len).min(self.len());
+        let mut pos = offset;
+        while pos < end {
+            let i = self.index.chunk_at(pos).expect("offset is inside the plaintext");
+            self.load_chunk(i)?;
+            let start = self.index.entries[i].plaintext_offset;
+            let chunk = self.package.plaintext();
+            let to = chunk.len().min((end - start) as usize);
+            out.write_all(&chunk[(pos - start) as usize..to])?;
+            pos = start + to as u64;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+
+    /// Seek to chunk `i` and decrypt it into the package buffers
+    fn load_chunk(&mut self, i: usize) -> Result<()> {
+        if self.loaded == Some(i) {
+            return Ok(());
+        }
+        self.loaded = None;
+        let package = &mut self.package;
+        let frame_offset = self.index.entries[i].frame_offset;
+        package.reader.seek(SeekFrom::Start(frame_offset))?;
+        (package.offset, package.seq) = (frame_offset, i as u64);
+        if !package.frame.read_into(&mut package.reader, &package.header, package.max_chunk)? {
+            return Err(anyhow::Error::new(PitlinkError::TruncatedPackage).context(format!("missing chunk {} (offset {})", i, frame_offset)));
+        }
+        package.open_frame()?;
+        if package.frame.is_final() != (i + 1 == self.index.entries.len()) {
+            bail!("final chunk flag of chunk {} does not match the index", i);
+        }
+        if package.plaintext().len() as u64 != self.index.chunk_len(i) {
+            bail!("chunk {} holds {} plaintext bytes but the index records {}", i, package.plaintext().len(), self.index.chunk_len(i));
+        }
+        self.loaded = Some(i);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{decrypt, encrypt, generate_keypair, EncryptOptions, KemId};
+    use common::container::MIN_CHUNK_SIZE;
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(DecryptRange::parse_bytes("100M-200M").unwrap(), DecryptRange::Bytes { start: 100 << 20, end: Some(200 << 20) });
+        assert_eq!(DecryptRange::parse_bytes("1G-").unwrap(), DecryptRange::Bytes { start: 1 << 30, end: None });
+        assert_eq!(DecryptRange::parse_chunks("5..10").unwrap(), DecryptRange::Chunks { start: 5, end: Some(10) });
+        assert_eq!(DecryptRange::parse_chunks("5..").unwrap(), DecryptRange::Chunks { start: 5, end: None });
+        for bad in ["100M", "200M-100M", "x-1"] {
+            assert!(DecryptRange::parse_bytes(bad).is_err(), "{}", bad);
+        }
+        for bad in ["5", "10..5", "..3", "a..b"] {
+            assert!(DecryptRange::parse_chunks(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn reads_ranges_and_rejects_a_tampered_index() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let plaintext: Vec<u8> = (0..5 * MIN_CHUNK_SIZE + 123).map(|i| (i % 251) as u8).collect();
+        for compress in [None, Some(crate::Compression::Lz4)] {
+            let opts = EncryptOptions { chunk_size: MIN_CHUNK_SIZE, compress, seekable: true, ..Default::default() };
+            let mut package = Vec::new();
+            encrypt(&plaintext[..], &mut package, &public, None, &opts).unwrap();
+
+            // A full read checks the index against the chunks
+            let mut decrypted = Vec::new();
+            decrypt(&package[..], &mut decrypted, &private, None).unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            let mut seekable = SeekablePackage::open(Cursor::new(&package), &private).unwrap();
+            assert_eq!((seekable.len(), seekable.chunk_count()), (plaintext.len() as u64, 6));
+            for (offset, len) in [(0, 10), (MIN_CHUNK_SIZE - 3, 7), (2 * MIN_CHUNK_SIZE + 5, 2 * MIN_CHUNK_SIZE), (plaintext.len() - 4, 100)] {
+                let end = (offset + len).min(plaintext.len());
+                assert_eq!(seekable.read_range(offset as u64, len as u64).unwrap(), &plaintext[offset..end]);
+            }
+            assert!(seekable.read_range(plaintext.len() as u64, 10).unwrap().is_empty());
+            let range = seekable.resolve(DecryptRange::parse_chunks("2..4").unwrap()).unwrap();
+            assert_eq!(range, (2 * MIN_CHUNK_SIZE as u64, 4 * MIN_CHUNK_SIZE as u64));
+            assert!(seekable.resolve(DecryptRange::parse_chunks("5..7").unwrap()).is_err());
+
+            // The index frame sits just before the trailer
+            let index_byte = package.len() - TRAILER_LEN - 1;
+            package[index_byte] ^= 1;
+            assert!(SeekablePackage::open(Cursor::new(&package), &private).is_err());
+            assert!(decrypt(&package[..], &mut Vec::new(), &private, None).is_err());
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Built-in known-answer tests for deployment acceptance checks
+//!
+//! The ML-KEM-768 vector exercises the deterministic keygen path (HKDF-expanded
+//! seed -> FIPS 203 KeyGen) and decapsulation of a stored ciphertext through to the
+//! KEK. HKDF uses RFC 5869 test case 3 and XChaCha20-Poly1305 the test vector from
+//! draft-irtf-cfrg-xchacha (A.3.1). Kyber round-3 has no seeded keygen, so it is
+//! only checked by an encapsulate/decapsulate roundtrip.
+
+use std::path::PathBuf;
+use anyhow::Result;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use common::container::{AeadId, KemId, MIN_CHUNK_SIZE};
+use common::{hkdf_derive, CHUNK_SIZE};
+
+use crate::cipher::Cipher;
+use crate::kem;
+use crate::ExposeSecret;
+use crate::output;
+
+/// Ciphertext encapsulated to the ML-KEM-768 key derived from `KAT_SEED`
+const MLKEM768_CT: &[u8] = include_bytes!("../kat/mlkem768.ct");
+const MLKEM768_PK_SHA256: &str = "8d149162d2682a982574a7741e22362ee5e520277327eb71010233afc625c088";
+const MLKEM768_KEK: &str = "29703a55db8f1059456f9c9f361f920e47a4610a4f117257d7dd66874837f2e2";
+
+const HKDF_OKM: &str = "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8";
+
+const XCHACHA_PLAINTEXT: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+const XCHACHA_AAD: &str = "50515253c0c1c2c3c4c5c6c7";
+const XCHACHA_CT: &str = concat!(
+    "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb",
+    "731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452",
+    "2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9",
+    "21f9664c97637da9768812f615c68b13b52e",
+    "c0875924c1c7987947deafd8780acf49",
+);
+
+/// Run every check, printing PASS/FAIL per test; fails if any check fails
+pub fn run() -> Result<()> {
+    let tests: [(&str, fn() -> Result<()>); 5] = [
+        ("ML-KEM-768 keygen/decapsulation KAT", mlkem768_kat),
+        ("Kyber round-3 encapsulate/decapsulate", kyber_roundtrip),
+        ("HKDF-SHA256 KAT (RFC 5869 #3)", hkdf_kat),
+        ("XChaCha20-Poly1305 KAT", xchacha_kat),
+        ("Package encrypt/decrypt roundtrip", package_roundtrip),
+    ];
+    let mut failed = 0;
+    let mut results = Vec::new();
+    for (name, test) in tests {
+        match test() {
+            Ok(()) => {
+                status!("PASS  {}", name);
+                results.push(json!({ "name": name, "passed": true }));
+            }
+            Err(e) => {
+                status!("FAIL  {}: {}", name, e);
+                results.push(json!({ "name": name, "passed": false, "error": e.to_string() }));
+                failed += 1;
+            }
+        }
+    }
+    // Recorded last so the package roundtrip's own results do not leak out
+    output::record(json!({ "tests": results, "failed": failed }));
+    if failed > 0 {
+        anyhow::bail!("{} of {} self-tests failed", failed, tests.len());
+    }
+    status!("All {} self-tests passed", tests.len());
+    Ok(())
+}
+
+fn check(what: &str, got: &[u8], expected_hex: &str) -> Result<()> {
+    let got = hex(got);
+    if got != expected_hex {
+        anyhow::bail!("{} mismatch: got {}, expected {}", what, got, expected_hex);
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Vec<u8> {
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).expect("valid hex constant")).collect()
+}
+
+fn mlkem768_kat() -> Result<()> {
+    let seed: Vec<u8> = (0u8..32).collect();
+    let (pk, sk) = kem::keypair_from_seed(KemId::MlKem768, &seed)?;
+    check("public key hash", &Sha256::digest(&pk), MLKEM768_PK_SHA256)?;
+    check("KEK", kem::decapsulate(KemId::MlKem768, &sk, MLKEM768_CT)?.expose_secret(), MLKEM768_KEK)
+}
+
+fn kyber_roundtrip() -> Result<()> {
+    for kem_id in [KemId::Kyber512, KemId::Kyber768, KemId::Kyber1024] {
+        let (pk, sk) = kem::generate_keypair(kem_id);
+        let (ct, kek) = kem::encapsulate(kem_id, &pk)?;
+        if kem::decapsulate(kem_id, &sk, &ct)?.expose_secret() != kek.expose_secret() {
+            anyhow::bail!("{:?} decapsulated a different KEK", kem_id);
+        }
+    }
+    Ok(())
+}
+
+fn hkdf_kat() -> Result<()> {
+    check("OKM", &hkdf_derive(&[0x0b; 22], b"", 42)?, HKDF_OKM)
+}
+
+fn xchacha_kat() -> Result<()> {
+    let key: Vec<u8> = (0x80u8..0xa0).collect();
+    let nonce: Vec<u8> = (0x40u8..0x58).collect();
+    let cipher = Cipher::new(AeadId::XChaCha20Poly1305, &key)?;
+    let aad = unhex(XCHACHA_AAD);
+    let ct = cipher.encrypt(&nonce, XCHACHA_PLAINTEXT, &aad).map_err(|e| anyhow::anyhow!("encrypt: {}", e))?;
+    check("ciphertext", &ct, XCHACHA_CT)?;
+    let pt = cipher.decrypt(&nonce, &ct, &aad).map_err(|e| anyhow::anyhow!("decrypt: {}", e))?;
+    if pt != XCHACHA_PLAINTEXT { anyhow::bail!("decrypted plaintext mismatch"); }
+    Ok(())
+}
+
+fn package_roundtrip() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("pitlink-selftest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let result = package_roundtrip_in(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+fn package_roundtrip_in(dir: &std::path::Path) -> Result<()> {
+    crate::keygen(dir.to_path_buf(), KemId::MlKem768, false, None, Default::default(), false)?;
+    let pubkey = dir.join("kyber_public.key");
+    let privkey = dir.join("kyber_private.key");
+    // Empty, exactly one chunk, and several chunks with a partial tail, at the
+    // default and the smallest chunk size
+    let cases = [0, CHUNK_SIZE, 2 * CHUNK_SIZE + 17].map(|size| (size, CHUNK_SIZE))
+        .into_iter().chain([(3 * MIN_CHUNK_SIZE + 5, MIN_CHUNK_SIZE)]);
+    for (size, chunk_size) in cases {
+        let mut data = vec![0u8; size];
+        getrandom::getrandom(&mut data)?;
+        let input: PathBuf = dir.join("plain");
+        let package = dir.join("package");
+        let output = dir.join("decrypted");
+        std::fs::write(&input, &data)?;
+        crate::encrypt_file(input, package.clone(), pubkey.clone(), crate::EncryptOptions { aead: AeadId::XChaCha20Poly1305, force: true, chunk_size, ..Default::default() })?;
+        crate::decrypt_file(package, output.clone(), privkey.clone(), crate::DecryptOptions { force: true, ..Default::default() })?;
+        if std::fs::read(&output)? != data {
+            anyhow::bail!("roundtrip of {} bytes produced different plaintext", size);
+        }
+    }
+    Ok(())
+}
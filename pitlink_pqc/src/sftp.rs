@@ -0,0 +1,264 @@
+//! SFTP upload target for `encrypt --upload sftp://user@host[:port]/path`
+//!
+//! For sites where SSH is the only way out. The package is written straight into
+//! `<path>.partial` over the SFTP channel as it is encrypted and renamed into place
+//! once the trailer is on the server, so nothing is staged on local disk:
+//!
+//! ```text
+//! sftp://ops@base.example/srv/laps/      stored as /srv/laps/<input name>.pqc
+//! sftp://ops@base.example:2222/~/lap.pqc stored as lap.pqc in ops' home directory
+//! ```
+//!
+//! A connection that drops is resumed: the upload reconnects with growing delays,
+//! asks the server how much of the partial file it holds and resends the rest from
+//! what it kept back. Everything written since the last checkpoint is kept for
+//! that; at each checkpoint the server has acknowledged every byte before it.
+//!
+//! The server's host key must already be in `~/.ssh/known_hosts`. Authentication
+//! tries the SSH agent, then `~/.ssh/id_ed25519`, `id_ecdsa` and `id_rsa`. The
+//! SSH side needs the `sftp` feature.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, warn};
+
+use crate::{upload_name, EncryptOptions};
+
+#[cfg(feature = "sftp")]
+mod ssh;
+
+/// Reconnects tried before an upload is given up
+const RECONNECTS: u32 = 5;
+/// Wait before the first reconnect; it doubles for each one after
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// Bytes written between checkpoints, and so the most kept back for a resend
+const CHECKPOINT: usize = 16 * 1024 * 1024;
+
+/// A remote location, `sftp://[user@]host[:port]/path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SftpUrl {
+    /// Login name; `$USER` when the URL has none
+    pub user: Option<String>,
+    pub host: String,
+    pub port: u16,
+    /// Absolute path, or relative to the login directory when the URL path starts
+    /// with `/~/`; a directory when it does not end in `.pqc`
+    pub path: String,
+}
+
+impl SftpUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("sftp://") else { bail!("{} is not an sftp://user@host/path URL", url) };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/~/"),
+        };
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_string()), host),
+            None => (None, authority),
+        };
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().with_context(|| format!("{} has an invalid port", url))?),
+            None => (host, 22),
+        };
+        if host.is_empty() || user.as_deref() == Some("") {
+            bail!("{} names no host or an empty user", url);
+        }
+        let path = match path.strip_prefix("/~") {
+            Some(home) => home.trim_start_matches('/').to_string(),
+            None => path.to_string(),
+        };
+        Ok(Self { user, host: host.to_string(), port, path })
+    }
+}
+
+/// A file being written on the server, which can be asked how much it holds
+#[cfg_attr(not(feature = "sftp"), allow(dead_code))]
+trait RemoteFile: Write {
+    /// Bytes of the file on the server
+    fn size(&mut self) -> io::Result<u64>;
+    /// Go on writing at `offset`
+    fn seek_to(&mut self, offset: u64) -> io::Result<()>;
+}
+
+/// Writes to a remote file and, when the connection drops, reopens it with
+/// `reopen` and carries on from what the server holds
+#[cfg_attr(not(feature = "sftp"), allow(dead_code))]
+struct Resuming<F: FnMut() -> Result<Box<dyn RemoteFile>>> {
+    file: Box<dyn RemoteFile>,
+    reopen: F,
+    /// Written since the last checkpoint, kept for a resend
+    pending: Vec<u8>,
+    /// File offset `pending` starts at
+    base: u64,
+    reconnects: u32,
+    delay: Duration,
+}
+
+#[cfg_attr(not(feature = "sftp"), allow(dead_code))]
+impl<F: FnMut() -> Result<Box<dyn RemoteFile>>> Resuming<F> {
+    fn new(file: Box<dyn RemoteFile>, reopen: F) -> Self {
+        Self { file, reopen, pending: Vec::new(), base: 0, reconnects: 0, delay: RECONNECT_DELAY }
+    }
+
+    /// Reconnect until the file is back where it was, or give up after [`RECONNECTS`]
+    fn recover(&mut self, mut cause: io::Error) -> io::Result<()> {
+        loop {
+            if self.reconnects == RECONNECTS || cause.kind() == io::ErrorKind::InvalidData {
+                return Err(io::Error::new(cause.kind(), format!("upload failed after {} reconnects: {}", self.reconnects, cause)));
+            }
+            self.reconnects += 1;
+            warn!(target: "io", "connection lost ({}); reconnecting ({} of {})", cause, self.reconnects, RECONNECTS);
+            std::thread::sleep(self.delay * (1 << (self.reconnects - 1)));
+            match self.resume() {
+                Ok(held) => {
+                    info!(target: "io", "Resumed the upload at byte {}", held);
+                    return Ok(());
+                }
+                Err(e) => cause = e,
+            }
+        }
+    }
+
+    /// Reopen the file and resend what the server is missing; returns where it resumed
+    fn resume(&mut self) -> io::Result<u64> {
+        let mut file = (self.reopen)().map_err(|e| io::Error::other(format!("{:#}", e)))?;
+        let held = file.size()?;
+        if held < self.base || held > self.base + self.pending.len() as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the partial file on the server changed (it holds {} bytes)", held)));
+        }
+        file.seek_to(held)?;
+        file.write_all(&self.pending[(held - self.base) as usize..])?;
+        self.file = file;
+        Ok(held)
+    }
+
+    /// Wait until the server has everything written so far, then drop the copy
+    fn checkpoint(&mut self) -> io::Result<()> {
+        while let Err(e) = self.file.flush() {
+            self.recover(e)?;
+        }
+        self.base += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl<F: FnMut() -> Result<Box<dyn RemoteFile>>> Write for Resuming<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if let Err(e) = self.file.write_all(buf) {
+            // Resending from the server's size covers `buf` too
+            self.recover(e)?;
+        }
+        if self.pending.len() >= CHECKPOINT {
+            self.checkpoint()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.checkpoint()
+    }
+}
+
+/// Encrypt `input` (`-` for stdin, a directory with `opts.recursive`) like
+/// [`encrypt_file`](crate::encrypt_file) and upload the package to `url`
+/// (`sftp://user@host/dir/` or `sftp://user@host/dir/name.pqc`). `mmap` and
+/// `append` do not apply; `force` replaces an existing file on the server.
+pub fn upload_file(input: PathBuf, url: &str, pubkey_path: PathBuf, opts: EncryptOptions) -> Result<()> {
+    if opts.mmap || opts.append {
+        bail!("--upload streams the package; it cannot be combined with --mmap or --append");
+    }
+    let url = SftpUrl::parse(url)?;
+    let path = upload_name(&url.path, &input)?;
+    #[cfg(feature = "sftp")]
+    {
+        ssh::upload(input, &url, path, pubkey_path, opts)
+    }
+    #[cfg(not(feature = "sftp"))]
+    {
+        let _ = (path, pubkey_path);
+        bail!("built without SFTP support; rebuild with the sftp feature")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A server file reached over a link that drops after `budget` bytes
+    struct Flaky {
+        server: Arc<Mutex<Vec<u8>>>,
+        pos: usize,
+        budget: usize,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.budget == 0 {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "link dropped"));
+            }
+            let n = buf.len().min(self.budget);
+            let mut file = self.server.lock().unwrap();
+            file.truncate(self.pos);
+            file.extend_from_slice(&buf[..n]);
+            (self.pos, self.budget) = (self.pos + n, self.budget - n);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RemoteFile for Flaky {
+        fn size(&mut self) -> io::Result<u64> {
+            Ok(self.server.lock().unwrap().len() as u64)
+        }
+
+        fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+            self.pos = offset as usize;
+            Ok(())
+        }
+    }
+
+    fn upload(data: &[u8], budgets: &[usize]) -> (io::Result<()>, Vec<u8>, u32) {
+        let server = Arc::new(Mutex::new(Vec::new()));
+        let link = |budget| -> Box<dyn RemoteFile> { Box::new(Flaky { server: server.clone(), pos: 0, budget }) };
+        let mut budgets = budgets.iter().copied();
+        let mut out = Resuming::new(link(budgets.next().unwrap()), || Ok(link(budgets.next().unwrap_or(0))));
+        out.delay = Duration::ZERO;
+        let result = data.chunks(7000).try_for_each(|piece| out.write_all(piece)).and_then(|()| out.flush());
+        let reconnects = out.reconnects;
+        drop(out);
+        let held = server.lock().unwrap().clone();
+        (result, held, reconnects)
+    }
+
+    #[test]
+    fn resumes_where_the_server_stopped() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let (result, held, reconnects) = upload(&data, &[30_000, 0, 25_000, 60_000]);
+        result.unwrap();
+        assert_eq!((held.as_slice(), reconnects), (data.as_slice(), 3));
+
+        let (result, _, reconnects) = upload(&data, &[10_000]);
+        assert!(result.is_err());
+        assert_eq!(reconnects, RECONNECTS);
+    }
+
+    #[test]
+    fn parses_urls() {
+        let url = SftpUrl::parse("sftp://ops@base.example:2222/srv/laps/").unwrap();
+        assert_eq!(url, SftpUrl { user: Some("ops".into()), host: "base.example".into(), port: 2222, path: "/srv/laps/".into() });
+        let url = SftpUrl::parse("sftp://base.example/~/lap.pqc").unwrap();
+        assert_eq!((url.user, url.port, url.path.as_str()), (None, 22, "lap.pqc"));
+        assert_eq!(SftpUrl::parse("sftp://base.example").unwrap().path, "");
+        assert!(SftpUrl::parse("sftp://@host/x").is_err() && SftpUrl::parse("sftp://host:ssh/x").is_err() && SftpUrl::parse("s3://b/k").is_err());
+    }
+}
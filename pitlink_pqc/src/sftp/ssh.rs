@@ -0,0 +1,154 @@
+//! SSH side of the SFTP upload target, on libssh2
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use ssh2::{CheckResult, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session};
+use tracing::info;
+
+use super::{RemoteFile, Resuming, SftpUrl};
+use crate::keyfile::{KeyFile, KeyKind, SigningKeyFile};
+use crate::{output, streamed_plaintext, throttle, write_package, EncryptOptions};
+
+/// How long a blocked SSH call may take before the connection counts as lost
+const TIMEOUT_MS: u32 = 120_000;
+/// Keys tried from `~/.ssh` when the agent has none the server accepts
+const DEFAULT_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// An authenticated SFTP session
+pub(super) struct Sftp {
+    sftp: ssh2::Sftp,
+    _session: Session,
+}
+
+impl Sftp {
+    pub(super) fn connect(url: &SftpUrl) -> Result<Self> {
+        let address = format!("{}:{}", url.host, url.port);
+        let stream = TcpStream::connect(&address).with_context(|| format!("cannot connect to {}", address))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(stream);
+        session.set_timeout(TIMEOUT_MS);
+        session.handshake().with_context(|| format!("SSH handshake with {} failed", address))?;
+        check_host_key(&session, url)?;
+
+        let user = match &url.user {
+            Some(user) => user.clone(),
+            None => std::env::var("USER").context("the URL names no user and $USER is not set")?,
+        };
+        if session.userauth_agent(&user).is_err() {
+            let ssh_dir = dirs::home_dir().unwrap_or_default().join(".ssh");
+            for key in DEFAULT_KEYS.iter().map(|name| ssh_dir.join(name)).filter(|key| key.exists()) {
+                if session.userauth_pubkey_file(&user, None, &key, None).is_ok() {
+                    break;
+                }
+            }
+        }
+        if !session.authenticated() {
+            bail!("{}@{} accepted neither the SSH agent nor a key in ~/.ssh", user, address);
+        }
+        let sftp = session.sftp().context("the server refused the SFTP subsystem")?;
+        Ok(Self { sftp, _session: session })
+    }
+
+    pub(super) fn exists(&self, path: &str) -> bool {
+        self.sftp.stat(Path::new(path)).is_ok()
+    }
+
+    /// Create (or truncate) `path`, readable only by its owner
+    pub(super) fn create(&self, path: &str) -> Result<Box<dyn RemoteFile>> {
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let file = self.sftp.open_mode(Path::new(path), flags, 0o600, OpenType::File).with_context(|| format!("cannot create {} on the server", path))?;
+        Ok(Box::new(file))
+    }
+
+    /// Open the existing `path` to go on writing it
+    pub(super) fn open(&self, path: &str) -> Result<Box<dyn RemoteFile>> {
+        let file = self.sftp.open_mode(Path::new(path), OpenFlags::WRITE, 0o600, OpenType::File).with_context(|| format!("cannot reopen {} on the server", path))?;
+        Ok(Box::new(file))
+    }
+
+    /// Move the finished `temp` to `path`, replacing it when `replace` is set
+    pub(super) fn finish(&self, temp: &str, path: &str, replace: bool) -> Result<()> {
+        // SFTP v3 servers refuse to rename over an existing file
+        if replace && self.exists(path) {
+            self.remove(path)?;
+        }
+        self.sftp.rename(Path::new(temp), Path::new(path), Some(RenameFlags::ATOMIC)).with_context(|| format!("cannot rename {} to {} on the server", temp, path))
+    }
+
+    pub(super) fn remove(&self, path: &str) -> Result<()> {
+        self.sftp.unlink(Path::new(path)).with_context(|| format!("cannot remove {} on the server", path))
+    }
+}
+
+/// Refuse hosts whose key is not in `~/.ssh/known_hosts`
+fn check_host_key(session: &Session, url: &SftpUrl) -> Result<()> {
+    let (key, _) = session.host_key().context("the server sent no host key")?;
+    let mut known = session.known_hosts()?;
+    let file = dirs::home_dir().unwrap_or_default().join(".ssh/known_hosts");
+    if file.exists() {
+        known.read_file(&file, KnownHostFileKind::OpenSSH).with_context(|| format!("cannot read {}", file.display()))?;
+    }
+    match known.check_port(&url.host, url.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => bail!("{} is not in {}; connect once with ssh to add its host key", url.host, file.display()),
+        CheckResult::Mismatch => bail!("the host key of {} does not match {}; refusing to connect", url.host, file.display()),
+        CheckResult::Failure => bail!("cannot check the host key of {}", url.host),
+    }
+}
+
+impl RemoteFile for ssh2::File {
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.stat()?.size.unwrap_or(0))
+    }
+
+    fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset)).map(drop)
+    }
+}
+
+/// Encrypt `input` into `path` on the server named by `url`; see [`upload_file`](super::upload_file)
+pub(super) fn upload(input: PathBuf, url: &SftpUrl, path: String, pubkey_path: PathBuf, opts: EncryptOptions) -> Result<()> {
+    let start = Instant::now();
+    let recipient = KeyFile::read(pubkey_path, KeyKind::Public)?;
+    let signer = opts.sign_key.as_ref().map(|path| SigningKeyFile::read(path, KeyKind::SigningPrivate)).transpose()?;
+    let location = format!("sftp://{}:{}/{}", url.host, url.port, path.trim_start_matches('/'));
+    let sftp = Sftp::connect(url)?;
+    if !opts.force && sftp.exists(&path) {
+        bail!("{} already exists (use --force to overwrite)", location);
+    }
+    let temp = format!("{}.partial", path);
+    let plaintext = streamed_plaintext(&input, &opts)?;
+
+    let mut out = Resuming::new(sftp.create(&temp)?, || Sftp::connect(url)?.open(&temp));
+    let written = match opts.rate_limit {
+        Some(rate) => write_package(plaintext, &mut throttle::Throttled::new(&mut out, rate), &recipient, signer.as_ref(), &opts),
+        None => write_package(plaintext, &mut out, &recipient, signer.as_ref(), &opts),
+    };
+    let summary = match written.and_then(|summary| Ok(out.flush().map(|()| summary)?)) {
+        Ok(summary) => summary,
+        Err(e) => {
+            // A new run starts a new package, so what is on the server is of no use
+            let _ = Sftp::connect(url).map(|sftp| sftp.remove(&temp));
+            return Err(e);
+        }
+    };
+    let (package_bytes, reconnects) = (out.base, out.reconnects);
+    drop(out);
+    // The first session may be the one that dropped
+    let sftp = if reconnects == 0 { sftp } else { Sftp::connect(url)? };
+    sftp.finish(&temp, &path, opts.force)?;
+
+    info!(target: "io", "Uploaded encrypted package to {}", location);
+    output::record(json!({
+        "input": input, "output": location, "recipient_fingerprint": recipient.fingerprint()?, "kem": format!("{:?}", recipient.kem),
+        "aead": format!("{:?}", opts.aead), "chunks": summary.chunks, "chunk_size": opts.chunk_size, "compression": opts.compress.map(|c| format!("{:?}", c)),
+        "plaintext_bytes": summary.plaintext_len, "package_bytes": package_bytes, "reconnects": reconnects,
+        "signed_with": signer.as_ref().map(|s| format!("{:?}", s.alg)), "elapsed_ms": start.elapsed().as_millis() as u64,
+    }));
+    Ok(())
+}
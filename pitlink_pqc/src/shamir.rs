@@ -0,0 +1,106 @@
+//! Shamir secret sharing over GF(256)
+//!
+//! Each byte of the secret is shared independently with a random polynomial of
+//! degree `threshold - 1`; share `i` holds the evaluations at `x = i`.
+
+use anyhow::{bail, Result};
+
+/// One share of a secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub x: u8,
+    pub y: Vec<u8>,
+}
+
+/// Split `secret` into `shares` shares, any `threshold` of which reconstruct it
+pub fn split(secret: &[u8], shares: u8, threshold: u8) -> Result<Vec<Share>> {
+    if threshold < 2 { bail!("threshold must be at least 2"); }
+    if shares < threshold { bail!("share count {} is below threshold {}", shares, threshold); }
+
+    let mut coeffs = vec![0u8; (threshold as usize - 1) * secret.len()];
+    getrandom::getrandom(&mut coeffs)?;
+
+    let mut out: Vec<Share> = (1..=shares).map(|x| Share { x, y: Vec::with_capacity(secret.len()) }).collect();
+    for (i, &s) in secret.iter().enumerate() {
+        let poly: Vec<u8> = std::iter::once(s)
+            .chain(coeffs.iter().skip(i).step_by(secret.len()).copied())
+            .collect();
+        for share in out.iter_mut() {
+            share.y.push(eval(&poly, share.x));
+        }
+    }
+    Ok(out)
+}
+
+/// Reconstruct a secret from at least `threshold` distinct shares
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>> {
+    if shares.len() < 2 { bail!("need at least 2 shares"); }
+    let len = shares[0].y.len();
+    for (i, a) in shares.iter().enumerate() {
+        if a.x == 0 { bail!("invalid share index 0"); }
+        if a.y.len() != len { bail!("shares have different lengths"); }
+        if shares[..i].iter().any(|b| b.x == a.x) { bail!("duplicate share index {}", a.x); }
+    }
+
+    let mut secret = vec![0u8; len];
+    for (i, si) in shares.iter().enumerate() {
+        // Lagrange basis polynomial for share i evaluated at x = 0
+        let mut basis = 1u8;
+        for (j, sj) in shares.iter().enumerate() {
+            if i != j {
+                basis = mul(basis, mul(sj.x, inv(sj.x ^ si.x)));
+            }
+        }
+        for (b, &y) in secret.iter_mut().zip(&si.y) {
+            *b ^= mul(y, basis);
+        }
+    }
+    Ok(secret)
+}
+
+/// Horner evaluation of `poly` (constant term first) at `x`
+fn eval(poly: &[u8], x: u8) -> u8 {
+    poly.iter().rev().fold(0u8, |acc, &c| mul(acc, x) ^ c)
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0u8;
+    while b != 0 {
+        if b & 1 != 0 { p ^= a; }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 { a ^= 0x1b; }
+        b >>= 1;
+    }
+    p
+}
+
+/// Multiplicative inverse via a^254
+fn inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exp = 254u8;
+    while exp != 0 {
+        if exp & 1 != 0 { result = mul(result, base); }
+        base = mul(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_combine_any_threshold_subset() {
+        let secret: Vec<u8> = (0u8..32).collect();
+        let shares = split(&secret, 5, 3).unwrap();
+
+        assert_eq!(combine(&shares[..3]).unwrap(), secret);
+        assert_eq!(combine(&[shares[4].clone(), shares[1].clone(), shares[3].clone()]).unwrap(), secret);
+        assert_eq!(combine(&shares).unwrap(), secret);
+        assert_ne!(combine(&shares[..2]).unwrap(), secret);
+    }
+}
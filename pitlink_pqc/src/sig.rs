@@ -0,0 +1,238 @@
+//! Signature dispatch and signature file format
+//!
+//! Each `SigId` maps to a keypair generator and a sign/verify pair. ML-DSA is the
+//! default; SPHINCS+-SHA2 trades much larger signatures for security that rests
+//! only on the hash function, for long-term signing. Falcon-512 has the smallest
+//! signatures (~660 bytes, variable length) for bandwidth-constrained links.
+//! Signatures cover a SHA-512 digest of the content rather than the content
+//! itself, so large files are hashed in a single streaming pass.
+//!
+//! A signed file is `SIG_MAGIC | version u8 | sig_id u8 | sig_len u32 | signature`
+//! followed by the content; a detached `.sig` file is the same block on its own.
+//! The signed message is the signature header up to and including `sig_id`, then
+//! the content digest, which binds the algorithm choice.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Result};
+use pqcrypto_mldsa::{mldsa44, mldsa65, mldsa87};
+use pqcrypto_falcon::falcon512;
+use pqcrypto_sphincsplus::{sphincssha2128ssimple, sphincssha2192ssimple, sphincssha2256ssimple};
+use pqcrypto_traits::sign::*;
+use sha2::{Digest, Sha512};
+
+use common::container::SigId;
+
+/// Magic prefix of signature blocks
+pub const SIG_MAGIC: &[u8] = b"RKPQS";
+
+/// Current signature block version
+pub const SIG_VERSION: u8 = 1;
+
+/// Upper bound on the signature length accepted by readers
+const MAX_SIGNATURE_LEN: usize = 64 * 1024;
+
+macro_rules! sig_keypair {
+    ($m:ident) => {{
+        let (pk, sk) = $m::keypair();
+        (pk.as_bytes().to_vec(), sk.as_bytes().to_vec())
+    }};
+}
+
+macro_rules! sig_sign {
+    ($m:ident, $sk:expr, $msg:expr) => {{
+        let sk = $m::SecretKey::from_bytes($sk).map_err(|e| anyhow::anyhow!("SecretKey from_bytes: {}", e))?;
+        $m::detached_sign($msg, &sk).as_bytes().to_vec()
+    }};
+}
+
+macro_rules! sig_verify {
+    ($m:ident, $pk:expr, $msg:expr, $sig:expr) => {{
+        let pk = $m::PublicKey::from_bytes($pk).map_err(|e| anyhow::anyhow!("PublicKey from_bytes: {}", e))?;
+        let sig = $m::DetachedSignature::from_bytes($sig).map_err(|e| anyhow::anyhow!("Signature from_bytes: {}", e))?;
+        $m::verify_detached_signature(&sig, $msg, &pk).is_ok()
+    }};
+}
+
+/// Parse a `--sig-alg` name
+pub fn parse_alg(name: &str) -> Result<SigId> {
+    match name {
+        "mldsa44" => Ok(SigId::MlDsa44),
+        "mldsa65" => Ok(SigId::MlDsa65),
+        "mldsa87" => Ok(SigId::MlDsa87),
+        "sphincs-sha2-128s" => Ok(SigId::SphincsSha2128s),
+        "sphincs-sha2-192s" => Ok(SigId::SphincsSha2192s),
+        "sphincs-sha2-256s" => Ok(SigId::SphincsSha2256s),
+        "falcon512" => Ok(SigId::Falcon512),
+        other => bail!("unknown signature algorithm '{}'", other),
+    }
+}
+
+/// Map a `--level` value to the ML-DSA parameter set of matching strength
+pub fn sig_for_level(level: u16) -> Result<SigId> {
+    match level {
+        512 => Ok(SigId::MlDsa44),
+        768 => Ok(SigId::MlDsa65),
+        1024 => Ok(SigId::MlDsa87),
+        other => bail!("unsupported security level {} (expected 512, 768 or 1024)", other),
+    }
+}
+
+/// Public key length in bytes
+pub fn public_key_len(alg: SigId) -> usize {
+    match alg {
+        SigId::MlDsa44 => mldsa44::public_key_bytes(),
+        SigId::MlDsa65 => mldsa65::public_key_bytes(),
+        SigId::MlDsa87 => mldsa87::public_key_bytes(),
+        SigId::SphincsSha2128s => sphincssha2128ssimple::public_key_bytes(),
+        SigId::SphincsSha2192s => sphincssha2192ssimple::public_key_bytes(),
+        SigId::SphincsSha2256s => sphincssha2256ssimple::public_key_bytes(),
+        SigId::Falcon512 => falcon512::public_key_bytes(),
+    }
+}
+
+/// Private key length in bytes
+pub fn secret_key_len(alg: SigId) -> usize {
+    match alg {
+        SigId::MlDsa44 => mldsa44::secret_key_bytes(),
+        SigId::MlDsa65 => mldsa65::secret_key_bytes(),
+        SigId::MlDsa87 => mldsa87::secret_key_bytes(),
+        SigId::SphincsSha2128s => sphincssha2128ssimple::secret_key_bytes(),
+        SigId::SphincsSha2192s => sphincssha2192ssimple::secret_key_bytes(),
+        SigId::SphincsSha2256s => sphincssha2256ssimple::secret_key_bytes(),
+        SigId::Falcon512 => falcon512::secret_key_bytes(),
+    }
+}
+
+/// Generate a signing keypair, returning `(public, private)` key bytes
+pub fn generate_keypair(alg: SigId) -> (Vec<u8>, Vec<u8>) {
+    match alg {
+        SigId::MlDsa44 => sig_keypair!(mldsa44),
+        SigId::MlDsa65 => sig_keypair!(mldsa65),
+        SigId::MlDsa87 => sig_keypair!(mldsa87),
+        SigId::SphincsSha2128s => sig_keypair!(sphincssha2128ssimple),
+        SigId::SphincsSha2192s => sig_keypair!(sphincssha2192ssimple),
+        SigId::SphincsSha2256s => sig_keypair!(sphincssha2256ssimple),
+        SigId::Falcon512 => sig_keypair!(falcon512),
+    }
+}
+
+/// Sign `msg` with a private key
+pub fn sign(alg: SigId, sk: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+    Ok(match alg {
+        SigId::MlDsa44 => sig_sign!(mldsa44, sk, msg),
+        SigId::MlDsa65 => sig_sign!(mldsa65, sk, msg),
+        SigId::MlDsa87 => sig_sign!(mldsa87, sk, msg),
+        SigId::SphincsSha2128s => sig_sign!(sphincssha2128ssimple, sk, msg),
+        SigId::SphincsSha2192s => sig_sign!(sphincssha2192ssimple, sk, msg),
+        SigId::SphincsSha2256s => sig_sign!(sphincssha2256ssimple, sk, msg),
+        SigId::Falcon512 => sig_sign!(falcon512, sk, msg),
+    })
+}
+
+/// Check a signature over `msg` against a public key
+pub fn verify(alg: SigId, pk: &[u8], msg: &[u8], signature: &[u8]) -> Result<bool> {
+    Ok(match alg {
+        SigId::MlDsa44 => sig_verify!(mldsa44, pk, msg, signature),
+        SigId::MlDsa65 => sig_verify!(mldsa65, pk, msg, signature),
+        SigId::MlDsa87 => sig_verify!(mldsa87, pk, msg, signature),
+        SigId::SphincsSha2128s => sig_verify!(sphincssha2128ssimple, pk, msg, signature),
+        SigId::SphincsSha2192s => sig_verify!(sphincssha2192ssimple, pk, msg, signature),
+        SigId::SphincsSha2256s => sig_verify!(sphincssha2256ssimple, pk, msg, signature),
+        SigId::Falcon512 => sig_verify!(falcon512, pk, msg, signature),
+    })
+}
+
+/// Default detached signature path for `input`: `<input>.sig`
+pub fn detached_path(input: &Path) -> PathBuf {
+    let mut name = input.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".sig");
+    input.with_file_name(name)
+}
+
+/// SHA-512 digest of everything readable from `r`
+pub fn content_digest<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+    content_digest_into(r, &mut io::sink())
+}
+
+/// SHA-512 digest of everything readable from `r`, copying the bytes to `out`
+/// as they are hashed
+pub fn content_digest_into<R: Read, W: Write>(r: &mut R, out: &mut W) -> Result<Vec<u8>> {
+    let mut hasher = Sha512::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match r.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// A signature block as stored in signed files
+#[derive(Debug, Clone)]
+pub struct SignatureBlock {
+    pub alg: SigId,
+    pub signature: Vec<u8>,
+}
+
+impl SignatureBlock {
+    /// Sign a content digest
+    pub fn sign(alg: SigId, sk: &[u8], digest: &[u8]) -> Result<Self> {
+        let signature = sign(alg, sk, &signed_message(alg, digest))?;
+        Ok(Self { alg, signature })
+    }
+
+    /// Check the signature over a content digest
+    pub fn verify(&self, pk: &[u8], digest: &[u8]) -> Result<bool> {
+        verify(self.alg, pk, &signed_message(self.alg, digest), &self.signature)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SIG_MAGIC.len() + 6 + self.signature.len());
+        out.extend_from_slice(SIG_MAGIC);
+        out.push(SIG_VERSION);
+        out.push(self.alg.as_u8());
+        out.extend_from_slice(&(self.signature.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.signature);
+        out
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Read a signature block, leaving `r` positioned at the signed content
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let mut head = [0u8; 5 + 6];
+        r.read_exact(&mut head).map_err(|_| anyhow::anyhow!("file too short for a signature block"))?;
+        if &head[..SIG_MAGIC.len()] != SIG_MAGIC {
+            bail!("not a signed file (bad magic)");
+        }
+        if head[5] != SIG_VERSION {
+            bail!("unsupported signature version {}", head[5]);
+        }
+        let alg = SigId::from_u8(head[6])?;
+        let len = u32::from_be_bytes([head[7], head[8], head[9], head[10]]) as usize;
+        if len > MAX_SIGNATURE_LEN {
+            bail!("signature length {} exceeds limit", len);
+        }
+        let mut signature = vec![0u8; len];
+        r.read_exact(&mut signature)?;
+        Ok(Self { alg, signature })
+    }
+}
+
+fn signed_message(alg: SigId, digest: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(SIG_MAGIC.len() + 2 + digest.len());
+    msg.extend_from_slice(SIG_MAGIC);
+    msg.push(SIG_VERSION);
+    msg.push(alg.as_u8());
+    msg.extend_from_slice(digest);
+    msg
+}
@@ -0,0 +1,228 @@
+//! `Write`/`Read` adapters that produce and consume packages incrementally
+//!
+//! [`EncryptingWriter`] buffers one chunk of plaintext and emits each chunk frame
+//! as soon as the next byte arrives; [`EncryptingWriter::finish`] writes the final
+//! chunk, the sender signature, the chunk index of a seekable package and the
+//! trailer. [`DecryptingReader`] yields the plaintext of each chunk once its tag
+//! has been checked.
+//!
+//! Neither side needs to know the length up front, so packages written here carry
+//! no Merkle tree or plaintext length, like `encrypt -i -`.
+
+use std::io::{self, Read, Write};
+
+use anyhow::Result;
+use sha2::{Digest, Sha512};
+
+use common::container::{ChunkIndex, FormatVersion, PackageMac};
+
+use crate::keyfile::{KeyFile, SigningKeyFile};
+use crate::{EncryptOptions, NewPackage, OpenPackage, PackageSealer, PackageSummary, PitlinkError, SigId};
+
+pub(crate) fn io_error(e: anyhow::Error) -> io::Error {
+    match PitlinkError::classify(&e) {
+        Some(PitlinkError::Io(kind)) => io::Error::new(kind, e),
+        // Carried as the inner error so `PitlinkError::classify` still finds it
+        Some(typed) => io::Error::new(io::ErrorKind::InvalidData, typed),
+        None => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// Encrypts everything written to it into a package on `inner`
+///
+/// The header goes out when the writer is created. Dropping the writer without
+/// calling [`finish`](Self::finish) leaves a package that readers reject as
+/// truncated, as does any earlier error.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    sealer: PackageSealer,
+    mac: PackageMac,
+    signer: Option<SigningKeyFile>,
+    chunk_size: usize,
+    /// Plaintext of the chunk being filled
+    buf: Vec<u8>,
+    plaintext_hash: Sha512,
+    plaintext_len: u64,
+    seq: u64,
+    /// Byte offset of the next frame and the chunk index, for seekable packages
+    offset: u64,
+    index: Option<ChunkIndex>,
+    /// Plaintext size recorded in the metadata frame, checked by `finish`
+    metadata_size: Option<u64>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Start a package for `recipient`, signed by `signer` when given. Of `opts`,
+    /// the path-level settings (`sign_key`, `force`, `recursive`, `mmap`, `threads`,
+    /// `progress`, `append`) and `armor` are ignored; `previous_member` is honoured.
+    pub fn new(mut inner: W, recipient: &KeyFile, signer: Option<&SigningKeyFile>, opts: &EncryptOptions) -> Result<Self> {
+        let opts = EncryptOptions { recursive: false, ..opts.clone() };
+        let sealer = NewPackage::new(recipient, signer, &opts)?.seal()?;
+        inner.write_all(&sealer.header_bytes)?;
+        let mut mac = sealer.trailer_mac();
+        let mut offset = sealer.header_bytes.len() as u64;
+        if let Some(metadata) = &opts.metadata {
+            let frame = sealer.metadata_frame(metadata)?;
+            frame.write_to(&mut inner)?;
+            frame.write_to(&mut mac)?;
+            offset += frame.encoded_len(FormatVersion::V2) as u64;
+        }
+        Ok(Self {
+            inner,
+            mac,
+            offset,
+            metadata_size: opts.metadata.as_ref().map(|m| m.size),
+            index: sealer.header.is_seekable().then(ChunkIndex::default),
+            sealer,
+            signer: signer.cloned(),
+            chunk_size: opts.chunk_size,
+            buf: Vec::with_capacity(opts.chunk_size),
+            plaintext_hash: Sha512::new(),
+            plaintext_len: 0,
+            seq: 0,
+        })
+    }
+
+    /// Encrypt the buffered plaintext as the next chunk
+    fn emit(&mut self, last: bool) -> Result<()> {
+        let frame = self.sealer.seal_chunk(self.seq, &self.buf, last)?;
+        if let Some(index) = &mut self.index { index.push(self.offset, self.buf.len() as u64); }
+        self.offset += frame.encoded_len(FormatVersion::V2) as u64;
+        frame.write_to(&mut self.inner)?;
+        frame.write_to(&mut self.mac)?;
+        self.plaintext_hash.update(&self.buf);
+        self.plaintext_len += self.buf.len() as u64;
+        self.seq += 1;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Write the final chunk, the sender signature, the chunk index and the trailer, and hand back
+    /// the inner writer (flushed)
+    pub fn finish(mut self) -> Result<(W, PackageSummary)> {
+        // The final chunk is the short one; a full buffer is followed by an empty chunk
+        if self.buf.len() == self.chunk_size {
+            self.emit(false)?;
+        }
+        self.emit(true)?;
+        let Self { mut inner, sealer, mut mac, signer, plaintext_hash, plaintext_len, seq, index, metadata_size, .. } = self;
+        if let Some(size) = metadata_size.filter(|&size| size != plaintext_len) {
+            anyhow::bail!("metadata records {} bytes but {} were written", size, plaintext_len);
+        }
+        if let Some(signer) = &signer {
+            let frame = sealer.signature_frame(seq, signer, &plaintext_hash.finalize())?;
+            frame.write_to(&mut inner)?;
+            frame.write_to(&mut mac)?;
+        }
+        if let Some(index) = &index {
+            let frame = sealer.index_frame(seq, index)?;
+            frame.write_to(&mut inner)?;
+            frame.write_to(&mut mac)?;
+        }
+        mac.finish(plaintext_len, seq).write_to(&mut inner)?;
+        inner.flush()?;
+        Ok((inner, PackageSummary { chunks: seq, plaintext_len }))
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A full chunk is only sent once more data shows it is not the last
+        if self.buf.len() == self.chunk_size && !data.is_empty() {
+            self.emit(false).map_err(io_error)?;
+        }
+        let n = data.len().min(self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    /// Flushes the inner writer; buffered plaintext stays until its chunk is full
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a package read from `inner`
+///
+/// Each chunk's plaintext is returned once its tag has been checked, but the sender
+/// signature and the trailer are only checked when the last chunk has been read:
+/// `read` returns `Ok(0)` only after they pass. Until then, treat the plaintext
+/// as unverified and discard it if any read fails.
+pub struct DecryptingReader<R: Read> {
+    package: OpenPackage<R>,
+    sender: Option<SigningKeyFile>,
+    /// Plaintext bytes of the current chunk already returned
+    pos: usize,
+    /// Plaintext bytes of the current chunk
+    filled: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    /// Read the header from `inner` and unwrap the file key with `identity`.
+    /// With `sender`, the package must carry a valid signature from that key.
+    pub fn new(inner: R, identity: &KeyFile, sender: Option<&SigningKeyFile>) -> Result<Self> {
+        let package = OpenPackage::from_reader(inner, identity, false, sender)?;
+        Ok(Self { package, sender: sender.cloned(), pos: 0, filled: 0 })
+    }
+
+    /// Signature algorithm announced by the header, if the package is signed
+    pub fn signed_with(&self) -> Option<SigId> {
+        self.package.signed_with
+    }
+
+    /// Chunks and plaintext bytes decrypted so far
+    pub fn summary(&self) -> PackageSummary {
+        self.package.summary()
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.filled {
+            match self.package.next_chunk(self.sender.as_ref()).map_err(io_error)? {
+                Some(pt) => self.filled = pt.len(),
+                None => return Ok(0),
+            }
+            self.pos = 0;
+        }
+        let chunk = &self.package.plaintext()[self.pos..self.filled];
+        let n = out.len().min(chunk.len());
+        out[..n].copy_from_slice(&chunk[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_keypair, KemId};
+    use crate::keyfile::KeyMetadata;
+    use common::container::MIN_CHUNK_SIZE;
+
+    #[test]
+    fn roundtrip_across_chunk_boundaries() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, KeyMetadata::default()).unwrap();
+        let opts = EncryptOptions { chunk_size: MIN_CHUNK_SIZE, ..Default::default() };
+        for len in [0, 1, MIN_CHUNK_SIZE, 2 * MIN_CHUNK_SIZE + 3] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let mut writer = EncryptingWriter::new(Vec::new(), &public, None, &opts).unwrap();
+            // Uneven writes, so chunks are assembled from several calls
+            for piece in plaintext.chunks(1000) {
+                writer.write_all(piece).unwrap();
+            }
+            let (package, summary) = writer.finish().unwrap();
+            assert_eq!(summary.plaintext_len, len as u64);
+
+            let mut reader = DecryptingReader::new(&package[..], &private, None).unwrap();
+            let mut decrypted = Vec::new();
+            reader.read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, plaintext);
+            assert_eq!(reader.summary(), summary);
+
+            // Without the trailer the end of the stream is an error, not EOF
+            let mut reader = DecryptingReader::new(&package[..package.len() - 1], &private, None).unwrap();
+            assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Bandwidth limits (`--rate-limit 5MBps`) for transfers and package writes
+//!
+//! A token bucket refills at the configured rate and every byte read or written
+//! takes a token, sleeping when the bucket is empty. The bucket holds a quarter
+//! second of traffic, so short bursts go out at full speed but the average over
+//! any second stays at the limit, leaving the shared uplink to everything else.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::parse_size;
+
+/// Smallest burst, so a low rate still moves whole frames at a time
+const MIN_BURST: u64 = 16 * 1024;
+
+/// Parse a rate such as `5MBps`, `512KB/s`, `40Mbps` or `1048576`: a size as for
+/// [`parse_size`](crate::parse_size), optionally followed by `ps` or `/s`. A
+/// lowercase `b` (`Mb`, `Kbit`) counts bits. Returns bytes per second.
+pub fn parse_rate(s: &str) -> Result<u64> {
+    let trimmed = s.trim();
+    let size = trimmed.strip_suffix("ps").or_else(|| trimmed.strip_suffix("/s")).unwrap_or(trimmed);
+    let (size, bits) = match size.strip_suffix("bit").or_else(|| size.strip_suffix('b').filter(|rest| rest.ends_with(|c: char| c.is_ascii_alphabetic()))) {
+        Some(prefix) => (prefix, true),
+        None => (size, false),
+    };
+    let rate = parse_size(size).map_err(|_| anyhow::anyhow!("invalid rate {:?} (expected e.g. 5MBps, 512KB/s or 40Mbps)", s))?;
+    let rate = if bits { rate / 8 } else { rate };
+    if rate == 0 {
+        anyhow::bail!("rate limit must be at least one byte per second");
+    }
+    Ok(rate)
+}
+
+/// Token bucket refilled at `rate` bytes per second
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    burst: u64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket that starts full
+    pub fn new(rate: u64) -> Self {
+        let burst = (rate / 4).max(MIN_BURST);
+        Self { rate, burst, tokens: burst as f64, refilled: Instant::now() }
+    }
+
+    /// Most bytes one call may take
+    pub fn burst(&self) -> usize {
+        self.burst as usize
+    }
+
+    /// Take `n` tokens, first sleeping until the bucket holds them
+    pub fn take(&mut self, n: usize) {
+        self.refill();
+        let n = n as f64;
+        if self.tokens < n {
+            std::thread::sleep(Duration::from_secs_f64((n - self.tokens) / self.rate as f64));
+            self.refill();
+        }
+        self.tokens -= n;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.rate as f64;
+        self.tokens = (self.tokens + earned).min(self.burst as f64);
+        self.refilled = now;
+    }
+}
+
+/// Reader or writer that keeps `inner` to the rate of its bucket
+pub struct Throttled<T> {
+    inner: T,
+    bucket: TokenBucket,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, rate: u64) -> Self {
+        Self { inner, bucket: TokenBucket::new(rate) }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.bucket.burst());
+        let n = self.inner.read(&mut buf[..len])?;
+        self.bucket.take(n);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.bucket.burst());
+        self.bucket.take(len);
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates_and_holds_them() {
+        assert_eq!(parse_rate("5MBps").unwrap(), 5 << 20);
+        assert_eq!(parse_rate("512KB/s").unwrap(), 512 << 10);
+        assert_eq!(parse_rate("40Mbps").unwrap(), 5 << 20);
+        assert_eq!(parse_rate("64Kbit").unwrap(), 8 << 10);
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+
+        // The first burst is free; the next 64 KiB at 256 KiB/s take about a quarter second
+        let mut out = Throttled::new(Vec::new(), 256 * 1024);
+        let start = Instant::now();
+        out.write_all(&vec![0u8; 128 * 1024]).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        assert_eq!(out.into_inner().len(), 128 * 1024);
+    }
+}
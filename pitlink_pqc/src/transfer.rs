@@ -0,0 +1,880 @@
+//! `send` / `receive`: stream a package over TCP or QUIC instead of writing it to a file
+//!
+//! ```text
+//! sender -> receiver  HELLO | kem_id u8 | ct_len u16 | KEM ciphertext
+//! receiver -> sender  HELLO | confirmation[32]
+//! sender -> receiver  package header, Merkle leaves and file metadata
+//! receiver -> sender  resume_at u64
+//! sender -> receiver  rest of the package from chunk resume_at; the sender then
+//!                     finishes its sending side
+//! receiver -> sender  0x01 | plaintext_len u64 | chunk_count u64
+//!                  or 0x00 | msg_len u16 | message
+//! ```
+//!
+//! With [`PeerAuth`] on both sides the handshake is mutual instead, Noise-style:
+//! each side encapsulates to the other's KEM key, both shared secrets and the
+//! transcript feed the confirmation key, and each side signs the transcript with
+//! its signing key, whose fingerprint the other side must have been given:
+//!
+//! ```text
+//! sender -> receiver  HELLO2 | kem_id u8 | ct_len u16 | KEM ciphertext to receiver
+//!                     | sender kem_id u8 | pk_len u16 | sender KEM public key
+//! receiver -> sender  HELLO2 | ct_len u16 | KEM ciphertext to sender
+//!                     | sig_id u8 | pk_len u16 | receiver signing public key
+//!                     | confirmation[32] | sig_len u32 | signature
+//! sender -> receiver  sig_id u8 | pk_len u16 | sender signing public key
+//!                     | confirmation[32] | sig_len u32 | signature
+//! receiver -> sender  0x01, or 0x00 | msg_len u16 | message
+//! ```
+//!
+//! Without it, anyone who can reach `receive` may send it a file; with it, only
+//! the configured peers can.
+//!
+//! The handshake encapsulates a fresh secret to the recipient key and the receiver
+//! answers with a key derived from it, so nothing is streamed to a listener that
+//! does not hold the private key. The package is the usual chunk format: the
+//! receiver authenticates every chunk as it arrives and moves the file into place
+//! only once the trailer checks out, then reports the totals back to the sender.
+//!
+//! A receiver that loses the connection keeps the chunks it had checked in the
+//! `.partial` file next to the output. When the sender tries again, the receiver
+//! checks that file chunk by chunk against the Merkle leaves of the new package
+//! and answers with how many chunks still match, so only the rest is sent.
+//!
+//! `send --since old.manifest` sends only what changed since the send that manifest
+//! records: the receiver rebuilds the file from its previous version and the
+//! changed chunks (see [`delta`]). `--write-manifest` records a send for the next one.
+//!
+//! Every transport carries the same exchange: TCP directly, QUIC on one stream (see
+//! [`quic`], behind the `quic` feature) and a serial port through framing with
+//! CRCs and resends (see [`serial`], behind the `serial` feature). An MQTT broker
+//! cannot carry it, having no way back to the sender; [`publish_file`] and
+//! [`subscribe_file`] send the package one way over it instead (see [`mqtt`],
+//! behind the `mqtt` feature).
+
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use common::cdc::Cdc;
+use common::hkdf_derive;
+use common::container::{FileMetadata, KemId, SigId};
+
+use crate::keyfile::{KeyFile, KeyKind, SigningKeyFile};
+use crate::progress::Progress;
+use crate::throttle::Throttled;
+use crate::{check_overwrite, kem, open_input, output, perms, sig, write_package_from, AtomicOutput, DecryptOptions, EncryptOptions, ExposeSecret, OpenPackage, PackageSummary, Plaintext};
+
+/// Protocol magic and version, sent first by both sides
+const HELLO: &[u8; 5] = b"PLXF\x01";
+const CONFIRM_LABEL: &[u8] = b"pitlink-transfer-confirm-v1";
+const CONFIRM_LEN: usize = 32;
+const STATUS_OK: u8 = 1;
+const STATUS_FAILED: u8 = 0;
+/// Longest a single read or write may stall before the transfer is abandoned
+const IO_TIMEOUT: Duration = Duration::from_secs(120);
+/// Protocol magic of the mutually authenticated handshake
+const HELLO_MUTUAL: &[u8; 5] = b"PLXF\x02";
+const MUTUAL_KEY_LABEL: &[u8] = b"pitlink-transfer-mutual-v1";
+const SENDER_LABEL: &[u8] = b"pitlink-transfer-sender-v1";
+const RECEIVER_LABEL: &[u8] = b"pitlink-transfer-receiver-v1";
+/// Upper bound on a handshake signature
+const MAX_HANDSHAKE_SIGNATURE: usize = 64 * 1024;
+
+mod delta;
+#[cfg(feature = "quic")]
+mod quic;
+#[cfg(feature = "serial")]
+mod serial;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+
+/// Network transport for `send` / `receive`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transport {
+    #[default]
+    Tcp,
+    /// Needs the `quic` feature
+    Quic,
+    /// A serial port at this baud rate, addressed by its device path; needs the
+    /// `serial` feature
+    Serial(u32),
+}
+
+impl Transport {
+    /// Parse a network transport name (`tcp`, `quic`)
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "tcp" => Ok(Transport::Tcp),
+            "quic" => Ok(Transport::Quic),
+            other => bail!("unknown transport {}; expected tcp or quic", other),
+        }
+    }
+}
+
+/// Our keys and the peers we accept, for the mutually authenticated handshake
+#[derive(Clone, Debug, Default)]
+pub struct PeerAuth {
+    /// Our KEM private key, for `send_file`; `receive_file` uses its own private key
+    pub kem_key: Option<PathBuf>,
+    /// Our signing private key
+    pub signing_key: PathBuf,
+    /// The public half of `signing_key`, shown to the peer
+    pub signing_public: PathBuf,
+    /// Signing-key fingerprints the peer must present one of
+    pub peers: Vec<String>,
+}
+
+/// [`PeerAuth`] with its signing keys loaded
+struct Credentials {
+    signing: SigningKeyFile,
+    signing_public: SigningKeyFile,
+    peers: Vec<String>,
+}
+
+impl Credentials {
+    fn load(auth: &PeerAuth, allow_insecure_key: bool) -> Result<Self> {
+        if auth.peers.is_empty() {
+            bail!("mutual authentication needs at least one trusted peer fingerprint");
+        }
+        perms::check_private(&auth.signing_key, allow_insecure_key)?;
+        let signing = SigningKeyFile::read(&auth.signing_key, KeyKind::SigningPrivate)?;
+        let signing_public = SigningKeyFile::read(&auth.signing_public, KeyKind::SigningPublic)?;
+        let probe = sig::sign(signing.alg, signing.key.expose_secret(), SENDER_LABEL)?;
+        if signing.alg != signing_public.alg || !sig::verify(signing_public.alg, signing_public.key.expose_secret(), SENDER_LABEL, &probe)? {
+            bail!("{} is not the public half of {}", auth.signing_public.display(), auth.signing_key.display());
+        }
+        let peers = auth.peers.iter().map(|fp| fp.trim().to_ascii_lowercase()).collect();
+        Ok(Self { signing, signing_public, peers })
+    }
+
+    /// Our signing key as sent in the handshake
+    fn announce(&self) -> Vec<u8> {
+        let mut out = vec![self.signing_public.alg.as_u8()];
+        put16(&mut out, self.signing_public.key.expose_secret());
+        out
+    }
+
+    /// Check the peer's signing key is one we were told to expect
+    fn check_peer(&self, peer: &SigningKeyFile) -> Result<String> {
+        let fp = peer.fingerprint()?;
+        if !self.peers.contains(&fp) {
+            bail!("peer signing key {} is not a trusted peer", fp);
+        }
+        Ok(fp)
+    }
+}
+
+/// Delta sync settings for [`send_file`]
+#[derive(Clone, Debug, Default)]
+pub struct SyncOptions {
+    /// Manifest of an earlier send; only chunks whose content is not in it are sent
+    pub since: Option<PathBuf>,
+    /// Write the manifest of this send here, for a later `since`
+    pub write_manifest: Option<PathBuf>,
+}
+
+/// A connected byte stream between sender and receiver
+trait Channel: Read + Write {
+    /// Tell the peer nothing more will be sent; the read side stays open
+    fn finish_sending(&mut self) -> Result<()>;
+}
+
+impl Channel for TcpStream {
+    fn finish_sending(&mut self) -> Result<()> {
+        self.shutdown(Shutdown::Write)?;
+        Ok(())
+    }
+}
+
+impl Channel for Throttled<Box<dyn Channel>> {
+    fn finish_sending(&mut self) -> Result<()> {
+        self.get_mut().finish_sending()
+    }
+}
+
+/// Encrypt the file at `input` to the public key at `pubkey_path` and stream it to
+/// the `receive` listener at `to` (`host:port`, or the device path of a serial
+/// port). Of `opts`, `recursive`, `armor`, `append` and `metadata` are ignored: the
+/// file's name, mtime and size always travel with it. Returns once the receiver
+/// has confirmed the file is complete. With `auth`, the receiver must also prove
+/// it holds one of the trusted signing keys. With `sync.since`, only the chunks
+/// that changed since that manifest are sent.
+pub fn send_file(to: &str, transport: Transport, input: PathBuf, pubkey_path: PathBuf, opts: EncryptOptions, auth: Option<PeerAuth>, sync: SyncOptions) -> Result<()> {
+    if input.as_os_str() == "-" || !input.is_file() {
+        bail!("send needs a regular input file, not stdin or a directory");
+    }
+    let recipient = KeyFile::read(pubkey_path, KeyKind::Public)?;
+    let signer = opts.sign_key.as_ref().map(|path| SigningKeyFile::read(path, KeyKind::SigningPrivate)).transpose()?;
+    let opts = EncryptOptions { metadata: Some(FileMetadata::from_path(&input)?), recursive: false, armor: false, append: false, previous_member: None, ..opts };
+    let auth = auth.map(|auth| -> Result<_> {
+        let Some(kem_key) = &auth.kem_key else { bail!("mutual authentication needs our own KEM private key") };
+        perms::check_private(kem_key, false)?;
+        Ok((KeyFile::read(kem_key, KeyKind::Private)?, Credentials::load(&auth, false)?))
+    }).transpose()?;
+    let delta = sync.since.as_deref().map(|since| delta::Delta::prepare(&input, since)).transpose()?;
+    let manifest = match (&delta, &sync.write_manifest) {
+        (Some(delta), _) => Some(delta.manifest.clone()),
+        (None, Some(_)) => {
+            let cdc = Cdc::default();
+            Some(delta::manifest_of(&input, Some(cdc), cdc.avg as u64)?)
+        }
+        (None, None) => None,
+    };
+
+    let (stream, peer) = connect(to, transport)?;
+    let mut stream = throttled(stream, opts.rate_limit);
+    let peer_fingerprint = match &auth {
+        Some((identity, credentials)) => Some(authenticate_receiver(&mut stream, &recipient, identity, credentials)?),
+        None => {
+            confirm_receiver(&mut stream, &recipient)?;
+            None
+        }
+    };
+    info!(target: "kem", "{} holds the private key for {}", peer, recipient.fingerprint()?);
+    if let Some(fp) = &peer_fingerprint {
+        info!(target: "sig", "{} authenticated as {}", peer, fp);
+    }
+
+    let start = Instant::now();
+    let plaintext = match &delta {
+        Some(delta) => delta.plaintext(&input)?,
+        None => {
+            let reopen = input.clone();
+            Plaintext::Reopen(open_input(&input)?, Box::new(move || open_input(&reopen)))
+        }
+    };
+    let mut out = BufWriter::with_capacity(64 * 1024, &mut stream);
+    let mut resumed_at = 0;
+    let mut resume = |out: &mut BufWriter<&mut Box<dyn Channel>>| -> Result<u64> {
+        out.flush()?;
+        let mut chunks = [0u8; 8];
+        out.get_mut().read_exact(&mut chunks).context("receiver closed the connection before the file was sent")?;
+        resumed_at = u64::from_be_bytes(chunks);
+        if resumed_at > 0 {
+            info!(target: "io", "{} already holds the first {} chunks; resuming", peer, resumed_at);
+        }
+        Ok(resumed_at)
+    };
+    let summary = if delta.is_some() {
+        // A delta package is rebuilt rather than resumed
+        out.write_all(delta::DELTA)?;
+        write_package_from(plaintext, &mut out, &recipient, signer.as_ref(), &opts, None)?
+    } else {
+        write_package_from(plaintext, &mut out, &recipient, signer.as_ref(), &opts, Some(&mut resume))?
+    };
+    out.flush()?;
+    drop(out);
+    stream.finish_sending()?;
+
+    let received = read_result(&mut stream)?;
+    if received != summary {
+        bail!("receiver got {} bytes in {} chunks but {} bytes in {} chunks were sent", received.plaintext_len, received.chunks, summary.plaintext_len, summary.chunks);
+    }
+    if let (Some(path), Some(manifest)) = (&sync.write_manifest, &manifest) {
+        let mut out = AtomicOutput::create(path)?;
+        out.write_all(manifest.to_json()?.as_bytes())?;
+        out.commit()?;
+        info!(target: "io", "Wrote the manifest of {} to {}", input.display(), path.display());
+    }
+    match &delta {
+        Some(delta) => status!("Sent the changes to {} ({} bytes) to {}", input.display(), summary.plaintext_len, peer),
+        None => status!("Sent {} ({} bytes) to {}", input.display(), summary.plaintext_len, peer),
+    }
+    output::record(json!({
+        "input": input, "to": peer, "transport": format!("{:?}", transport), "recipient_fingerprint": recipient.fingerprint()?, "kem": format!("{:?}", recipient.kem),
+        "chunks": summary.chunks, "plaintext_bytes": summary.plaintext_len, "signed_with": signer.as_ref().map(|s| format!("{:?}", s.alg)),
+        "peer_fingerprint": peer_fingerprint, "resumed_at_chunk": resumed_at, "delta": delta.as_ref().map(delta::Delta::summary),
+        "manifest": sync.write_manifest, "elapsed_ms": start.elapsed().as_millis() as u64,
+    }));
+    Ok(())
+}
+
+/// Wait on `listen` (`host:port`, or a serial device path) for one `send`, decrypt the file it streams with
+/// the private key at `privkey_path` and store it under its own name in the
+/// directory `output`. Of `opts`, `hybrid`, `allow_insecure_key`, `verify_sender`,
+/// `progress` and `force` apply. With `auth`, only a sender holding one of the
+/// trusted signing keys is accepted. A delta transfer replaces the previous
+/// version of the file without `force` only from a sender authenticated that way
+/// or verified with `verify_sender`.
+pub fn receive_file(listen: &str, transport: Transport, output: PathBuf, privkey_path: PathBuf, opts: DecryptOptions, auth: Option<PeerAuth>) -> Result<()> {
+    if !output.is_dir() {
+        bail!("{} is not a directory", output.display());
+    }
+    perms::check_private(&privkey_path, opts.allow_insecure_key)?;
+    let identity = KeyFile::read(&privkey_path, KeyKind::Private)?;
+    let sender = opts.verify_sender.as_ref().map(|path| SigningKeyFile::read(path, KeyKind::SigningPublic)).transpose()?;
+    let credentials = auth.map(|auth| Credentials::load(&auth, opts.allow_insecure_key)).transpose()?;
+
+    let (stream, peer) = accept(listen, transport)?;
+    let mut stream = throttled(stream, opts.rate_limit);
+    info!(target: "io", "Connection from {}", peer);
+    let peer_fingerprint = answer_sender(&mut stream, &identity, credentials.as_ref())?;
+    if let Some(fp) = &peer_fingerprint {
+        info!(target: "sig", "{} authenticated as {}", peer, fp);
+    }
+
+    let start = Instant::now();
+    let result = receive_package(&mut stream, &identity, &output, sender.as_ref(), peer_fingerprint.is_some(), &opts, Some(|stream: &mut Box<dyn Channel>, chunks: u64| -> Result<()> {
+        stream.write_all(&chunks.to_be_bytes())?;
+        stream.flush()?;
+        Ok(())
+    }));
+    let reply = match &result {
+        Ok((_, _, summary)) => {
+            let mut reply = vec![STATUS_OK];
+            reply.extend_from_slice(&summary.plaintext_len.to_be_bytes());
+            reply.extend_from_slice(&summary.chunks.to_be_bytes());
+            reply
+        }
+        Err(e) => failure_reply(e),
+    };
+    // The file is already in place (or discarded); a sender that has gone away only misses the verdict
+    if let Err(e) = stream.write_all(&reply).map_err(anyhow::Error::from).and_then(|()| stream.finish_sending()) {
+        warn!(target: "io", "could not report the result to {}: {}", peer, e);
+    }
+    drop(stream);
+    let (target, metadata, summary) = result?;
+    status!("Received {} ({} bytes) from {}", target.display(), summary.plaintext_len, peer);
+    output::record(json!({
+        "output": target, "from": peer, "transport": format!("{:?}", transport), "kem": format!("{:?}", identity.kem),
+        "chunks": summary.chunks, "plaintext_bytes": summary.plaintext_len, "sender_verified": sender.is_some(), "peer_fingerprint": peer_fingerprint,
+        "metadata": json!({ "name": metadata.name, "mtime": metadata.mtime, "size": metadata.size }),
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    }));
+    Ok(())
+}
+
+/// Encrypt the file at `input` to the public key at `pubkey_path` and publish it on
+/// the topic of the broker URL `url` (`mqtt[s]://[user[:password]@]host[:port]/topic`).
+/// The options apply as for [`send_file`]. Returns once the broker has taken every
+/// message; whether a subscriber got them is not known.
+pub fn publish_file(url: &str, input: PathBuf, pubkey_path: PathBuf, opts: EncryptOptions) -> Result<()> {
+    if input.as_os_str() == "-" || !input.is_file() {
+        bail!("send needs a regular input file, not stdin or a directory");
+    }
+    #[cfg(feature = "mqtt")]
+    {
+        mqtt::publish(url, input, pubkey_path, opts)
+    }
+    #[cfg(not(feature = "mqtt"))]
+    {
+        let _ = (url, pubkey_path, opts);
+        bail!("built without MQTT support; rebuild with the mqtt feature")
+    }
+}
+
+/// Wait for the next file published on the topic of the broker URL `url`, decrypt
+/// it with the private key at `privkey_path` and store it under its own name in the
+/// directory `output`. The options apply as for [`receive_file`].
+pub fn subscribe_file(url: &str, output: PathBuf, privkey_path: PathBuf, opts: DecryptOptions) -> Result<()> {
+    if !output.is_dir() {
+        bail!("{} is not a directory", output.display());
+    }
+    #[cfg(feature = "mqtt")]
+    {
+        mqtt::subscribe(url, output, privkey_path, opts)
+    }
+    #[cfg(not(feature = "mqtt"))]
+    {
+        let _ = (url, privkey_path, opts);
+        bail!("built without MQTT support; rebuild with the mqtt feature")
+    }
+}
+
+fn connect(to: &str, transport: Transport) -> Result<(Box<dyn Channel>, String)> {
+    match transport {
+        Transport::Tcp => {
+            let stream = TcpStream::connect(to).with_context(|| format!("cannot connect to {}", to))?;
+            set_timeouts(&stream)?;
+            let peer = stream.peer_addr()?;
+            Ok((Box::new(stream), peer.to_string()))
+        }
+        #[cfg(feature = "quic")]
+        Transport::Quic => {
+            let (channel, peer) = quic::connect(to)?;
+            Ok((Box::new(channel), peer.to_string()))
+        }
+        #[cfg(not(feature = "quic"))]
+        Transport::Quic => bail!("built without QUIC support; rebuild with the quic feature"),
+        #[cfg(feature = "serial")]
+        Transport::Serial(baud) => Ok((Box::new(serial::open(to, baud)?), to.to_string())),
+        #[cfg(not(feature = "serial"))]
+        Transport::Serial(_) => bail!("built without serial support; rebuild with the serial feature"),
+    }
+}
+
+fn accept(listen: &str, transport: Transport) -> Result<(Box<dyn Channel>, String)> {
+    match transport {
+        Transport::Tcp => {
+            let listener = TcpListener::bind(listen).with_context(|| format!("cannot listen on {}", listen))?;
+            status!("Waiting for a sender on {}", listener.local_addr()?);
+            let (stream, peer) = listener.accept()?;
+            set_timeouts(&stream)?;
+            Ok((Box::new(stream), peer.to_string()))
+        }
+        #[cfg(feature = "quic")]
+        Transport::Quic => {
+            let (channel, peer) = quic::accept(listen)?;
+            Ok((Box::new(channel), peer.to_string()))
+        }
+        #[cfg(not(feature = "quic"))]
+        Transport::Quic => bail!("built without QUIC support; rebuild with the quic feature"),
+        #[cfg(feature = "serial")]
+        Transport::Serial(baud) => {
+            let link = serial::open(listen, baud)?;
+            status!("Waiting for a sender on {} ({} baud)", listen, baud);
+            Ok((Box::new(link), listen.to_string()))
+        }
+        #[cfg(not(feature = "serial"))]
+        Transport::Serial(_) => bail!("built without serial support; rebuild with the serial feature"),
+    }
+}
+
+/// `stream`, held to `rate_limit` bytes per second each way when one is set
+fn throttled(stream: Box<dyn Channel>, rate_limit: Option<u64>) -> Box<dyn Channel> {
+    match rate_limit {
+        Some(rate) => Box::new(Throttled::new(stream, rate)),
+        None => stream,
+    }
+}
+
+fn set_timeouts(stream: &TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    Ok(())
+}
+
+/// Encapsulate to `recipient` and check the receiver derives the same secret
+fn confirm_receiver(stream: &mut (impl Read + Write), recipient: &KeyFile) -> Result<()> {
+    let (ct, kek) = kem::encapsulate(recipient.kem, recipient.key.expose_secret())?;
+    let mut hello = HELLO.to_vec();
+    hello.push(recipient.kem.as_u8());
+    hello.extend_from_slice(&(ct.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&ct);
+    stream.write_all(&hello)?;
+
+    let mut reply = [0u8; HELLO.len() + CONFIRM_LEN];
+    stream.read_exact(&mut reply).context("receiver closed the connection during the handshake")?;
+    let expected = hkdf_derive(kek.expose_secret(), CONFIRM_LABEL, CONFIRM_LEN)?;
+    if reply[..HELLO.len()] != HELLO[..] || reply[HELLO.len()..] != expected[..] {
+        bail!("receiver does not hold the private key for {}", recipient.fingerprint()?);
+    }
+    Ok(())
+}
+
+/// Decapsulate the sender's handshake with `identity` and answer with the
+/// confirmation. With `auth`, run the mutual handshake and return the sender's
+/// signing-key fingerprint.
+fn answer_sender(stream: &mut (impl Read + Write), identity: &KeyFile, auth: Option<&Credentials>) -> Result<Option<String>> {
+    let mut magic = [0u8; HELLO.len()];
+    stream.read_exact(&mut magic).context("sender closed the connection during the handshake")?;
+    match (&magic, auth) {
+        (HELLO, None) => {}
+        (HELLO_MUTUAL, Some(auth)) => return authenticate_sender(stream, identity, auth).map(Some),
+        (HELLO, Some(_)) => bail!("sender did not authenticate itself; it needs --auth-key and --peer too"),
+        (HELLO_MUTUAL, None) => bail!("sender asks for mutual authentication; start receive with --auth-key and --peer"),
+        _ => bail!("peer is not a pitlink sender (or speaks another protocol version)"),
+    }
+    let mut head = [0u8; 3];
+    stream.read_exact(&mut head).context("sender closed the connection during the handshake")?;
+    let kem_id = KemId::from_u8(head[0])?;
+    if !kem::key_compatible(identity.kem, kem_id) {
+        bail!("sender encrypts with {:?} but the private key is {:?}", kem_id, identity.kem);
+    }
+    let mut ct = vec![0u8; u16::from_be_bytes([head[1], head[2]]) as usize];
+    stream.read_exact(&mut ct)?;
+    let kek = kem::decapsulate(kem_id, identity.key.expose_secret(), &ct)?;
+    let mut reply = HELLO.to_vec();
+    reply.extend_from_slice(&hkdf_derive(kek.expose_secret(), CONFIRM_LABEL, CONFIRM_LEN)?);
+    stream.write_all(&reply)?;
+    Ok(None)
+}
+
+/// Sender half of the mutual handshake: returns the receiver's signing-key fingerprint
+fn authenticate_receiver(stream: &mut (impl Read + Write), recipient: &KeyFile, identity: &KeyFile, auth: &Credentials) -> Result<String> {
+    let (ct, receiver_secret) = kem::encapsulate(recipient.kem, recipient.key.expose_secret())?;
+    let mut transcript = HELLO_MUTUAL.to_vec();
+    transcript.push(recipient.kem.as_u8());
+    put16(&mut transcript, &ct);
+    transcript.push(identity.kem.as_u8());
+    put16(&mut transcript, &kem::public_from_secret(identity.kem, identity.key.expose_secret())?);
+    stream.write_all(&transcript)?;
+
+    let mut magic = [0u8; HELLO_MUTUAL.len()];
+    stream.read_exact(&mut magic).context("receiver closed the connection during the handshake")?;
+    if &magic != HELLO_MUTUAL {
+        bail!("receiver does not do mutual authentication");
+    }
+    transcript.extend_from_slice(&magic);
+    let ct = read16(stream, &mut transcript)?;
+    let sender_secret = kem::decapsulate(identity.kem, identity.key.expose_secret(), &ct)?;
+    let key = mutual_key(receiver_secret.expose_secret(), sender_secret.expose_secret(), &transcript)?;
+    let receiver = read_signing_key(stream, &mut transcript)?;
+    check_proof(stream, &key, RECEIVER_LABEL, &receiver, &transcript).with_context(|| format!("receiver does not hold the private key for {}", recipient.fingerprint()?))?;
+    let fp = auth.check_peer(&receiver)?;
+
+    let mut reply = auth.announce();
+    transcript.extend_from_slice(&reply);
+    reply.extend_from_slice(&proof(&key, SENDER_LABEL, auth, &transcript)?);
+    stream.write_all(&reply)?;
+    let mut status = [0u8; 1];
+    stream.read_exact(&mut status).context("receiver closed the connection during the handshake")?;
+    if status[0] != STATUS_OK {
+        bail!("receiver rejected us: {}", read_failure(stream)?);
+    }
+    Ok(fp)
+}
+
+/// Receiver half of the mutual handshake, after the magic: returns the sender's
+/// signing-key fingerprint
+fn authenticate_sender(stream: &mut (impl Read + Write), identity: &KeyFile, auth: &Credentials) -> Result<String> {
+    let mut transcript = HELLO_MUTUAL.to_vec();
+    let kem_id = KemId::from_u8(read_u8(stream, &mut transcript)?)?;
+    if !kem::key_compatible(identity.kem, kem_id) {
+        bail!("sender encrypts with {:?} but the private key is {:?}", kem_id, identity.kem);
+    }
+    let ct = read16(stream, &mut transcript)?;
+    let sender_kem = KemId::from_u8(read_u8(stream, &mut transcript)?)?;
+    let sender_public = read16(stream, &mut transcript)?;
+    if sender_public.len() != kem::public_key_len(sender_kem) {
+        bail!("sender KEM public key has the wrong length for {:?}", sender_kem);
+    }
+    let receiver_secret = kem::decapsulate(kem_id, identity.key.expose_secret(), &ct)?;
+    let (ct, sender_secret) = kem::encapsulate(sender_kem, &sender_public)?;
+
+    let mut reply = HELLO_MUTUAL.to_vec();
+    put16(&mut reply, &ct);
+    transcript.extend_from_slice(&reply);
+    let key = mutual_key(receiver_secret.expose_secret(), sender_secret.expose_secret(), &transcript)?;
+    let announce = auth.announce();
+    transcript.extend_from_slice(&announce);
+    reply.extend_from_slice(&announce);
+    reply.extend_from_slice(&proof(&key, RECEIVER_LABEL, auth, &transcript)?);
+    stream.write_all(&reply)?;
+
+    let verdict = read_signing_key(stream, &mut transcript).and_then(|sender| {
+        check_proof(stream, &key, SENDER_LABEL, &sender, &transcript).context("sender failed to authenticate")?;
+        auth.check_peer(&sender)
+    });
+    let reply = match &verdict {
+        Ok(_) => vec![STATUS_OK],
+        Err(e) => failure_reply(e),
+    };
+    if let Err(e) = stream.write_all(&reply) {
+        warn!(target: "io", "could not report the handshake result: {}", e);
+    }
+    verdict
+}
+
+/// Confirmation key of the mutual handshake, from both shared secrets and the
+/// transcript so far
+fn mutual_key(receiver_secret: &[u8], sender_secret: &[u8], transcript: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let mut ikm = Zeroizing::new(receiver_secret.to_vec());
+    ikm.extend_from_slice(sender_secret);
+    let mut info = MUTUAL_KEY_LABEL.to_vec();
+    info.extend_from_slice(&Sha256::digest(transcript));
+    hkdf_derive(&ikm, &info, CONFIRM_LEN)
+}
+
+/// `confirmation | sig_len u32 | signature` proving we hold both the shared
+/// secrets and our signing key
+fn proof(key: &[u8], label: &[u8], auth: &Credentials, transcript: &[u8]) -> Result<Vec<u8>> {
+    let mut out = hkdf_derive(key, label, CONFIRM_LEN)?.to_vec();
+    let signature = sig::sign(auth.signing.alg, auth.signing.key.expose_secret(), &signed_message(label, transcript))?;
+    out.extend_from_slice(&(signature.len() as u32).to_be_bytes());
+    out.extend_from_slice(&signature);
+    Ok(out)
+}
+
+/// Read and check the peer's [`proof`]
+fn check_proof(stream: &mut impl Read, key: &[u8], label: &[u8], peer: &SigningKeyFile, transcript: &[u8]) -> Result<()> {
+    let mut confirmation = [0u8; CONFIRM_LEN];
+    stream.read_exact(&mut confirmation)?;
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_HANDSHAKE_SIGNATURE {
+        bail!("handshake signature of {} bytes is too long", len);
+    }
+    let mut signature = vec![0u8; len];
+    stream.read_exact(&mut signature)?;
+    if confirmation[..] != hkdf_derive(key, label, CONFIRM_LEN)?[..] {
+        bail!("key confirmation does not match");
+    }
+    if !sig::verify(peer.alg, peer.key.expose_secret(), &signed_message(label, transcript), &signature)? {
+        bail!("handshake signature does not verify");
+    }
+    Ok(())
+}
+
+fn signed_message(label: &[u8], transcript: &[u8]) -> Vec<u8> {
+    let mut message = label.to_vec();
+    message.extend_from_slice(&Sha256::digest(transcript));
+    message
+}
+
+/// `sig_id u8 | pk_len u16 | public key`, appended to `transcript`
+fn read_signing_key(stream: &mut impl Read, transcript: &mut Vec<u8>) -> Result<SigningKeyFile> {
+    let alg = SigId::from_u8(read_u8(stream, transcript)?)?;
+    let key = read16(stream, transcript)?;
+    if key.len() != sig::public_key_len(alg) {
+        bail!("peer signing key has the wrong length for {:?}", alg);
+    }
+    Ok(SigningKeyFile::new(KeyKind::SigningPublic, alg, key))
+}
+
+fn read_u8(stream: &mut impl Read, transcript: &mut Vec<u8>) -> Result<u8> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).context("peer closed the connection during the handshake")?;
+    transcript.push(byte[0]);
+    Ok(byte[0])
+}
+
+/// `len u16 | bytes`, appended to `transcript`
+fn read16(stream: &mut impl Read, transcript: &mut Vec<u8>) -> Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).context("peer closed the connection during the handshake")?;
+    let mut field = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut field).context("peer closed the connection during the handshake")?;
+    transcript.extend_from_slice(&len);
+    transcript.extend_from_slice(&field);
+    Ok(field)
+}
+
+fn put16(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u16).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+/// `0x00 | msg_len u16 | message`
+fn failure_reply(e: &anyhow::Error) -> Vec<u8> {
+    let message = format!("{:#}", e);
+    let message = &message.as_bytes()[..message.len().min(u16::MAX as usize)];
+    let mut reply = vec![STATUS_FAILED];
+    reply.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    reply.extend_from_slice(message);
+    reply
+}
+
+/// The message of a failure reply, after its status byte
+fn read_failure(stream: &mut impl Read) -> Result<String> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(String::from_utf8_lossy(&message).into_owned())
+}
+
+/// Decrypt the package arriving on `stream` into `output_dir`, under the file name
+/// it carries. With `resume`, what an earlier attempt left in the `.partial` file
+/// is checked and kept, `resume` tells the sender the chunk to go on from, and the
+/// `.partial` file stays behind if this attempt fails too. A delta package is
+/// rebuilt from the previous version instead and never resumed; it may replace
+/// that version without `force` only when the transport `authenticated` the
+/// sender or `sender` verifies the package.
+fn receive_package<R: Read>(stream: &mut R, identity: &KeyFile, output_dir: &Path, sender: Option<&SigningKeyFile>, authenticated: bool, opts: &DecryptOptions, resume: Option<fn(&mut R, u64) -> Result<()>>) -> Result<(PathBuf, FileMetadata, PackageSummary)> {
+    let mut magic = [0u8; delta::DELTA.len()];
+    stream.read_exact(&mut magic).context("sender closed the connection before the package")?;
+    let (target, metadata, summary) = if &magic == delta::DELTA {
+        delta::receive(stream, identity, output_dir, sender, authenticated, opts)?
+    } else {
+        receive_whole(&magic, stream, identity, output_dir, sender, opts, resume)?
+    };
+    let mtime = UNIX_EPOCH + Duration::from_secs(metadata.mtime);
+    if let Err(e) = std::fs::File::options().write(true).open(&target).and_then(|f| f.set_modified(mtime)) {
+        warn!(target: "io", "could not restore the modification time of {}: {}", target.display(), e);
+    }
+    Ok((target, metadata, summary))
+}
+
+/// [`receive_package`] for a whole package, whose first bytes `head` were already read
+fn receive_whole<R: Read>(head: &[u8], stream: &mut R, identity: &KeyFile, output_dir: &Path, sender: Option<&SigningKeyFile>, opts: &DecryptOptions, resume: Option<fn(&mut R, u64) -> Result<()>>) -> Result<(PathBuf, FileMetadata, PackageSummary)> {
+    let mut package = OpenPackage::from_reader(head.chain(&mut *stream), identity, opts.hybrid, sender)?;
+    let Some(metadata) = package.metadata.clone() else { bail!("sender did not include the file name") };
+    let target = output_dir.join(&metadata.name);
+    check_overwrite(&target, opts.force)?;
+    let mut out = match resume {
+        Some(resume) => {
+            let out = AtomicOutput::resume(&target, |earlier| Ok(package.resume_from(BufReader::new(earlier))? * package.chunk_size as u64))?;
+            if package.seq > 0 {
+                info!(target: "io", "{} already holds the first {} chunks", target.display(), package.seq);
+            }
+            resume(package.reader.get_mut().get_mut().1, package.seq)?;
+            out
+        }
+        None => AtomicOutput::create(&target)?,
+    };
+    let summary = package.read_chunks(&mut out, sender, &mut Progress::new(opts.progress.clone(), None))?;
+    out.commit()?;
+    Ok((target, metadata, summary))
+}
+
+/// The receiver's verdict on the package just sent
+fn read_result(stream: &mut impl Read) -> Result<PackageSummary> {
+    let mut status = [0u8; 1];
+    stream.read_exact(&mut status).context("receiver closed the connection without confirming the file")?;
+    if status[0] == STATUS_OK {
+        let mut totals = [0u8; 16];
+        stream.read_exact(&mut totals)?;
+        let plaintext_len = u64::from_be_bytes(totals[..8].try_into().expect("8 bytes"));
+        let chunks = u64::from_be_bytes(totals[8..].try_into().expect("8 bytes"));
+        return Ok(PackageSummary { chunks, plaintext_len });
+    }
+    bail!("receiver rejected the file: {}", read_failure(stream)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn handshake_needs_the_private_key() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let (_, other) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        for (identity, ok) in [(private, true), (other, false)] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let receiver = std::thread::spawn(move || answer_sender(&mut listener.accept().unwrap().0, &identity, None));
+            let mut stream = TcpStream::connect(addr).unwrap();
+            assert_eq!(confirm_receiver(&mut stream, &public).is_ok(), ok);
+            receiver.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn mutual_handshake_checks_the_peer() {
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let (_, sender_kem) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let sender_keys = sig::generate_keypair(SigId::MlDsa44);
+        let receiver_keys = sig::generate_keypair(SigId::MlDsa44);
+        let fp = |keys: &(Vec<u8>, Vec<u8>)| SigningKeyFile::new(KeyKind::SigningPublic, SigId::MlDsa44, keys.0.clone()).fingerprint().unwrap();
+        let credentials = |keys: &(Vec<u8>, Vec<u8>), peer: String| Credentials {
+            signing: SigningKeyFile::new(KeyKind::SigningPrivate, SigId::MlDsa44, keys.1.clone()),
+            signing_public: SigningKeyFile::new(KeyKind::SigningPublic, SigId::MlDsa44, keys.0.clone()),
+            peers: vec![peer],
+        };
+        for (trusted, ok) in [(fp(&sender_keys), true), (fp(&receiver_keys), false)] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let receiver_auth = credentials(&receiver_keys, trusted);
+            std::thread::scope(|scope| {
+                let receiver = scope.spawn(|| answer_sender(&mut listener.accept().unwrap().0, &private, Some(&receiver_auth)));
+                let mut stream = TcpStream::connect(addr).unwrap();
+                let sent = authenticate_receiver(&mut stream, &public, &sender_kem, &credentials(&sender_keys, fp(&receiver_keys)));
+                assert_eq!(sent.ok(), ok.then(|| fp(&receiver_keys)));
+                assert_eq!(receiver.join().unwrap().ok().flatten(), ok.then(|| fp(&sender_keys)));
+            });
+        }
+    }
+
+    #[test]
+    fn resumes_from_the_partial_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let received = dir.join("received");
+        std::fs::create_dir_all(&received).unwrap();
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let chunk_size = common::container::MIN_CHUNK_SIZE;
+        let data: Vec<u8> = (0..chunk_size * 9 / 2).map(|i| (i % 251) as u8).collect();
+        let input = dir.join("lap-12.csv");
+        std::fs::write(&input, &data).unwrap();
+        // An earlier attempt got two good chunks and a damaged third
+        let mut partial = data[..chunk_size * 3].to_vec();
+        partial[chunk_size * 2] ^= 1;
+        std::fs::write(received.join("lap-12.csv.partial"), &partial).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiver = std::thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            receive_package(&mut stream, &private, &received, None, false, &DecryptOptions::default(), Some(|stream: &mut TcpStream, chunks: u64| -> Result<()> {
+                stream.write_all(&chunks.to_be_bytes())?;
+                Ok(())
+            }))
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let opts = EncryptOptions { chunk_size, metadata: Some(FileMetadata::from_path(&input).unwrap()), ..Default::default() };
+        let reopen = input.clone();
+        let plaintext = Plaintext::Reopen(open_input(&input).unwrap(), Box::new(move || open_input(&reopen)));
+        let mut resumed_at = 0;
+        let mut resume = |out: &mut BufWriter<&mut TcpStream>| -> Result<u64> {
+            out.flush()?;
+            let mut chunks = [0u8; 8];
+            out.get_mut().read_exact(&mut chunks)?;
+            resumed_at = u64::from_be_bytes(chunks);
+            Ok(resumed_at)
+        };
+        let summary = write_package_from(plaintext, &mut BufWriter::new(&mut stream), &public, None, &opts, Some(&mut resume)).unwrap();
+        drop(stream);
+        let (target, _, got) = receiver.join().unwrap().unwrap();
+        assert_eq!(resumed_at, 2);
+        assert_eq!(got, summary);
+        assert_eq!(std::fs::read(&target).unwrap(), data);
+        assert!(!dir.join("received/lap-12.csv.partial").exists());
+    }
+
+    #[test]
+    fn delta_sends_only_changed_chunks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let received = dir.join("received");
+        std::fs::create_dir_all(&received).unwrap();
+        let (public, private) = generate_keypair(KemId::MlKem768, None, Default::default()).unwrap();
+        let mut state = 9u64;
+        let old: Vec<u8> = (0..600_000).map(|_| { state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407); (state >> 56) as u8 }).collect();
+        let input = dir.join("telemetry.bin");
+        std::fs::write(&input, &old).unwrap();
+        std::fs::write(received.join("telemetry.bin"), &old).unwrap();
+        let cdc = Cdc::new(4 * 1024, 16 * 1024, 64 * 1024).unwrap();
+        let since = dir.join("telemetry.manifest.json");
+        std::fs::write(&since, delta::manifest_of(&input, Some(cdc), cdc.avg as u64).unwrap().to_json().unwrap()).unwrap();
+
+        // Overnight a few bytes were rewritten in the middle and a lap appended
+        let mut new = old.clone();
+        new[300_000..300_010].copy_from_slice(b"pit stop!!");
+        new.extend_from_slice(&old[..20_000]);
+        std::fs::write(&input, &new).unwrap();
+        let delta = delta::Delta::prepare(&input, &since).unwrap();
+        let stats = delta.summary();
+        assert!(stats["sent_bytes"].as_u64().unwrap() < 150_000, "{}", stats);
+        assert_eq!(stats["reused_bytes"].as_u64().unwrap() + stats["sent_bytes"].as_u64().unwrap(), new.len() as u64);
+
+        let opts = EncryptOptions { metadata: Some(FileMetadata::from_path(&input).unwrap()), ..Default::default() };
+        let send = |authenticated: bool| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            std::thread::scope(|scope| {
+                let receiver = scope.spawn(|| {
+                    let mut stream = listener.accept().unwrap().0;
+                    receive_package(&mut stream, &private, &received, None, authenticated, &DecryptOptions::default(), None)
+                });
+                let mut stream = TcpStream::connect(addr).unwrap();
+                let mut out = BufWriter::new(&mut stream);
+                out.write_all(delta::DELTA).unwrap();
+                // The receiver may hang up early, so only its side tells how it went
+                let summary = write_package_from(delta.plaintext(&input).unwrap(), &mut out, &public, None, &opts, None);
+                drop(out);
+                drop(stream);
+                (receiver.join().unwrap(), summary)
+            })
+        };
+
+        // An unauthenticated sender may not replace the existing file
+        let err = send(false).0.unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{:#}", err);
+        assert_eq!(std::fs::read(received.join("telemetry.bin")).unwrap(), old);
+        let (got, summary) = send(true);
+        let (target, _, got) = got.unwrap();
+        assert_eq!(got, summary.unwrap());
+        assert_eq!(std::fs::read(&target).unwrap(), new);
+    }
+}
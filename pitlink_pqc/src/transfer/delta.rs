@@ -0,0 +1,271 @@
+//! Delta sync for `send --since old.manifest`
+//!
+//! A manifest with per-chunk content hashes (from `send --write-manifest` or
+//! `pipeline --cdc`) records what the receiver got last time. The next `send` cuts
+//! the updated file the same way, by the manifest's content-defined bounds or
+//! chunk size, and only the chunks whose BLAKE3 is not in the old manifest travel.
+//! The receiver rebuilds the file from its copy of the previous version (the
+//! basis, named by the manifest's input) and the chunks it was sent:
+//!
+//! ```text
+//! sender -> receiver  DELTA marker in place of the package magic, then a package
+//!                     whose plaintext is plan_len u32 | plan JSON | changed chunks
+//! plan                {"basis":"lap.csv","basis_bytes":N,"ops":[
+//!                       {"copy":{"offset":0,"len":1048576,"hash":"9b1c..."}},
+//!                       {"data":{"len":1052311,"hash":"07e4..."}}, ...]}
+//! ```
+//!
+//! The plan lists the new file chunk by chunk, in order. It travels inside the
+//! package, so it is encrypted and authenticated like the data. Every chunk is
+//! checked against its hash before it is written, which catches a basis that is
+//! not the version the manifest describes; the file is moved into place only once
+//! all of them match and the package trailer checks out.
+//!
+//! The plan names the file it replaces, so only a sender that proved who it is,
+//! by mutual authentication or a package signature checked with `--verify-sender`,
+//! may replace its basis without `--force`. Otherwise anyone who can reach
+//! `receive` could overwrite any file in the output directory with a plan made
+//! only of data.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use common::cdc::{Cdc, MAX_SIZE_CEILING};
+use common::container::FileMetadata;
+use common::manifest::{self, Algorithms, Manifest, ManifestChunk};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::keyfile::{KeyFile, SigningKeyFile};
+use crate::progress::Progress;
+use crate::{check_overwrite, AtomicOutput, ChunkReader, DecryptOptions, OpenPackage, PackageSummary, Plaintext};
+
+/// Sent instead of the package magic, which has the same length, ahead of a delta package
+pub(super) const DELTA: &[u8; 5] = b"PLXD\x01";
+/// Upper bound on the encoded plan
+const MAX_PLAN: usize = 64 * 1024 * 1024;
+
+/// How to rebuild the new file, chunk by chunk
+#[derive(Debug, Serialize, Deserialize)]
+struct Plan {
+    /// File name of the previous version on the receiver
+    basis: String,
+    basis_bytes: u64,
+    ops: Vec<Op>,
+}
+
+/// One chunk of the new file; `hash` is its BLAKE3 in hex
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    /// Already in the basis at `offset`
+    Copy { offset: u64, len: u64, hash: String },
+    /// Next in the package
+    Data { len: u64, hash: String },
+}
+
+/// What `send --since` sends for one input
+pub(super) struct Delta {
+    plan: Plan,
+    /// Offset and length in the input of every chunk in the package
+    changed: Vec<(u64, u64)>,
+    /// Manifest of the input, for the next `--since`
+    pub(super) manifest: Manifest,
+}
+
+impl Delta {
+    /// Cut `input` like the manifest at `since` and find the chunks it does not hold
+    pub(super) fn prepare(input: &Path, since: &Path) -> Result<Self> {
+        let old = Manifest::from_json(&std::fs::read(since).with_context(|| format!("cannot read {}", since.display()))?)
+            .with_context(|| format!("{} is not a valid manifest", since.display()))?;
+        let Some(basis) = Path::new(&old.input).file_name().and_then(OsStr::to_str) else { bail!("{} does not name its input file", since.display()) };
+        if old.cdc.is_none() && old.chunk_size == 0 {
+            bail!("{} records neither a chunk size nor content-defined bounds", since.display());
+        }
+        let mut known = HashMap::new();
+        for chunk in &old.chunks {
+            let Some(hash) = &chunk.content_hash else {
+                bail!("{} has no content hashes; make it with send --write-manifest or pipeline --cdc", since.display())
+            };
+            known.entry(hash.clone()).or_insert(chunk.offset);
+        }
+
+        let manifest = manifest_of(input, old.cdc, old.chunk_size)?;
+        let (mut ops, mut changed) = (Vec::new(), Vec::new());
+        for chunk in &manifest.chunks {
+            let (len, hash) = (chunk.size, chunk.hash.clone());
+            match known.get(&hash) {
+                Some(&offset) => ops.push(Op::Copy { offset, len, hash }),
+                None => {
+                    ops.push(Op::Data { len, hash });
+                    changed.push((chunk.offset, len));
+                }
+            }
+        }
+        let delta = Self { plan: Plan { basis: basis.to_string(), basis_bytes: old.total_bytes, ops }, changed, manifest };
+        info!(target: "io", "{} of {} chunks changed since {}", delta.changed.len(), delta.manifest.chunks.len(), since.display());
+        Ok(delta)
+    }
+
+    /// The package plaintext: the plan, then the changed chunks read from `input`
+    pub(super) fn plaintext(&self, input: &Path) -> Result<Plaintext<'static>> {
+        let plan = serde_json::to_vec(&self.plan)?;
+        let mut head = (plan.len() as u32).to_be_bytes().to_vec();
+        head.extend_from_slice(&plan);
+        let (path, changed) = (input.to_path_buf(), self.changed.clone());
+        let open = move || -> Result<Box<dyn Read + Send>> {
+            Ok(Box::new(DeltaSource { head: Cursor::new(head.clone()), file: File::open(&path)?, ranges: changed.clone().into_iter(), left: 0 }))
+        };
+        Ok(Plaintext::Reopen(open()?, Box::new(open)))
+    }
+
+    /// Sizes for the command result
+    pub(super) fn summary(&self) -> Value {
+        let sent: u64 = self.changed.iter().map(|&(_, len)| len).sum();
+        json!({
+            "basis": self.plan.basis, "chunks": self.manifest.chunks.len(), "changed_chunks": self.changed.len(),
+            "sent_bytes": sent, "reused_bytes": self.manifest.total_bytes - sent,
+        })
+    }
+}
+
+/// Manifest of `input` cut by `cdc`, or every `chunk_size` bytes without it, with
+/// each chunk's BLAKE3 as its hash and content hash
+pub(super) fn manifest_of(input: &Path, cdc: Option<Cdc>, chunk_size: u64) -> Result<Manifest> {
+    let Some(name) = input.file_name().and_then(OsStr::to_str) else { bail!("file name of {} is not UTF-8", input.display()) };
+    // min == max cuts every chunk_size bytes
+    let fixed = Cdc { min: chunk_size as usize, avg: chunk_size as usize, max: chunk_size as usize };
+    let cuts = cdc.unwrap_or(fixed).scan(BufReader::new(File::open(input)?))?;
+    let mut package_id = [0u8; 16];
+    getrandom::getrandom(&mut package_id)?;
+    let algorithms = Algorithms { hash: "blake3".to_string(), ..Default::default() };
+    let mut manifest = Manifest::new(manifest::to_hex(&package_id), name.to_string(), algorithms, chunk_size);
+    manifest.cdc = cdc;
+    for (len, hash) in cuts {
+        let hash = manifest::to_hex(&hash);
+        let (index, offset) = (manifest.chunks.len() as u64 + 1, manifest.total_bytes);
+        manifest.push(ManifestChunk { index, file: name.to_string(), offset, size: len, stored_size: len, frames: None, hash: hash.clone(), content_hash: Some(hash) });
+    }
+    Ok(manifest)
+}
+
+/// Reads the plan header, then the byte ranges of the changed chunks from the input
+struct DeltaSource {
+    head: Cursor<Vec<u8>>,
+    file: File,
+    ranges: std::vec::IntoIter<(u64, u64)>,
+    /// Bytes still to read from the current range
+    left: u64,
+}
+
+impl Read for DeltaSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.head.read(buf)?;
+        if n > 0 || buf.is_empty() {
+            return Ok(n);
+        }
+        while self.left == 0 {
+            let Some((offset, len)) = self.ranges.next() else { return Ok(0) };
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.left = len;
+        }
+        let want = self.left.min(buf.len() as u64) as usize;
+        let n = self.file.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input shrank while it was being sent"));
+        }
+        self.left -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Decrypt the delta package on `stream` and rebuild the file it describes in
+/// `output_dir` from the basis there. When the transport `authenticated` the
+/// sender or `sender` verifies the package, the basis itself may be replaced
+/// without `force`; any other existing file may not.
+pub(super) fn receive<R: Read>(stream: &mut R, identity: &KeyFile, output_dir: &Path, sender: Option<&SigningKeyFile>, authenticated: bool, opts: &DecryptOptions) -> Result<(PathBuf, FileMetadata, PackageSummary)> {
+    let mut package = OpenPackage::from_reader(&mut *stream, identity, opts.hybrid, sender)?;
+    let Some(metadata) = package.metadata.clone() else { bail!("sender did not include the file name") };
+    let target = output_dir.join(&metadata.name);
+    let mut progress = Progress::new(opts.progress.clone(), None);
+    let mut source = ChunkReader { package: &mut package, sender, progress: &mut progress, buf: Vec::new(), pos: 0, error: None };
+    let rebuilt = rebuild(&mut source, output_dir, &target, &metadata, opts.force, authenticated || sender.is_some());
+    // A failed chunk surfaces in the rebuild as an I/O error; report the chunk's own
+    if let Some(e) = source.error.take() {
+        return Err(e);
+    }
+    let (reused, received) = rebuilt?;
+    info!(target: "io", "Rebuilt {}: {} bytes from the previous version, {} received", target.display(), reused, received);
+    Ok((target, metadata, package.summary()))
+}
+
+/// Write the file the plan at the start of `source` describes to `target`;
+/// returns the bytes taken from the basis and from `source`. Only a `trusted`
+/// sender may replace the basis without `force`.
+fn rebuild(source: &mut impl Read, output_dir: &Path, target: &Path, metadata: &FileMetadata, force: bool, trusted: bool) -> Result<(u64, u64)> {
+    let mut len = [0u8; 4];
+    source.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_PLAN {
+        bail!("delta plan of {} bytes is too long", len);
+    }
+    let mut plan = vec![0u8; len];
+    source.read_exact(&mut plan)?;
+    let plan: Plan = serde_json::from_slice(&plan).context("delta plan is malformed")?;
+    if Path::new(&plan.basis).file_name() != Some(OsStr::new(&plan.basis)) {
+        bail!("delta plan names {:?}, which is not a plain file name", plan.basis);
+    }
+
+    let basis_path = output_dir.join(&plan.basis);
+    let mut basis = File::open(&basis_path).with_context(|| format!("a delta transfer needs the previous version at {}", basis_path.display()))?;
+    if basis.metadata()?.len() != plan.basis_bytes {
+        bail!("{} is {} bytes but the sender's manifest describes {}; send without --since", basis_path.display(), basis.metadata()?.len(), plan.basis_bytes);
+    }
+    if !trusted || target != basis_path {
+        check_overwrite(target, force)?;
+    }
+    let mut out = AtomicOutput::create(target)?;
+    let mut buf = Vec::new();
+    let (mut reused, mut received) = (0u64, 0u64);
+    for (i, op) in plan.ops.iter().enumerate() {
+        let (&len, hash) = match op {
+            Op::Copy { len, hash, .. } | Op::Data { len, hash } => (len, hash),
+        };
+        if len > MAX_SIZE_CEILING as u64 {
+            bail!("delta chunk {} of {} bytes is too long", i + 1, len);
+        }
+        buf.resize(len as usize, 0);
+        match op {
+            Op::Copy { offset, .. } => {
+                basis.seek(SeekFrom::Start(*offset))?;
+                basis.read_exact(&mut buf).with_context(|| format!("{} ends before chunk {}", basis_path.display(), i + 1))?;
+                reused += len;
+            }
+            Op::Data { .. } => {
+                source.read_exact(&mut buf)?;
+                received += len;
+            }
+        }
+        if manifest::to_hex(blake3::hash(&buf).as_bytes()) != *hash {
+            match op {
+                Op::Copy { .. } => bail!("chunk {} of {} does not match the sender's manifest; send without --since", i + 1, basis_path.display()),
+                Op::Data { .. } => bail!("chunk {} changed on the sender while it was being sent", i + 1),
+            }
+        }
+        out.write_all(&buf)?;
+    }
+    // Reading past the last chunk checks the signature and trailer
+    if source.read(&mut [0u8; 1])? != 0 {
+        bail!("delta package holds more data than its plan");
+    }
+    if reused + received != metadata.size {
+        bail!("delta plan rebuilds {} bytes but the file has {}", reused + received, metadata.size);
+    }
+    out.commit()?;
+    Ok((reused, received))
+}
@@ -0,0 +1,194 @@
+//! QUIC transport for `send` / `receive` (`--transport quic`)
+//!
+//! The transfer runs over one bidirectional stream of a quinn connection. TLS only
+//! carries the stream: the receiver presents a throwaway self-signed certificate
+//! that the sender does not check, because the KEM handshake inside the stream is
+//! what proves the receiver holds the private key. Keep-alives hold the connection
+//! open through quiet spells on lossy links, and quinn follows the sender to a new
+//! address when its network changes.
+//!
+//! quinn is async; the synchronous transfer code reaches its streams through
+//! `SyncIoBridge` on a small runtime owned by the channel.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::rustls::{self, pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime}};
+use quinn::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use quinn::rustls::crypto::CryptoProvider;
+use quinn::rustls::{DigitallySignedStruct, SignatureScheme};
+use quinn::{Connection, Endpoint, RecvStream, SendStream, TransportConfig};
+use tokio::runtime::Runtime;
+use tokio_util::io::SyncIoBridge;
+use tracing::debug;
+
+use super::{Channel, IO_TIMEOUT};
+
+const ALPN: &[u8] = b"pitlink-transfer/1";
+/// Server name the sender asks for; the certificate is not checked against it
+const SERVER_NAME: &str = "pitlink";
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+/// How long to wait for the peer to read the last bytes before closing
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One bidirectional stream of a QUIC connection
+pub(super) struct QuicChannel {
+    send: Option<SyncIoBridge<SendStream>>,
+    recv: SyncIoBridge<RecvStream>,
+    connection: Connection,
+    endpoint: Endpoint,
+    runtime: Runtime,
+}
+
+/// Connect to the receiver at `to` and open the transfer stream
+pub(super) fn connect(to: &str) -> Result<(QuicChannel, SocketAddr)> {
+    let addr = resolve(to)?;
+    let runtime = runtime()?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let mut config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+    config.transport_config(transport_config()?);
+
+    let (endpoint, connection, (send, recv)) = runtime.block_on(async {
+        let local: SocketAddr = if addr.is_ipv6() { "[::]:0".parse()? } else { "0.0.0.0:0".parse()? };
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(config);
+        let connection = endpoint.connect(addr, SERVER_NAME)?.await.with_context(|| format!("cannot connect to {}", to))?;
+        let streams = connection.open_bi().await?;
+        anyhow::Ok((endpoint, connection, streams))
+    })?;
+    debug!(target: "io", "QUIC connection to {} established", addr);
+    Ok((QuicChannel::new(runtime, endpoint, connection, send, recv), addr))
+}
+
+/// Wait on `listen` for one sender and accept its transfer stream
+pub(super) fn accept(listen: &str) -> Result<(QuicChannel, SocketAddr)> {
+    let addr = resolve(listen)?;
+    let runtime = runtime()?;
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])?;
+    let cert = certified.cert.der().clone();
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key.into())?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?));
+    config.transport_config(transport_config()?);
+
+    let (endpoint, connection, (send, recv)) = runtime.block_on(async {
+        let endpoint = Endpoint::server(config, addr).with_context(|| format!("cannot listen on {}", listen))?;
+        status!("Waiting for a sender on {} (QUIC)", endpoint.local_addr()?);
+        let incoming = endpoint.accept().await.ok_or_else(|| anyhow!("QUIC endpoint closed"))?;
+        let connection = incoming.await?;
+        let streams = connection.accept_bi().await?;
+        anyhow::Ok((endpoint, connection, streams))
+    })?;
+    let peer = connection.remote_address();
+    Ok((QuicChannel::new(runtime, endpoint, connection, send, recv), peer))
+}
+
+fn resolve(addr: &str) -> Result<SocketAddr> {
+    addr.to_socket_addrs()?.next().ok_or_else(|| anyhow!("{} does not resolve to an address", addr))
+}
+
+fn runtime() -> Result<Runtime> {
+    Ok(tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build()?)
+}
+
+fn transport_config() -> Result<Arc<TransportConfig>> {
+    let mut transport = TransportConfig::default();
+    transport.max_idle_timeout(Some(IO_TIMEOUT.try_into()?));
+    transport.keep_alive_interval(Some(KEEP_ALIVE));
+    Ok(Arc::new(transport))
+}
+
+impl QuicChannel {
+    fn new(runtime: Runtime, endpoint: Endpoint, connection: Connection, send: SendStream, recv: RecvStream) -> Self {
+        let handle = runtime.handle().clone();
+        Self {
+            send: Some(SyncIoBridge::new_with_handle(send, handle.clone())),
+            recv: SyncIoBridge::new_with_handle(recv, handle),
+            connection,
+            endpoint,
+            runtime,
+        }
+    }
+
+    fn send_stream(&mut self) -> std::io::Result<&mut SyncIoBridge<SendStream>> {
+        self.send.as_mut().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "QUIC stream already closed"))
+    }
+}
+
+impl Read for QuicChannel {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.recv.read(buf)
+    }
+}
+
+impl Write for QuicChannel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.send_stream()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_stream()?.flush()
+    }
+}
+
+impl Channel for QuicChannel {
+    fn finish_sending(&mut self) -> Result<()> {
+        self.send_stream()?.shutdown()?;
+        Ok(())
+    }
+}
+
+impl Drop for QuicChannel {
+    fn drop(&mut self) {
+        // Closing resets streams whose data is still in flight, so first give the
+        // peer a moment to read everything sent
+        if let Some(send) = self.send.take() {
+            let send = send.into_inner();
+            self.runtime.block_on(async {
+                let _ = tokio::time::timeout(CLOSE_TIMEOUT, send.stopped()).await;
+            });
+        }
+        self.connection.close(0u32.into(), b"done");
+        self.runtime.block_on(async {
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, self.endpoint.wait_idle()).await;
+        });
+    }
+}
+
+/// Accepts any certificate: the receiver is authenticated by the KEM handshake
+/// carried inside the stream, not by TLS. Signatures are still checked, so the
+/// TLS handshake itself is sound.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
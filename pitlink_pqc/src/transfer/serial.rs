@@ -0,0 +1,288 @@
+//! Serial transport for `send` / `receive` (`--serial DEVICE --baud RATE`)
+//!
+//! Carries the transfer over a UART, e.g. straight through the pit radio modem.
+//! Bytes are cut into frames that are COBS-encoded and ended by a zero byte, so a
+//! receiver resynchronises at the next zero after line noise:
+//!
+//! ```text
+//! frame  COBS(kind u8 | seq u8 | payload | CRC-32 u32 LE) | 0x00
+//! kind   DATA (payload up to MAX_PAYLOAD bytes), ACK (no payload), FIN (end of sending)
+//! ```
+//!
+//! Each direction is stop-and-wait: a DATA or FIN frame is resent until the peer
+//! acknowledges its sequence number, and frames that fail the CRC are dropped and
+//! left to the resend. Duplicates (the ACK was lost) are acknowledged again and
+//! otherwise ignored.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use tracing::debug;
+
+use super::{Channel, IO_TIMEOUT};
+
+const KIND_DATA: u8 = 1;
+const KIND_ACK: u8 = 2;
+const KIND_FIN: u8 = 3;
+/// Payload bytes per frame; a full frame takes about 22 ms at 115200 baud
+const MAX_PAYLOAD: usize = 240;
+/// Longest a raw frame can be before COBS, plus headroom; longer runs are noise
+const MAX_RAW_FRAME: usize = 2 * (2 + MAX_PAYLOAD + 4);
+/// How long a port read blocks before the resend timer is checked
+const POLL: Duration = Duration::from_millis(100);
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_ATTEMPTS: u32 = 30;
+/// How long a closing link keeps acknowledging, in case its last ACK was lost
+const LINGER: Duration = Duration::from_secs(2);
+
+/// Open `device` at `baud` as a transfer channel
+pub(super) fn open(device: &str, baud: u32) -> Result<SerialLink<Box<dyn serialport::SerialPort>>> {
+    let port = serialport::new(device, baud).timeout(POLL).open().with_context(|| format!("cannot open {}", device))?;
+    Ok(SerialLink::new(port))
+}
+
+/// Reliable byte stream over a lossy serial port. `port` reads must time out
+/// (after about [`POLL`]) rather than block forever.
+pub(super) struct SerialLink<P: Read + Write> {
+    port: P,
+    /// Received bytes not yet split into frames
+    rx: Vec<u8>,
+    /// Written bytes waiting to fill a frame
+    out: Vec<u8>,
+    /// Delivered payload not yet read
+    inbox: Vec<u8>,
+    send_seq: u8,
+    recv_seq: u8,
+    peer_finished: bool,
+}
+
+impl<P: Read + Write> SerialLink<P> {
+    pub(super) fn new(port: P) -> Self {
+        Self { port, rx: Vec::new(), out: Vec::new(), inbox: Vec::new(), send_seq: 0, recv_seq: 0, peer_finished: false }
+    }
+
+    fn send_frame(&mut self, kind: u8, seq: u8, payload: &[u8]) -> Result<()> {
+        let mut raw = vec![kind, seq];
+        raw.extend_from_slice(payload);
+        let crc = crc32fast::hash(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        let mut frame = cobs_encode(&raw);
+        frame.push(0);
+        self.port.write_all(&frame)?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    /// The next intact frame, or `None` when the port read timed out first
+    fn receive_frame(&mut self) -> Result<Option<(u8, u8, Vec<u8>)>> {
+        loop {
+            if let Some(end) = self.rx.iter().position(|&b| b == 0) {
+                let encoded: Vec<u8> = self.rx.drain(..=end).collect();
+                let Some(raw) = cobs_decode(&encoded[..end]) else { continue };
+                if raw.len() < 6 {
+                    continue;
+                }
+                let (body, crc) = raw.split_at(raw.len() - 4);
+                if crc32fast::hash(body).to_le_bytes()[..] != crc[..] {
+                    debug!(target: "io", "dropped a serial frame with a bad CRC");
+                    continue;
+                }
+                return Ok(Some((body[0], body[1], body[2..].to_vec())));
+            }
+            if self.rx.len() > MAX_RAW_FRAME {
+                self.rx.clear();
+            }
+            let mut buf = [0u8; 512];
+            match self.port.read(&mut buf) {
+                Ok(0) => bail!("serial port closed"),
+                Ok(n) => self.rx.extend_from_slice(&buf[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Act on a received frame; returns the sequence number an ACK acknowledges
+    fn take_frame(&mut self, kind: u8, seq: u8, payload: &[u8]) -> Result<Option<u8>> {
+        match kind {
+            KIND_ACK => return Ok(Some(seq)),
+            KIND_DATA | KIND_FIN if seq == self.recv_seq => {
+                if kind == KIND_FIN {
+                    self.peer_finished = true;
+                } else {
+                    self.inbox.extend_from_slice(payload);
+                }
+                self.recv_seq = self.recv_seq.wrapping_add(1);
+                self.send_frame(KIND_ACK, seq, &[])?;
+            }
+            // Our ACK was lost and the peer resent
+            KIND_DATA | KIND_FIN if seq == self.recv_seq.wrapping_sub(1) => self.send_frame(KIND_ACK, seq, &[])?,
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Send one frame and wait until the peer acknowledges it
+    fn transmit(&mut self, kind: u8, payload: &[u8]) -> Result<()> {
+        let seq = self.send_seq;
+        for attempt in 1..=MAX_ATTEMPTS {
+            self.send_frame(kind, seq, payload)?;
+            let deadline = Instant::now() + ACK_TIMEOUT;
+            while Instant::now() < deadline {
+                if let Some((kind, got, payload)) = self.receive_frame()? {
+                    if self.take_frame(kind, got, &payload)? == Some(seq) {
+                        self.send_seq = seq.wrapping_add(1);
+                        return Ok(());
+                    }
+                }
+            }
+            debug!(target: "io", "serial frame {} not acknowledged after attempt {}", seq, attempt);
+        }
+        bail!("serial peer stopped acknowledging frames")
+    }
+
+    fn send_out(&mut self) -> Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        let payload = std::mem::take(&mut self.out);
+        self.transmit(KIND_DATA, &payload)
+    }
+}
+
+fn io_error(e: anyhow::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl<P: Read + Write> Read for SerialLink<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The peer will not answer what it has not been sent
+        self.send_out().map_err(io_error)?;
+        let start = Instant::now();
+        while self.inbox.is_empty() && !self.peer_finished {
+            if start.elapsed() > IO_TIMEOUT {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "serial peer went quiet"));
+            }
+            if let Some((kind, seq, payload)) = self.receive_frame().map_err(io_error)? {
+                self.take_frame(kind, seq, &payload).map_err(io_error)?;
+            }
+        }
+        let n = buf.len().min(self.inbox.len());
+        buf[..n].copy_from_slice(&self.inbox[..n]);
+        self.inbox.drain(..n);
+        Ok(n)
+    }
+}
+
+impl<P: Read + Write> Write for SerialLink<P> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(MAX_PAYLOAD - self.out.len());
+        self.out.extend_from_slice(&data[..n]);
+        if self.out.len() == MAX_PAYLOAD {
+            self.send_out().map_err(io_error)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_out().map_err(io_error)
+    }
+}
+
+impl<P: Read + Write> Channel for SerialLink<P> {
+    fn finish_sending(&mut self) -> Result<()> {
+        self.send_out()?;
+        self.transmit(KIND_FIN, &[])
+    }
+}
+
+impl<P: Read + Write> Drop for SerialLink<P> {
+    fn drop(&mut self) {
+        let until = Instant::now() + LINGER;
+        while Instant::now() < until {
+            match self.receive_frame() {
+                Ok(Some((kind, seq, payload))) => {
+                    let _ = self.take_frame(kind, seq, &payload);
+                }
+                Ok(None) => {}
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// Consistent Overhead Byte Stuffing: `data` without any zero bytes
+fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_at = 0;
+    out.push(0);
+    let mut code = 1u8;
+    for &byte in data {
+        if byte != 0 {
+            out.push(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            out[code_at] = code;
+            code_at = out.len();
+            out.push(0);
+            code = 1;
+        }
+    }
+    out[code_at] = code;
+    out
+}
+
+fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        let end = i + code;
+        if code == 0 || end > data.len() {
+            return None;
+        }
+        out.extend_from_slice(&data[i + 1..end]);
+        i = end;
+        if code < 0xff && i < data.len() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    #[test]
+    fn link_survives_line_noise() {
+        let (mut a, b) = UnixStream::pair().unwrap();
+        for stream in [&a, &b] {
+            stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        }
+        a.write_all(&[7, 0, 3, 1, 2, 0, 0xff, 0xff]).unwrap();
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let expected = data.clone();
+        let peer = std::thread::spawn(move || {
+            let mut link = SerialLink::new(b);
+            let mut received = Vec::new();
+            link.read_to_end(&mut received).unwrap();
+            link.write_all(b"verdict").unwrap();
+            link.finish_sending().unwrap();
+            received
+        });
+        let mut link = SerialLink::new(a);
+        link.write_all(&data).unwrap();
+        link.finish_sending().unwrap();
+        let mut reply = Vec::new();
+        link.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"verdict");
+        assert_eq!(peer.join().unwrap(), expected);
+        assert_eq!(cobs_decode(&cobs_encode(&[0, 0, 1, 0])).unwrap(), [0, 0, 1, 0]);
+    }
+}
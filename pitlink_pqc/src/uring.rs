@@ -0,0 +1,249 @@
+//! io_uring file I/O for Linux builds with the `io-uring` feature
+//!
+//! Reads run ahead of the consumer and writes are queued behind the producer,
+//! up to `QUEUE_DEPTH` blocks at a time, so the kernel works on the next blocks
+//! while the current one is being encrypted.
+//!
+//! A buffer handed to the kernel stays owned by the in-flight queue until its
+//! completion has been reaped. If waiting for a completion fails, the buffer is
+//! leaked rather than freed under the kernel.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+/// Operations in flight per file
+const QUEUE_DEPTH: usize = 8;
+/// Bytes per read or write operation
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+struct Ring {
+    ring: IoUring,
+    next_id: u64,
+    /// Results reaped while waiting for another operation
+    done: HashMap<u64, i32>,
+}
+
+impl Ring {
+    fn new() -> io::Result<Self> {
+        Ok(Self { ring: IoUring::new(QUEUE_DEPTH as u32)?, next_id: 0, done: HashMap::new() })
+    }
+
+    /// Queue an operation without submitting it
+    ///
+    /// # Safety
+    /// The buffer referenced by `entry` must stay valid until `wait` has returned
+    /// the operation's result.
+    unsafe fn push(&mut self, entry: squeue::Entry) -> io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        // Never more than QUEUE_DEPTH operations are in flight, so there is room
+        self.ring.submission().push(&entry.user_data(id)).map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        Ok(id)
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    /// Wait for the result of operation `id` (a byte count or a negated errno)
+    fn wait(&mut self, id: u64) -> io::Result<i32> {
+        loop {
+            if let Some(res) = self.done.remove(&id) {
+                return Ok(res);
+            }
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            for cqe in self.ring.completion() {
+                self.done.insert(cqe.user_data(), cqe.result());
+            }
+        }
+    }
+}
+
+/// Sequential reader of a regular file with read-ahead
+pub struct UringReader {
+    ring: Ring,
+    file: File,
+    /// Read up to here; the length when the file was opened
+    len: u64,
+    next_offset: u64,
+    /// `(id, offset, requested length, buffer)` in file order
+    inflight: VecDeque<(u64, u64, usize, Box<[u8]>)>,
+    current: Option<Box<[u8]>>,
+    pos: usize,
+    filled: usize,
+    spare: Vec<Box<[u8]>>,
+}
+
+impl UringReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let ring = Ring::new()?;
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = Self { ring, file, len, next_offset: 0, inflight: VecDeque::new(), current: None, pos: 0, filled: 0, spare: Vec::new() };
+        reader.fill_queue()?;
+        Ok(reader)
+    }
+
+    fn fill_queue(&mut self) -> io::Result<()> {
+        let mut queued = false;
+        while self.inflight.len() < QUEUE_DEPTH && self.next_offset < self.len {
+            let want = (self.len - self.next_offset).min(BLOCK_SIZE as u64) as usize;
+            let mut buf = self.spare.pop().unwrap_or_else(|| vec![0u8; BLOCK_SIZE].into_boxed_slice());
+            let entry = opcode::Read::new(types::Fd(self.file.as_raw_fd()), buf.as_mut_ptr(), want as u32).offset(self.next_offset).build();
+            // SAFETY: the buffer moves into `inflight` and is only released after its completion
+            let id = unsafe { self.ring.push(entry)? };
+            self.inflight.push_back((id, self.next_offset, want, buf));
+            self.next_offset += want as u64;
+            queued = true;
+        }
+        if queued { self.ring.submit()?; }
+        Ok(())
+    }
+
+    /// Wait out every read in flight, discarding its data
+    fn drain(&mut self) {
+        while let Some((id, _, _, buf)) = self.inflight.pop_front() {
+            match self.ring.wait(id) {
+                Ok(_) => self.spare.push(buf),
+                Err(_) => std::mem::forget(buf),
+            }
+        }
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.filled {
+            let Some((id, offset, want, buf)) = self.inflight.pop_front() else { return Ok(0) };
+            let res = match self.ring.wait(id) {
+                Ok(res) => res,
+                Err(e) => {
+                    std::mem::forget(buf);
+                    return Err(e);
+                }
+            };
+            if res < 0 {
+                self.spare.push(buf);
+                self.drain();
+                return Err(io::Error::from_raw_os_error(-res));
+            }
+            let n = res as usize;
+            if let Some(old) = self.current.replace(buf) { self.spare.push(old); }
+            self.pos = 0;
+            self.filled = n;
+            if n < want {
+                // Short read: drop the read-ahead and continue right after the data received
+                self.drain();
+                if n == 0 {
+                    // The file shrank; stop here like a plain read would
+                    self.len = offset;
+                    return Ok(0);
+                }
+                self.next_offset = offset + n as u64;
+            }
+            self.fill_queue()?;
+        }
+        let current = self.current.as_ref().expect("a filled buffer");
+        let n = out.len().min(self.filled - self.pos);
+        out[..n].copy_from_slice(&current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        self.drain();
+    }
+}
+
+/// Sequential writer that queues full blocks to the kernel
+pub struct UringWriter {
+    ring: Ring,
+    file: File,
+    offset: u64,
+    /// Block being filled
+    buf: Vec<u8>,
+    /// `(id, offset, block)` in submission order
+    inflight: VecDeque<(u64, u64, Vec<u8>)>,
+    spare: Vec<Vec<u8>>,
+}
+
+impl UringWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let ring = Ring::new()?;
+        let file = File::create(path)?;
+        Ok(Self { ring, file, offset: 0, buf: Vec::with_capacity(BLOCK_SIZE), inflight: VecDeque::new(), spare: Vec::new() })
+    }
+
+    fn submit_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() { return Ok(()); }
+        if self.inflight.len() >= QUEUE_DEPTH { self.complete_one()?; }
+        let next = self.spare.pop().unwrap_or_else(|| Vec::with_capacity(BLOCK_SIZE));
+        let block = std::mem::replace(&mut self.buf, next);
+        let entry = opcode::Write::new(types::Fd(self.file.as_raw_fd()), block.as_ptr(), block.len() as u32).offset(self.offset).build();
+        // SAFETY: the block moves into `inflight` and is only released after its completion
+        let id = unsafe { self.ring.push(entry)? };
+        self.offset += block.len() as u64;
+        self.inflight.push_back((id, self.offset - block.len() as u64, block));
+        self.ring.submit()
+    }
+
+    /// Reap the oldest write, finishing it synchronously if the kernel wrote less
+    fn complete_one(&mut self) -> io::Result<()> {
+        let Some((id, offset, mut block)) = self.inflight.pop_front() else { return Ok(()) };
+        let res = match self.ring.wait(id) {
+            Ok(res) => res,
+            Err(e) => {
+                std::mem::forget(block);
+                return Err(e);
+            }
+        };
+        if res < 0 { return Err(io::Error::from_raw_os_error(-res)); }
+        let n = res as usize;
+        if n < block.len() {
+            self.file.write_all_at(&block[n..], offset + n as u64)?;
+        }
+        block.clear();
+        self.spare.push(block);
+        Ok(())
+    }
+}
+
+impl Write for UringWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(BLOCK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == BLOCK_SIZE { self.submit_block()?; }
+        Ok(n)
+    }
+
+    /// Submit the partial block and wait for every write to land
+    fn flush(&mut self) -> io::Result<()> {
+        self.submit_block()?;
+        while !self.inflight.is_empty() {
+            self.complete_one()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UringWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        while let Some((id, _, block)) = self.inflight.pop_front() {
+            if self.ring.wait(id).is_err() { std::mem::forget(block); }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * pitlink_pqc C API
+ *
+ * Post-quantum file encryption (ML-KEM + XChaCha20-Poly1305), producing the same
+ * RKPQ1 packages as the rust_pqc CLI. Link against libpitlink_pqc_ffi (cdylib or
+ * staticlib) built with `cargo build --release -p pitlink_pqc_ffi`.
+ *
+ * Every function returns a PITLINK_* status code. After a failure,
+ * pitlink_last_error() describes it; the message belongs to the calling thread
+ * and stays valid until that thread's next call.
+ *
+ * Buffers filled by the library must be released with pitlink_buffer_free().
+ * Pass output buffers empty ({NULL, 0}) or freed; a filled buffer is overwritten.
+ */
+
+#ifndef PITLINK_PQC_H
+#define PITLINK_PQC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PITLINK_OK 0
+/* A null pointer, a path that is not UTF-8 or an out-of-range argument */
+#define PITLINK_ERR_ARGUMENT -1
+/* Key generation, encryption or decryption failed (including authentication) */
+#define PITLINK_ERR_FAILED -2
+/* The library panicked; this is a bug */
+#define PITLINK_ERR_PANIC -3
+
+/* Bytes owned by the library */
+typedef struct pitlink_buffer {
+    uint8_t *data;
+    size_t len;
+} pitlink_buffer;
+
+/* Library version, e.g. "0.1.0" (static storage) */
+const char *pitlink_version(void);
+
+/* Message for the last failed call on this thread, or NULL after a success */
+const char *pitlink_last_error(void);
+
+/* Release a buffer filled by the library and reset it to {NULL, 0}; NULL is ignored */
+void pitlink_buffer_free(pitlink_buffer *buf);
+
+/*
+ * Generate an ML-KEM keypair at level 512, 768 or 1024, or with hybrid != 0 the
+ * X25519 + Kyber-768 hybrid (level 768 only). The buffers receive key file bytes
+ * in the format the CLI reads and writes.
+ */
+int pitlink_keygen(uint16_t level, int hybrid, pitlink_buffer *public_key, pitlink_buffer *private_key);
+
+/* Encrypt plaintext to a public key (key file bytes) into a package */
+int pitlink_encrypt(const uint8_t *plaintext, size_t plaintext_len,
+                    const uint8_t *public_key, size_t public_key_len,
+                    pitlink_buffer *package);
+
+/*
+ * Decrypt a package with a private key (key file bytes). The plaintext is only
+ * returned once the whole package has authenticated.
+ */
+int pitlink_decrypt(const uint8_t *package, size_t package_len,
+                    const uint8_t *private_key, size_t private_key_len,
+                    pitlink_buffer *plaintext);
+
+/* Encrypt the file at input to a public key file, replacing output */
+int pitlink_encrypt_file(const char *input, const char *output, const char *public_key_path);
+
+/*
+ * Decrypt the package at input with a private key file (which must not be
+ * readable by other users), replacing output once the package has authenticated
+ */
+int pitlink_decrypt_file(const char *input, const char *output, const char *private_key_path);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PITLINK_PQC_H */
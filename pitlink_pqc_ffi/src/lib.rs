@@ -0,0 +1,243 @@
+//! C bindings for pitlink_pqc
+//!
+//! `include/pitlink_pqc.h` declares everything exported here. Every function
+//! returns a `PITLINK_*` status code; on failure `pitlink_last_error` describes
+//! the error on the calling thread. Buffers handed out by the library are released
+//! with `pitlink_buffer_free`. Panics never cross the boundary.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use pitlink_pqc::keyfile::{KeyFile, KeyKind, KeyMetadata};
+use pitlink_pqc::{DecryptOptions, EncryptOptions};
+
+pub const PITLINK_OK: c_int = 0;
+/// A null pointer, a path that is not UTF-8 or an out-of-range argument
+pub const PITLINK_ERR_ARGUMENT: c_int = -1;
+/// Key generation, encryption or decryption failed (including authentication)
+pub const PITLINK_ERR_FAILED: c_int = -2;
+/// The library panicked; this is a bug
+pub const PITLINK_ERR_PANIC: c_int = -3;
+
+/// Bytes owned by the library
+#[repr(C)]
+pub struct PitlinkBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl PitlinkBuffer {
+    fn from_vec(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Self { data, len }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+enum FfiError {
+    Argument(&'static str),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for FfiError {
+    fn from(e: anyhow::Error) -> Self {
+        FfiError::Failed(e)
+    }
+}
+
+fn set_last_error(msg: Option<String>) {
+    let msg = msg.map(|m| CString::new(m.replace('\0', " ")).expect("NUL bytes replaced"));
+    LAST_ERROR.with(|e| *e.borrow_mut() = msg);
+}
+
+/// Run `f`, turning its result or panic into a status code
+fn guard(f: impl FnOnce() -> Result<(), FfiError>) -> c_int {
+    let (code, msg) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (PITLINK_OK, None),
+        Ok(Err(FfiError::Argument(msg))) => (PITLINK_ERR_ARGUMENT, Some(msg.to_string())),
+        Ok(Err(FfiError::Failed(e))) => (PITLINK_ERR_FAILED, Some(format!("{:#}", e))),
+        Err(_) => (PITLINK_ERR_PANIC, Some("internal error (panic)".to_string())),
+    };
+    set_last_error(msg);
+    code
+}
+
+/// # Safety
+/// `ptr` must be null or point to `len` readable bytes that outlive `'a`.
+unsafe fn input<'a>(ptr: *const u8, len: usize, what: &'static str) -> Result<&'a [u8], FfiError> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(FfiError::Argument(what)),
+        (false, _) => Ok(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// # Safety
+/// `ptr` must be null or a NUL-terminated string.
+unsafe fn path(ptr: *const c_char, what: &'static str) -> Result<PathBuf, FfiError> {
+    if ptr.is_null() {
+        return Err(FfiError::Argument(what));
+    }
+    let s = CStr::from_ptr(ptr).to_str().map_err(|_| FfiError::Argument(what))?;
+    Ok(PathBuf::from(s))
+}
+
+/// # Safety
+/// `ptr` must be null or point to a writable `PitlinkBuffer`.
+unsafe fn output<'a>(ptr: *mut PitlinkBuffer, what: &'static str) -> Result<&'a mut PitlinkBuffer, FfiError> {
+    ptr.as_mut().ok_or(FfiError::Argument(what))
+}
+
+/// Library version as a static NUL-terminated string
+#[no_mangle]
+pub extern "C" fn pitlink_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Message for the last failed call on this thread, or null after a success. The
+/// string stays valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn pitlink_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(std::ptr::null(), |m| m.as_ptr()))
+}
+
+/// Release a buffer filled by the library and reset it to empty
+///
+/// # Safety
+/// `buf` must be null or point to a buffer filled by this library (or zeroed).
+#[no_mangle]
+pub unsafe extern "C" fn pitlink_buffer_free(buf: *mut PitlinkBuffer) {
+    let Some(buf) = buf.as_mut() else { return };
+    if !buf.data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(buf.data, buf.len)));
+    }
+    buf.data = std::ptr::null_mut();
+    buf.len = 0;
+}
+
+/// Generate an ML-KEM keypair at `level` (512, 768 or 1024), or the X25519 +
+/// Kyber-768 hybrid with `hybrid` set (level 768 only), as key file bytes
+///
+/// # Safety
+/// `public_key` and `private_key` must point to writable buffers.
+#[no_mangle]
+pub unsafe extern "C" fn pitlink_keygen(level: u16, hybrid: c_int, public_key: *mut PitlinkBuffer, private_key: *mut PitlinkBuffer) -> c_int {
+    guard(|| {
+        let public_out = output(public_key, "public_key is null")?;
+        let private_out = output(private_key, "private_key is null")?;
+        let kem = pitlink_pqc::select_kem(level, false, hybrid != 0)?;
+        let (public, private) = pitlink_pqc::generate_keypair(kem, None, KeyMetadata::now(None, None))?;
+        *public_out = PitlinkBuffer::from_vec(public.to_bytes());
+        *private_out = PitlinkBuffer::from_vec(private.to_bytes());
+        Ok(())
+    })
+}
+
+/// Encrypt `plaintext` to the public key file bytes in `public_key`, returning the
+/// package in `package`
+///
+/// # Safety
+/// `plaintext` and `public_key` must point to their lengths in readable bytes (or be
+/// null with length 0); `package` must point to a writable buffer.
+#[no_mangle]
+pub unsafe extern "C" fn pitlink_encrypt(plaintext: *const u8, plaintext_len: usize, public_key: *const u8, public_key_len: usize, package: *mut PitlinkBuffer) -> c_int {
+    guard(|| {
+        let plaintext = input(plaintext, plaintext_len, "plaintext is null")?;
+        let public_key = input(public_key, public_key_len, "public_key is null")?;
+        let package = output(package, "package is null")?;
+        let recipient = KeyFile::from_bytes(public_key, KeyKind::Public)?;
+        let mut out = Vec::new();
+        pitlink_pqc::encrypt(plaintext, &mut out, &recipient, None, &EncryptOptions::default())?;
+        *package = PitlinkBuffer::from_vec(out);
+        Ok(())
+    })
+}
+
+/// Decrypt `package` with the private key file bytes in `private_key`. The
+/// plaintext is only returned once the whole package has authenticated.
+///
+/// # Safety
+/// `package` and `private_key` must point to their lengths in readable bytes;
+/// `plaintext` must point to a writable buffer.
+#[no_mangle]
+pub unsafe extern "C" fn pitlink_decrypt(package: *const u8, package_len: usize, private_key: *const u8, private_key_len: usize, plaintext: *mut PitlinkBuffer) -> c_int {
+    guard(|| {
+        let package = input(package, package_len, "package is null")?;
+        let private_key = input(private_key, private_key_len, "private_key is null")?;
+        let plaintext = output(plaintext, "plaintext is null")?;
+        let identity = KeyFile::from_bytes(private_key, KeyKind::Private)?;
+        let mut out = Vec::new();
+        pitlink_pqc::decrypt(package, &mut out, &identity, None)?;
+        *plaintext = PitlinkBuffer::from_vec(out);
+        Ok(())
+    })
+}
+
+/// Encrypt the file at `input` to the public key file at `public_key_path`,
+/// replacing `output`
+///
+/// # Safety
+/// All arguments must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn pitlink_encrypt_file(input: *const c_char, output: *const c_char, public_key_path: *const c_char) -> c_int {
+    guard(|| {
+        let (input, output) = (path(input, "input is null or not UTF-8")?, path(output, "output is null or not UTF-8")?);
+        let public_key_path = path(public_key_path, "public_key_path is null or not UTF-8")?;
+        pitlink_pqc::encrypt_file(input, output, public_key_path, EncryptOptions { force: true, ..Default::default() })?;
+        Ok(())
+    })
+}
+
+/// Decrypt the package at `input` with the private key file at `private_key_path`,
+/// replacing `output` only once the package has fully authenticated
+///
+/// # Safety
+/// All arguments must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn pitlink_decrypt_file(input: *const c_char, output: *const c_char, private_key_path: *const c_char) -> c_int {
+    guard(|| {
+        let (input, output) = (path(input, "input is null or not UTF-8")?, path(output, "output is null or not UTF-8")?);
+        let private_key_path = path(private_key_path, "private_key_path is null or not UTF-8")?;
+        pitlink_pqc::decrypt_file(input, output, private_key_path, DecryptOptions { force: true, ..Default::default() })?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> PitlinkBuffer {
+        PitlinkBuffer { data: std::ptr::null_mut(), len: 0 }
+    }
+
+    #[test]
+    fn buffer_roundtrip() {
+        let (mut public, mut private, mut package, mut plaintext) = (empty(), empty(), empty(), empty());
+        let msg = b"collector sample";
+        unsafe {
+            assert_eq!(pitlink_keygen(768, 0, &mut public, &mut private), PITLINK_OK);
+            assert_eq!(pitlink_encrypt(msg.as_ptr(), msg.len(), public.data, public.len, &mut package), PITLINK_OK);
+            assert_eq!(pitlink_decrypt(package.data, package.len, private.data, private.len, &mut plaintext), PITLINK_OK);
+            assert_eq!(std::slice::from_raw_parts(plaintext.data, plaintext.len), msg);
+            assert!(pitlink_last_error().is_null());
+
+            // The public key cannot decrypt, and the reason is reported
+            pitlink_buffer_free(&mut plaintext);
+            assert_eq!(pitlink_decrypt(package.data, package.len, public.data, public.len, &mut plaintext), PITLINK_ERR_FAILED);
+            assert!(!pitlink_last_error().is_null());
+            assert!(plaintext.data.is_null());
+
+            assert_eq!(pitlink_encrypt(std::ptr::null(), 1, public.data, public.len, &mut package), PITLINK_ERR_ARGUMENT);
+            for buf in [&mut public, &mut private, &mut package, &mut plaintext] {
+                pitlink_buffer_free(buf);
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! WebAssembly bindings for pitlink packages
+//!
+//! Exposes ML-KEM keygen, encrypt and decrypt over byte arrays to JavaScript via
+//! wasm-bindgen, so a browser can open small packages (such as dashboard
+//! diagnostics) client-side. Keys are key file bytes and packages are RKPQ1 v2,
+//! both interchangeable with the `rust_pqc` CLI; see [`package`] for what is
+//! supported. Build with `wasm-pack build pitlink_pqc_wasm --target web`.
+
+use wasm_bindgen::prelude::*;
+
+pub mod package;
+
+fn js_error(e: anyhow::Error) -> JsError {
+    JsError::new(&format!("{:#}", e))
+}
+
+/// A freshly generated keypair, as key file bytes
+#[wasm_bindgen]
+pub struct KeyPair {
+    public_key: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl KeyPair {
+    #[wasm_bindgen(getter, js_name = publicKey)]
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public_key.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = privateKey)]
+    pub fn private_key(&self) -> Vec<u8> {
+        self.private_key.clone()
+    }
+}
+
+/// Generate an ML-KEM keypair at `level` (512, 768 or 1024)
+#[wasm_bindgen]
+pub fn keygen(level: u16) -> Result<KeyPair, JsError> {
+    let (public_key, private_key) = package::generate_keypair(level).map_err(js_error)?;
+    Ok(KeyPair { public_key, private_key })
+}
+
+/// Encrypt `plaintext` to a public key file, returning the package
+#[wasm_bindgen]
+pub fn encrypt(plaintext: &[u8], public_key: &[u8]) -> Result<Vec<u8>, JsError> {
+    package::encrypt(plaintext, public_key).map_err(js_error)
+}
+
+/// Decrypt a package with a private key file. Throws unless the whole package
+/// authenticates.
+#[wasm_bindgen]
+pub fn decrypt(package: &[u8], private_key: &[u8]) -> Result<Vec<u8>, JsError> {
+    package::decrypt(package, private_key).map_err(js_error)
+}
@@ -0,0 +1,426 @@
+//! Keygen, encrypt and decrypt over byte slices, in pure Rust
+//!
+//! A sequential, in-memory implementation of the RKPQ1 v2 package format for
+//! ML-KEM recipients. Packages written here match what `rust_pqc encrypt` writes
+//! for a file: XChaCha20-Poly1305 chunks, a Merkle tree, the plaintext length and
+//! the trailer. Reading accepts any v2 ML-KEM package; the sender signature frame
+//! is authenticated but not verified (the signature schemes need pqcrypto), and
+//! the chunk index of a seekable package is checked but not used for range reads.
+//! File metadata (`encrypt --store-metadata`) is authenticated and skipped.
+//! Frames, key files and their limits come from `common`, as in the native
+//! library; only the reading order and the pure-Rust ciphers live here.
+//!
+//! Kyber round-3 keys share the ML-KEM encoding, so they can encrypt to and open
+//! ML-KEM packages. Kyber round-3 and hybrid packages need the native library.
+
+use anyhow::{anyhow, bail, Result};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{Ciphertext, Encoded, EncodedSizeUser, KemCore, MlKem1024, MlKem512, MlKem768};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use common::container::{
+    chunk_aad, merkle_key, ChunkKdf, read_merkle_leaves, trailer_key, AeadId, ChunkFrame, ChunkIndex, FileMetadata, Header, KemId,
+    PackageMac, Trailer, CHUNK_FLAG_FINAL, CHUNK_FLAG_INDEX, CHUNK_FLAG_METADATA, EXT_METADATA, EXT_NONCE_PREFIX,
+    EXT_SIGNATURE, EXT_TRAILER, MAX_SIGNATURE_FRAME, METADATA_SEQ, NONCE_COUNTER_LEN, RECIPIENT_HINT_SALT_LEN,
+};
+use common::error::PitlinkError;
+use common::keyfile::{KeyEnvelope, KeyKind};
+use common::merkle::{self, MerkleTree};
+use common::{hkdf_derive, CHUNK_SIZE};
+
+/// Generate an ML-KEM keypair at `level` (512, 768 or 1024), returning
+/// `(public, private)` key file bytes
+pub fn generate_keypair(level: u16) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (kem, (public, private)) = match level {
+        512 => (KemId::MlKem512, keypair_with::<MlKem512>()),
+        768 => (KemId::MlKem768, keypair_with::<MlKem768>()),
+        1024 => (KemId::MlKem1024, keypair_with::<MlKem1024>()),
+        other => bail!("unsupported ML-KEM level {} (expected 512, 768 or 1024)", other),
+    };
+    Ok((encode_key(KeyKind::Public, kem, &public), encode_key(KeyKind::Private, kem, &private)))
+}
+
+/// Encrypt `plaintext` to the public key file bytes in `public_key`
+pub fn encrypt(plaintext: &[u8], public_key: &[u8]) -> Result<Vec<u8>> {
+    let (key_kem, key) = decode_key(public_key, KeyKind::Public)?;
+    let kem = ml_kem_for(key_kem)?;
+    let (kem_ct, shared) = match kem {
+        KemId::MlKem512 => encapsulate_with::<MlKem512>(key)?,
+        KemId::MlKem768 => encapsulate_with::<MlKem768>(key)?,
+        _ => encapsulate_with::<MlKem1024>(key)?,
+    };
+    let kek = hkdf_derive(&shared, kem.kek_label(), 32)?;
+
+    let aead = AeadId::XChaCha20Poly1305;
+    let file_key = Zeroizing::new(random_bytes(32)?);
+    let mut header = Header::new(kem, aead, kem_ct, random_bytes(aead.nonce_len())?);
+    header.set_extension(EXT_NONCE_PREFIX, random_bytes(aead.nonce_len() - NONCE_COUNTER_LEN)?);
+    header.set_recipient_hint(&random_bytes(RECIPIENT_HINT_SALT_LEN)?, key);
+    header.set_extension(EXT_TRAILER, Vec::new());
+    header.set_chunk_size(CHUNK_SIZE);
+    header.set_chunk_kdf(ChunkKdf::HkdfSha256);
+
+    // Full chunks followed by a short (possibly empty) final one
+    let chunks: Vec<&[u8]> = plaintext.chunks(CHUNK_SIZE).chain((plaintext.len() % CHUNK_SIZE == 0).then_some(&plaintext[..0])).collect();
+    let merkle_key = merkle_key(&file_key)?;
+    let tree = MerkleTree::from_leaves(&merkle_key, chunks.iter().map(|chunk| merkle::leaf_hash(&merkle_key, chunk)).collect())?;
+    header.set_merkle_root(&tree.root(), chunks.len() as u64);
+    header.set_plaintext_len(plaintext.len() as u64);
+
+    let wrap_aad = header.wrap_aad();
+    header.wrapped_key = Cipher::new(aead, &kek)?.encrypt(&header.wrap_nonce, &file_key, &wrap_aad).map_err(|e| anyhow!("key wrap: {}", e))?;
+    let header_bytes = header.to_bytes();
+    let header_hash = Sha256::digest(&header_bytes);
+
+    let mut out = Vec::with_capacity(header_bytes.len() + chunks.len() * (32 + 5 + aead.tag_len()) + plaintext.len() + 64);
+    out.extend_from_slice(&header_bytes);
+    tree.leaves().iter().for_each(|leaf| out.extend_from_slice(leaf));
+    for (seq, chunk) in chunks.iter().enumerate() {
+        let seq = seq as u64;
+        let flags = if seq + 1 == chunks.len() as u64 { CHUNK_FLAG_FINAL } else { 0 };
+        let nonce = header.chunk_nonce(seq).expect("nonce prefix set above");
+        let ciphertext = frame_cipher(&header, &file_key, seq)?.encrypt(&nonce, chunk, &chunk_aad(&header_hash, seq, flags)).map_err(|e| anyhow!("chunk {}: {}", seq, e))?;
+        ChunkFrame { nonce: Vec::new(), flags, ciphertext }.write_to(&mut out)?;
+    }
+
+    // The trailer MAC covers every byte written before it
+    let mut mac = PackageMac::new(&trailer_key(&file_key)?);
+    mac.update(&out);
+    mac.finish(plaintext.len() as u64, chunks.len() as u64).write_to(&mut out)?;
+    Ok(out)
+}
+
+/// Decrypt `package` with the private key file bytes in `private_key`. The
+/// plaintext is only returned once the whole package has authenticated.
+pub fn decrypt(package: &[u8], private_key: &[u8]) -> Result<Vec<u8>> {
+    let (key_kem, key) = decode_key(private_key, KeyKind::Private)?;
+    let kem = ml_kem_for(key_kem)?;
+    let mut reader = package;
+    let (header, header_bytes) = Header::read_from(&mut reader)?;
+    if !matches!(header.kem, KemId::MlKem512 | KemId::MlKem768 | KemId::MlKem1024) {
+        bail!("package uses {:?}; only ML-KEM packages can be opened here", header.kem);
+    }
+    if header.kem != kem {
+        bail!("package uses {:?} but private key is {:?}", header.kem, key_kem);
+    }
+
+    let shared = match kem {
+        KemId::MlKem512 => decapsulate_with::<MlKem512>(key, &header.kem_ct)?,
+        KemId::MlKem768 => decapsulate_with::<MlKem768>(key, &header.kem_ct)?,
+        _ => decapsulate_with::<MlKem1024>(key, &header.kem_ct)?,
+    };
+    if header.has_recipient_hint() && !header.matches_recipient(embedded_public(key))? {
+        bail!(PitlinkError::WrongRecipient);
+    }
+    let kek = hkdf_derive(&shared, kem.kek_label(), 32)?;
+    let file_key = Zeroizing::new(
+        Cipher::new(header.aead, &kek)?
+            .decrypt(&header.wrap_nonce, &header.wrapped_key, &header.wrap_aad())
+            .map_err(|e| anyhow!("AEAD unwrap error: {}", e))?,
+    );
+    let header_hash = Sha256::digest(&header_bytes);
+
+    let mut mac = match header.extension(EXT_TRAILER) {
+        Some(_) => Some(PackageMac::new(&trailer_key(&file_key)?)),
+        None => None,
+    };
+    if let Some(mac) = &mut mac {
+        mac.update(&header_bytes);
+    }
+
+    // The leaf table must reproduce the root authenticated by the header
+    let merkle = match header.merkle_root()? {
+        Some((root, count)) => {
+            if count > (reader.len() / 32) as u64 {
+                return Err(anyhow::Error::new(PitlinkError::TruncatedPackage).context("in the Merkle leaf table"));
+            }
+            let key = merkle_key(&file_key)?;
+            let tree = MerkleTree::from_leaves(&key, read_merkle_leaves(&mut reader, count)?)?;
+            if tree.root() != root {
+                bail!("Merkle leaf table does not match the header root");
+            }
+            if let Some(mac) = &mut mac {
+                tree.leaves().iter().for_each(|leaf| mac.update(leaf));
+            }
+            Some((key, tree))
+        }
+        None => None,
+    };
+    if header.extension(EXT_METADATA).is_some() {
+        let frame = ChunkFrame::read_from(&mut reader, &header, header.max_metadata_frame_len())?
+            .filter(|f| f.flags == CHUNK_FLAG_METADATA)
+            .ok_or_else(|| anyhow::Error::new(PitlinkError::TruncatedPackage).context("missing file metadata"))?;
+        if let Some(mac) = &mut mac {
+            frame.write_to(mac)?;
+        }
+        let nonce = header.chunk_nonce(METADATA_SEQ).unwrap_or_else(|| frame.nonce.clone());
+        let bytes = frame_cipher(&header, &file_key, METADATA_SEQ)?
+            .decrypt(&nonce, &frame.ciphertext, &chunk_aad(&header_hash, METADATA_SEQ, frame.flags))
+            .map_err(|_| anyhow!("AEAD decrypt failed for the file metadata"))?;
+        FileMetadata::from_bytes(&bytes)?;
+    }
+
+    let max_chunk = header.max_frame_len()?;
+    let (chunk_size, compression) = (header.chunk_size()?, header.compression()?);
+    let mut unpacked = Vec::new();
+    let expected_len = header.plaintext_len()?;
+    let mut plaintext = Vec::with_capacity(expected_len.unwrap_or(0).min(package.len() as u64) as usize);
+    let mut seq = 0u64;
+    loop {
+        let frame = ChunkFrame::read_from(&mut reader, &header, max_chunk)?
+            .ok_or_else(|| anyhow::Error::new(PitlinkError::TruncatedPackage).context(format!("missing final chunk after {} chunks", seq)))?;
+        if frame.is_signature() {
+            bail!("unexpected signature frame at chunk {}", seq);
+        }
+        if let Some(mac) = &mut mac {
+            frame.write_to(mac)?;
+        }
+        let nonce = header.chunk_nonce(seq).unwrap_or_else(|| frame.nonce.clone());
+        let pt = frame_cipher(&header, &file_key, seq)?
+            .decrypt(&nonce, &frame.ciphertext, &chunk_aad(&header_hash, seq, frame.flags))
+            .map_err(|_| PitlinkError::ChunkAuthFailed { index: seq })?;
+        let pt = match compression {
+            Some(compression) => {
+                compression.decompress_into(&pt, chunk_size, &mut unpacked)?;
+                &unpacked[..]
+            }
+            None => &pt[..],
+        };
+        if let Some((key, tree)) = &merkle {
+            if tree.leaves().get(seq as usize) != Some(&merkle::leaf_hash(key, pt)) {
+                bail!("chunk {} does not match its Merkle leaf", seq);
+            }
+        }
+        plaintext.extend_from_slice(pt);
+        seq += 1;
+        if frame.is_final() {
+            break;
+        }
+    }
+
+    let plaintext_len = plaintext.len() as u64;
+    if let Some(len) = expected_len.filter(|&len| len != plaintext_len) {
+        bail!("package holds {} plaintext bytes but its header records {}", plaintext_len, len);
+    }
+    if let Some((_, tree)) = &merkle {
+        if tree.leaves().len() as u64 != seq {
+            bail!("package has {} chunks but its Merkle tree has {} leaves", seq, tree.leaves().len());
+        }
+    }
+    if header.extension(EXT_SIGNATURE).is_some() {
+        let frame = ChunkFrame::read_from(&mut reader, &header, MAX_SIGNATURE_FRAME)?
+            .filter(|f| f.is_signature())
+            .ok_or_else(|| anyhow::Error::new(PitlinkError::TruncatedPackage).context("missing sender signature"))?;
+        if let Some(mac) = &mut mac {
+            frame.write_to(mac)?;
+        }
+        let nonce = header.chunk_nonce(seq).unwrap_or_else(|| frame.nonce.clone());
+        frame_cipher(&header, &file_key, seq)?
+            .decrypt(&nonce, &frame.ciphertext, &chunk_aad(&header_hash, seq, frame.flags))
+            .map_err(|_| anyhow!("AEAD decrypt failed for the sender signature"))?;
+    }
+    if header.is_seekable() {
+        let frame = ChunkFrame::read_from(&mut reader, &header, header.max_index_frame_len(seq))?
+            .filter(|f| f.flags == CHUNK_FLAG_INDEX)
+            .ok_or_else(|| anyhow::Error::new(PitlinkError::TruncatedPackage).context("missing chunk index"))?;
+        if let Some(mac) = &mut mac {
+            frame.write_to(mac)?;
+        }
+        let index_seq = header.index_seq(seq);
+        let nonce = header.chunk_nonce(index_seq).unwrap_or_else(|| frame.nonce.clone());
+        let index_bytes = frame_cipher(&header, &file_key, index_seq)?
+            .decrypt(&nonce, &frame.ciphertext, &chunk_aad(&header_hash, index_seq, frame.flags))
+            .map_err(|_| anyhow!("AEAD decrypt failed for the chunk index"))?;
+        let index = ChunkIndex::from_bytes(&index_bytes)?;
+        if index.entries.len() as u64 != seq || index.plaintext_len != plaintext_len {
+            bail!("chunk index records {} bytes in {} chunks but {} bytes in {} chunks were read", index.plaintext_len, index.entries.len(), plaintext_len, seq);
+        }
+    }
+    if let Some(mac) = mac {
+        let trailer = Trailer::read_from(&mut reader)?;
+        if trailer.plaintext_len != plaintext_len || trailer.chunk_count != seq {
+            bail!("package trailer records {} bytes in {} chunks but {} bytes in {} chunks were read",
+                trailer.plaintext_len, trailer.chunk_count, plaintext_len, seq);
+        }
+        if !mac.verify(&trailer) {
+            bail!("package trailer MAC mismatch: package modified or spliced");
+        }
+    }
+    if !reader.is_empty() {
+        bail!("trailing data after final chunk");
+    }
+    Ok(plaintext)
+}
+
+/// The ML-KEM parameter set that keys of `kem` work with
+fn ml_kem_for(kem: KemId) -> Result<KemId> {
+    match kem {
+        KemId::MlKem512 | KemId::Kyber512 => Ok(KemId::MlKem512),
+        KemId::MlKem768 | KemId::Kyber768 => Ok(KemId::MlKem768),
+        KemId::MlKem1024 | KemId::Kyber1024 => Ok(KemId::MlKem1024),
+        other => bail!("{:?} keys need the native library; only ML-KEM keys are supported here", other),
+    }
+}
+
+fn keypair_with<K: KemCore>() -> (Vec<u8>, Vec<u8>) {
+    let (dk, ek) = K::generate(&mut OsRng);
+    (ek.as_bytes().to_vec(), dk.as_bytes().to_vec())
+}
+
+/// Returns `(ciphertext, shared secret)`
+fn encapsulate_with<K: KemCore>(public: &[u8]) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+    let encoded = Encoded::<K::EncapsulationKey>::try_from(public).map_err(|_| anyhow!("public key has the wrong length ({} bytes)", public.len()))?;
+    let (ct, shared) = K::EncapsulationKey::from_bytes(&encoded)
+        .encapsulate(&mut OsRng)
+        .map_err(|_| anyhow!("ML-KEM encapsulation failed"))?;
+    Ok((ct.to_vec(), Zeroizing::new(shared.to_vec())))
+}
+
+fn decapsulate_with<K: KemCore>(private: &[u8], kem_ct: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let encoded = Encoded::<K::DecapsulationKey>::try_from(private).map_err(|_| anyhow!("private key has the wrong length ({} bytes)", private.len()))?;
+    let ct = Ciphertext::<K>::try_from(kem_ct).map_err(|_| anyhow!("KEM ciphertext has the wrong length ({} bytes)", kem_ct.len()))?;
+    let shared = K::DecapsulationKey::from_bytes(&encoded)
+        .decapsulate(&ct)
+        .map_err(|_| anyhow!("ML-KEM decapsulation failed"))?;
+    Ok(Zeroizing::new(shared.to_vec()))
+}
+
+/// The public key inside an ML-KEM private key of checked length, which is laid
+/// out as `dk_pke (384k) || ek (384k + 32) || H(ek) || z`
+fn embedded_public(private: &[u8]) -> &[u8] {
+    let start = (private.len() - 96) / 2;
+    &private[start..2 * start + 32]
+}
+
+fn encode_key(kind: KeyKind, kem: KemId, key: &[u8]) -> Vec<u8> {
+    KeyEnvelope { kind, alg: kem.as_u8(), meta: &[], key }.to_bytes()
+}
+
+/// Parse binary key file bytes of the given kind; metadata is skipped
+fn decode_key(bytes: &[u8], kind: KeyKind) -> Result<(KemId, &[u8])> {
+    parse_key(bytes, kind).map_err(|e| PitlinkError::KeyFormat(format!("{:#}", e)).into())
+}
+
+fn parse_key(bytes: &[u8], kind: KeyKind) -> Result<(KemId, &[u8])> {
+    let name = if kind == KeyKind::Public { "public" } else { "private" };
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    if bytes[start..].starts_with(b"-----BEGIN ") {
+        bail!("armored key files are not supported here; pass the binary {} key file", name);
+    }
+    let Some(envelope) = KeyEnvelope::from_bytes(bytes)? else {
+        bail!("not a pitlink {} key file", name);
+    };
+    if envelope.kind != kind {
+        bail!("expected a {} key file", name);
+    }
+    Ok((KemId::from_u8(envelope.alg)?, envelope.key))
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut out = vec![0u8; len];
+    getrandom::getrandom(&mut out)?;
+    Ok(out)
+}
+
+/// Cipher of the frame at `seq`: the file key, or the frame's own subkey when the
+/// header records a chunk KDF
+fn frame_cipher(header: &Header, file_key: &[u8], seq: u64) -> Result<Cipher> {
+    match header.chunk_kdf()? {
+        Some(kdf) => Cipher::new(header.aead, &kdf.frame_key(file_key, seq)?[..]),
+        None => Cipher::new(header.aead, file_key),
+    }
+}
+
+/// The AEAD named by a package header
+enum Cipher {
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    Aes256Gcm(Aes256Gcm),
+}
+
+impl Cipher {
+    fn new(aead: AeadId, key: &[u8]) -> Result<Self> {
+        Ok(match aead {
+            AeadId::XChaCha20Poly1305 => Cipher::XChaCha20Poly1305(XChaCha20Poly1305::new_from_slice(key).map_err(|e| anyhow!("invalid key: {}", e))?),
+            AeadId::Aes256Gcm => Cipher::Aes256Gcm(Aes256Gcm::new_from_slice(key).map_err(|e| anyhow!("invalid key: {}", e))?),
+        })
+    }
+
+    fn nonce_len(&self) -> usize {
+        match self {
+            Cipher::XChaCha20Poly1305(_) => AeadId::XChaCha20Poly1305.nonce_len(),
+            Cipher::Aes256Gcm(_) => AeadId::Aes256Gcm.nonce_len(),
+        }
+    }
+
+    fn encrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != self.nonce_len() { bail!("nonce has length {}, expected {}", nonce.len(), self.nonce_len()); }
+        let payload = Payload { msg, aad };
+        match self {
+            Cipher::XChaCha20Poly1305(c) => c.encrypt(nonce.into(), payload),
+            Cipher::Aes256Gcm(c) => c.encrypt(nonce.into(), payload),
+        }
+        .map_err(|e| anyhow!("AEAD encrypt: {}", e))
+    }
+
+    fn decrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != self.nonce_len() { bail!("nonce has length {}, expected {}", nonce.len(), self.nonce_len()); }
+        let payload = Payload { msg, aad };
+        match self {
+            Cipher::XChaCha20Poly1305(c) => c.decrypt(nonce.into(), payload),
+            Cipher::Aes256Gcm(c) => c.decrypt(nonce.into(), payload),
+        }
+        .map_err(|e| anyhow!("AEAD decrypt: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_tamper() {
+        let (public, private) = generate_keypair(768).unwrap();
+        for len in [0, 17, CHUNK_SIZE, CHUNK_SIZE + 5] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 13) as u8).collect();
+            let package = encrypt(&plaintext, &public).unwrap();
+            assert_eq!(decrypt(&package, &private).unwrap(), plaintext);
+
+            let mut tampered = package.clone();
+            let mid = tampered.len() / 2;
+            tampered[mid] ^= 1;
+            assert!(decrypt(&tampered, &private).is_err());
+            assert!(decrypt(&package[..package.len() - 1], &private).is_err());
+        }
+        assert!(decrypt(&encrypt(b"x", &public).unwrap(), &public).is_err());
+        assert!(generate_keypair(640).is_err());
+    }
+
+    /// Packages and keys interoperate with the native library
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn native_interop() {
+        use pitlink_pqc::keyfile::{KeyFile, KeyKind, KeyMetadata};
+
+        let (public, private) = pitlink_pqc::generate_keypair(KemId::MlKem1024, None, KeyMetadata::now(None, None)).unwrap();
+        let msg = b"lap 12 sector 2 telemetry";
+        let mut package = Vec::new();
+        pitlink_pqc::encrypt(&msg[..], &mut package, &public, None, &pitlink_pqc::EncryptOptions::default()).unwrap();
+        assert_eq!(decrypt(&package, &private.to_bytes()).unwrap(), msg);
+        let opts = pitlink_pqc::EncryptOptions { compress: Some(pitlink_pqc::Compression::Lz4), ..Default::default() };
+        let mut package = Vec::new();
+        pitlink_pqc::encrypt(&msg[..], &mut package, &public, None, &opts).unwrap();
+        assert_eq!(decrypt(&package, &private.to_bytes()).unwrap(), msg);
+
+        let (public, private) = generate_keypair(512).unwrap();
+        let package = encrypt(msg, &public).unwrap();
+        let identity = KeyFile::from_bytes(&private, KeyKind::Private).unwrap();
+        let mut out = Vec::new();
+        pitlink_pqc::decrypt(&package[..], &mut out, &identity, None).unwrap();
+        assert_eq!(out, msg);
+    }
+}